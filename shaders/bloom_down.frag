@@ -0,0 +1,59 @@
+#version 450
+
+layout(binding = 0) uniform texture2D sourceImage;
+layout(binding = 1) uniform sampler sourceSampler;
+
+layout(push_constant) uniform PushConstants {
+    vec2 texelSize;
+    float threshold;
+    float knee;
+    float radius;
+    uint prefilter;
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 sampleSource(vec2 offset) {
+    return texture(sampler2D(sourceImage, sourceSampler), fragTexCoord + offset * pcs.texelSize).rgb;
+}
+
+// Keeps the part of the color above the threshold, easing in over the knee so bright edges do
+// not pop. A zero threshold keeps everything.
+vec3 prefilter(vec3 color) {
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - pcs.threshold + pcs.knee, 0.0, 2.0 * pcs.knee);
+    soft = soft * soft / (4.0 * pcs.knee + 0.00001);
+    float contribution = max(soft, brightness - pcs.threshold) / max(brightness, 0.00001);
+    return color * contribution;
+}
+
+// The 13 tap downsample from Call of Duty: Advanced Warfare, a box filter over overlapping 2×2
+// blocks that avoids the shimmer of a plain bilinear downsample.
+void main() {
+    vec3 a = sampleSource(vec2(-2.0, 2.0));
+    vec3 b = sampleSource(vec2(0.0, 2.0));
+    vec3 c = sampleSource(vec2(2.0, 2.0));
+    vec3 d = sampleSource(vec2(-2.0, 0.0));
+    vec3 e = sampleSource(vec2(0.0, 0.0));
+    vec3 f = sampleSource(vec2(2.0, 0.0));
+    vec3 g = sampleSource(vec2(-2.0, -2.0));
+    vec3 h = sampleSource(vec2(0.0, -2.0));
+    vec3 i = sampleSource(vec2(2.0, -2.0));
+    vec3 j = sampleSource(vec2(-1.0, 1.0));
+    vec3 k = sampleSource(vec2(1.0, 1.0));
+    vec3 l = sampleSource(vec2(-1.0, -1.0));
+    vec3 m = sampleSource(vec2(1.0, -1.0));
+
+    vec3 color = e * 0.125;
+    color += (a + c + g + i) * 0.03125;
+    color += (b + d + f + h) * 0.0625;
+    color += (j + k + l + m) * 0.125;
+
+    if (pcs.prefilter != 0u) {
+        color = prefilter(color);
+    }
+
+    outColor = vec4(max(color, vec3(0.0)), 1.0);
+}
@@ -0,0 +1,29 @@
+#version 450
+
+layout(binding = 0) uniform texture2D sourceImage;
+layout(binding = 1) uniform sampler sourceSampler;
+
+layout(push_constant) uniform PushConstants {
+    vec2 texelSize;
+    float threshold;
+    float knee;
+    float radius;
+    uint prefilter;
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 sampleSource(vec2 offset) {
+    return texture(sampler2D(sourceImage, sourceSampler), fragTexCoord + offset * pcs.texelSize * pcs.radius).rgb;
+}
+
+// A 3×3 tent filter over the smaller level, blended onto the larger one.
+void main() {
+    vec3 color = sampleSource(vec2(0.0, 0.0)) * 4.0;
+    color += (sampleSource(vec2(0.0, 1.0)) + sampleSource(vec2(-1.0, 0.0)) + sampleSource(vec2(1.0, 0.0)) + sampleSource(vec2(0.0, -1.0))) * 2.0;
+    color += sampleSource(vec2(-1.0, 1.0)) + sampleSource(vec2(1.0, 1.0)) + sampleSource(vec2(-1.0, -1.0)) + sampleSource(vec2(1.0, -1.0));
+
+    outColor = vec4(color / 16.0, 1.0);
+}
@@ -0,0 +1,290 @@
+#version 450
+
+const int MAX_LIGHTS = 16;
+const int MAX_CASCADES = 4;
+const int MAX_POINT_SHADOWS = 4;
+const float PI = 3.14159265359;
+const float MAX_REFLECTION_LOD = 4.0;
+
+const float DIRECTIONAL = 0.0;
+const float POINT = 1.0;
+const float SPOT = 2.0;
+
+const uint BLINN_PHONG = 0u;
+const uint PBR = 1u;
+
+struct Light {
+    vec4 position;
+    vec4 direction;
+    vec4 ambient;
+    vec4 diffuse;
+    vec4 specular;
+    vec4 attenuation;
+    vec4 cone;
+};
+
+layout(binding = 3) uniform Lights {
+    vec4 cameraPosition;
+    uint count;
+    float shininess;
+    uint shading;
+    uint ambientOcclusion;
+    uint environment;
+    float environmentIntensity;
+    Light lights[MAX_LIGHTS];
+} lighting;
+
+layout(binding = 4) uniform Shadows {
+    mat4 lightViewProj[MAX_CASCADES];
+    vec4 splits;
+    uint cascadeCount;
+    uint pcfKernelSize;
+    uint light;
+    float pointBias;
+    mat4 pointViewProj[MAX_POINT_SHADOWS * 6];
+    vec4 pointPositions[MAX_POINT_SHADOWS];
+    uvec4 pointLights;
+} shadows;
+
+layout(binding = 5) uniform texture2DArray shadowMap;
+layout(binding = 6) uniform samplerShadow shadowSampler;
+layout(binding = 7) uniform textureCube pointShadowMap0;
+layout(binding = 8) uniform textureCube pointShadowMap1;
+layout(binding = 9) uniform textureCube pointShadowMap2;
+layout(binding = 10) uniform textureCube pointShadowMap3;
+layout(binding = 11) uniform sampler pointShadowSampler;
+layout(binding = 12) uniform texture2D ambientOcclusionImage;
+layout(binding = 13) uniform sampler ambientOcclusionSampler;
+layout(binding = 14) uniform textureCube environmentMap;
+layout(binding = 15) uniform textureCube irradianceMap;
+layout(binding = 16) uniform textureCube prefilteredMap;
+layout(binding = 17) uniform texture2D brdfLut;
+layout(binding = 18) uniform sampler environmentSampler;
+
+layout(set = 1, binding = 0) uniform texture2D gAlbedo;
+layout(set = 1, binding = 1) uniform texture2D gNormal;
+layout(set = 1, binding = 2) uniform texture2D gMaterial;
+layout(set = 1, binding = 3) uniform texture2D gEmissive;
+layout(set = 1, binding = 4) uniform texture2D gDepth;
+layout(set = 1, binding = 5) uniform sampler gSampler;
+
+layout(location = 0) flat in vec4 inverseViewProj0;
+layout(location = 1) flat in vec4 inverseViewProj1;
+layout(location = 2) flat in vec4 inverseViewProj2;
+layout(location = 3) flat in vec4 inverseViewProj3;
+layout(location = 4) flat in vec4 viewRow;
+
+layout(location = 0) out vec4 outColor;
+
+// Reconstructed from depth in `main`, under the names the forward shader uses.
+vec3 fragPosition;
+float fragViewDepth;
+
+// Direction to the light and its attenuation at the fragment.
+vec4 getLightDirection(Light light) {
+    if (light.position.w == DIRECTIONAL) {
+        return vec4(-light.direction.xyz, 1.0);
+    }
+
+    vec3 toLight = light.position.xyz - fragPosition;
+    float distance = length(toLight);
+    vec3 lightDirection = toLight / distance;
+    float attenuation = 1.0 / (light.attenuation.x + light.attenuation.y * distance + light.attenuation.z * distance * distance);
+
+    if (light.position.w == SPOT) {
+        float theta = dot(-lightDirection, light.direction.xyz);
+        attenuation *= clamp((theta - light.cone.y) / max(light.cone.x - light.cone.y, 0.0001), 0.0, 1.0);
+    }
+
+    return vec4(lightDirection, attenuation);
+}
+
+// Fraction of the shadowed light reaching the fragment, filtered over a square PCF kernel in
+// the cascade covering the fragment's view depth.
+float getShadow() {
+    uint cascade = 0u;
+    for (uint c = 0u; c + 1u < shadows.cascadeCount; c++) {
+        if (fragViewDepth > shadows.splits[c]) {
+            cascade = c + 1u;
+        }
+    }
+
+    vec4 position = shadows.lightViewProj[cascade] * vec4(fragPosition, 1.0);
+    vec3 coords = position.xyz / position.w;
+    if (coords.z > 1.0) {
+        return 1.0;
+    }
+
+    vec2 uv = coords.xy * 0.5 + 0.5;
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DArrayShadow(shadowMap, shadowSampler), 0).xy);
+    int radius = int(shadows.pcfKernelSize) / 2;
+    float lit = 0.0;
+    for (int x = -radius; x <= radius; x++) {
+        for (int y = -radius; y <= radius; y++) {
+            vec2 offset = vec2(float(x), float(y)) * texel;
+            lit += texture(sampler2DArrayShadow(shadowMap, shadowSampler), vec4(uv + offset, float(cascade), coords.z));
+        }
+    }
+
+    float size = float(2 * radius + 1);
+    return lit / (size * size);
+}
+
+// Stored distance to the nearest caster along `direction` from a point light, divided by the
+// far plane.
+float samplePointShadow(uint slot, vec3 direction) {
+    if (slot == 0u) {
+        return texture(samplerCube(pointShadowMap0, pointShadowSampler), direction).r;
+    } else if (slot == 1u) {
+        return texture(samplerCube(pointShadowMap1, pointShadowSampler), direction).r;
+    } else if (slot == 2u) {
+        return texture(samplerCube(pointShadowMap2, pointShadowSampler), direction).r;
+    }
+    return texture(samplerCube(pointShadowMap3, pointShadowSampler), direction).r;
+}
+
+// Fraction of a point light reaching the fragment, comparing distances to the light over a
+// cubic PCF kernel around the sample direction.
+float getPointShadow(uint slot) {
+    vec4 light = shadows.pointPositions[slot];
+    vec3 direction = fragPosition - light.xyz;
+    float far = light.w;
+    float current = (length(direction) - shadows.pointBias) / far;
+    if (current > 1.0) {
+        return 1.0;
+    }
+
+    float spread = length(direction) * 2.0 / float(textureSize(samplerCube(pointShadowMap0, pointShadowSampler), 0).x);
+    int radius = int(shadows.pcfKernelSize) / 2;
+    float lit = 0.0;
+    for (int x = -radius; x <= radius; x++) {
+        for (int y = -radius; y <= radius; y++) {
+            for (int z = -radius; z <= radius; z++) {
+                vec3 offset = vec3(float(x), float(y), float(z)) * spread;
+                lit += current <= samplePointShadow(slot, direction + offset) ? 1.0 : 0.0;
+            }
+        }
+    }
+
+    float size = float(2 * radius + 1);
+    return lit / (size * size * size);
+}
+
+// Screen-space ambient occlusion at the fragment, or none while the pass is off.
+float getAmbientOcclusion() {
+    if (lighting.ambientOcclusion == 0u) {
+        return 1.0;
+    }
+    return texelFetch(sampler2D(ambientOcclusionImage, ambientOcclusionSampler), ivec2(gl_FragCoord.xy), 0).r;
+}
+
+float distributionGGX(float NdotH, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = NdotH * NdotH * (a2 - 1.0) + 1.0;
+    return a2 / max(PI * d * d, 0.0000001);
+}
+
+float geometrySchlickGGX(float NdotX, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    return NdotX / (NdotX * (1.0 - k) + k);
+}
+
+vec3 fresnelSchlick(float cosTheta, vec3 F0) {
+    return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+// Ambient light from the environment maps: irradiance for the diffuse part and, with PBR
+// shading, the prefiltered reflection scaled by the split-sum BRDF for the specular part.
+vec3 getEnvironmentLight(vec3 N, vec3 V, float NdotV, vec3 baseColor, vec3 F0, float metallic, float roughness) {
+    if (lighting.environment == 0u) {
+        return vec3(0.0);
+    }
+
+    vec3 irradiance = texture(samplerCube(irradianceMap, environmentSampler), N).rgb;
+    if (lighting.shading != PBR) {
+        return irradiance * baseColor * lighting.environmentIntensity;
+    }
+
+    vec3 F = F0 + (max(vec3(1.0 - roughness), F0) - F0) * pow(clamp(1.0 - NdotV, 0.0, 1.0), 5.0);
+    vec3 diffuse = (1.0 - F) * (1.0 - metallic) * irradiance * baseColor;
+
+    vec3 R = reflect(-V, N);
+    vec3 prefiltered = textureLod(samplerCube(prefilteredMap, environmentSampler), R, roughness * MAX_REFLECTION_LOD).rgb;
+    vec2 brdf = texture(sampler2D(brdfLut, environmentSampler), vec2(NdotV, roughness)).rg;
+    vec3 specular = prefiltered * (F * brdf.x + brdf.y);
+
+    return (diffuse + specular) * lighting.environmentIntensity;
+}
+
+void main() {
+    ivec2 coords = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(sampler2D(gDepth, gSampler), coords, 0).r;
+    vec2 uv = gl_FragCoord.xy / vec2(textureSize(sampler2D(gDepth, gSampler), 0));
+    mat4 inverseViewProj = mat4(inverseViewProj0, inverseViewProj1, inverseViewProj2, inverseViewProj3);
+
+    // Nothing was drawn here, so the sky shows through.
+    if (depth >= 1.0) {
+        if (lighting.environment == 0u) {
+            discard;
+        }
+        vec4 far = inverseViewProj * vec4(uv * 2.0 - 1.0, 1.0, 1.0);
+        vec3 direction = far.xyz / far.w - lighting.cameraPosition.xyz;
+        outColor = vec4(textureLod(samplerCube(environmentMap, environmentSampler), direction, 0.0).rgb, 1.0);
+        return;
+    }
+
+    vec4 world = inverseViewProj * vec4(uv * 2.0 - 1.0, depth, 1.0);
+    fragPosition = world.xyz / world.w;
+    fragViewDepth = -dot(viewRow, vec4(fragPosition, 1.0));
+
+    vec4 albedo = texelFetch(sampler2D(gAlbedo, gSampler), coords, 0);
+    vec4 metallicRoughness = texelFetch(sampler2D(gMaterial, gSampler), coords, 0);
+    vec3 baseColor = albedo.rgb;
+    float occlusion = albedo.a * getAmbientOcclusion();
+    float metallic = metallicRoughness.r;
+    float roughness = metallicRoughness.g;
+    vec3 emissive = texelFetch(sampler2D(gEmissive, gSampler), coords, 0).rgb;
+    vec3 N = normalize(texelFetch(sampler2D(gNormal, gSampler), coords, 0).xyz);
+
+    vec3 V = normalize(lighting.cameraPosition.xyz - fragPosition);
+    float NdotV = max(dot(N, V), 0.0001);
+    vec3 F0 = mix(vec3(0.04), baseColor, metallic);
+    vec3 color = vec3(0.0);
+
+    for (uint i = 0u; i < lighting.count; i++) {
+        Light light = lighting.lights[i];
+        vec4 direction = getLightDirection(light);
+        vec3 L = direction.xyz;
+        float attenuation = direction.w;
+        if (i == shadows.light) {
+            attenuation *= getShadow();
+        }
+        for (uint s = 0u; s < uint(MAX_POINT_SHADOWS); s++) {
+            if (i == shadows.pointLights[s]) {
+                attenuation *= getPointShadow(s);
+            }
+        }
+        vec3 H = normalize(L + V);
+        float NdotL = max(dot(N, L), 0.0);
+
+        color += light.ambient.rgb * baseColor * occlusion;
+
+        if (lighting.shading == PBR) {
+            float D = distributionGGX(max(dot(N, H), 0.0), roughness);
+            float G = geometrySchlickGGX(NdotV, roughness) * geometrySchlickGGX(NdotL, roughness);
+            vec3 F = fresnelSchlick(max(dot(H, V), 0.0), F0);
+
+            vec3 specular = D * G * F / (4.0 * NdotV * NdotL + 0.0001);
+            vec3 diffuse = (1.0 - F) * (1.0 - metallic) * baseColor / PI;
+            color += (diffuse + specular) * light.diffuse.rgb * attenuation * NdotL;
+        } else {
+            float specular = NdotL > 0.0 ? pow(max(dot(N, H), 0.0), lighting.shininess) : 0.0;
+            color += attenuation * (light.diffuse.rgb * NdotL * baseColor + light.specular.rgb * specular);
+        }
+    }
+
+    color += getEnvironmentLight(N, V, NdotV, baseColor, F0, metallic, roughness) * occlusion;
+
+    outColor = vec4(color + emissive, 1.0);
+}
@@ -0,0 +1,28 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    mat4 normal;
+} ubo;
+
+layout(location = 0) flat out vec4 inverseViewProj0;
+layout(location = 1) flat out vec4 inverseViewProj1;
+layout(location = 2) flat out vec4 inverseViewProj2;
+layout(location = 3) flat out vec4 inverseViewProj3;
+layout(location = 4) flat out vec4 viewRow;
+
+// A single triangle covering the screen. The inverse view-projection is computed once per
+// vertex rather than per pixel.
+void main() {
+    vec2 uv = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2));
+    gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+
+    mat4 inverseViewProj = inverse(ubo.proj * ubo.view);
+    inverseViewProj0 = inverseViewProj[0];
+    inverseViewProj1 = inverseViewProj[1];
+    inverseViewProj2 = inverseViewProj[2];
+    inverseViewProj3 = inverseViewProj[3];
+    viewRow = vec4(ubo.view[0][2], ubo.view[1][2], ubo.view[2][2], ubo.view[3][2]);
+}
@@ -0,0 +1,43 @@
+#version 450
+
+const float PI = 3.14159265359;
+
+layout(binding = 0) uniform texture2D sourceImage;
+layout(binding = 1) uniform sampler sourceSampler;
+
+layout(push_constant) uniform PushConstants {
+    vec4 sunDirection;
+    uint face;
+    float roughness;
+    float resolution;
+    uint sampleCount;
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+// Direction through `uv` on cube face `face`, in the +X, -X, +Y, -Y, +Z, -Z layer order.
+vec3 cubeDirection(uint face, vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    if (face == 0u) {
+        return normalize(vec3(1.0, -st.y, -st.x));
+    } else if (face == 1u) {
+        return normalize(vec3(-1.0, -st.y, st.x));
+    } else if (face == 2u) {
+        return normalize(vec3(st.x, 1.0, st.y));
+    } else if (face == 3u) {
+        return normalize(vec3(st.x, -1.0, -st.y));
+    } else if (face == 4u) {
+        return normalize(vec3(st.x, -st.y, 1.0));
+    }
+    return normalize(vec3(-st.x, -st.y, -1.0));
+}
+
+// Resamples a latitude-longitude panorama into the environment's base level. The top row is
+// the zenith (+Z) and the left edge looks along -X, turning towards -Y.
+void main() {
+    vec3 direction = cubeDirection(pcs.face, fragTexCoord);
+    vec2 uv = vec2(atan(direction.y, direction.x) / (2.0 * PI) + 0.5, acos(clamp(direction.z, -1.0, 1.0)) / PI);
+    outColor = vec4(textureLod(sampler2D(sourceImage, sourceSampler), uv, 0.0).rgb, 1.0);
+}
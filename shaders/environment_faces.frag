@@ -0,0 +1,39 @@
+#version 450
+
+layout(binding = 0) uniform textureCube sourceImage;
+layout(binding = 1) uniform sampler sourceSampler;
+
+layout(push_constant) uniform PushConstants {
+    vec4 sunDirection;
+    uint face;
+    float roughness;
+    float resolution;
+    uint sampleCount;
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+// Direction through `uv` on cube face `face`, in the +X, -X, +Y, -Y, +Z, -Z layer order.
+vec3 cubeDirection(uint face, vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    if (face == 0u) {
+        return normalize(vec3(1.0, -st.y, -st.x));
+    } else if (face == 1u) {
+        return normalize(vec3(-1.0, -st.y, st.x));
+    } else if (face == 2u) {
+        return normalize(vec3(st.x, 1.0, st.y));
+    } else if (face == 3u) {
+        return normalize(vec3(st.x, -1.0, -st.y));
+    } else if (face == 4u) {
+        return normalize(vec3(st.x, -st.y, 1.0));
+    }
+    return normalize(vec3(-st.x, -st.y, -1.0));
+}
+
+// Resamples six supplied faces, given along the world axes, into the environment's base level.
+void main() {
+    vec3 direction = cubeDirection(pcs.face, fragTexCoord);
+    outColor = vec4(texture(samplerCube(sourceImage, sourceSampler), direction).rgb, 1.0);
+}
@@ -0,0 +1,58 @@
+#version 450
+
+const float PI = 3.14159265359;
+
+layout(binding = 0) uniform textureCube environmentMap;
+layout(binding = 1) uniform sampler environmentSampler;
+
+layout(push_constant) uniform PushConstants {
+    vec4 sunDirection;
+    uint face;
+    float roughness;
+    float resolution;
+    uint sampleCount;
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+// Direction through `uv` on cube face `face`, in the +X, -X, +Y, -Y, +Z, -Z layer order.
+vec3 cubeDirection(uint face, vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    if (face == 0u) {
+        return normalize(vec3(1.0, -st.y, -st.x));
+    } else if (face == 1u) {
+        return normalize(vec3(-1.0, -st.y, st.x));
+    } else if (face == 2u) {
+        return normalize(vec3(st.x, 1.0, st.y));
+    } else if (face == 3u) {
+        return normalize(vec3(st.x, -1.0, -st.y));
+    } else if (face == 4u) {
+        return normalize(vec3(st.x, -st.y, 1.0));
+    }
+    return normalize(vec3(-st.x, -st.y, -1.0));
+}
+
+// Cosine-weighted integral of the environment over the hemisphere around the texel's
+// direction, sampled from a blurred level so the sun does not alias.
+void main() {
+    vec3 N = cubeDirection(pcs.face, fragTexCoord);
+    vec3 up = abs(N.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 right = normalize(cross(up, N));
+    up = cross(N, right);
+
+    const float step = 0.025;
+    vec3 irradiance = vec3(0.0);
+    float count = 0.0;
+    for (float phi = 0.0; phi < 2.0 * PI; phi += step) {
+        for (float theta = 0.0; theta < 0.5 * PI; theta += step) {
+            vec3 local = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            vec3 direction = local.x * right + local.y * up + local.z * N;
+            irradiance += textureLod(samplerCube(environmentMap, environmentSampler), direction, 2.0).rgb * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+
+    outColor = vec4(PI * irradiance / count, 1.0);
+}
@@ -0,0 +1,99 @@
+#version 450
+
+const float PI = 3.14159265359;
+
+layout(binding = 0) uniform textureCube environmentMap;
+layout(binding = 1) uniform sampler environmentSampler;
+
+layout(push_constant) uniform PushConstants {
+    vec4 sunDirection;
+    uint face;
+    float roughness;
+    float resolution;
+    uint sampleCount;
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+// Direction through `uv` on cube face `face`, in the +X, -X, +Y, -Y, +Z, -Z layer order.
+vec3 cubeDirection(uint face, vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    if (face == 0u) {
+        return normalize(vec3(1.0, -st.y, -st.x));
+    } else if (face == 1u) {
+        return normalize(vec3(-1.0, -st.y, st.x));
+    } else if (face == 2u) {
+        return normalize(vec3(st.x, 1.0, st.y));
+    } else if (face == 3u) {
+        return normalize(vec3(st.x, -1.0, -st.y));
+    } else if (face == 4u) {
+        return normalize(vec3(st.x, -st.y, 1.0));
+    }
+    return normalize(vec3(-st.x, -st.y, -1.0));
+}
+
+// Van der Corput radical inverse of `i`, the second coordinate of the Hammersley set.
+float radicalInverse(uint bits) {
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return float(bits) * 2.3283064365386963e-10;
+}
+
+vec2 hammersley(uint i, uint count) {
+    return vec2(float(i) / float(count), radicalInverse(i));
+}
+
+// Half vector around `N` distributed by the GGX lobe of `roughness`.
+vec3 importanceSampleGGX(vec2 xi, vec3 N, float roughness) {
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+    vec3 H = vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+
+    vec3 up = abs(N.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, N));
+    vec3 bitangent = cross(N, tangent);
+    return normalize(tangent * H.x + bitangent * H.y + N * H.z);
+}
+
+float distributionGGX(float NdotH, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = NdotH * NdotH * (a2 - 1.0) + 1.0;
+    return a2 / max(PI * d * d, 0.0000001);
+}
+
+// The environment convolved with the GGX lobe for this level's roughness, assuming the view
+// and reflection directions equal the normal. Each sample reads the level whose texels cover
+// its share of the lobe, which keeps bright spots from turning into fireflies.
+void main() {
+    vec3 N = cubeDirection(pcs.face, fragTexCoord);
+    vec3 V = N;
+
+    float texelSolidAngle = 4.0 * PI / (6.0 * pcs.resolution * pcs.resolution);
+    vec3 color = vec3(0.0);
+    float weight = 0.0;
+    for (uint i = 0u; i < pcs.sampleCount; i++) {
+        vec3 H = importanceSampleGGX(hammersley(i, pcs.sampleCount), N, pcs.roughness);
+        vec3 L = normalize(2.0 * dot(V, H) * H - V);
+        float NdotL = dot(N, L);
+        if (NdotL > 0.0) {
+            float NdotH = max(dot(N, H), 0.0);
+            float HdotV = max(dot(H, V), 0.0);
+            float pdf = distributionGGX(NdotH, pcs.roughness) * NdotH / (4.0 * HdotV) + 0.0001;
+            float sampleSolidAngle = 1.0 / (float(pcs.sampleCount) * pdf + 0.0001);
+            float lod = pcs.roughness == 0.0 ? 0.0 : 0.5 * log2(sampleSolidAngle / texelSolidAngle);
+
+            color += textureLod(samplerCube(environmentMap, environmentSampler), L, lod).rgb * NdotL;
+            weight += NdotL;
+        }
+    }
+
+    outColor = vec4(color / max(weight, 0.0001), 1.0);
+}
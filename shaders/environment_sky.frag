@@ -0,0 +1,53 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    vec4 sunDirection;
+    uint face;
+    float roughness;
+    float resolution;
+    uint sampleCount;
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+// Direction through `uv` on cube face `face`, in the +X, -X, +Y, -Y, +Z, -Z layer order.
+vec3 cubeDirection(uint face, vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    if (face == 0u) {
+        return normalize(vec3(1.0, -st.y, -st.x));
+    } else if (face == 1u) {
+        return normalize(vec3(-1.0, -st.y, st.x));
+    } else if (face == 2u) {
+        return normalize(vec3(st.x, 1.0, st.y));
+    } else if (face == 3u) {
+        return normalize(vec3(st.x, -1.0, -st.y));
+    } else if (face == 4u) {
+        return normalize(vec3(st.x, -st.y, 1.0));
+    }
+    return normalize(vec3(-st.x, -st.y, -1.0));
+}
+
+// A Z-up sky fading from zenith to horizon over a dim ground, with a sun opposite
+// `sunDirection`.
+void main() {
+    vec3 direction = cubeDirection(pcs.face, fragTexCoord);
+    vec3 toSun = -normalize(pcs.sunDirection.xyz);
+
+    vec3 zenith = vec3(0.12, 0.3, 0.8);
+    vec3 horizon = vec3(0.7, 0.8, 0.95);
+    vec3 ground = vec3(0.2, 0.18, 0.16);
+
+    vec3 color = direction.z >= 0.0
+        ? mix(horizon, zenith, sqrt(direction.z))
+        : mix(horizon, ground, pow(-direction.z, 0.3));
+
+    float cosSun = dot(direction, toSun);
+    color += vec3(1.0, 0.85, 0.6) * pow(max(cosSun, 0.0), 64.0) * 0.5;
+    if (cosSun > 0.9998) {
+        color += vec3(1.0, 0.95, 0.85) * 50.0;
+    }
+
+    outColor = vec4(color, 1.0);
+}
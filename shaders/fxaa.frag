@@ -0,0 +1,174 @@
+#version 450
+
+const int SEARCH_STEPS = 12;
+
+layout(binding = 0) uniform texture2D inputImage;
+layout(binding = 1) uniform sampler inputSampler;
+
+// Parameters: subpixel blending, relative edge threshold, absolute edge threshold.
+layout(push_constant) uniform PushConstants {
+    vec2 texelSize;
+    vec4 parameters[2];
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 sampleColor(vec2 uv) {
+    return textureLod(sampler2D(inputImage, inputSampler), uv, 0.0).rgb;
+}
+
+// Perceptual luma of linear color.
+float luma(vec3 color) {
+    return sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+}
+
+float lumaAt(vec2 uv) {
+    return luma(sampleColor(uv));
+}
+
+// Distance the edge search advances at step `i`; it speeds up the further it gets.
+float stepSize(int i) {
+    if (i < 5) {
+        return 1.0;
+    } else if (i == 5) {
+        return 1.5;
+    } else if (i < 10) {
+        return 2.0;
+    } else if (i == 10) {
+        return 4.0;
+    }
+    return 8.0;
+}
+
+// FXAA 3.11 quality: finds the local edge orientation, searches along the edge for its ends
+// and resamples across it by how far the pixel is from the nearer end.
+void main() {
+    vec2 uv = fragTexCoord;
+    vec2 t = pcs.texelSize;
+    float subpixelQuality = pcs.parameters[0].x;
+    float edgeThreshold = pcs.parameters[0].y;
+    float edgeThresholdMin = pcs.parameters[0].z;
+
+    vec3 color = sampleColor(uv);
+    float lumaCenter = luma(color);
+    float lumaN = lumaAt(uv + vec2(0.0, -t.y));
+    float lumaS = lumaAt(uv + vec2(0.0, t.y));
+    float lumaW = lumaAt(uv + vec2(-t.x, 0.0));
+    float lumaE = lumaAt(uv + vec2(t.x, 0.0));
+
+    float lumaMin = min(lumaCenter, min(min(lumaN, lumaS), min(lumaW, lumaE)));
+    float lumaMax = max(lumaCenter, max(max(lumaN, lumaS), max(lumaW, lumaE)));
+    float lumaRange = lumaMax - lumaMin;
+    if (lumaRange < max(edgeThresholdMin, lumaMax * edgeThreshold)) {
+        outColor = vec4(color, 1.0);
+        return;
+    }
+
+    float lumaNW = lumaAt(uv + vec2(-t.x, -t.y));
+    float lumaNE = lumaAt(uv + vec2(t.x, -t.y));
+    float lumaSW = lumaAt(uv + vec2(-t.x, t.y));
+    float lumaSE = lumaAt(uv + vec2(t.x, t.y));
+
+    float lumaNS = lumaN + lumaS;
+    float lumaWE = lumaW + lumaE;
+    float lumaWestCorners = lumaNW + lumaSW;
+    float lumaEastCorners = lumaNE + lumaSE;
+    float lumaNorthCorners = lumaNW + lumaNE;
+    float lumaSouthCorners = lumaSW + lumaSE;
+
+    float edgeHorizontal = abs(-2.0 * lumaW + lumaWestCorners)
+        + abs(-2.0 * lumaCenter + lumaNS) * 2.0
+        + abs(-2.0 * lumaE + lumaEastCorners);
+    float edgeVertical = abs(-2.0 * lumaN + lumaNorthCorners)
+        + abs(-2.0 * lumaCenter + lumaWE) * 2.0
+        + abs(-2.0 * lumaS + lumaSouthCorners);
+    bool isHorizontal = edgeHorizontal >= edgeVertical;
+
+    // Side 1 lies in the negative direction across the edge.
+    float luma1 = isHorizontal ? lumaN : lumaW;
+    float luma2 = isHorizontal ? lumaS : lumaE;
+    float gradient1 = luma1 - lumaCenter;
+    float gradient2 = luma2 - lumaCenter;
+    bool is1Steepest = abs(gradient1) >= abs(gradient2);
+    float gradientScaled = 0.25 * max(abs(gradient1), abs(gradient2));
+
+    float stepLength = isHorizontal ? t.y : t.x;
+    float lumaLocalAverage = 0.0;
+    if (is1Steepest) {
+        stepLength = -stepLength;
+        lumaLocalAverage = 0.5 * (luma1 + lumaCenter);
+    } else {
+        lumaLocalAverage = 0.5 * (luma2 + lumaCenter);
+    }
+
+    vec2 edgeUv = uv;
+    if (isHorizontal) {
+        edgeUv.y += stepLength * 0.5;
+    } else {
+        edgeUv.x += stepLength * 0.5;
+    }
+
+    // Search both ways along the edge until the luma leaves the edge's average.
+    vec2 offset = isHorizontal ? vec2(t.x, 0.0) : vec2(0.0, t.y);
+    vec2 uv1 = edgeUv - offset;
+    vec2 uv2 = edgeUv + offset;
+    float lumaEnd1 = lumaAt(uv1) - lumaLocalAverage;
+    float lumaEnd2 = lumaAt(uv2) - lumaLocalAverage;
+    bool reached1 = abs(lumaEnd1) >= gradientScaled;
+    bool reached2 = abs(lumaEnd2) >= gradientScaled;
+    if (!reached1) {
+        uv1 -= offset;
+    }
+    if (!reached2) {
+        uv2 += offset;
+    }
+
+    for (int i = 2; i < SEARCH_STEPS; i++) {
+        if (reached1 && reached2) {
+            break;
+        }
+        if (!reached1) {
+            lumaEnd1 = lumaAt(uv1) - lumaLocalAverage;
+            reached1 = abs(lumaEnd1) >= gradientScaled;
+        }
+        if (!reached2) {
+            lumaEnd2 = lumaAt(uv2) - lumaLocalAverage;
+            reached2 = abs(lumaEnd2) >= gradientScaled;
+        }
+        if (!reached1) {
+            uv1 -= offset * stepSize(i);
+        }
+        if (!reached2) {
+            uv2 += offset * stepSize(i);
+        }
+    }
+
+    float distance1 = isHorizontal ? uv.x - uv1.x : uv.y - uv1.y;
+    float distance2 = isHorizontal ? uv2.x - uv.x : uv2.y - uv.y;
+    bool isDirection1 = distance1 < distance2;
+    float distanceFinal = min(distance1, distance2);
+    float edgeLength = distance1 + distance2;
+    float pixelOffset = -distanceFinal / edgeLength + 0.5;
+
+    // Only blend when the luma at the nearer end varies the same way as at the center.
+    bool isLumaCenterSmaller = lumaCenter < lumaLocalAverage;
+    bool correctVariation = ((isDirection1 ? lumaEnd1 : lumaEnd2) < 0.0) != isLumaCenterSmaller;
+    float finalOffset = correctVariation ? pixelOffset : 0.0;
+
+    // Subpixel aliasing, for features thinner than a pixel the edge search misses.
+    float lumaAverage = (1.0 / 12.0) * (2.0 * (lumaNS + lumaWE) + lumaWestCorners + lumaEastCorners);
+    float subpixel = clamp(abs(lumaAverage - lumaCenter) / lumaRange, 0.0, 1.0);
+    subpixel = (-2.0 * subpixel + 3.0) * subpixel * subpixel;
+    finalOffset = max(finalOffset, subpixel * subpixel * subpixelQuality);
+
+    vec2 finalUv = uv;
+    if (isHorizontal) {
+        finalUv.y += finalOffset * stepLength;
+    } else {
+        finalUv.x += finalOffset * stepLength;
+    }
+
+    outColor = vec4(sampleColor(finalUv), 1.0);
+}
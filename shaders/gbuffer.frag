@@ -0,0 +1,89 @@
+#version 450
+
+layout(set = 1, binding = 0) uniform Material {
+    vec4 baseColorFactor;
+    vec4 emissiveFactor;
+    float metallicFactor;
+    float roughnessFactor;
+    float normalScale;
+    float occlusionStrength;
+    float alphaCutoff;
+    uint alphaMask;
+    uint hasNormalTexture;
+} material;
+
+layout(set = 1, binding = 1) uniform texture2D baseColorTexture;
+layout(set = 1, binding = 2) uniform texture2D metallicRoughnessTexture;
+layout(set = 1, binding = 3) uniform texture2D normalTexture;
+layout(set = 1, binding = 4) uniform texture2D occlusionTexture;
+layout(set = 1, binding = 5) uniform texture2D emissiveTexture;
+layout(set = 1, binding = 6) uniform sampler materialSampler;
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragNormal;
+layout(location = 3) in vec3 fragPosition;
+layout(location = 4) in float fragViewDepth;
+layout(location = 5) flat in uint fragObjectId;
+layout(location = 6) in vec4 fragClipPosition;
+layout(location = 7) in vec4 fragPreviousClipPosition;
+
+// Surface attributes for the deferred lighting pass. Albedo carries occlusion in alpha.
+layout(location = 0) out vec4 outAlbedo;
+layout(location = 1) out vec4 outNormal;
+layout(location = 2) out vec4 outMaterial;
+layout(location = 3) out vec4 outEmissive;
+layout(location = 4) out vec2 outVelocity;
+layout(location = 5) out uint outObjectId;
+
+// Motion of this fragment since the previous frame, in texture coordinates.
+vec2 velocity() {
+    vec2 current = fragClipPosition.xy / fragClipPosition.w;
+    vec2 previous = fragPreviousClipPosition.xy / fragPreviousClipPosition.w;
+    return (current - previous) * 0.5;
+}
+
+// Perturbs the geometric normal with the normal texture, building the tangent frame from
+// screen-space derivatives so the vertex format needs no tangents.
+vec3 getNormal() {
+    vec3 normal = normalize(fragNormal);
+    if (material.hasNormalTexture == 0u) {
+        return normal;
+    }
+
+    vec3 dp1 = dFdx(fragPosition);
+    vec3 dp2 = dFdy(fragPosition);
+    vec2 duv1 = dFdx(fragTexCoord);
+    vec2 duv2 = dFdy(fragTexCoord);
+
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+    float scale = inversesqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 0.0000001));
+
+    vec3 sampled = texture(sampler2D(normalTexture, materialSampler), fragTexCoord).xyz * 2.0 - 1.0;
+    sampled.xy *= material.normalScale;
+    return normalize(mat3(tangent * scale, bitangent * scale, normal) * sampled);
+}
+
+void main() {
+    vec4 baseColor = material.baseColorFactor * texture(sampler2D(baseColorTexture, materialSampler), fragTexCoord);
+    baseColor.rgb *= fragColor;
+    if (material.alphaMask != 0u && baseColor.a < material.alphaCutoff) {
+        discard;
+    }
+
+    vec4 metallicRoughness = texture(sampler2D(metallicRoughnessTexture, materialSampler), fragTexCoord);
+    float metallic = clamp(material.metallicFactor * metallicRoughness.b, 0.0, 1.0);
+    float roughness = clamp(material.roughnessFactor * metallicRoughness.g, 0.04, 1.0);
+    float occlusion = mix(1.0, texture(sampler2D(occlusionTexture, materialSampler), fragTexCoord).r, material.occlusionStrength);
+    vec3 emissive = material.emissiveFactor.rgb * texture(sampler2D(emissiveTexture, materialSampler), fragTexCoord).rgb;
+
+    outAlbedo = vec4(baseColor.rgb, occlusion);
+    outNormal = vec4(getNormal(), 0.0);
+    outMaterial = vec4(metallic, roughness, 0.0, 0.0);
+    outEmissive = vec4(emissive, 1.0);
+    outVelocity = velocity();
+    outObjectId = fragObjectId;
+}
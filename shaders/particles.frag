@@ -0,0 +1,30 @@
+#version 450
+
+const uint ADDITIVE = 0u;
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProj;
+    vec4 cameraRight;
+    vec4 cameraUp;
+    uint blend;
+} pcs;
+
+layout(location = 0) in vec4 fragColor;
+layout(location = 1) in vec2 fragOffset;
+
+layout(location = 0) out vec4 outColor;
+
+// A soft disc. Additive particles are premultiplied, alpha blended ones are not.
+void main() {
+    float falloff = 1.0 - dot(fragOffset, fragOffset);
+    if (falloff <= 0.0) {
+        discard;
+    }
+
+    float alpha = fragColor.a * falloff * falloff;
+    if (pcs.blend == ADDITIVE) {
+        outColor = vec4(fragColor.rgb * alpha, 0.0);
+    } else {
+        outColor = vec4(fragColor.rgb, alpha);
+    }
+}
@@ -0,0 +1,74 @@
+#version 450
+
+const uint MAX_PARTICLES = 65536u;
+const uint MAX_EMITTERS = 16u;
+
+struct Particle {
+    vec4 position;
+    vec4 velocity;
+    uint emitter;
+    float size;
+    uint _padding0;
+    uint _padding1;
+};
+
+struct Emitter {
+    vec4 position;
+    vec4 direction;
+    vec4 color;
+    vec4 acceleration;
+    vec4 parameters;
+    uvec4 spawn;
+};
+
+layout(std430, binding = 0) readonly buffer Particles {
+    Particle particles[MAX_PARTICLES];
+} particleBuffer;
+
+layout(std430, binding = 1) readonly buffer Lists {
+    uint alive[MAX_PARTICLES];
+    uint dead[MAX_PARTICLES];
+} lists;
+
+layout(std430, binding = 3) readonly buffer Emitters {
+    Emitter emitters[MAX_EMITTERS];
+} emitterBuffer;
+
+layout(push_constant) uniform PushConstants {
+    mat4 viewProj;
+    vec4 cameraRight;
+    vec4 cameraUp;
+    uint blend;
+} pcs;
+
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 fragOffset;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+// Expands each live particle into a camera-facing quad. Particles of emitters using the other
+// blend mode are dropped by placing them outside the clip volume.
+void main() {
+    Particle particle = particleBuffer.particles[lists.alive[gl_InstanceIndex]];
+    Emitter emitter = emitterBuffer.emitters[particle.emitter];
+    vec2 corner = CORNERS[gl_VertexIndex];
+
+    if (emitter.spawn.z != pcs.blend) {
+        gl_Position = vec4(0.0, 0.0, 2.0, 1.0);
+        fragColor = vec4(0.0);
+        fragOffset = corner;
+        return;
+    }
+
+    float life = clamp(particle.position.w / particle.velocity.w, 0.0, 1.0);
+    float size = particle.size * (1.0 - 0.5 * life);
+    vec3 position = particle.position.xyz
+        + (pcs.cameraRight.xyz * corner.x + pcs.cameraUp.xyz * corner.y) * size;
+
+    gl_Position = pcs.viewProj * vec4(position, 1.0);
+    fragColor = vec4(emitter.color.rgb, emitter.color.a * (1.0 - life));
+    fragOffset = corner;
+}
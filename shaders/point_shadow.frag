@@ -0,0 +1,26 @@
+#version 450
+
+const int MAX_CASCADES = 4;
+const int MAX_POINT_SHADOWS = 4;
+
+layout(binding = 4) uniform Shadows {
+    mat4 lightViewProj[MAX_CASCADES];
+    vec4 splits;
+    uint cascadeCount;
+    uint pcfKernelSize;
+    uint light;
+    float pointBias;
+    mat4 pointViewProj[MAX_POINT_SHADOWS * 6];
+    vec4 pointPositions[MAX_POINT_SHADOWS];
+    uvec4 pointLights;
+} shadows;
+
+layout(location = 0) in vec3 fragPosition;
+layout(location = 1) flat in uint fragSlot;
+
+// Cube maps store the distance to the light rather than projected depth, so one comparison
+// works for every face.
+void main() {
+    vec4 light = shadows.pointPositions[fragSlot];
+    gl_FragDepth = length(fragPosition - light.xyz) / light.w;
+}
@@ -0,0 +1,58 @@
+#version 450
+
+const int MAX_JOINTS = 256;
+const int MAX_CASCADES = 4;
+const int MAX_POINT_SHADOWS = 4;
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    mat4 normal;
+} ubo;
+
+layout(binding = 2) uniform JointMatrices {
+    mat4 joints[MAX_JOINTS];
+} skin;
+
+layout(binding = 4) uniform Shadows {
+    mat4 lightViewProj[MAX_CASCADES];
+    vec4 splits;
+    uint cascadeCount;
+    uint pcfKernelSize;
+    uint light;
+    float pointBias;
+    mat4 pointViewProj[MAX_POINT_SHADOWS * 6];
+    vec4 pointPositions[MAX_POINT_SHADOWS];
+    uvec4 pointLights;
+} shadows;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    int jointOffset;
+    uint view;
+} pcs;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 3) in uvec4 inJoints;
+layout(location = 4) in vec4 inWeights;
+
+layout(location = 0) out vec3 fragPosition;
+layout(location = 1) flat out uint fragSlot;
+
+void main() {
+    mat4 skinMatrix = mat4(1.0);
+    if (pcs.jointOffset >= 0) {
+        uint offset = uint(pcs.jointOffset);
+        skinMatrix =
+            inWeights.x * skin.joints[offset + inJoints.x] +
+            inWeights.y * skin.joints[offset + inJoints.y] +
+            inWeights.z * skin.joints[offset + inJoints.z] +
+            inWeights.w * skin.joints[offset + inJoints.w];
+    }
+
+    vec4 position = ubo.model * pcs.model * skinMatrix * vec4(inPosition, 1.0);
+    fragPosition = position.xyz;
+    fragSlot = pcs.view / 6u;
+    gl_Position = shadows.pointViewProj[pcs.view] * position;
+}
@@ -0,0 +1,13 @@
+#version 450
+
+layout(binding = 0) uniform texture2D inputImage;
+layout(binding = 1) uniform sampler inputSampler;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+// Presents the output of a compute pass, which cannot write the swapchain image itself.
+void main() {
+    outColor = texture(sampler2D(inputImage, inputSampler), fragTexCoord);
+}
@@ -0,0 +1,29 @@
+#version 450
+
+layout(set = 1, binding = 0) uniform Material {
+    vec4 baseColorFactor;
+    vec4 emissiveFactor;
+    float metallicFactor;
+    float roughnessFactor;
+    float normalScale;
+    float occlusionStrength;
+    float alphaCutoff;
+    uint alphaMask;
+    uint hasNormalTexture;
+} material;
+
+layout(set = 1, binding = 1) uniform texture2D baseColorTexture;
+layout(set = 1, binding = 6) uniform sampler materialSampler;
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+
+// Depth only; alpha-masked texels are discarded as in the shading pass.
+void main() {
+    if (material.alphaMask != 0u) {
+        float alpha = material.baseColorFactor.a * texture(sampler2D(baseColorTexture, materialSampler), fragTexCoord).a;
+        if (alpha < material.alphaCutoff) {
+            discard;
+        }
+    }
+}
@@ -1,8 +1,320 @@
 #version 450
 
+const int MAX_LIGHTS = 16;
+const int MAX_CASCADES = 4;
+const int MAX_POINT_SHADOWS = 4;
+const float PI = 3.14159265359;
+const float MAX_REFLECTION_LOD = 4.0;
+
+const float DIRECTIONAL = 0.0;
+const float POINT = 1.0;
+const float SPOT = 2.0;
+
+const uint BLINN_PHONG = 0u;
+const uint PBR = 1u;
+
+struct Light {
+    vec4 position;
+    vec4 direction;
+    vec4 ambient;
+    vec4 diffuse;
+    vec4 specular;
+    vec4 attenuation;
+    vec4 cone;
+};
+
+layout(binding = 3) uniform Lights {
+    vec4 cameraPosition;
+    uint count;
+    float shininess;
+    uint shading;
+    uint ambientOcclusion;
+    uint environment;
+    float environmentIntensity;
+    Light lights[MAX_LIGHTS];
+} lighting;
+
+layout(binding = 4) uniform Shadows {
+    mat4 lightViewProj[MAX_CASCADES];
+    vec4 splits;
+    uint cascadeCount;
+    uint pcfKernelSize;
+    uint light;
+    float pointBias;
+    mat4 pointViewProj[MAX_POINT_SHADOWS * 6];
+    vec4 pointPositions[MAX_POINT_SHADOWS];
+    uvec4 pointLights;
+} shadows;
+
+layout(binding = 5) uniform texture2DArray shadowMap;
+layout(binding = 6) uniform samplerShadow shadowSampler;
+layout(binding = 7) uniform textureCube pointShadowMap0;
+layout(binding = 8) uniform textureCube pointShadowMap1;
+layout(binding = 9) uniform textureCube pointShadowMap2;
+layout(binding = 10) uniform textureCube pointShadowMap3;
+layout(binding = 11) uniform sampler pointShadowSampler;
+layout(binding = 12) uniform texture2D ambientOcclusionImage;
+layout(binding = 13) uniform sampler ambientOcclusionSampler;
+layout(binding = 14) uniform textureCube environmentMap;
+layout(binding = 15) uniform textureCube irradianceMap;
+layout(binding = 16) uniform textureCube prefilteredMap;
+layout(binding = 17) uniform texture2D brdfLut;
+layout(binding = 18) uniform sampler environmentSampler;
+
+layout(set = 1, binding = 0) uniform Material {
+    vec4 baseColorFactor;
+    vec4 emissiveFactor;
+    float metallicFactor;
+    float roughnessFactor;
+    float normalScale;
+    float occlusionStrength;
+    float alphaCutoff;
+    uint alphaMask;
+    uint hasNormalTexture;
+} material;
+
+layout(set = 1, binding = 1) uniform texture2D baseColorTexture;
+layout(set = 1, binding = 2) uniform texture2D metallicRoughnessTexture;
+layout(set = 1, binding = 3) uniform texture2D normalTexture;
+layout(set = 1, binding = 4) uniform texture2D occlusionTexture;
+layout(set = 1, binding = 5) uniform texture2D emissiveTexture;
+layout(set = 1, binding = 6) uniform sampler materialSampler;
+
 layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragNormal;
+layout(location = 3) in vec3 fragPosition;
+layout(location = 4) in float fragViewDepth;
+layout(location = 5) flat in uint fragObjectId;
+layout(location = 6) in vec4 fragClipPosition;
+layout(location = 7) in vec4 fragPreviousClipPosition;
+
 layout(location = 0) out vec4 outColor;
+layout(location = 1) out vec2 outVelocity;
+layout(location = 2) out uint outObjectId;
+
+// Motion of this fragment since the previous frame, in texture coordinates.
+vec2 velocity() {
+    vec2 current = fragClipPosition.xy / fragClipPosition.w;
+    vec2 previous = fragPreviousClipPosition.xy / fragPreviousClipPosition.w;
+    return (current - previous) * 0.5;
+}
+
+// Perturbs the geometric normal with the normal texture, building the tangent frame from
+// screen-space derivatives so the vertex format needs no tangents.
+vec3 getNormal() {
+    vec3 normal = normalize(fragNormal);
+    if (material.hasNormalTexture == 0u) {
+        return normal;
+    }
+
+    vec3 dp1 = dFdx(fragPosition);
+    vec3 dp2 = dFdy(fragPosition);
+    vec2 duv1 = dFdx(fragTexCoord);
+    vec2 duv2 = dFdy(fragTexCoord);
+
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+    float scale = inversesqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 0.0000001));
+
+    vec3 sampled = texture(sampler2D(normalTexture, materialSampler), fragTexCoord).xyz * 2.0 - 1.0;
+    sampled.xy *= material.normalScale;
+    return normalize(mat3(tangent * scale, bitangent * scale, normal) * sampled);
+}
+
+// Direction to the light and its attenuation at the fragment.
+vec4 getLightDirection(Light light) {
+    if (light.position.w == DIRECTIONAL) {
+        return vec4(-light.direction.xyz, 1.0);
+    }
+
+    vec3 toLight = light.position.xyz - fragPosition;
+    float distance = length(toLight);
+    vec3 lightDirection = toLight / distance;
+    float attenuation = 1.0 / (light.attenuation.x + light.attenuation.y * distance + light.attenuation.z * distance * distance);
+
+    if (light.position.w == SPOT) {
+        float theta = dot(-lightDirection, light.direction.xyz);
+        attenuation *= clamp((theta - light.cone.y) / max(light.cone.x - light.cone.y, 0.0001), 0.0, 1.0);
+    }
+
+    return vec4(lightDirection, attenuation);
+}
+
+// Fraction of the shadowed light reaching the fragment, filtered over a square PCF kernel in
+// the cascade covering the fragment's view depth.
+float getShadow() {
+    uint cascade = 0u;
+    for (uint c = 0u; c + 1u < shadows.cascadeCount; c++) {
+        if (fragViewDepth > shadows.splits[c]) {
+            cascade = c + 1u;
+        }
+    }
+
+    vec4 position = shadows.lightViewProj[cascade] * vec4(fragPosition, 1.0);
+    vec3 coords = position.xyz / position.w;
+    if (coords.z > 1.0) {
+        return 1.0;
+    }
+
+    vec2 uv = coords.xy * 0.5 + 0.5;
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DArrayShadow(shadowMap, shadowSampler), 0).xy);
+    int radius = int(shadows.pcfKernelSize) / 2;
+    float lit = 0.0;
+    for (int x = -radius; x <= radius; x++) {
+        for (int y = -radius; y <= radius; y++) {
+            vec2 offset = vec2(float(x), float(y)) * texel;
+            lit += texture(sampler2DArrayShadow(shadowMap, shadowSampler), vec4(uv + offset, float(cascade), coords.z));
+        }
+    }
+
+    float size = float(2 * radius + 1);
+    return lit / (size * size);
+}
+
+// Stored distance to the nearest caster along `direction` from a point light, divided by the
+// far plane.
+float samplePointShadow(uint slot, vec3 direction) {
+    if (slot == 0u) {
+        return texture(samplerCube(pointShadowMap0, pointShadowSampler), direction).r;
+    } else if (slot == 1u) {
+        return texture(samplerCube(pointShadowMap1, pointShadowSampler), direction).r;
+    } else if (slot == 2u) {
+        return texture(samplerCube(pointShadowMap2, pointShadowSampler), direction).r;
+    }
+    return texture(samplerCube(pointShadowMap3, pointShadowSampler), direction).r;
+}
+
+// Fraction of a point light reaching the fragment, comparing distances to the light over a
+// cubic PCF kernel around the sample direction.
+float getPointShadow(uint slot) {
+    vec4 light = shadows.pointPositions[slot];
+    vec3 direction = fragPosition - light.xyz;
+    float far = light.w;
+    float current = (length(direction) - shadows.pointBias) / far;
+    if (current > 1.0) {
+        return 1.0;
+    }
+
+    float spread = length(direction) * 2.0 / float(textureSize(samplerCube(pointShadowMap0, pointShadowSampler), 0).x);
+    int radius = int(shadows.pcfKernelSize) / 2;
+    float lit = 0.0;
+    for (int x = -radius; x <= radius; x++) {
+        for (int y = -radius; y <= radius; y++) {
+            for (int z = -radius; z <= radius; z++) {
+                vec3 offset = vec3(float(x), float(y), float(z)) * spread;
+                lit += current <= samplePointShadow(slot, direction + offset) ? 1.0 : 0.0;
+            }
+        }
+    }
+
+    float size = float(2 * radius + 1);
+    return lit / (size * size * size);
+}
+
+// Screen-space ambient occlusion at the fragment, or none while the pass is off.
+float getAmbientOcclusion() {
+    if (lighting.ambientOcclusion == 0u) {
+        return 1.0;
+    }
+    return texelFetch(sampler2D(ambientOcclusionImage, ambientOcclusionSampler), ivec2(gl_FragCoord.xy), 0).r;
+}
+
+float distributionGGX(float NdotH, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = NdotH * NdotH * (a2 - 1.0) + 1.0;
+    return a2 / max(PI * d * d, 0.0000001);
+}
+
+float geometrySchlickGGX(float NdotX, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    return NdotX / (NdotX * (1.0 - k) + k);
+}
+
+vec3 fresnelSchlick(float cosTheta, vec3 F0) {
+    return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+// Ambient light from the environment maps: irradiance for the diffuse part and, with PBR
+// shading, the prefiltered reflection scaled by the split-sum BRDF for the specular part.
+vec3 getEnvironmentLight(vec3 N, vec3 V, float NdotV, vec3 baseColor, vec3 F0, float metallic, float roughness) {
+    if (lighting.environment == 0u) {
+        return vec3(0.0);
+    }
+
+    vec3 irradiance = texture(samplerCube(irradianceMap, environmentSampler), N).rgb;
+    if (lighting.shading != PBR) {
+        return irradiance * baseColor * lighting.environmentIntensity;
+    }
+
+    vec3 F = F0 + (max(vec3(1.0 - roughness), F0) - F0) * pow(clamp(1.0 - NdotV, 0.0, 1.0), 5.0);
+    vec3 diffuse = (1.0 - F) * (1.0 - metallic) * irradiance * baseColor;
+
+    vec3 R = reflect(-V, N);
+    vec3 prefiltered = textureLod(samplerCube(prefilteredMap, environmentSampler), R, roughness * MAX_REFLECTION_LOD).rgb;
+    vec2 brdf = texture(sampler2D(brdfLut, environmentSampler), vec2(NdotV, roughness)).rg;
+    vec3 specular = prefiltered * (F * brdf.x + brdf.y);
+
+    return (diffuse + specular) * lighting.environmentIntensity;
+}
 
 void main() {
-    outColor = vec4(fragColor, 1.0);
-}
\ No newline at end of file
+    vec4 baseColor = material.baseColorFactor * texture(sampler2D(baseColorTexture, materialSampler), fragTexCoord);
+    baseColor.rgb *= fragColor;
+    if (material.alphaMask != 0u && baseColor.a < material.alphaCutoff) {
+        discard;
+    }
+
+    vec4 metallicRoughness = texture(sampler2D(metallicRoughnessTexture, materialSampler), fragTexCoord);
+    float metallic = clamp(material.metallicFactor * metallicRoughness.b, 0.0, 1.0);
+    float roughness = clamp(material.roughnessFactor * metallicRoughness.g, 0.04, 1.0);
+    float occlusion = mix(1.0, texture(sampler2D(occlusionTexture, materialSampler), fragTexCoord).r, material.occlusionStrength) * getAmbientOcclusion();
+    vec3 emissive = material.emissiveFactor.rgb * texture(sampler2D(emissiveTexture, materialSampler), fragTexCoord).rgb;
+
+    vec3 N = getNormal();
+    vec3 V = normalize(lighting.cameraPosition.xyz - fragPosition);
+    float NdotV = max(dot(N, V), 0.0001);
+    vec3 F0 = mix(vec3(0.04), baseColor.rgb, metallic);
+    vec3 color = vec3(0.0);
+
+    for (uint i = 0u; i < lighting.count; i++) {
+        Light light = lighting.lights[i];
+        vec4 direction = getLightDirection(light);
+        vec3 L = direction.xyz;
+        float attenuation = direction.w;
+        if (i == shadows.light) {
+            attenuation *= getShadow();
+        }
+        for (uint s = 0u; s < uint(MAX_POINT_SHADOWS); s++) {
+            if (i == shadows.pointLights[s]) {
+                attenuation *= getPointShadow(s);
+            }
+        }
+        vec3 H = normalize(L + V);
+        float NdotL = max(dot(N, L), 0.0);
+
+        color += light.ambient.rgb * baseColor.rgb * occlusion;
+
+        if (lighting.shading == PBR) {
+            float D = distributionGGX(max(dot(N, H), 0.0), roughness);
+            float G = geometrySchlickGGX(NdotV, roughness) * geometrySchlickGGX(NdotL, roughness);
+            vec3 F = fresnelSchlick(max(dot(H, V), 0.0), F0);
+
+            vec3 specular = D * G * F / (4.0 * NdotV * NdotL + 0.0001);
+            vec3 diffuse = (1.0 - F) * (1.0 - metallic) * baseColor.rgb / PI;
+            color += (diffuse + specular) * light.diffuse.rgb * attenuation * NdotL;
+        } else {
+            float specular = NdotL > 0.0 ? pow(max(dot(N, H), 0.0), lighting.shininess) : 0.0;
+            color += attenuation * (light.diffuse.rgb * NdotL * baseColor.rgb + light.specular.rgb * specular);
+        }
+    }
+
+    color += getEnvironmentLight(N, V, NdotV, baseColor.rgb, F0, metallic, roughness) * occlusion;
+
+    outColor = vec4(color + emissive, baseColor.a);
+    outVelocity = velocity();
+    outObjectId = fragObjectId;
+}
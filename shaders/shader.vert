@@ -1,16 +1,127 @@
 #version 450
 
+const int MAX_JOINTS = 256;
+const uint MAX_NODES = 16384u;
+
 layout(binding = 0) uniform UniformBufferObject {
     mat4 model;
     mat4 view;
     mat4 proj;
+    mat4 normal;
+    mat4 unjitteredProj;
+    mat4 previousModel;
+    mat4 previousView;
+    mat4 previousProj;
 } ubo;
 
+layout(binding = 2) uniform JointMatrices {
+    mat4 joints[MAX_JOINTS];
+} skin;
+
+struct Object {
+    vec4 sphere;
+    uint indexCount;
+    uint firstIndex;
+    int vertexOffset;
+    int jointOffset;
+    uint objectId;
+    uint batch;
+    uint batchStart;
+    uint padding;
+};
+
+struct Transform {
+    mat4 model;
+    mat3 normalMatrix;
+};
+
+layout(binding = 19) readonly buffer ObjectBuffer {
+    Object objects[];
+} objectBuffer;
+
+layout(binding = 20) readonly buffer TransformBuffer {
+    Transform transforms[];
+} transformBuffer;
+
+// What the previous frame was drawn with: its joint matrices, then each node's world transform.
+layout(binding = 21) readonly buffer PreviousFrame {
+    mat4 joints[MAX_JOINTS];
+    mat4 models[MAX_NODES];
+} previous;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    mat3 normalMatrix;
+    int jointOffset;
+    uint objectId;
+    uint indirect;
+} pcs;
+
 layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec3 inColor;
+layout(location = 2) in vec2 inTexCoord;
+layout(location = 3) in uvec4 inJoints;
+layout(location = 4) in vec4 inWeights;
+layout(location = 5) in vec3 inNormal;
+
 layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec2 fragTexCoord;
+layout(location = 2) out vec3 fragNormal;
+layout(location = 3) out vec3 fragPosition;
+layout(location = 4) out float fragViewDepth;
+layout(location = 5) flat out uint fragObjectId;
+layout(location = 6) out vec4 fragClipPosition;
+layout(location = 7) out vec4 fragPreviousClipPosition;
 
 void main() {
-    gl_Position = ubo.proj * ubo.view * ubo.model * vec4(inPosition, 1.0);
+    mat4 model = pcs.model;
+    mat3 normalMatrix = pcs.normalMatrix;
+    int jointOffset = pcs.jointOffset;
+    uint objectId = pcs.objectId;
+
+    // Indirect draws are culled and issued by the GPU, one instance per object.
+    if (pcs.indirect != 0u) {
+        Object object = objectBuffer.objects[gl_InstanceIndex];
+        model = transformBuffer.transforms[gl_InstanceIndex].model;
+        normalMatrix = transformBuffer.transforms[gl_InstanceIndex].normalMatrix;
+        jointOffset = object.jointOffset;
+        objectId = object.objectId;
+    }
+
+    mat4 skinMatrix = mat4(1.0);
+    mat4 previousSkinMatrix = mat4(1.0);
+    if (jointOffset >= 0) {
+        uint offset = uint(jointOffset);
+        skinMatrix =
+            inWeights.x * skin.joints[offset + inJoints.x] +
+            inWeights.y * skin.joints[offset + inJoints.y] +
+            inWeights.z * skin.joints[offset + inJoints.z] +
+            inWeights.w * skin.joints[offset + inJoints.w];
+        previousSkinMatrix =
+            inWeights.x * previous.joints[offset + inJoints.x] +
+            inWeights.y * previous.joints[offset + inJoints.y] +
+            inWeights.z * previous.joints[offset + inJoints.z] +
+            inWeights.w * previous.joints[offset + inJoints.w];
+    }
+
+    // Nodes past the buffer keep no previous transform and are drawn without motion.
+    mat4 previousModel = model;
+    if (objectId - 1u < MAX_NODES) {
+        previousModel = previous.models[objectId - 1u];
+    }
+
+    vec4 worldPosition = ubo.model * model * skinMatrix * vec4(inPosition, 1.0);
+    vec4 viewPosition = ubo.view * worldPosition;
+    gl_Position = ubo.proj * viewPosition;
     fragColor = inColor;
-}
\ No newline at end of file
+    fragTexCoord = inTexCoord;
+    fragNormal = mat3(ubo.normal) * normalMatrix * mat3(skinMatrix) * inNormal;
+    fragPosition = worldPosition.xyz;
+    fragViewDepth = -viewPosition.z;
+    fragObjectId = objectId;
+
+    // Unjittered, so the velocity target holds only the motion since the previous frame.
+    fragClipPosition = ubo.unjitteredProj * viewPosition;
+    vec4 previousWorldPosition = ubo.previousModel * previousModel * previousSkinMatrix * vec4(inPosition, 1.0);
+    fragPreviousClipPosition = ubo.previousProj * ubo.previousView * previousWorldPosition;
+}
@@ -0,0 +1,16 @@
+#version 450
+
+layout(binding = 14) uniform textureCube environmentMap;
+layout(binding = 18) uniform sampler environmentSampler;
+
+layout(location = 0) in vec4 direction;
+layout(location = 1) in vec4 clipPosition;
+layout(location = 2) in vec4 previousClipPosition;
+
+layout(location = 0) out vec4 outColor;
+layout(location = 1) out vec2 outVelocity;
+
+void main() {
+    outColor = vec4(textureLod(samplerCube(environmentMap, environmentSampler), direction.xyz / direction.w, 0.0).rgb, 1.0);
+    outVelocity = (clipPosition.xy / clipPosition.w - previousClipPosition.xy / previousClipPosition.w) * 0.5;
+}
@@ -0,0 +1,28 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    mat4 normal;
+    mat4 unjitteredProj;
+    mat4 previousModel;
+    mat4 previousView;
+    mat4 previousProj;
+} ubo;
+
+layout(location = 0) out vec4 direction;
+layout(location = 1) out vec4 clipPosition;
+layout(location = 2) out vec4 previousClipPosition;
+
+// A single triangle on the far plane. The homogeneous view direction interpolates linearly
+// across it and is divided per fragment.
+void main() {
+    vec2 uv = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2));
+    gl_Position = vec4(uv * 2.0 - 1.0, 1.0, 1.0);
+    direction = inverse(ubo.proj * mat4(mat3(ubo.view))) * gl_Position;
+
+    // The sky only turns with the view; clip positions are linear across the triangle too.
+    clipPosition = ubo.unjitteredProj * mat4(mat3(ubo.view)) * direction;
+    previousClipPosition = ubo.previousProj * mat4(mat3(ubo.previousView)) * direction;
+}
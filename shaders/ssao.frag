@@ -0,0 +1,90 @@
+#version 450
+
+const int MAX_SAMPLES = 64;
+const int NOISE_SIZE = 4;
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    mat4 normal;
+} ubo;
+
+layout(set = 1, binding = 0) uniform texture2D depthImage;
+layout(set = 1, binding = 1) uniform texture2D noiseImage;
+layout(set = 1, binding = 2) uniform Kernel {
+    vec4 samples[MAX_SAMPLES];
+} kernel;
+layout(set = 1, binding = 3) uniform sampler nearestSampler;
+
+layout(push_constant) uniform PushConstants {
+    float radius;
+    float bias;
+    uint sampleCount;
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out float outOcclusion;
+
+// View-space depth of a depth buffer value under the perspective projection.
+float viewDepth(float depth) {
+    return -ubo.proj[3][2] / (depth + ubo.proj[2][2]);
+}
+
+// View-space position of the depth buffer texel at `coord`.
+vec3 viewPosition(ivec2 coord) {
+    ivec2 size = textureSize(sampler2D(depthImage, nearestSampler), 0);
+    coord = clamp(coord, ivec2(0), size - 1);
+    float z = viewDepth(texelFetch(sampler2D(depthImage, nearestSampler), coord, 0).r);
+    vec2 ndc = (vec2(coord) + 0.5) / vec2(size) * 2.0 - 1.0;
+    float x = (ndc.x * -z - ubo.proj[2][0] * z) / ubo.proj[0][0];
+    float y = (ndc.y * -z - ubo.proj[2][1] * z) / ubo.proj[1][1];
+    return vec3(x, y, z);
+}
+
+// Normal of the surface at `coord`, taking the smaller depth difference on each axis so that
+// silhouette edges do not bend it.
+vec3 viewNormal(ivec2 coord, vec3 position) {
+    vec3 left = position - viewPosition(coord - ivec2(1, 0));
+    vec3 right = viewPosition(coord + ivec2(1, 0)) - position;
+    vec3 up = position - viewPosition(coord - ivec2(0, 1));
+    vec3 down = viewPosition(coord + ivec2(0, 1)) - position;
+
+    vec3 dx = abs(left.z) < abs(right.z) ? left : right;
+    vec3 dy = abs(up.z) < abs(down.z) ? up : down;
+    vec3 normal = normalize(cross(dx, dy));
+    return dot(normal, position) > 0.0 ? -normal : normal;
+}
+
+void main() {
+    ivec2 coord = ivec2(gl_FragCoord.xy);
+    if (texelFetch(sampler2D(depthImage, nearestSampler), coord, 0).r >= 1.0) {
+        outOcclusion = 1.0;
+        return;
+    }
+
+    vec3 position = viewPosition(coord);
+    vec3 normal = viewNormal(coord, position);
+
+    // Rotate the kernel by the tiled noise to trade banding for noise the blur removes.
+    vec3 random = texelFetch(sampler2D(noiseImage, nearestSampler), coord & (NOISE_SIZE - 1), 0).xyz * 2.0 - 1.0;
+    vec3 tangent = normalize(random - normal * dot(random, normal));
+    vec3 bitangent = cross(normal, tangent);
+    mat3 tbn = mat3(tangent, bitangent, normal);
+
+    uint count = min(pcs.sampleCount, uint(MAX_SAMPLES));
+    float occlusion = 0.0;
+    for (uint i = 0u; i < count; i++) {
+        vec3 samplePosition = position + tbn * kernel.samples[i].xyz * pcs.radius;
+
+        vec4 clip = ubo.proj * vec4(samplePosition, 1.0);
+        vec2 uv = clip.xy / clip.w * 0.5 + 0.5;
+        float sceneDepth = viewDepth(texture(sampler2D(depthImage, nearestSampler), uv).r);
+
+        float range = smoothstep(0.0, 1.0, pcs.radius / abs(position.z - sceneDepth));
+        occlusion += (sceneDepth >= samplePosition.z + pcs.bias ? 1.0 : 0.0) * range;
+    }
+
+    outOcclusion = 1.0 - occlusion / float(max(count, 1u));
+}
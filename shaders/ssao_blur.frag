@@ -0,0 +1,50 @@
+#version 450
+
+const int NOISE_SIZE = 4;
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    mat4 normal;
+} ubo;
+
+layout(set = 1, binding = 0) uniform texture2D depthImage;
+layout(set = 1, binding = 3) uniform sampler nearestSampler;
+layout(set = 1, binding = 4) uniform texture2D occlusionImage;
+
+layout(push_constant) uniform PushConstants {
+    float radius;
+    float bias;
+    uint sampleCount;
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out float outOcclusion;
+
+float viewDepth(ivec2 coord) {
+    float depth = texelFetch(sampler2D(depthImage, nearestSampler), coord, 0).r;
+    return -ubo.proj[3][2] / (depth + ubo.proj[2][2]);
+}
+
+// Averages the noise tile around the pixel, down-weighting texels whose depth differs by
+// more than the sampling radius so occlusion does not bleed across edges.
+void main() {
+    ivec2 coord = ivec2(gl_FragCoord.xy);
+    ivec2 size = textureSize(sampler2D(occlusionImage, nearestSampler), 0);
+    float center = viewDepth(coord);
+
+    float occlusion = 0.0;
+    float weights = 0.0;
+    for (int x = -NOISE_SIZE / 2; x < NOISE_SIZE / 2; x++) {
+        for (int y = -NOISE_SIZE / 2; y < NOISE_SIZE / 2; y++) {
+            ivec2 sampleCoord = clamp(coord + ivec2(x, y), ivec2(0), size - 1);
+            float weight = max(1.0 - abs(viewDepth(sampleCoord) - center) / pcs.radius, 0.0) + 0.0001;
+            occlusion += texelFetch(sampler2D(occlusionImage, nearestSampler), sampleCoord, 0).r * weight;
+            weights += weight;
+        }
+    }
+
+    outOcclusion = occlusion / weights;
+}
@@ -0,0 +1,55 @@
+#version 450
+
+layout(binding = 0) uniform texture2D currentImage;
+layout(binding = 1) uniform texture2D historyImage;
+layout(binding = 2) uniform texture2D velocityImage;
+layout(binding = 3) uniform sampler taaSampler;
+
+layout(push_constant) uniform PushConstants {
+    vec2 texelSize;
+    float feedback;
+    uint historyValid;
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Blends the jittered frame into the reprojected history. The history is clamped to the
+// range of the current pixel's neighborhood, which rejects it where it no longer matches, and
+// both are weighted by inverse luminance so bright outliers do not flicker.
+void main() {
+    ivec2 coords = ivec2(gl_FragCoord.xy);
+    ivec2 size = textureSize(sampler2D(currentImage, taaSampler), 0);
+
+    vec3 current = texelFetch(sampler2D(currentImage, taaSampler), coords, 0).rgb;
+    vec3 minimum = current;
+    vec3 maximum = current;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            ivec2 neighbor = clamp(coords + ivec2(x, y), ivec2(0), size - 1);
+            vec3 color = texelFetch(sampler2D(currentImage, taaSampler), neighbor, 0).rgb;
+            minimum = min(minimum, color);
+            maximum = max(maximum, color);
+        }
+    }
+
+    vec2 motion = texelFetch(sampler2D(velocityImage, taaSampler), coords, 0).rg;
+    vec2 historyTexCoord = fragTexCoord - motion;
+    bool offscreen = any(lessThan(historyTexCoord, vec2(0.0))) || any(greaterThan(historyTexCoord, vec2(1.0)));
+    if (pcs.historyValid == 0u || offscreen) {
+        outColor = vec4(current, 1.0);
+        return;
+    }
+
+    vec3 history = textureLod(sampler2D(historyImage, taaSampler), historyTexCoord, 0.0).rgb;
+    history = clamp(history, minimum, maximum);
+
+    float currentWeight = (1.0 - pcs.feedback) / (1.0 + luminance(current));
+    float historyWeight = pcs.feedback / (1.0 + luminance(history));
+    outColor = vec4((current * currentWeight + history * historyWeight) / (currentWeight + historyWeight), 1.0);
+}
@@ -0,0 +1,95 @@
+#version 450
+
+const uint ACES = 0u;
+const uint REINHARD = 1u;
+const uint AGX = 2u;
+
+layout(binding = 0) uniform texture2D hdrImage;
+layout(binding = 1) uniform sampler hdrSampler;
+
+layout(std430, binding = 2) readonly buffer Exposure {
+    float averageLog2;
+} adaptation;
+
+layout(binding = 3) uniform texture2D bloomImage;
+
+layout(push_constant) uniform PushConstants {
+    float exposure;
+    uint operator;
+    uint autoExposure;
+    float bloomIntensity;
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms.
+vec3 aces(vec3 color) {
+    const mat3 inputMatrix = mat3(
+        0.59719, 0.07600, 0.02840,
+        0.35458, 0.90834, 0.13383,
+        0.04823, 0.01566, 0.83777);
+    const mat3 outputMatrix = mat3(
+        1.60475, -0.10208, -0.00327,
+        -0.53108, 1.10813, -0.07276,
+        -0.07367, -0.00605, 1.07602);
+
+    vec3 v = inputMatrix * color;
+    vec3 a = v * (v + 0.0245786) - 0.000090537;
+    vec3 b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(outputMatrix * (a / b), 0.0, 1.0);
+}
+
+// Reinhard applied to luminance, which keeps hue and saturation.
+vec3 reinhard(vec3 color) {
+    float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    return color / (1.0 + luminance);
+}
+
+// Polynomial fit of the AgX default sigmoid over log2 encoded input.
+vec3 agxContrast(vec3 x) {
+    vec3 x2 = x * x;
+    vec3 x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+// Troy Sobotka's AgX base look, returned to linear for the sRGB swapchain.
+vec3 agx(vec3 color) {
+    const mat3 inset = mat3(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104);
+    const mat3 outset = mat3(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116);
+    const float minEv = -12.47393;
+    const float maxEv = 4.026069;
+
+    vec3 encoded = clamp((log2(max(inset * color, vec3(1e-10))) - minEv) / (maxEv - minEv), 0.0, 1.0);
+    vec3 curve = clamp(outset * agxContrast(encoded), 0.0, 1.0);
+    return pow(curve, vec3(2.2));
+}
+
+void main() {
+    // Automatic exposure maps the adapted average luminance to middle grey.
+    float ev = pcs.exposure;
+    if (pcs.autoExposure != 0u) {
+        ev += log2(0.18) - adaptation.averageLog2;
+    }
+
+    vec3 color = texture(sampler2D(hdrImage, hdrSampler), fragTexCoord).rgb;
+    color += texture(sampler2D(bloomImage, hdrSampler), fragTexCoord).rgb * pcs.bloomIntensity;
+    color *= exp2(ev);
+
+    if (pcs.operator == ACES) {
+        color = aces(color);
+    } else if (pcs.operator == REINHARD) {
+        color = reinhard(color);
+    } else {
+        color = agx(color);
+    }
+
+    outColor = vec4(color, 1.0);
+}
@@ -12,15 +12,40 @@ mod sync;
 mod vertex_buffer;
 mod image;
 mod descriptor;
+mod upload;
+mod image_state;
+mod render_pass;
+mod motion;
+pub mod scene;
+pub mod gltf_loader;
+pub mod animation;
+pub mod picking;
+pub mod mesh_processing;
+pub mod clock;
+pub mod lighting;
+pub mod material;
+pub mod shadow;
+pub mod deferred;
+pub mod tonemap;
+pub mod exposure;
+pub mod bloom;
+pub mod ssao;
+pub mod environment;
+pub mod postprocess;
+pub mod antialiasing;
+pub mod compute;
+pub mod particles;
+pub mod indirect;
 
 use anyhow::{anyhow, Result};
 use lazy_static::lazy_static;
+use log::*;
 use nalgebra_glm as glm;
 use vulkanalia::loader::{LibloadingLoader, LIBRARY};
 use vulkanalia::prelude::v1_0::*;
 use vulkanalia::window as vk_window;
 use winit::window::{Window};
-use std::time::Instant;
+use std::path::Path;
 use std::mem::size_of;
 use std::ptr::copy_nonoverlapping as memcpy;
 
@@ -30,18 +55,18 @@ use vulkanalia::vk::KhrSwapchainExtension;
 
 lazy_static! {
     static ref VERTICES: Vec<vertex_buffer::Vertex> = vec![
-        vertex_buffer::Vertex::new(glm::vec3(-0.5, -0.5, 0.0),glm::vec3(1.0, 0.0, 0.0)),
-        vertex_buffer::Vertex::new(glm::vec3(0.5, -0.5, 0.0), glm::vec3(0.0, 1.0, 0.0)),
-        vertex_buffer::Vertex::new(glm::vec3(0.5, 0.5, 0.0), glm::vec3(0.0, 0.0, 1.0)),
-        vertex_buffer::Vertex::new(glm::vec3(-0.5, 0.5, 0.0), glm::vec3(1.0, 1.0, 1.0)),
-
-        vertex_buffer::Vertex::new(glm::vec3(-0.5, -0.5, 1.0),glm::vec3(1.0, 1.0, 0.0)),
-        vertex_buffer::Vertex::new(glm::vec3(0.5, -0.5, 1.0), glm::vec3(0.0, 1.0, 1.0)),
-        vertex_buffer::Vertex::new(glm::vec3(0.5, 0.5, 1.0), glm::vec3(1.0, 0.0, 1.0)),
-        vertex_buffer::Vertex::new(glm::vec3(-0.5, 0.5, 1.0), glm::vec3(1.0, 1.0, 1.0)),
+        vertex_buffer::Vertex::new(glm::vec3(-0.5, -0.5, 0.0), glm::vec3(1.0, 0.0, 0.0), glm::vec2(1.0, 0.0)),
+        vertex_buffer::Vertex::new(glm::vec3(0.5, -0.5, 0.0), glm::vec3(0.0, 1.0, 0.0), glm::vec2(0.0, 0.0)),
+        vertex_buffer::Vertex::new(glm::vec3(0.5, 0.5, 0.0), glm::vec3(0.0, 0.0, 1.0), glm::vec2(0.0, 1.0)),
+        vertex_buffer::Vertex::new(glm::vec3(-0.5, 0.5, 0.0), glm::vec3(1.0, 1.0, 1.0), glm::vec2(1.0, 1.0)),
+
+        vertex_buffer::Vertex::new(glm::vec3(-0.5, -0.5, 1.0), glm::vec3(1.0, 1.0, 0.0), glm::vec2(1.0, 0.0)),
+        vertex_buffer::Vertex::new(glm::vec3(0.5, -0.5, 1.0), glm::vec3(0.0, 1.0, 1.0), glm::vec2(0.0, 0.0)),
+        vertex_buffer::Vertex::new(glm::vec3(0.5, 0.5, 1.0), glm::vec3(1.0, 0.0, 1.0), glm::vec2(0.0, 1.0)),
+        vertex_buffer::Vertex::new(glm::vec3(-0.5, 0.5, 1.0), glm::vec3(1.0, 1.0, 1.0), glm::vec2(1.0, 1.0)),
     ];
 
-    static ref INDICES: Vec<u16> = vec![
+    static ref INDICES: Vec<u32> = vec![
         // bottom flipped
         0, 1, 2, 2, 3, 0, // bottom
         4, 5, 6, 6, 7, 4, // top
@@ -53,22 +78,49 @@ lazy_static! {
     ];
 }
 
+/// Initial settings applied before anything that depends on them is built.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Whether the object ID attachment used for picking is created.
+    pub picking: bool,
+    /// How the frame clock advances, such as `Manual` to render exact times headless.
+    pub clock_mode: clock::Mode,
+    /// What image-based lighting and the background are baked from.
+    pub environment: environment::Source,
+}
+
 #[derive(Clone, Debug)]
 pub struct App {
+    #[allow(dead_code)] // Keeps the Vulkan loader alive.
     entry: Entry,
     instance: Instance,
     data: app_data::Data,
     device: Device,
+    scene: scene::Scene,
+    animation_player: animation::AnimationPlayer,
     frame: usize,
     pub resized: bool,
-    start: Instant,
+    clock: clock::Clock,
+    lights: Vec<lighting::Light>,
+    shading: lighting::Shading,
+    pick_request: Option<(u32, u32)>,
+    /// Pixels whose IDs were copied by each frame in flight and are waiting on its fence.
+    pending_picks: Vec<Option<(u32, u32)>>,
+    pick: Option<picking::Pick>,
+    /// Problems found while importing the model.
+    diagnostics: Vec<gltf_loader::Diagnostic>,
 }
 
 impl App {
-    pub unsafe fn create(window: &Window) -> Result<Self> {
+    /// Creates the renderer, drawing the glTF file at `model` or a cube if none is given.
+    pub unsafe fn create(window: &Window, model: Option<&Path>, options: &Options) -> Result<Self> {
         let loader = LibloadingLoader::new(LIBRARY)?;
         let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
-        let mut data = app_data::Data::default();
+        let mut data = app_data::Data {
+            picking: options.picking,
+            environment_source: options.environment.clone(),
+            ..Default::default()
+        };
         let instance = instance::create(window, &entry, &mut data)?;
 
         data.surface = vk_window::create_surface(&instance, &window, &window)?;
@@ -84,39 +136,408 @@ impl App {
         pipeline::create_render_pass(&instance, &device, &mut data)?;
 
         descriptor::create_descriptor_set_layout(&device, &mut data)?;
+        material::create_descriptor_set_layout(&device, &mut data)?;
 
         pipeline::create_pipeline(&device, &mut data)?;
+        environment::create_skybox_pipeline(&device, &mut data)?;
+        shadow::create_shadow_objects(&instance, &device, &mut data)?;
 
         command_buffer::create_command_pool(&instance, &device, &mut data)?;
 
+        // Uploads are batched on the transfer queue and submitted once the scene is recorded.
+        let mut uploads = upload::begin(&instance, &device, &data)?;
+
         swapchain::create_depth_objects(&instance, &device, &mut data)?;
+        motion::create_velocity_objects(&instance, &device, &mut data)?;
+        tonemap::create_hdr_objects(&instance, &device, &mut data)?;
+
+        if data.picking {
+            picking::create_id_objects(&instance, &device, &mut data)?;
+        }
 
         framebuffer::create(&device, &mut data)?;
 
-        vertex_buffer::create(&instance, &device, &mut data, &VERTICES)?;
-        vertex_buffer::create_index_buffer(&instance, &device, &mut data, &INDICES)?;
+        if data.render_path == deferred::RenderPath::Deferred {
+            deferred::create_deferred_objects(&instance, &device, &mut data)?;
+        }
+
+        exposure::create_exposure_buffer(&instance, &device, &mut data)?;
+        ssao::create_ssao_kernel(&instance, &device, &mut data, &mut uploads)?;
+        environment::create_environment_maps(&instance, &device, &mut data)?;
+        particles::create_particle_buffers(&instance, &device, &mut data)?;
+        particles::create_particle_objects(&instance, &device, &mut data)?;
+        indirect::create_indirect_buffers(&instance, &device, &mut data)?;
+        ssao::create_ssao_objects(&instance, &device, &mut data)?;
+
+        if data.anti_aliasing == antialiasing::AntiAliasing::Taa {
+            antialiasing::create_taa_objects(&instance, &device, &mut data)?;
+        }
+
+        bloom::create_bloom_objects(&instance, &device, &mut data)?;
+        postprocess::create_post_process_objects(&instance, &device, &mut data)?;
+        tonemap::create_tonemap_objects(&device, &mut data)?;
+        exposure::create_exposure_objects(&instance, &device, &mut data)?;
+
+        let (mut scene, diagnostics) = match model {
+            Some(path) => {
+                let import = gltf_loader::load(path)?;
+                for diagnostic in &import.diagnostics {
+                    match diagnostic.severity {
+                        gltf_loader::Severity::Warning => warn!("{}", diagnostic.message),
+                        gltf_loader::Severity::Error => error!("{}", diagnostic.message),
+                    }
+                }
+                (import.scene, import.diagnostics)
+            }
+            None => (scene::Scene::new(&VERTICES, &INDICES), vec![]),
+        };
+
+        mesh_processing::optimize(&mut scene, &mesh_processing::Options::default());
+        data.primitive_bounds = indirect::primitive_bounds(&scene);
+
+        vertex_buffer::create(&instance, &device, &mut data, &mut uploads, &scene.vertices)?;
+        vertex_buffer::create_index_buffer(&instance, &device, &mut data, &mut uploads, &scene.indices)?;
+
+        for image in &scene.images {
+            let (texture_image, texture_image_memory, texture_image_view) =
+                image::create_texture_image(&instance, &device, &data, &mut uploads, image)?;
+            data.texture_images.push(texture_image);
+            data.texture_images_memory.push(texture_image_memory);
+            data.texture_image_views.push(texture_image_view);
+        }
+
+        material::create_texture_sampler(&device, &mut data)?;
+        shadow::create_shadow_sampler(&device, &mut data)?;
+        shadow::create_point_shadow_sampler(&device, &mut data)?;
+        material::create_materials(&instance, &device, &mut data, &mut uploads, &scene)?;
+        upload::submit(&device, &mut data, uploads)?;
 
         vertex_buffer::create_uniform_buffers(&instance, &device, &mut data)?;
+        vertex_buffer::create_joint_buffers(&instance, &device, &mut data)?;
+        motion::create_previous_frame_buffers(&instance, &device, &mut data)?;
+        lighting::create_light_buffers(&instance, &device, &mut data)?;
+        shadow::create_shadow_buffers(&instance, &device, &mut data)?;
+        picking::create_readback_buffers(&instance, &device, &mut data)?;
+        indirect::create_object_staging_buffers(&instance, &device, &mut data)?;
 
         descriptor::create_descriptor_pool(&device, &mut data)?;
         descriptor::create_descriptor_sets(&device, &mut data)?;
 
-        command_buffer::create_command_buffers(&device, &mut data, INDICES.len() as u32)?;
+        command_buffer::create_command_buffers(&device, &mut data)?;
 
         sync::create_sync_objects(&device, &mut data)?;
 
-        Ok(Self {entry, instance, data, device, frame: 0, resized: false, start: Instant::now() })
+        let mut animation_player = animation::AnimationPlayer::default();
+        if !scene.animations.is_empty() {
+            animation_player.play(0, true);
+        }
+
+        Ok(Self {
+            entry,
+            instance,
+            data,
+            device,
+            scene,
+            animation_player,
+            frame: 0,
+            resized: false,
+            clock: clock::Clock::new(options.clock_mode, app_defines::SIMULATION_STEP),
+            shading: lighting::Shading::Pbr,
+            lights: vec![lighting::Light::directional(glm::vec3(-1.0, -0.5, -2.0), glm::vec3(1.0, 1.0, 1.0))],
+            pick_request: None,
+            pending_picks: vec![None; app_defines::MAX_FRAMES_IN_FLIGHT],
+            pick: None,
+            diagnostics,
+        })
+    }
+
+    pub fn scene(&self) -> &scene::Scene {
+        &self.scene
+    }
+
+    /// Warnings about parts of the model that were skipped or could not be imported faithfully.
+    pub fn diagnostics(&self) -> &[gltf_loader::Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn animation_player(&mut self) -> &mut animation::AnimationPlayer {
+        &mut self.animation_player
+    }
+
+    pub fn clock(&mut self) -> &mut clock::Clock {
+        &mut self.clock
+    }
+
+    pub fn set_shading(&mut self, shading: lighting::Shading) {
+        self.shading = shading;
+    }
+
+    pub fn shadow_settings(&self) -> shadow::ShadowSettings {
+        self.data.shadow_settings
+    }
+
+    /// Rebuilds the shadow map and pipeline with new settings.
+    pub unsafe fn set_shadow_settings(&mut self, window: &Window, settings: shadow::ShadowSettings) -> Result<()> {
+        self.data.shadow_settings = settings;
+        self.recreate_swapchain(window)
+    }
+
+    pub fn render_path(&self) -> deferred::RenderPath {
+        self.data.render_path
+    }
+
+    /// Switches between forward and deferred shading, building or dropping the G-buffer.
+    pub unsafe fn set_render_path(&mut self, window: &Window, path: deferred::RenderPath) -> Result<()> {
+        self.data.render_path = path;
+        self.recreate_swapchain(window)
+    }
+
+    pub fn tone_mapping(&self) -> tonemap::ToneMapping {
+        self.data.tone_mapping
+    }
+
+    pub fn set_tone_mapping(&mut self, tone_mapping: tonemap::ToneMapping) {
+        self.data.tone_mapping = tone_mapping;
+    }
+
+    pub fn auto_exposure(&self) -> exposure::AutoExposure {
+        self.data.auto_exposure
+    }
+
+    pub fn set_auto_exposure(&mut self, auto_exposure: exposure::AutoExposure) {
+        self.data.auto_exposure = auto_exposure;
+    }
+
+    pub fn ssao(&self) -> ssao::Ssao {
+        self.data.ssao
+    }
+
+    /// Turning ambient occlusion on or off rebuilds the forward pass, which loads depth from
+    /// a prepass while it is on.
+    pub unsafe fn set_ssao(&mut self, window: &Window, ssao: ssao::Ssao) -> Result<()> {
+        let rebuild = ssao.enabled != self.data.ssao.enabled;
+        self.data.ssao = ssao;
+        if rebuild {
+            self.recreate_swapchain(window)?;
+        }
+        Ok(())
+    }
+
+    pub fn environment(&self) -> environment::Environment {
+        self.data.environment
+    }
+
+    pub fn set_environment(&mut self, environment: environment::Environment) {
+        self.data.environment = environment;
+    }
+
+    /// Bakes the environment maps again from `source`.
+    pub unsafe fn set_environment_source(&mut self, window: &Window, source: environment::Source) -> Result<()> {
+        source.validate()?;
+        self.device.device_wait_idle()?;
+        environment::destroy_environment_maps(&self.device, &mut self.data);
+        self.data.environment_source = source;
+        environment::create_environment_maps(&self.instance, &self.device, &mut self.data)?;
+
+        // Descriptor sets are rebuilt with the new views.
+        self.recreate_swapchain(window)
+    }
+
+    pub fn bloom(&self) -> bloom::Bloom {
+        self.data.bloom
+    }
+
+    pub fn set_bloom(&mut self, bloom: bloom::Bloom) {
+        self.data.bloom = bloom;
+    }
+
+    pub fn anti_aliasing(&self) -> antialiasing::AntiAliasing {
+        self.data.anti_aliasing
+    }
+
+    /// Switching anti-aliasing rebuilds the swapchain objects, since FXAA adds a pass to the
+    /// post-processing chain and TAA keeps history targets.
+    pub unsafe fn set_anti_aliasing(&mut self, window: &Window, anti_aliasing: antialiasing::AntiAliasing) -> Result<()> {
+        if anti_aliasing != self.data.anti_aliasing {
+            self.data.anti_aliasing = anti_aliasing;
+            self.recreate_swapchain(window)?;
+        }
+        Ok(())
+    }
+
+    /// The post-processing passes run after tone mapping, in order.
+    pub fn post_process(&self) -> &[postprocess::PostProcessPass] {
+        &self.data.post_process
+    }
+
+    /// Replaces the post-processing chain. Adding, removing, reordering or toggling passes
+    /// rebuilds the chain; changing only parameters does not.
+    pub unsafe fn set_post_process(&mut self, window: &Window, passes: Vec<postprocess::PostProcessPass>) -> Result<()> {
+        postprocess::validate(&passes)?;
+        let rebuild = !postprocess::same_structure(&self.data.post_process, &passes);
+        self.data.post_process = passes;
+        if rebuild {
+            self.recreate_swapchain(window)?;
+        }
+        Ok(())
+    }
+
+    /// Creates a host-visible storage buffer of `size` bytes for compute kernels, returning its
+    /// index.
+    pub unsafe fn create_compute_buffer(&mut self, size: u64) -> Result<usize> {
+        let buffer = compute::create_compute_buffer(&self.instance, &self.device, &self.data, size)?;
+        self.data.compute_buffers.push(buffer);
+        Ok(self.data.compute_buffers.len() - 1)
+    }
+
+    /// Copies `values` into a compute buffer. Waits for the frames in flight first, since
+    /// in-frame jobs may be using it.
+    pub unsafe fn write_compute_buffer<T: Copy>(&self, buffer: usize, values: &[T]) -> Result<()> {
+        self.wait_for_gpu(self.data.submitted_value)?;
+        compute::write_compute_buffer(&self.device, &self.data.compute_buffers[buffer], values)
+    }
+
+    /// Reads `count` values from a compute buffer once the frames in flight have finished.
+    pub unsafe fn read_compute_buffer<T: Copy>(&self, buffer: usize, count: usize) -> Result<Vec<T>> {
+        self.wait_for_gpu(self.data.submitted_value)?;
+        compute::read_compute_buffer(&self.device, &self.data.compute_buffers[buffer], count)
+    }
+
+    /// Destroys a compute buffer once the frames using it have finished. Its index stays taken,
+    /// and kernels bound to it must not be dispatched again.
+    pub fn destroy_compute_buffer(&mut self, buffer: usize) {
+        let buffer = std::mem::take(&mut self.data.compute_buffers[buffer]);
+        sync::defer_deletion(&mut self.data, sync::Deletion::Buffer(buffer.buffer, buffer.memory));
+    }
+
+    /// Creates a 2D storage image for compute kernels, returning its index.
+    pub unsafe fn create_compute_image(&mut self, width: u32, height: u32, format: vk::Format) -> Result<usize> {
+        let image = compute::create_compute_image(&self.instance, &self.device, &mut self.data, width, height, format)?;
+        self.data.compute_images.push(image);
+        Ok(self.data.compute_images.len() - 1)
+    }
+
+    /// Destroys a compute image once the frames using it have finished. Its index stays taken,
+    /// and kernels bound to it must not be dispatched again.
+    pub fn destroy_compute_image(&mut self, image: usize) {
+        let image = std::mem::take(&mut self.data.compute_images[image]);
+        sync::defer_deletion(&mut self.data, sync::Deletion::Image(image.image, image.memory, image.view));
+    }
+
+    /// Creates a compute kernel from SPIR-V, binding `resources` in order from binding 0 of set
+    /// 0, and returns its index.
+    pub unsafe fn create_compute_kernel(&mut self, comp: &[u8], resources: &[compute::Resource], push_constant_size: u32) -> Result<usize> {
+        let bindings = resources.iter()
+            .map(|r| match r {
+                compute::Resource::Buffer(i) => compute::Binding::StorageBuffer(self.data.compute_buffers[*i].buffer),
+                compute::Resource::Image(i) => compute::Binding::StorageImage(self.data.compute_images[*i].view),
+            })
+            .collect::<Vec<_>>();
+
+        let kernel = compute::create_kernel(&self.device, comp, &bindings, push_constant_size)?;
+        self.data.compute_kernels.push(kernel);
+        Ok(self.data.compute_kernels.len() - 1)
+    }
+
+    /// Runs a kernel once on the compute queue, outside any frame, and waits for it. Frames in
+    /// flight finish first, since their compute jobs may use the same buffers and images.
+    pub unsafe fn run_compute(&self, kernel: usize, push_constants: &[u8], group_count: [u32; 3]) -> Result<()> {
+        self.wait_for_gpu(self.data.submitted_value)?;
+
+        let kernel = &self.data.compute_kernels[kernel];
+        compute::run_standalone(&self.device, &self.data, |command_buffer| {
+            compute::cmd_dispatch(&self.device, command_buffer, kernel, push_constants, group_count);
+        })
+    }
+
+    /// Dispatches recorded at the start of every frame, before shadows and shading.
+    pub fn compute_jobs(&mut self) -> &mut Vec<compute::ComputeJob> {
+        &mut self.data.compute_jobs
+    }
+
+    /// World-space particle emitters; the first `MAX_EMITTERS` are used.
+    pub fn particle_emitters(&mut self) -> &mut Vec<particles::Emitter> {
+        &mut self.data.particle_emitters
+    }
+
+    pub fn gpu_driven(&self) -> bool {
+        self.data.gpu_driven
+    }
+
+    /// Culls on the GPU and draws the scene with indirect commands. Ignored if the device lacks
+    /// multi-draw indirect, in which case the scene is always drawn from the CPU.
+    pub fn set_gpu_driven(&mut self, gpu_driven: bool) {
+        self.data.gpu_driven = gpu_driven && self.data.gpu_driven_supported;
+    }
+
+    /// The GPU progress value of the last frame submitted. Each frame advances it by one.
+    pub fn submitted_value(&self) -> u64 {
+        self.data.submitted_value
+    }
+
+    /// The GPU progress value of the last frame the GPU has finished.
+    pub unsafe fn completed_value(&self) -> Result<u64> {
+        sync::completed_value(&self.device, &self.data)
+    }
+
+    /// Blocks until the GPU has finished the frame that was submitted with `value`, for example
+    /// before destroying or rewriting resources it used.
+    pub unsafe fn wait_for_gpu(&self, value: u64) -> Result<()> {
+        sync::wait_for_value(&self.device, &self.data, value)
+    }
+
+    pub fn dynamic_rendering(&self) -> bool {
+        self.data.dynamic_rendering
+    }
+
+    /// Begins every pass recreated with the swapchain with dynamic rendering instead of a render
+    /// pass and framebuffers, rebuilding the pipelines that draw into them. Ignored if the
+    /// device lacks dynamic rendering. The one-time environment bake always uses render passes.
+    pub unsafe fn set_dynamic_rendering(&mut self, window: &Window, dynamic_rendering: bool) -> Result<()> {
+        let dynamic_rendering = dynamic_rendering && self.data.dynamic_rendering_supported;
+        if dynamic_rendering != self.data.dynamic_rendering {
+            self.data.dynamic_rendering = dynamic_rendering;
+            self.recreate_swapchain(window)?;
+        }
+        Ok(())
+    }
+
+    /// World-space lights; the first `MAX_LIGHTS` are used.
+    pub fn lights(&mut self) -> &mut Vec<lighting::Light> {
+        &mut self.lights
+    }
+
+    /// Adds or removes the object ID attachment used for picking.
+    pub unsafe fn set_picking(&mut self, window: &Window, enabled: bool) -> Result<()> {
+        self.data.picking = enabled;
+        self.recreate_swapchain(window)
+    }
+
+    /// Requests the scene node under a window pixel. The result is available from `take_pick`
+    /// once the frame that reads it back has finished on the GPU.
+    pub fn request_pick(&mut self, x: u32, y: u32) {
+        self.pick_request = Some((x, y));
+    }
+
+    pub fn take_pick(&mut self) -> Option<picking::Pick> {
+        self.pick.take()
     }
 
     pub unsafe fn render(&mut self, window: &Window) -> Result<()> {
-        let in_flight_fence = self.data.in_flight_fences[self.frame];
+        sync::wait_for_value(&self.device, &self.data, self.data.frame_values[self.frame])?;
+
+        upload::collect_finished(&self.device, &mut self.data)?;
+        vertex_buffer::collect_single_time_commands(&self.device, &mut self.data)?;
+        sync::collect_deletions(&self.device, &mut self.data)?;
 
-        self.device
-            .wait_for_fences(&[in_flight_fence], true, u64::max_value())?;
+        if let Some((x, y)) = self.pending_picks[self.frame].take() {
+            let id = picking::read_id(&self.device, &self.data, self.frame)?;
+            self.pick = Some(picking::Pick { x, y, node: id.checked_sub(1).map(|n| n as usize) });
+        }
 
         let result = self.device.acquire_next_image_khr(
             self.data.swapchain,
-            u64::max_value(),
+            u64::MAX,
             self.data.image_available_semaphores[self.frame],
             vk::Fence::null(),
         );
@@ -127,35 +548,72 @@ impl App {
             Err(e) => return Err(anyhow!(e)),
         };
 
-        let image_in_flight = self.data.images_in_flight[image_index];
-        if !image_in_flight.is_null() {
-            self.device
-                .wait_for_fences(&[image_in_flight], true, u64::max_value())?;
-        }
+        sync::wait_for_value(&self.device, &self.data, self.data.image_values[image_index])?;
 
-        self.data.images_in_flight[image_index] = in_flight_fence;
+        let tick = self.clock.tick();
+        for _ in 0..tick.steps {
+            self.update_simulation(self.clock.step_size() as f32);
+        }
 
+        self.update_taa();
+        self.update_particles(tick.delta as f32);
         self.update_uniform_buffer(image_index)?;
+        let joint_matrices = self.update_animation(image_index)?;
+
+        let extent = self.data.swapchain_extent;
+        let pick = self.pick_request.take()
+            .filter(|(x, y)| self.data.picking && *x < extent.width && *y < extent.height)
+            .map(|(x, y)| (self.frame, x, y));
+        self.pending_picks[self.frame] = pick.map(|(_, x, y)| (x, y));
+
+        let mut draws = self.scene.draws();
+        self.lod_selector().apply(&self.scene, &mut draws);
+
+        let (model, view, proj) = self.camera();
+        indirect::update_objects(&self.device, &mut self.data, image_index, &draws, &model, &(proj * view))?;
+        motion::update_previous_frame(&self.device, &mut self.data, image_index, &(model, view, proj), &draws, &joint_matrices)?;
+
+        // The compute queue runs first, so its command buffer is recorded first for the particle
+        // buffers' handovers to follow the order they execute in.
+        if self.data.async_compute {
+            self.submit_compute(image_index)?;
+        }
+
+        let point_shadows = self.point_shadow_lights().len();
+        command_buffer::update_command_buffer(&self.device, &mut self.data, image_index, &draws, point_shadows, tick.delta as f32, pick)?;
+        self.data.taa_history_valid = self.data.anti_aliasing == antialiasing::AntiAliasing::Taa;
+
+        let mut wait_semaphores = vec![self.data.image_available_semaphores[self.frame]];
+        let mut wait_stages = vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let mut signal_semaphores = vec![self.data.render_finished_semaphores[self.frame]];
+
+        if self.data.async_compute {
+            wait_semaphores.push(self.data.compute_finished_semaphores[self.frame]);
+            wait_stages.push(vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_SHADER);
+            signal_semaphores.push(self.data.graphics_released_semaphores[self.frame]);
+        }
 
-        let wait_semaphores = &[self.data.image_available_semaphores[self.frame]];
-        let wait_stages = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
         let command_buffers = &[self.data.command_buffers[image_index]];
-        let signal_semaphores = &[self.data.render_finished_semaphores[self.frame]];
-        let submit_info = vk::SubmitInfo::builder()
-            .wait_semaphores(wait_semaphores)
-            .wait_dst_stage_mask(wait_stages)
-            .command_buffers(command_buffers)
-            .signal_semaphores(signal_semaphores);
+        let value = sync::submit_frame(
+            &self.device,
+            &mut self.data,
+            self.frame,
+            &wait_semaphores,
+            &wait_stages,
+            command_buffers,
+            &signal_semaphores,
+        )?;
 
-        self.device.reset_fences(&[in_flight_fence])?;
+        self.data.image_values[image_index] = value;
 
-        self.device
-            .queue_submit(self.data.graphics_queue, &[submit_info], in_flight_fence)?;
+        if self.data.async_compute {
+            self.data.pending_graphics_release = self.data.graphics_released_semaphores[self.frame];
+        }
 
         let swapchains = &[self.data.swapchain];
         let image_indices = &[image_index as u32];
         let present_info = vk::PresentInfoKHR::builder()
-            .wait_semaphores(signal_semaphores)
+            .wait_semaphores(&signal_semaphores[..1])
             .swapchains(swapchains)
             .image_indices(image_indices);
 
@@ -182,19 +640,48 @@ impl App {
 
         pipeline::create_render_pass(&self.instance, &self.device, &mut self.data)?;
         pipeline::create_pipeline(&self.device, &mut self.data)?;
+        environment::create_skybox_pipeline(&self.device, &mut self.data)?;
+        shadow::create_shadow_objects(&self.instance, &self.device, &mut self.data)?;
 
         swapchain::create_depth_objects(&self.instance, &self.device, &mut self.data)?;
+        motion::create_velocity_objects(&self.instance, &self.device, &mut self.data)?;
+        tonemap::create_hdr_objects(&self.instance, &self.device, &mut self.data)?;
+
+        if self.data.picking {
+            picking::create_id_objects(&self.instance, &self.device, &mut self.data)?;
+        }
 
         framebuffer::create(&self.device, &mut self.data)?;
 
+        if self.data.render_path == deferred::RenderPath::Deferred {
+            deferred::create_deferred_objects(&self.instance, &self.device, &mut self.data)?;
+        }
+
+        ssao::create_ssao_objects(&self.instance, &self.device, &mut self.data)?;
+        particles::create_particle_objects(&self.instance, &self.device, &mut self.data)?;
+
+        if self.data.anti_aliasing == antialiasing::AntiAliasing::Taa {
+            antialiasing::create_taa_objects(&self.instance, &self.device, &mut self.data)?;
+        }
+
+        bloom::create_bloom_objects(&self.instance, &self.device, &mut self.data)?;
+        postprocess::create_post_process_objects(&self.instance, &self.device, &mut self.data)?;
+        tonemap::create_tonemap_objects(&self.device, &mut self.data)?;
+        exposure::create_exposure_objects(&self.instance, &self.device, &mut self.data)?;
+
         vertex_buffer::create_uniform_buffers(&self.instance, &self.device, &mut self.data)?;
+        vertex_buffer::create_joint_buffers(&self.instance, &self.device, &mut self.data)?;
+        motion::create_previous_frame_buffers(&self.instance, &self.device, &mut self.data)?;
+        lighting::create_light_buffers(&self.instance, &self.device, &mut self.data)?;
+        shadow::create_shadow_buffers(&self.instance, &self.device, &mut self.data)?;
+        indirect::create_object_staging_buffers(&self.instance, &self.device, &mut self.data)?;
 
         descriptor::create_descriptor_pool(&self.device, &mut self.data)?;
         descriptor::create_descriptor_sets(&self.device, &mut self.data)?;
 
-        command_buffer::create_command_buffers(&self.device, &mut self.data,  INDICES.len() as u32)?;
+        command_buffer::create_command_buffers(&self.device, &mut self.data)?;
 
-        self.data.images_in_flight.resize(self.data.swapchain_images.len(), vk::Fence::null());
+        self.data.image_values.resize(self.data.swapchain_images.len(), 0);
 
         Ok(())
     }
@@ -204,15 +691,43 @@ impl App {
 
         self.destroy_swapchain();
 
-        self.data.in_flight_fences.iter().for_each(|f| self.device.destroy_fence(*f, None));
-        self.data.render_finished_semaphores.iter().for_each(|s| self.device.destroy_semaphore(*s, None));
-        self.data.image_available_semaphores.iter().for_each(|s| self.device.destroy_semaphore(*s, None));
+        sync::collect_deletions(&self.device, &mut self.data).unwrap();
+        sync::destroy_sync_objects(&self.device, &mut self.data);
+        self.data.pick_buffers_memory.iter().for_each(|m| self.device.free_memory(*m, None));
+        self.data.pick_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
+        self.data.texture_image_views.iter().for_each(|v| self.device.destroy_image_view(*v, None));
+        self.data.texture_images_memory.iter().for_each(|m| self.device.free_memory(*m, None));
+        self.data.texture_images.iter().for_each(|i| self.device.destroy_image(*i, None));
+        self.device.destroy_sampler(self.data.texture_sampler, None);
+        self.device.destroy_sampler(self.data.shadow_sampler, None);
+        self.device.destroy_sampler(self.data.point_shadow_sampler, None);
+        self.device.free_memory(self.data.exposure_buffer_memory, None);
+        self.device.destroy_buffer(self.data.exposure_buffer, None);
+        self.device.destroy_image_view(self.data.ssao_noise_image_view, None);
+        self.device.free_memory(self.data.ssao_noise_image_memory, None);
+        self.device.destroy_image(self.data.ssao_noise_image, None);
+        self.device.free_memory(self.data.ssao_kernel_buffer_memory, None);
+        self.device.destroy_buffer(self.data.ssao_kernel_buffer, None);
+        environment::destroy_environment_maps(&self.device, &mut self.data);
+        particles::destroy_particle_buffers(&self.device, &mut self.data);
+        indirect::destroy_indirect_buffers(&self.device, &mut self.data);
+        self.data.compute_kernels.iter().for_each(|k| compute::destroy_kernel(&self.device, k));
+        self.data.compute_images.iter().for_each(|i| compute::destroy_compute_image(&self.device, i));
+        self.data.compute_buffers.iter().for_each(|b| compute::destroy_compute_buffer(&self.device, b));
+        self.data.material_buffers_memory.iter().for_each(|m| self.device.free_memory(*m, None));
+        self.data.material_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
+        self.device.destroy_descriptor_pool(self.data.material_descriptor_pool, None);
         self.device.free_memory(self.data.index_buffer_memory, None);
         self.device.destroy_buffer(self.data.index_buffer, None);
         self.device.free_memory(self.data.vertex_buffer_memory, None);
         self.device.destroy_buffer(self.data.vertex_buffer, None);
+        upload::collect_finished(&self.device, &mut self.data).unwrap();
+        vertex_buffer::collect_single_time_commands(&self.device, &mut self.data).unwrap();
+        self.device.destroy_command_pool(self.data.transfer_command_pool, None);
+        self.device.destroy_command_pool(self.data.compute_command_pool, None);
         self.device.destroy_command_pool(self.data.command_pool, None);
         self.device.destroy_descriptor_set_layout(self.data.descriptor_set_layout, None);
+        self.device.destroy_descriptor_set_layout(self.data.material_descriptor_set_layout, None);
         self.device.destroy_device(None);
         self.instance.destroy_surface_khr(self.data.surface, None);
 
@@ -225,13 +740,74 @@ impl App {
 
     unsafe fn destroy_swapchain(&mut self) {
         self.device.free_command_buffers(self.data.command_pool, &self.data.command_buffers);
+        if self.data.async_compute {
+            self.device.free_command_buffers(self.data.compute_command_pool, &self.data.compute_command_buffers);
+        }
         self.device.destroy_descriptor_pool(self.data.descriptor_pool, None);
+        self.data.image_states = image_state::ImageStates::default();
+        self.data.joint_buffers_memory.iter().for_each(|m| self.device.free_memory(*m, None));
+        self.data.joint_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
+        self.data.previous_frame_buffers_memory.iter().for_each(|m| self.device.free_memory(*m, None));
+        self.data.previous_frame_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
+        self.data.light_buffers_memory.iter().for_each(|m| self.device.free_memory(*m, None));
+        self.data.light_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
+        self.data.shadow_buffers_memory.iter().for_each(|m| self.device.free_memory(*m, None));
+        self.data.shadow_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
+        self.data.object_staging_buffers_memory.iter().for_each(|m| self.device.free_memory(*m, None));
+        self.data.object_staging_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
         self.data.uniform_buffers_memory.iter().for_each(|m| self.device.free_memory(*m, None));
         self.data.uniform_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
+        self.device.destroy_pipeline(self.data.exposure_pipeline, None);
+        self.device.destroy_pipeline(self.data.histogram_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.exposure_pipeline_layout, None);
+        self.device.destroy_descriptor_pool(self.data.exposure_descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.data.exposure_descriptor_set_layout, None);
+        self.device.free_memory(self.data.histogram_buffer_memory, None);
+        self.device.destroy_buffer(self.data.histogram_buffer, None);
+        self.device.destroy_pipeline(self.data.tonemap_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.tonemap_pipeline_layout, None);
+        self.device.destroy_descriptor_pool(self.data.tonemap_descriptor_pool, None);
+        self.device.destroy_descriptor_set_layout(self.data.tonemap_descriptor_set_layout, None);
+        self.device.destroy_sampler(self.data.tonemap_sampler, None);
+        self.data.tonemap_framebuffers.iter().for_each(|f| self.device.destroy_framebuffer(*f, None));
+        self.device.destroy_render_pass(self.data.tonemap_render_pass, None);
+        postprocess::destroy_post_process_objects(&self.device, &mut self.data);
+        bloom::destroy_bloom_objects(&self.device, &mut self.data);
+        antialiasing::destroy_taa_objects(&self.device, &mut self.data);
+        particles::destroy_particle_objects(&self.device, &mut self.data);
+        ssao::destroy_ssao_objects(&self.device, &mut self.data);
+        deferred::destroy_deferred_objects(&self.device, &mut self.data);
+        self.device.destroy_image_view(self.data.hdr_image_view, None);
+        self.device.free_memory(self.data.hdr_image_memory, None);
+        self.device.destroy_image(self.data.hdr_image, None);
+        self.device.destroy_image_view(self.data.id_image_view, None);
+        self.device.free_memory(self.data.id_image_memory, None);
+        self.device.destroy_image(self.data.id_image, None);
+        self.data.id_image_view = vk::ImageView::null();
+        self.data.id_image_memory = vk::DeviceMemory::null();
+        self.data.id_image = vk::Image::null();
+        self.device.destroy_image_view(self.data.velocity_image_view, None);
+        self.device.free_memory(self.data.velocity_image_memory, None);
+        self.device.destroy_image(self.data.velocity_image, None);
         self.device.destroy_image_view(self.data.depth_image_view, None);
         self.device.free_memory(self.data.depth_image_memory, None);
         self.device.destroy_image(self.data.depth_image, None);
+        self.device.destroy_pipeline(self.data.point_shadow_pipeline, None);
+        self.device.destroy_pipeline(self.data.shadow_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.shadow_pipeline_layout, None);
+        self.data.point_shadow_framebuffers.iter().for_each(|f| self.device.destroy_framebuffer(*f, None));
+        self.data.shadow_framebuffers.iter().for_each(|f| self.device.destroy_framebuffer(*f, None));
+        self.device.destroy_render_pass(self.data.shadow_render_pass, None);
+        self.data.shadow_layer_views.iter().for_each(|v| self.device.destroy_image_view(*v, None));
+        self.device.destroy_image_view(self.data.shadow_image_view, None);
+        self.device.free_memory(self.data.shadow_image_memory, None);
+        self.device.destroy_image(self.data.shadow_image, None);
+        self.data.point_shadow_face_views.iter().for_each(|v| self.device.destroy_image_view(*v, None));
+        self.data.point_shadow_cube_views.iter().for_each(|v| self.device.destroy_image_view(*v, None));
+        self.device.free_memory(self.data.point_shadow_image_memory, None);
+        self.device.destroy_image(self.data.point_shadow_image, None);
         self.data.framebuffers.iter().for_each(|f| self.device.destroy_framebuffer(*f, None));
+        self.device.destroy_pipeline(self.data.skybox_pipeline, None);
         self.device.destroy_pipeline(self.data.pipeline, None);
         self.device.destroy_pipeline_layout(self.data.pipeline_layout, None);
         self.device.destroy_render_pass(self.data.render_pass, None);
@@ -239,10 +815,9 @@ impl App {
         self.device.destroy_swapchain_khr(self.data.swapchain, None);
     }
 
-    unsafe fn update_uniform_buffer(&self, image_index: usize) -> Result<()> {
-        // MVP
-
-        let time = self.start.elapsed().as_secs_f32();
+    /// The model, view and projection matrices of the current frame.
+    fn camera(&self) -> (glm::Mat4, glm::Mat4, glm::Mat4) {
+        let time = self.clock.time() as f32;
 
         let model = glm::rotate(
             &glm::identity(),
@@ -259,13 +834,69 @@ impl App {
         let mut proj = glm::perspective_rh_zo(
             self.data.swapchain_extent.width as f32 / self.data.swapchain_extent.height as f32,
             glm::radians(&glm::vec1(45.0))[0],
-            0.1,
-            10.0,
+            app_defines::NEAR_PLANE,
+            app_defines::FAR_PLANE,
         );
 
         proj[(1, 1)] *= -1.0;
 
-        let ubo = vertex_buffer::UniformBufferObject { model, view, proj };
+        (model, view, proj)
+    }
+
+    fn lod_selector(&self) -> mesh_processing::LodSelector {
+        let (model, view, proj) = self.camera();
+
+        mesh_processing::LodSelector {
+            view: view * model,
+            projection_scale: proj[(1, 1)].abs(),
+            viewport_height: self.data.swapchain_extent.height as f32,
+            max_pixel_error: 1.0,
+        }
+    }
+
+    /// Records and submits the compute queue's share of the frame. It waits for the previous
+    /// frame's graphics work to hand back what it shares with compute.
+    unsafe fn submit_compute(&mut self, image_index: usize) -> Result<()> {
+        command_buffer::update_compute_command_buffer(&self.device, &mut self.data, image_index)?;
+
+        let mut wait_semaphores = vec![];
+        let mut wait_stages = vec![];
+        if !self.data.pending_graphics_release.is_null() {
+            wait_semaphores.push(self.data.pending_graphics_release);
+            wait_stages.push(vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::TRANSFER);
+        }
+
+        let command_buffers = &[self.data.compute_command_buffers[image_index]];
+        let signal_semaphores = &[self.data.compute_finished_semaphores[self.frame]];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(command_buffers)
+            .signal_semaphores(signal_semaphores);
+
+        self.device.queue_submit(self.data.compute_queue, &[submit_info], vk::Fence::null())?;
+        self.data.pending_graphics_release = vk::Semaphore::null();
+
+        Ok(())
+    }
+
+    unsafe fn update_uniform_buffer(&self, image_index: usize) -> Result<()> {
+        // MVP
+
+        let (model, view, proj) = self.camera();
+
+        let normal = glm::inverse_transpose(model);
+        let (previous_model, previous_view, previous_proj) = self.data.previous_frame.camera(&(model, view, proj));
+        let ubo = vertex_buffer::UniformBufferObject {
+            model,
+            view,
+            proj: antialiasing::jittered_projection(&self.data, &proj),
+            normal,
+            unjittered_proj: proj,
+            previous_model,
+            previous_view,
+            previous_proj,
+        };
 
         // Copy
 
@@ -280,6 +911,89 @@ impl App {
 
         self.device.unmap_memory(self.data.uniform_buffers_memory[image_index]);
 
+        // Lights
+
+        let camera_position = glm::inverse(&view).column(3).xyz();
+        let lights = lighting::LightsUniform::new(&self.lights, camera_position, 32.0, self.shading)
+            .with_ambient_occlusion(self.data.ssao.enabled)
+            .with_environment(&self.data.environment);
+        lighting::update_light_buffer(&self.device, &self.data, image_index, &lights)?;
+
+        // Shadows
+
+        let light = self.lights.iter().take(app_defines::MAX_LIGHTS).enumerate().find_map(|(i, l)| match l.kind {
+            lighting::LightKind::Directional { direction } => Some((i, direction)),
+            _ => None,
+        });
+
+        let shadows = shadow::cascades(&self.data.shadow_settings, &view, &proj, app_defines::NEAR_PLANE, app_defines::FAR_PLANE, light)
+            .with_point_lights(&self.data.shadow_settings, &self.point_shadow_lights());
+        shadow::update_shadow_buffer(&self.device, &self.data, image_index, &shadows)?;
+
         Ok(())
     }
+
+    /// Advances the TAA jitter sequence. Motion comes from the velocity target the scene passes
+    /// write from this frame's and the previous frame's transforms.
+    fn update_taa(&mut self) {
+        if self.data.anti_aliasing != antialiasing::AntiAliasing::Taa {
+            return;
+        }
+
+        self.data.taa_frame += 1;
+        self.data.taa_jitter = antialiasing::jitter(self.data.taa_frame);
+    }
+
+    /// Spawns particles for `delta` seconds and updates the camera they face. Particles live in
+    /// world space, so the scene's rotation does not apply to them.
+    fn update_particles(&mut self, delta: f32) {
+        let (_, view, proj) = self.camera();
+        let proj = antialiasing::jittered_projection(&self.data, &proj);
+        particles::update_particles(&mut self.data, delta, &view, &proj);
+    }
+
+    /// Index and position of the point and spot lights that get a cube shadow map.
+    fn point_shadow_lights(&self) -> Vec<(usize, glm::Vec3)> {
+        self.lights.iter().take(app_defines::MAX_LIGHTS).enumerate()
+            .filter_map(|(i, l)| match l.kind {
+                lighting::LightKind::Point { position } | lighting::LightKind::Spot { position, .. } => Some((i, position)),
+                _ => None,
+            })
+            .take(app_defines::MAX_POINT_SHADOWS)
+            .collect()
+    }
+
+    /// Advances everything simulated at a fixed rate by one step of `delta` seconds.
+    fn update_simulation(&mut self, delta: f32) {
+        self.animation_player.advance(&self.scene.animations, delta);
+    }
+
+    /// Poses the scene and writes its joint matrices for swapchain image `image_index`,
+    /// returning them.
+    unsafe fn update_animation(&mut self, image_index: usize) -> Result<Vec<glm::Mat4>> {
+        // Pose
+
+        self.animation_player.apply(&mut self.scene);
+
+        // Copy
+
+        // The loader rejects scenes with more joints than the buffer holds.
+        let joint_matrices = self.scene.joint_matrices();
+        if joint_matrices.is_empty() || joint_matrices.len() > app_defines::MAX_JOINTS {
+            return Ok(joint_matrices);
+        }
+
+        let memory = self.device.map_memory(
+            self.data.joint_buffers_memory[image_index],
+            0,
+            (size_of::<glm::Mat4>() * joint_matrices.len()) as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+
+        memcpy(joint_matrices.as_ptr(), memory.cast(), joint_matrices.len());
+
+        self.device.unmap_memory(self.data.joint_buffers_memory[image_index]);
+
+        Ok(joint_matrices)
+    }
 }
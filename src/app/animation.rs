@@ -0,0 +1,275 @@
+use super::scene;
+
+use nalgebra_glm as glm;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    Step,
+    /// Values are stored as `[in_tangent, value, out_tangent]` triples per keyframe.
+    CubicSpline,
+}
+
+/// Keyframes animating one property of one node. Rotations are `(x, y, z, w)` quaternions;
+/// translations and scales leave `w` unused.
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub values: Vec<glm::Vec4>,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl Channel {
+    pub fn sample(&self, time: f32) -> glm::Vec4 {
+        let value = |key: usize| match self.interpolation {
+            Interpolation::CubicSpline => self.values[key * 3 + 1],
+            _ => self.values[key],
+        };
+
+        let last = self.times.len() - 1;
+        if time <= self.times[0] {
+            return value(0);
+        } else if time >= self.times[last] {
+            return value(last);
+        }
+
+        let next = self.times.partition_point(|t| *t <= time);
+        let previous = next - 1;
+        let delta = self.times[next] - self.times[previous];
+        let t = (time - self.times[previous]) / delta;
+
+        let result = match self.interpolation {
+            Interpolation::Step => return value(previous),
+            Interpolation::Linear if self.property == Property::Rotation => {
+                let a = glm::quat(value(previous).x, value(previous).y, value(previous).z, value(previous).w);
+                let b = glm::quat(value(next).x, value(next).y, value(next).z, value(next).w);
+                return glm::quat_slerp(&a, &b, t).coords;
+            }
+            Interpolation::Linear => glm::lerp(&value(previous), &value(next), t),
+            Interpolation::CubicSpline => {
+                let (t2, t3) = (t * t, t * t * t);
+                let out_tangent = self.values[previous * 3 + 2] * delta;
+                let in_tangent = self.values[next * 3] * delta;
+                value(previous) * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + out_tangent * (t3 - 2.0 * t2 + t)
+                    + value(next) * (-2.0 * t3 + 3.0 * t2)
+                    + in_tangent * (t3 - t2)
+            }
+        };
+
+        if self.property == Property::Rotation {
+            glm::normalize(&result)
+        } else {
+            result
+        }
+    }
+}
+
+/// A clip being played with its own time, speed and blend weight.
+#[derive(Copy, Clone, Debug)]
+pub struct Playback {
+    pub clip: usize,
+    pub time: f32,
+    pub speed: f32,
+    pub weight: f32,
+    pub looping: bool,
+    /// Weight change per second while cross-fading.
+    fade_rate: f32,
+}
+
+/// Plays and blends animation clips, then poses the scene's nodes.
+#[derive(Clone, Debug, Default)]
+pub struct AnimationPlayer {
+    pub layers: Vec<Playback>,
+}
+
+impl AnimationPlayer {
+    /// Stops every clip and plays `clip` alone.
+    pub fn play(&mut self, clip: usize, looping: bool) {
+        self.layers.clear();
+        self.blend(clip, 1.0, looping);
+    }
+
+    /// Plays `clip` on top of the current clips with a fixed blend weight.
+    pub fn blend(&mut self, clip: usize, weight: f32, looping: bool) {
+        self.layers.push(Playback { clip, time: 0.0, speed: 1.0, weight, looping, fade_rate: 0.0 });
+    }
+
+    /// Fades `clip` in over `duration` seconds while fading every other clip out.
+    pub fn cross_fade(&mut self, clip: usize, duration: f32, looping: bool) {
+        let rate = 1.0 / duration.max(f32::EPSILON);
+        self.layers.iter_mut().for_each(|l| l.fade_rate = -rate * l.weight);
+        self.layers.push(Playback { clip, time: 0.0, speed: 1.0, weight: 0.0, looping, fade_rate: rate });
+    }
+
+    pub fn stop(&mut self) {
+        self.layers.clear();
+    }
+
+    pub fn advance(&mut self, clips: &[AnimationClip], delta: f32) {
+        for layer in &mut self.layers {
+            let duration = clips[layer.clip].duration;
+            layer.time += delta * layer.speed;
+            if layer.looping && duration > 0.0 {
+                layer.time = layer.time.rem_euclid(duration);
+            } else {
+                layer.time = layer.time.clamp(0.0, duration);
+            }
+
+            if layer.fade_rate != 0.0 {
+                layer.weight = (layer.weight + layer.fade_rate * delta).clamp(0.0, 1.0);
+                if layer.weight == 1.0 {
+                    layer.fade_rate = 0.0;
+                }
+            }
+        }
+
+        self.layers.retain(|l| l.weight > 0.0 || l.fade_rate >= 0.0);
+    }
+
+    /// Writes the blended pose of every animated node into its local transform. Weights that
+    /// add up to less than one blend towards the node's rest pose.
+    pub fn apply(&self, scene: &mut scene::Scene) {
+        let mut poses: Vec<Option<Pose>> = vec![None; scene.nodes.len()];
+
+        for layer in self.layers.iter().filter(|l| l.weight > 0.0) {
+            for channel in &scene.animations[layer.clip].channels {
+                let pose = poses[channel.node].get_or_insert_with(Pose::default);
+                let value = channel.sample(layer.time);
+                match channel.property {
+                    Property::Translation => pose.translation.accumulate(value, layer.weight),
+                    Property::Scale => pose.scale.accumulate(value, layer.weight),
+                    Property::Rotation => {
+                        // Keep quaternions in the same hemisphere so they average correctly.
+                        let sign = if glm::dot(&pose.rotation.sum, &value) < 0.0 { -1.0 } else { 1.0 };
+                        pose.rotation.accumulate(value * sign, layer.weight);
+                    }
+                }
+            }
+        }
+
+        for (node, pose) in scene.nodes.iter_mut().zip(poses) {
+            if let Some(pose) = pose {
+                let rest = node.rest;
+                let rest_rotation = if glm::dot(&pose.rotation.sum, &rest.rotation.coords) < 0.0 {
+                    -rest.rotation.coords
+                } else {
+                    rest.rotation.coords
+                };
+
+                let transform = scene::Transform {
+                    translation: pose.translation.resolve(glm::vec3_to_vec4(&rest.translation)).xyz(),
+                    rotation: glm::quat_normalize(&glm::Quat::from(pose.rotation.resolve(rest_rotation))),
+                    scale: pose.scale.resolve(glm::vec3_to_vec4(&rest.scale)).xyz(),
+                };
+                node.transform = transform.matrix();
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Accumulator {
+    sum: glm::Vec4,
+    weight: f32,
+}
+
+impl Accumulator {
+    fn accumulate(&mut self, value: glm::Vec4, weight: f32) {
+        self.sum += value * weight;
+        self.weight += weight;
+    }
+
+    fn resolve(&self, rest: glm::Vec4) -> glm::Vec4 {
+        if self.weight == 0.0 {
+            rest
+        } else if self.weight < 1.0 {
+            self.sum + rest * (1.0 - self.weight)
+        } else {
+            self.sum / self.weight
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Pose {
+    translation: Accumulator,
+    rotation: Accumulator,
+    scale: Accumulator,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(property: Property, interpolation: Interpolation, times: &[f32], values: &[glm::Vec4]) -> Channel {
+        Channel { node: 0, property, interpolation, times: times.to_vec(), values: values.to_vec() }
+    }
+
+    fn assert_close(a: glm::Vec4, b: glm::Vec4) {
+        assert!(glm::distance(&a, &b) < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn step_holds_previous_key() {
+        let values = [glm::vec4(1.0, 0.0, 0.0, 0.0), glm::vec4(3.0, 0.0, 0.0, 0.0)];
+        let channel = channel(Property::Translation, Interpolation::Step, &[0.0, 1.0], &values);
+
+        assert_close(channel.sample(0.0), values[0]);
+        assert_close(channel.sample(0.99), values[0]);
+        assert_close(channel.sample(1.0), values[1]);
+    }
+
+    #[test]
+    fn linear_interpolates_and_clamps() {
+        let values = [glm::vec4(0.0, 0.0, 0.0, 0.0), glm::vec4(2.0, 4.0, 6.0, 0.0)];
+        let channel = channel(Property::Scale, Interpolation::Linear, &[1.0, 2.0], &values);
+
+        assert_close(channel.sample(1.25), glm::vec4(0.5, 1.0, 1.5, 0.0));
+        assert_close(channel.sample(0.0), values[0]);
+        assert_close(channel.sample(5.0), values[1]);
+    }
+
+    #[test]
+    fn linear_rotation_slerps() {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let values = [glm::vec4(0.0, 0.0, 0.0, 1.0), glm::vec4(0.0, 0.0, half, half)];
+        let channel = channel(Property::Rotation, Interpolation::Linear, &[0.0, 1.0], &values);
+
+        // Halfway between no rotation and 90 degrees about Z is 45 degrees, still unit length.
+        let angle = std::f32::consts::FRAC_PI_8;
+        assert_close(channel.sample(0.5), glm::vec4(0.0, 0.0, angle.sin(), angle.cos()));
+    }
+
+    #[test]
+    fn cubic_spline_uses_tangents() {
+        let zero = glm::Vec4::zeros();
+        let one = glm::vec4(1.0, 0.0, 0.0, 0.0);
+
+        // Flat tangents ease in and out symmetrically.
+        let flat = channel(Property::Translation, Interpolation::CubicSpline, &[0.0, 1.0], &[zero, zero, zero, zero, one, zero]);
+        assert_close(flat.sample(0.5), glm::vec4(0.5, 0.0, 0.0, 0.0));
+        assert_close(flat.sample(0.25), glm::vec4(0.15625, 0.0, 0.0, 0.0));
+
+        // Unit tangents over a unit interval reproduce a straight line.
+        let straight = channel(Property::Translation, Interpolation::CubicSpline, &[0.0, 1.0], &[one, zero, one, one, one, one]);
+        assert_close(straight.sample(0.25), glm::vec4(0.25, 0.0, 0.0, 0.0));
+        assert_close(straight.sample(1.0), one);
+    }
+}
@@ -0,0 +1,357 @@
+use super::app_data;
+use super::image;
+use super::image_state;
+use super::pipeline;
+use super::render_pass;
+use super::postprocess;
+use super::tonemap;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+use nalgebra_glm as glm;
+use std::mem::size_of;
+
+/// Length of the jitter sequence TAA cycles through.
+const JITTER_SAMPLES: u64 = 8;
+
+/// Share of the history kept each frame by TAA.
+const TAA_FEEDBACK: f32 = 0.9;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AntiAliasing {
+    #[default]
+    None,
+    /// Fast approximate anti-aliasing, a single pass over the tone-mapped image.
+    Fxaa,
+    /// Temporal anti-aliasing, accumulating jittered frames in HDR before exposure and bloom.
+    /// The history is reprojected with the per-pixel motion the scene passes write, which
+    /// follows the camera, node transforms and skinning.
+    Taa,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct TaaPushConstants {
+    texel_size: [f32; 2],
+    feedback: f32,
+    history_valid: u32,
+}
+
+/// The built-in FXAA pass, placed first in the post-processing chain while FXAA is selected.
+pub fn fxaa_pass(data: &app_data::Data) -> Option<postprocess::PostProcessPass> {
+    if data.anti_aliasing != AntiAliasing::Fxaa {
+        return None;
+    }
+
+    let frag = include_bytes!("../../shaders/fxaa.bin");
+    let mut parameters = [0.0; postprocess::MAX_PARAMETERS];
+    parameters[..3].copy_from_slice(&[0.75, 0.166, 0.0833]);
+
+    Some(postprocess::PostProcessPass::fragment("FXAA", &frag[..], postprocess::SOURCE_FORMAT, postprocess::SOURCE_FORMAT)
+        .with_parameters(parameters))
+}
+
+/// Sub-pixel offset of jitter sample `sample`, from the base 2 and 3 Halton sequences, in
+/// pixels within `[-0.5, 0.5)`.
+pub fn jitter(sample: u64) -> glm::Vec2 {
+    let index = sample % JITTER_SAMPLES + 1;
+    glm::vec2(halton(index, 2) - 0.5, halton(index, 3) - 0.5)
+}
+
+fn halton(mut index: u64, base: u64) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Offsets `proj` by this frame's jitter while TAA is selected, or returns it unchanged.
+pub fn jittered_projection(data: &app_data::Data, proj: &glm::Mat4) -> glm::Mat4 {
+    let mut proj = *proj;
+    if data.anti_aliasing == AntiAliasing::Taa {
+        proj[(0, 2)] += data.taa_jitter.x * 2.0 / data.swapchain_extent.width as f32;
+        proj[(1, 2)] += data.taa_jitter.y * 2.0 / data.swapchain_extent.height as f32;
+    }
+    proj
+}
+
+/// Creates the two history images TAA alternates between and the pass resolving into them.
+/// Everything is sized to the swapchain, so this runs again on resize, which also discards the
+/// history.
+pub unsafe fn create_taa_objects(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    data.taa_history_valid = false;
+
+    // Images
+
+    for _ in 0..2 {
+        let (history_image, history_image_memory) = image::create_image(
+            instance,
+            device,
+            data,
+            data.swapchain_extent.width,
+            data.swapchain_extent.height,
+            1,
+            vk::ImageCreateFlags::empty(),
+            tonemap::HDR_FORMAT,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        // The first resolve binds the other image as history before anything wrote it.
+        image::transition_image_layout(
+            device,
+            data,
+            history_image,
+            image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER),
+        )?;
+
+        data.taa_history_images.push(history_image);
+        data.taa_history_images_memory.push(history_image_memory);
+        data.taa_history_image_views.push(image::create_image_view(
+            device,
+            history_image,
+            tonemap::HDR_FORMAT,
+            vk::ImageAspectFlags::COLOR,
+        )?);
+    }
+
+    // Render Pass
+
+    data.taa_render_pass = render_pass::create_unless_dynamic(device, data, &[resolve_attachment()])?;
+
+    // Framebuffers
+
+    data.taa_history_framebuffers = data.taa_history_image_views.iter()
+        .map(|v| render_pass::create_framebuffer(device, data.taa_render_pass, &[*v], data.swapchain_extent))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Sampler
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.taa_sampler = device.create_sampler(&info, None)?;
+
+    // Descriptor Sets
+
+    let bindings = (0..3)
+        .map(|binding| vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT))
+        .chain([vk::DescriptorSetLayoutBinding::builder()
+            .binding(3)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)])
+        .collect::<Vec<_>>();
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    data.taa_descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(6);
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLER)
+        .descriptor_count(2);
+
+    let pool_sizes = &[image_size, sampler_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(2);
+
+    data.taa_descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let layouts = &[data.taa_descriptor_set_layout; 2];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.taa_descriptor_pool)
+        .set_layouts(layouts);
+
+    data.taa_descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    // Set `n` resolves into history image `n` from the other one.
+    for (n, descriptor_set) in data.taa_descriptor_sets.iter().enumerate() {
+        let images = [
+            (data.hdr_image_view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            (data.taa_history_image_views[1 - n], vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            (data.velocity_image_view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        ];
+
+        let image_infos = images.iter()
+            .map(|(view, layout)| [vk::DescriptorImageInfo::builder()
+                .image_layout(*layout)
+                .image_view(*view)
+                .build()])
+            .collect::<Vec<_>>();
+
+        let sampler_info = &[vk::DescriptorImageInfo::builder().sampler(data.taa_sampler)];
+
+        let writes = image_infos.iter().enumerate()
+            .map(|(binding, info)| vk::WriteDescriptorSet::builder()
+                .dst_set(*descriptor_set)
+                .dst_binding(binding as u32)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(info)
+                .build())
+            .chain([vk::WriteDescriptorSet::builder()
+                .dst_set(*descriptor_set)
+                .dst_binding(3)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .image_info(sampler_info)
+                .build()])
+            .collect::<Vec<_>>();
+
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    // Pipelines
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<TaaPushConstants>() as u32);
+
+    let set_layouts = &[data.taa_descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.taa_pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    let vert = include_bytes!("../../shaders/fullscreen.bin");
+    let resolve = include_bytes!("../../shaders/taa_resolve.bin");
+
+    data.taa_resolve_pipeline = pipeline::create_fullscreen_pipeline(
+        device,
+        data.swapchain_extent,
+        &vert[..],
+        &resolve[..],
+        data.taa_pipeline_layout,
+        data.taa_render_pass,
+        tonemap::HDR_FORMAT,
+        false,
+    )?;
+
+    Ok(())
+}
+
+pub unsafe fn destroy_taa_objects(device: &Device, data: &mut app_data::Data) {
+    device.destroy_pipeline(data.taa_resolve_pipeline, None);
+    device.destroy_pipeline_layout(data.taa_pipeline_layout, None);
+    device.destroy_descriptor_pool(data.taa_descriptor_pool, None);
+    device.destroy_descriptor_set_layout(data.taa_descriptor_set_layout, None);
+    device.destroy_sampler(data.taa_sampler, None);
+    data.taa_history_framebuffers.drain(..).for_each(|f| device.destroy_framebuffer(f, None));
+    device.destroy_render_pass(data.taa_render_pass, None);
+    data.taa_history_image_views.drain(..).for_each(|v| device.destroy_image_view(v, None));
+    data.taa_history_images_memory.drain(..).for_each(|m| device.free_memory(m, None));
+    data.taa_history_images.drain(..).for_each(|i| device.destroy_image(i, None));
+}
+
+/// Resolves the lit HDR target against the history, reprojected with the velocity target,
+/// into this frame's history image, then copies the result back into the HDR target for
+/// exposure, bloom and tone mapping.
+pub unsafe fn cmd_resolve_taa(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+) {
+    let current = (data.taa_frame % 2) as usize;
+    let extent = data.swapchain_extent;
+
+    let push_constants = TaaPushConstants {
+        texel_size: [1.0 / extent.width as f32, 1.0 / extent.height as f32],
+        feedback: TAA_FEEDBACK,
+        history_valid: data.taa_history_valid as u32,
+    };
+
+    let history = data.taa_history_images[current];
+    let color = vk::ImageAspectFlags::COLOR;
+
+    let target = resolve_attachment().target(history, data.taa_history_image_views[current]);
+    let render_pass = data.taa_render_pass;
+    let framebuffer = data.taa_history_framebuffers[current];
+
+    render_pass::cmd_begin(device, command_buffer, data, images, render_pass, framebuffer, extent, &[target]);
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.taa_resolve_pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.taa_pipeline_layout,
+        0,
+        &[data.taa_descriptor_sets[current]],
+        &[],
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        data.taa_pipeline_layout,
+        vk::ShaderStageFlags::FRAGMENT,
+        0,
+        std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<TaaPushConstants>()),
+    );
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+    render_pass::cmd_end(device, command_buffer, data, render_pass);
+
+    // Copy
+
+    images.transition(history, color, image_state::Usage::TransferSrc);
+    images.cmd_use(device, command_buffer, data.hdr_image, color, image_state::Usage::TransferDst);
+
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let region = vk::ImageCopy::builder()
+        .src_subresource(subresource)
+        .src_offset(vk::Offset3D::default())
+        .dst_subresource(subresource)
+        .dst_offset(vk::Offset3D::default())
+        .extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 });
+
+    device.cmd_copy_image(
+        command_buffer,
+        history,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        data.hdr_image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[region],
+    );
+
+    images.transition(history, color, image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER));
+    images.transition(
+        data.hdr_image,
+        color,
+        image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER),
+    );
+    images.cmd_flush(device, command_buffer);
+}
+
+/// This frame's history image, written for every pixel.
+fn resolve_attachment() -> render_pass::Attachment {
+    render_pass::Attachment::color(tonemap::HDR_FORMAT, vk::AttachmentLoadOp::DONT_CARE)
+}
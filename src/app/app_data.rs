@@ -1,15 +1,38 @@
+use super::antialiasing;
+use super::bloom;
+use super::compute;
+use super::deferred;
+use super::environment;
+use super::image_state;
+use super::exposure;
+use super::indirect;
+use super::motion;
+use super::particles;
+use super::postprocess;
+use super::shadow;
+use super::ssao;
+use super::sync;
+use super::tonemap;
+use super::upload;
+use super::vertex_buffer;
+
 use vulkanalia::prelude::v1_0::*;
+use nalgebra_glm as glm;
 
 /// The Vulkan data structure.
 #[derive(Clone, Debug, Default)]
 pub struct Data {
     pub messenger: vk::DebugUtilsMessengerEXT,
+    /// The Vulkan version the instance was created with.
+    pub api_version: u32,
 
     pub surface: vk::SurfaceKHR,
 
     pub physical_device: vk::PhysicalDevice,
     pub graphics_queue: vk::Queue,
     pub present_queue: vk::Queue,
+    pub compute_queue: vk::Queue,
+    pub transfer_queue: vk::Queue,
 
     pub swapchain_format: vk::Format,
     pub swapchain_extent: vk::Extent2D,
@@ -21,15 +44,238 @@ pub struct Data {
     pub depth_image_memory: vk::DeviceMemory,
     pub depth_image_view: vk::ImageView,
 
+    // Motion
+    pub velocity_image: vk::Image,
+    pub velocity_image_memory: vk::DeviceMemory,
+    pub velocity_image_view: vk::ImageView,
+    pub previous_frame: motion::PreviousFrame,
+    pub previous_frame_buffers: Vec<vk::Buffer>,
+    pub previous_frame_buffers_memory: Vec<vk::DeviceMemory>,
+
+    // Picking
+    pub picking: bool,
+    pub id_image: vk::Image,
+    pub id_image_memory: vk::DeviceMemory,
+    pub id_image_view: vk::ImageView,
+    pub pick_buffers: Vec<vk::Buffer>,
+    pub pick_buffers_memory: Vec<vk::DeviceMemory>,
+
     // Pipeline
     pub render_pass: vk::RenderPass,
     pub pipeline_layout: vk::PipelineLayout,
     pub pipeline: vk::Pipeline,
     pub descriptor_set_layout: vk::DescriptorSetLayout,
+    /// Whether the passes recreated with the swapchain begin with dynamic rendering, leaving
+    /// their render passes and framebuffers null.
+    pub dynamic_rendering: bool,
+    pub dynamic_rendering_supported: bool,
+    /// Whether dynamic rendering comes from `VK_KHR_dynamic_rendering` rather than Vulkan 1.3.
+    pub dynamic_rendering_khr: bool,
+    pub depth_format: vk::Format,
 
     pub framebuffers: Vec<vk::Framebuffer>,
 
+    // Tone Mapping
+    pub tone_mapping: tonemap::ToneMapping,
+    pub hdr_image: vk::Image,
+    pub hdr_image_memory: vk::DeviceMemory,
+    pub hdr_image_view: vk::ImageView,
+    pub tonemap_render_pass: vk::RenderPass,
+    pub tonemap_framebuffers: Vec<vk::Framebuffer>,
+    pub tonemap_sampler: vk::Sampler,
+    pub tonemap_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub tonemap_descriptor_pool: vk::DescriptorPool,
+    pub tonemap_descriptor_set: vk::DescriptorSet,
+    pub tonemap_pipeline_layout: vk::PipelineLayout,
+    pub tonemap_pipeline: vk::Pipeline,
+
+    // Ambient Occlusion
+    pub ssao: ssao::Ssao,
+    pub ssao_kernel_buffer: vk::Buffer,
+    pub ssao_kernel_buffer_memory: vk::DeviceMemory,
+    pub ssao_noise_image: vk::Image,
+    pub ssao_noise_image_memory: vk::DeviceMemory,
+    pub ssao_noise_image_view: vk::ImageView,
+    pub ssao_raw_image: vk::Image,
+    pub ssao_raw_image_memory: vk::DeviceMemory,
+    pub ssao_raw_image_view: vk::ImageView,
+    pub ssao_image: vk::Image,
+    pub ssao_image_memory: vk::DeviceMemory,
+    pub ssao_image_view: vk::ImageView,
+    pub ssao_render_pass: vk::RenderPass,
+    pub ssao_raw_framebuffer: vk::Framebuffer,
+    pub ssao_framebuffer: vk::Framebuffer,
+    pub ssao_sampler: vk::Sampler,
+    pub ssao_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub ssao_descriptor_pool: vk::DescriptorPool,
+    pub ssao_descriptor_set: vk::DescriptorSet,
+    pub ssao_pipeline_layout: vk::PipelineLayout,
+    pub ssao_pipeline: vk::Pipeline,
+    pub ssao_blur_pipeline: vk::Pipeline,
+    pub depth_prepass_render_pass: vk::RenderPass,
+    pub depth_prepass_framebuffer: vk::Framebuffer,
+    pub depth_prepass_pipeline: vk::Pipeline,
+
+    // Environment
+    pub environment: environment::Environment,
+    /// What the environment cube map is baked from.
+    pub environment_source: environment::Source,
+    pub environment_image: vk::Image,
+    pub environment_image_memory: vk::DeviceMemory,
+    pub environment_image_view: vk::ImageView,
+    pub irradiance_image: vk::Image,
+    pub irradiance_image_memory: vk::DeviceMemory,
+    pub irradiance_image_view: vk::ImageView,
+    pub prefiltered_image: vk::Image,
+    pub prefiltered_image_memory: vk::DeviceMemory,
+    pub prefiltered_image_view: vk::ImageView,
+    pub brdf_lut_image: vk::Image,
+    pub brdf_lut_image_memory: vk::DeviceMemory,
+    pub brdf_lut_image_view: vk::ImageView,
+    pub environment_sampler: vk::Sampler,
+    pub skybox_pipeline: vk::Pipeline,
+
+    // Bloom
+    pub bloom: bloom::Bloom,
+    pub bloom_extents: Vec<vk::Extent2D>,
+    pub bloom_images: Vec<vk::Image>,
+    pub bloom_images_memory: Vec<vk::DeviceMemory>,
+    pub bloom_image_views: Vec<vk::ImageView>,
+    pub bloom_downsample_render_pass: vk::RenderPass,
+    pub bloom_upsample_render_pass: vk::RenderPass,
+    pub bloom_framebuffers: Vec<vk::Framebuffer>,
+    pub bloom_sampler: vk::Sampler,
+    pub bloom_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub bloom_descriptor_pool: vk::DescriptorPool,
+    pub bloom_downsample_descriptor_sets: Vec<vk::DescriptorSet>,
+    pub bloom_upsample_descriptor_sets: Vec<vk::DescriptorSet>,
+    pub bloom_pipeline_layout: vk::PipelineLayout,
+    pub bloom_downsample_pipelines: Vec<vk::Pipeline>,
+    pub bloom_upsample_pipelines: Vec<vk::Pipeline>,
+
+    // Post-Processing
+    pub post_process: Vec<postprocess::PostProcessPass>,
+    pub post_process_targets: Vec<postprocess::Target>,
+    pub post_process_render_passes: Vec<(vk::Format, vk::RenderPass)>,
+    pub post_process_present_render_pass: vk::RenderPass,
+    pub post_process_present_framebuffers: Vec<vk::Framebuffer>,
+    pub post_process_sampler: vk::Sampler,
+    pub post_process_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub post_process_descriptor_pool: vk::DescriptorPool,
+    pub post_process_pipeline_layout: vk::PipelineLayout,
+    pub post_process_steps: Vec<postprocess::Step>,
+
+    // Compute
+    pub graphics_family: u32,
+    pub compute_family: u32,
+    /// Whether compute work runs on its own queue, in `compute_command_buffers`.
+    pub async_compute: bool,
+    pub compute_command_buffers: Vec<vk::CommandBuffer>,
+    pub compute_finished_semaphores: Vec<vk::Semaphore>,
+    pub graphics_released_semaphores: Vec<vk::Semaphore>,
+    /// Signaled by the last graphics submission for the next compute submission to wait on.
+    pub pending_graphics_release: vk::Semaphore,
+    /// The queue family that last acquired the particle buffers, which it releases to the other
+    /// one before that acquires them in turn.
+    pub particles_owner: u32,
+    pub compute_kernels: Vec<compute::ComputeKernel>,
+    pub compute_buffers: Vec<compute::ComputeBuffer>,
+    pub compute_images: Vec<compute::ComputeImage>,
+    pub compute_jobs: Vec<compute::ComputeJob>,
+
+    // Particles
+    pub particle_emitters: Vec<particles::Emitter>,
+    pub particle_spawn_accumulators: Vec<f32>,
+    pub particle_frame: particles::ParticleFrame,
+    pub particle_buffer: vk::Buffer,
+    pub particle_buffer_memory: vk::DeviceMemory,
+    pub particle_list_buffer: vk::Buffer,
+    pub particle_list_buffer_memory: vk::DeviceMemory,
+    pub particle_state_buffer: vk::Buffer,
+    pub particle_state_buffer_memory: vk::DeviceMemory,
+    pub particle_emitter_buffer: vk::Buffer,
+    pub particle_emitter_buffer_memory: vk::DeviceMemory,
+    pub particle_emit_kernel: compute::ComputeKernel,
+    pub particle_simulate_kernel: compute::ComputeKernel,
+    pub particle_compact_kernel: compute::ComputeKernel,
+    pub particle_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub particle_descriptor_pool: vk::DescriptorPool,
+    pub particle_descriptor_set: vk::DescriptorSet,
+    pub particle_pipeline_layout: vk::PipelineLayout,
+    pub particle_render_pass: vk::RenderPass,
+    pub particle_framebuffer: vk::Framebuffer,
+    pub particle_pipelines: Vec<vk::Pipeline>,
+
+    // Indirect
+    pub gpu_driven: bool,
+    pub gpu_driven_supported: bool,
+    pub draw_indirect_count: bool,
+    pub primitive_bounds: Vec<Vec<glm::Vec4>>,
+    pub indirect_frame: indirect::IndirectFrame,
+    pub object_buffer: vk::Buffer,
+    pub object_buffer_memory: vk::DeviceMemory,
+    pub transform_buffer: vk::Buffer,
+    pub transform_buffer_memory: vk::DeviceMemory,
+    pub object_staging_buffers: Vec<vk::Buffer>,
+    pub object_staging_buffers_memory: Vec<vk::DeviceMemory>,
+    pub indirect_command_buffer: vk::Buffer,
+    pub indirect_command_buffer_memory: vk::DeviceMemory,
+    pub indirect_count_buffer: vk::Buffer,
+    pub indirect_count_buffer_memory: vk::DeviceMemory,
+    pub cull_kernel: compute::ComputeKernel,
+
+    // Anti-Aliasing
+    pub anti_aliasing: antialiasing::AntiAliasing,
+    pub taa_frame: u64,
+    pub taa_jitter: glm::Vec2,
+    pub taa_history_valid: bool,
+    pub taa_history_images: Vec<vk::Image>,
+    pub taa_history_images_memory: Vec<vk::DeviceMemory>,
+    pub taa_history_image_views: Vec<vk::ImageView>,
+    pub taa_render_pass: vk::RenderPass,
+    pub taa_history_framebuffers: Vec<vk::Framebuffer>,
+    pub taa_sampler: vk::Sampler,
+    pub taa_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub taa_descriptor_pool: vk::DescriptorPool,
+    pub taa_descriptor_sets: Vec<vk::DescriptorSet>,
+    pub taa_pipeline_layout: vk::PipelineLayout,
+    pub taa_resolve_pipeline: vk::Pipeline,
+
+    // Exposure
+    pub auto_exposure: exposure::AutoExposure,
+    pub exposure_buffer: vk::Buffer,
+    pub exposure_buffer_memory: vk::DeviceMemory,
+    pub histogram_buffer: vk::Buffer,
+    pub histogram_buffer_memory: vk::DeviceMemory,
+    pub exposure_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub exposure_descriptor_pool: vk::DescriptorPool,
+    pub exposure_descriptor_set: vk::DescriptorSet,
+    pub exposure_pipeline_layout: vk::PipelineLayout,
+    pub histogram_pipeline: vk::Pipeline,
+    pub exposure_pipeline: vk::Pipeline,
+
+    // Deferred
+    pub render_path: deferred::RenderPath,
+    pub gbuffer_images: Vec<vk::Image>,
+    pub gbuffer_images_memory: Vec<vk::DeviceMemory>,
+    pub gbuffer_image_views: Vec<vk::ImageView>,
+    pub gbuffer_render_pass: vk::RenderPass,
+    pub gbuffer_framebuffer: vk::Framebuffer,
+    pub gbuffer_pipeline: vk::Pipeline,
+    pub gbuffer_sampler: vk::Sampler,
+    pub gbuffer_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub gbuffer_descriptor_pool: vk::DescriptorPool,
+    pub gbuffer_descriptor_set: vk::DescriptorSet,
+    pub lighting_render_pass: vk::RenderPass,
+    pub lighting_framebuffers: Vec<vk::Framebuffer>,
+    pub lighting_pipeline_layout: vk::PipelineLayout,
+    pub lighting_pipeline: vk::Pipeline,
+
     pub command_pool: vk::CommandPool,
+    pub compute_command_pool: vk::CommandPool,
+    pub transfer_command_pool: vk::CommandPool,
+    pub pending_uploads: Vec<upload::PendingUpload>,
+    pub pending_commands: Vec<vertex_buffer::PendingCommands>,
 
     pub vertex_buffer: vk::Buffer,
     pub vertex_buffer_memory: vk::DeviceMemory,
@@ -37,15 +283,61 @@ pub struct Data {
     pub index_buffer_memory: vk::DeviceMemory,
     pub uniform_buffers: Vec<vk::Buffer>,
     pub uniform_buffers_memory: Vec<vk::DeviceMemory>,
+    pub joint_buffers: Vec<vk::Buffer>,
+    pub joint_buffers_memory: Vec<vk::DeviceMemory>,
+    pub light_buffers: Vec<vk::Buffer>,
+    pub light_buffers_memory: Vec<vk::DeviceMemory>,
+
+    // Shadows
+    pub shadow_settings: shadow::ShadowSettings,
+    pub shadow_image: vk::Image,
+    pub shadow_image_memory: vk::DeviceMemory,
+    pub shadow_image_view: vk::ImageView,
+    pub shadow_layer_views: Vec<vk::ImageView>,
+    pub shadow_render_pass: vk::RenderPass,
+    pub shadow_framebuffers: Vec<vk::Framebuffer>,
+    pub shadow_pipeline_layout: vk::PipelineLayout,
+    pub shadow_pipeline: vk::Pipeline,
+    pub shadow_sampler: vk::Sampler,
+    pub point_shadow_image: vk::Image,
+    pub point_shadow_image_memory: vk::DeviceMemory,
+    pub point_shadow_cube_views: Vec<vk::ImageView>,
+    pub point_shadow_face_views: Vec<vk::ImageView>,
+    pub point_shadow_framebuffers: Vec<vk::Framebuffer>,
+    pub point_shadow_pipeline: vk::Pipeline,
+    pub point_shadow_sampler: vk::Sampler,
+    pub shadow_buffers: Vec<vk::Buffer>,
+    pub shadow_buffers_memory: Vec<vk::DeviceMemory>,
+
+    pub texture_images: Vec<vk::Image>,
+    pub texture_images_memory: Vec<vk::DeviceMemory>,
+    pub texture_image_views: Vec<vk::ImageView>,
+    pub texture_sampler: vk::Sampler,
+
+    pub material_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub material_buffers: Vec<vk::Buffer>,
+    pub material_buffers_memory: Vec<vk::DeviceMemory>,
+    pub material_descriptor_pool: vk::DescriptorPool,
+    pub material_descriptor_sets: Vec<vk::DescriptorSet>,
 
     pub descriptor_pool: vk::DescriptorPool,
     pub descriptor_sets: Vec<vk::DescriptorSet>,
 
     pub command_buffers: Vec<vk::CommandBuffer>,
+    /// Layouts and last uses of the images frames transition, as of the last frame recorded.
+    pub image_states: image_state::ImageStates,
 
     // Sync Objects
     pub image_available_semaphores: Vec<vk::Semaphore>,
     pub render_finished_semaphores: Vec<vk::Semaphore>,
     pub in_flight_fences: Vec<vk::Fence>,
-    pub images_in_flight: Vec<vk::Fence>,
+    /// Whether frames signal `timeline_semaphore` instead of `in_flight_fences`.
+    pub timeline: bool,
+    pub timeline_semaphore: vk::Semaphore,
+    /// The GPU progress value signaled by the last frame submitted. Each frame signals one more.
+    pub submitted_value: u64,
+    /// The value each frame in flight and each swapchain image was last submitted with.
+    pub frame_values: Vec<u64>,
+    pub image_values: Vec<u64>,
+    pub deferred_deletions: Vec<(u64, sync::Deletion)>,
 }
\ No newline at end of file
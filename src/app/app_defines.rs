@@ -6,4 +6,26 @@ pub const VALIDATION_LAYER: vk::ExtensionName = vk::ExtensionName::from_bytes(b"
 
 pub const DEVICE_EXTENSIONS: &[vk::ExtensionName] = &[vk::KHR_SWAPCHAIN_EXTENSION.name];
 
+/// The newest Vulkan version whose features are used when the loader and device support it.
+pub const API_VERSION: u32 = vk::make_version(1, 3, 0);
+
 pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+/// Size of the joint matrix uniform array shared by every skinned node in the scene.
+pub const MAX_JOINTS: usize = 256;
+
+/// Size of the light array in the lights uniform block.
+pub const MAX_LIGHTS: usize = 16;
+
+/// Seconds per fixed simulation update.
+pub const SIMULATION_STEP: f64 = 1.0 / 60.0;
+
+/// Size of the cascade array in the shadow uniform block and the shadow map.
+pub const MAX_CASCADES: usize = 4;
+
+/// Camera clip planes, also bounding the shadow cascades.
+pub const NEAR_PLANE: f32 = 0.1;
+pub const FAR_PLANE: f32 = 10.0;
+
+/// Number of point or spot lights that can cast shadows at once, each with its own cube map.
+pub const MAX_POINT_SHADOWS: usize = 4;
@@ -0,0 +1,399 @@
+use super::app_data;
+use super::image;
+use super::image_state;
+use super::pipeline;
+use super::render_pass;
+use super::tonemap;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+use std::mem::size_of;
+
+/// Most levels in the downsample chain; the first is half the swapchain size.
+const MAX_BLOOM_LEVELS: usize = 6;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bloom {
+    pub enabled: bool,
+    /// Share of the blurred image added to the scene before tone mapping.
+    pub intensity: f32,
+    /// Spread of the upsample tent filter, in texels of the smaller level.
+    pub radius: f32,
+    /// Brightness below which pixels do not bloom; `0.0` lets every pixel bloom.
+    pub threshold: f32,
+    /// Width of the soft transition around `threshold`.
+    pub knee: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            intensity: 0.04,
+            radius: 1.0,
+            threshold: 0.0,
+            knee: 0.5,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct BloomPushConstants {
+    texel_size: [f32; 2],
+    threshold: f32,
+    knee: f32,
+    radius: f32,
+    prefilter: u32,
+}
+
+/// Creates the chain of half-size HDR levels, their passes and pipelines. Everything is sized
+/// to the swapchain, so this runs again on resize. Level 0 holds the bloom once
+/// `cmd_draw_bloom` has run.
+pub unsafe fn create_bloom_objects(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    // Levels
+
+    data.bloom_extents = level_extents(data.swapchain_extent);
+
+    for extent in data.bloom_extents.clone() {
+        let (bloom_image, bloom_image_memory) = image::create_image(
+            instance,
+            device,
+            data,
+            extent.width,
+            extent.height,
+            1,
+            vk::ImageCreateFlags::empty(),
+            tonemap::HDR_FORMAT,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        // Tone mapping samples level 0 even while bloom is off.
+        image::transition_image_layout(
+            device,
+            data,
+            bloom_image,
+            image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER),
+        )?;
+
+        data.bloom_images.push(bloom_image);
+        data.bloom_images_memory.push(bloom_image_memory);
+        data.bloom_image_views.push(image::create_image_view(
+            device,
+            bloom_image,
+            tonemap::HDR_FORMAT,
+            vk::ImageAspectFlags::COLOR,
+        )?);
+    }
+
+    // Render Passes
+
+    data.bloom_downsample_render_pass = render_pass::create_unless_dynamic(device, data, &[level_attachment(false)])?;
+    data.bloom_upsample_render_pass = render_pass::create_unless_dynamic(device, data, &[level_attachment(true)])?;
+
+    // Framebuffers
+
+    // Both passes are compatible, so each level needs one framebuffer.
+    data.bloom_framebuffers = data.bloom_image_views.iter()
+        .zip(data.bloom_extents.iter())
+        .map(|(v, e)| render_pass::create_framebuffer(device, data.bloom_downsample_render_pass, &[*v], *e))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Sampler
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.bloom_sampler = device.create_sampler(&info, None)?;
+
+    // Descriptor Sets
+
+    let image_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[image_binding, sampler_binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.bloom_descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    // Each level is downsampled from the one above it, and all but the last are upsampled
+    // onto from the one below.
+    let levels = data.bloom_extents.len();
+    let set_count = (2 * levels - 1) as u32;
+
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(set_count);
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLER)
+        .descriptor_count(set_count);
+
+    let pool_sizes = &[image_size, sampler_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(set_count);
+
+    data.bloom_descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let downsample_sources = std::iter::once(data.hdr_image_view)
+        .chain(data.bloom_image_views[..levels - 1].iter().copied())
+        .collect::<Vec<_>>();
+    data.bloom_downsample_descriptor_sets = create_descriptor_sets(device, data, &downsample_sources)?;
+
+    let upsample_sources = data.bloom_image_views[1..].to_vec();
+    data.bloom_upsample_descriptor_sets = create_descriptor_sets(device, data, &upsample_sources)?;
+
+    // Pipelines
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<BloomPushConstants>() as u32);
+
+    let set_layouts = &[data.bloom_descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.bloom_pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    let vert = include_bytes!("../../shaders/fullscreen.bin");
+    let downsample = include_bytes!("../../shaders/bloom_down.bin");
+    let upsample = include_bytes!("../../shaders/bloom_up.bin");
+
+    data.bloom_downsample_pipelines = data.bloom_extents.iter()
+        .map(|e| pipeline::create_fullscreen_pipeline(
+            device,
+            *e,
+            &vert[..],
+            &downsample[..],
+            data.bloom_pipeline_layout,
+            data.bloom_downsample_render_pass,
+            tonemap::HDR_FORMAT,
+            false,
+        ))
+        .collect::<Result<Vec<_>>>()?;
+
+    data.bloom_upsample_pipelines = data.bloom_extents[..levels - 1].iter()
+        .map(|e| pipeline::create_fullscreen_pipeline(
+            device,
+            *e,
+            &vert[..],
+            &upsample[..],
+            data.bloom_pipeline_layout,
+            data.bloom_upsample_render_pass,
+            tonemap::HDR_FORMAT,
+            true,
+        ))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(())
+}
+
+/// Half the swapchain size, then halving until a level is a single pixel across.
+fn level_extents(extent: vk::Extent2D) -> Vec<vk::Extent2D> {
+    let half = |e: vk::Extent2D| vk::Extent2D { width: (e.width / 2).max(1), height: (e.height / 2).max(1) };
+
+    let mut extents = vec![half(extent)];
+    while let Some(last) = extents.last().copied() {
+        if extents.len() == MAX_BLOOM_LEVELS || last.width == 1 || last.height == 1 {
+            break;
+        }
+        extents.push(half(last));
+    }
+
+    extents
+}
+
+/// A level drawn over whole, or blended onto when upsampling.
+fn level_attachment(upsample: bool) -> render_pass::Attachment {
+    let load_op = if upsample { vk::AttachmentLoadOp::LOAD } else { vk::AttachmentLoadOp::DONT_CARE };
+    render_pass::Attachment::color(tonemap::HDR_FORMAT, load_op)
+}
+
+/// One descriptor set per source view.
+unsafe fn create_descriptor_sets(
+    device: &Device,
+    data: &app_data::Data,
+    sources: &[vk::ImageView],
+) -> Result<Vec<vk::DescriptorSet>> {
+    let layouts = vec![data.bloom_descriptor_set_layout; sources.len()];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.bloom_descriptor_pool)
+        .set_layouts(&layouts);
+
+    let descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    for (set, source) in descriptor_sets.iter().zip(sources) {
+        let image_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(*source)];
+        let image_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(image_info);
+
+        let sampler_info = &[vk::DescriptorImageInfo::builder().sampler(data.bloom_sampler)];
+        let sampler_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
+            .dst_binding(1)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(sampler_info);
+
+        device.update_descriptor_sets(&[image_write, sampler_write], &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    Ok(descriptor_sets)
+}
+
+pub unsafe fn destroy_bloom_objects(device: &Device, data: &mut app_data::Data) {
+    data.bloom_upsample_pipelines.drain(..).for_each(|p| device.destroy_pipeline(p, None));
+    data.bloom_downsample_pipelines.drain(..).for_each(|p| device.destroy_pipeline(p, None));
+    device.destroy_pipeline_layout(data.bloom_pipeline_layout, None);
+    device.destroy_descriptor_pool(data.bloom_descriptor_pool, None);
+    device.destroy_descriptor_set_layout(data.bloom_descriptor_set_layout, None);
+    device.destroy_sampler(data.bloom_sampler, None);
+    data.bloom_framebuffers.drain(..).for_each(|f| device.destroy_framebuffer(f, None));
+    device.destroy_render_pass(data.bloom_upsample_render_pass, None);
+    device.destroy_render_pass(data.bloom_downsample_render_pass, None);
+    data.bloom_image_views.drain(..).for_each(|v| device.destroy_image_view(v, None));
+    data.bloom_images_memory.drain(..).for_each(|m| device.free_memory(m, None));
+    data.bloom_images.drain(..).for_each(|i| device.destroy_image(i, None));
+}
+
+/// Intensity tone mapping scales bloom level 0 by. Every level accumulates into level 0, so
+/// this also averages them.
+pub fn composite_intensity(data: &app_data::Data) -> f32 {
+    if data.bloom.enabled {
+        data.bloom.intensity / data.bloom_extents.len() as f32
+    } else {
+        0.0
+    }
+}
+
+/// Downsamples the HDR target through every level, then upsamples back to level 0. Must be
+/// recorded after the scene is lit and before it is tone mapped. Each level is left readable.
+pub unsafe fn cmd_draw_bloom(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+) {
+    let levels = data.bloom_extents.len();
+
+    // Downsample
+
+    for level in 0..levels {
+        let source = if level == 0 { data.swapchain_extent } else { data.bloom_extents[level - 1] };
+        let push_constants = BloomPushConstants {
+            texel_size: [1.0 / source.width as f32, 1.0 / source.height as f32],
+            threshold: data.bloom.threshold,
+            knee: data.bloom.knee,
+            radius: data.bloom.radius,
+            prefilter: (level == 0 && data.bloom.threshold > 0.0) as u32,
+        };
+
+        cmd_draw_level(
+            device,
+            command_buffer,
+            data,
+            images,
+            level,
+            false,
+            data.bloom_downsample_pipelines[level],
+            data.bloom_downsample_descriptor_sets[level],
+            &push_constants,
+        );
+    }
+
+    // Upsample
+
+    for level in (0..levels - 1).rev() {
+        let source = data.bloom_extents[level + 1];
+        let push_constants = BloomPushConstants {
+            texel_size: [1.0 / source.width as f32, 1.0 / source.height as f32],
+            threshold: data.bloom.threshold,
+            knee: data.bloom.knee,
+            radius: data.bloom.radius,
+            prefilter: 0,
+        };
+
+        cmd_draw_level(
+            device,
+            command_buffer,
+            data,
+            images,
+            level,
+            true,
+            data.bloom_upsample_pipelines[level],
+            data.bloom_upsample_descriptor_sets[level],
+            &push_constants,
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn cmd_draw_level(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+    level: usize,
+    upsample: bool,
+    pipeline: vk::Pipeline,
+    descriptor_set: vk::DescriptorSet,
+    push_constants: &BloomPushConstants,
+) {
+    let image = data.bloom_images[level];
+    let target = level_attachment(upsample).target(image, data.bloom_image_views[level]);
+    let render_pass = if upsample { data.bloom_upsample_render_pass } else { data.bloom_downsample_render_pass };
+    let framebuffer = data.bloom_framebuffers[level];
+    render_pass::cmd_begin(device, command_buffer, data, images, render_pass, framebuffer, data.bloom_extents[level], &[target]);
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.bloom_pipeline_layout,
+        0,
+        &[descriptor_set],
+        &[],
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        data.bloom_pipeline_layout,
+        vk::ShaderStageFlags::FRAGMENT,
+        0,
+        std::slice::from_raw_parts(push_constants as *const _ as *const u8, size_of::<BloomPushConstants>()),
+    );
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+    render_pass::cmd_end(device, command_buffer, data, render_pass);
+
+    let sampled = image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER);
+    images.cmd_use(device, command_buffer, image, vk::ImageAspectFlags::COLOR, sampled);
+}
@@ -0,0 +1,258 @@
+use anyhow::{anyhow, Result};
+use std::time::Instant;
+
+/// Most simulation steps run for one real-time frame; the rest of a long stall is dropped.
+const MAX_REAL_TIME_STEPS: u32 = 8;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Mode {
+    /// Follows the wall clock.
+    #[default]
+    RealTime,
+    /// Advances by the given number of seconds every frame, regardless of the wall clock.
+    FixedStep(f64),
+    /// Only advances through `Clock::advance` and `Clock::advance_to`.
+    Manual,
+}
+
+/// The time passed to one rendered frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tick {
+    /// Scaled seconds since the previous frame.
+    pub delta: f64,
+    /// Fixed simulation updates of `Clock::step_size` seconds to run before rendering.
+    pub steps: u32,
+}
+
+/// Frame clock driving a fixed-timestep simulation and a variable-rate render.
+#[derive(Clone, Debug)]
+pub struct Clock {
+    mode: Mode,
+    paused: bool,
+    scale: f64,
+    step_size: f64,
+    time: f64,
+    accumulator: f64,
+    pending: f64,
+    single_steps: u32,
+    last: Instant,
+}
+
+impl Clock {
+    /// A running clock at `t = 0` whose simulation updates every `step_size` seconds.
+    pub fn new(mode: Mode, step_size: f64) -> Self {
+        Self {
+            mode,
+            paused: false,
+            scale: 1.0,
+            step_size,
+            time: 0.0,
+            accumulator: 0.0,
+            pending: 0.0,
+            single_steps: 0,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.last = Instant::now();
+    }
+
+    /// Render time in scaled seconds.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Time reached by the simulation, at most one step behind `time`.
+    pub fn simulation_time(&self) -> f64 {
+        self.time - self.accumulator
+    }
+
+    pub fn step_size(&self) -> f64 {
+        self.step_size
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn time_scale(&self) -> f64 {
+        self.scale
+    }
+
+    pub fn set_time_scale(&mut self, scale: f64) {
+        self.scale = scale.max(0.0);
+    }
+
+    /// Runs one simulation step on the next frame, even while paused.
+    pub fn single_step(&mut self) {
+        self.single_steps += 1;
+    }
+
+    /// Queues `seconds` of unscaled time for the next unpaused frame in manual mode.
+    pub fn advance(&mut self, seconds: f64) {
+        self.pending += seconds.max(0.0);
+    }
+
+    /// Queues whatever time is left until `time`. The clock never runs backwards, and cannot
+    /// reach a later time with a time scale of zero.
+    pub fn advance_to(&mut self, time: f64) -> Result<()> {
+        if self.scale == 0.0 && time > self.time {
+            return Err(anyhow!("Cannot advance to t = {} with a time scale of zero.", time));
+        }
+
+        self.pending = ((time - self.time) / self.scale).max(0.0);
+        Ok(())
+    }
+
+    /// Advances the clock for one rendered frame.
+    pub fn tick(&mut self) -> Tick {
+        let now = Instant::now();
+        let elapsed = match self.mode {
+            Mode::RealTime => (now - self.last).as_secs_f64(),
+            Mode::FixedStep(delta) => delta,
+            // Queued time waits for the clock to be unpaused.
+            Mode::Manual if self.paused => 0.0,
+            Mode::Manual => std::mem::take(&mut self.pending),
+        };
+        self.last = now;
+
+        let mut delta = if self.paused { 0.0 } else { elapsed * self.scale };
+        delta += std::mem::take(&mut self.single_steps) as f64 * self.step_size;
+
+        self.time += delta;
+        self.accumulator += delta;
+
+        // The epsilon keeps exact multiples of the step, like t = 1.5 at 60 Hz, from rounding down.
+        let mut steps = (self.accumulator / self.step_size + 1e-9) as u32;
+        if self.mode == Mode::RealTime && steps > MAX_REAL_TIME_STEPS {
+            let dropped = (steps - MAX_REAL_TIME_STEPS) as f64 * self.step_size;
+            delta -= dropped;
+            self.time -= dropped;
+            self.accumulator -= dropped;
+            steps = MAX_REAL_TIME_STEPS;
+        }
+        self.accumulator = (self.accumulator - steps as f64 * self.step_size).max(0.0);
+
+        Tick { delta, steps }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const STEP: f64 = 1.0 / 60.0;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn fixed_step_runs_whole_steps() {
+        let mut clock = Clock::new(Mode::FixedStep(1.0 / 30.0), STEP);
+        for _ in 0..3 {
+            let tick = clock.tick();
+            assert_eq!(tick.steps, 2);
+            assert_close(tick.delta, 1.0 / 30.0);
+        }
+        assert_close(clock.time(), 0.1);
+
+        // Partial steps carry over to the next frame.
+        let mut clock = Clock::new(Mode::FixedStep(STEP * 1.5), STEP);
+        assert_eq!([clock.tick().steps, clock.tick().steps], [1, 2]);
+    }
+
+    #[test]
+    fn manual_advances_to_exact_time() {
+        let mut clock = Clock::new(Mode::Manual, STEP);
+        assert_eq!(clock.tick().steps, 0);
+
+        clock.advance_to(1.5).unwrap();
+        let tick = clock.tick();
+        assert_eq!(tick.steps, 90);
+        assert_close(clock.time(), 1.5);
+        assert_close(clock.simulation_time(), 1.5);
+
+        // The clock never runs backwards.
+        clock.advance_to(1.0).unwrap();
+        assert_eq!(clock.tick().steps, 0);
+        assert_close(clock.time(), 1.5);
+    }
+
+    #[test]
+    fn paused_clock_only_single_steps() {
+        let mut clock = Clock::new(Mode::FixedStep(STEP), STEP);
+        clock.set_paused(true);
+        assert_eq!(clock.tick(), Tick { delta: 0.0, steps: 0 });
+
+        clock.single_step();
+        let tick = clock.tick();
+        assert_eq!(tick.steps, 1);
+        assert_close(tick.delta, STEP);
+        assert_eq!(clock.tick().steps, 0);
+    }
+
+    #[test]
+    fn paused_clock_keeps_queued_time() {
+        let mut clock = Clock::new(Mode::Manual, STEP);
+        clock.set_paused(true);
+        clock.advance_to(1.5).unwrap();
+        assert_eq!(clock.tick().steps, 0);
+        assert_close(clock.time(), 0.0);
+
+        clock.set_paused(false);
+        assert_eq!(clock.tick().steps, 90);
+        assert_close(clock.time(), 1.5);
+    }
+
+    #[test]
+    fn zero_time_scale_cannot_advance() {
+        let mut clock = Clock::new(Mode::Manual, STEP);
+        clock.set_time_scale(0.0);
+        assert!(clock.advance_to(1.5).is_err());
+        assert_eq!(clock.tick().steps, 0);
+        assert_close(clock.time(), 0.0);
+
+        // Staying put needs no time.
+        assert!(clock.advance_to(0.0).is_ok());
+    }
+
+    #[test]
+    fn time_scale_speeds_up_simulation() {
+        let mut clock = Clock::new(Mode::FixedStep(STEP), STEP);
+        clock.set_time_scale(2.0);
+        let tick = clock.tick();
+        assert_eq!(tick.steps, 2);
+        assert_close(tick.delta, 2.0 * STEP);
+
+        // Target times are in scaled seconds.
+        let mut clock = Clock::new(Mode::Manual, STEP);
+        clock.set_time_scale(0.5);
+        clock.advance_to(1.5).unwrap();
+        assert_eq!(clock.tick().steps, 90);
+        assert_close(clock.time(), 1.5);
+    }
+
+    #[test]
+    fn real_time_drops_long_stalls() {
+        let mut clock = Clock::new(Mode::RealTime, STEP);
+        clock.last = Instant::now() - Duration::from_secs(1);
+
+        let tick = clock.tick();
+        assert_eq!(tick.steps, MAX_REAL_TIME_STEPS);
+        assert_close(tick.delta, MAX_REAL_TIME_STEPS as f64 * STEP + clock.accumulator);
+        assert!(clock.accumulator < STEP);
+        assert_close(clock.simulation_time(), MAX_REAL_TIME_STEPS as f64 * STEP);
+    }
+}
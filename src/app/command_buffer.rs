@@ -1,74 +1,324 @@
+use super::antialiasing;
 use super::app_data;
+use super::bloom;
+use super::compute;
+use super::deferred;
+use super::environment;
+use super::exposure;
+use super::image_state;
+use super::indirect;
+use super::material;
+use super::particles;
+use super::picking;
+use super::pipeline;
+use super::postprocess;
 use super::queue_family;
+use super::render_pass;
+use super::scene;
+use super::shadow;
+use super::ssao;
+use super::tonemap;
+use super::vertex_buffer;
 
 use anyhow::{Result};
 use vulkanalia::prelude::v1_0::*;
+use std::mem::size_of;
 
 pub unsafe fn create_command_pool(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
     let indices = queue_family::QueueFamilyIndices::get(instance, data, data.physical_device)?;
 
-    let info = vk::CommandPoolCreateInfo::builder().queue_family_index(indices.graphics);
+    let info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+        .queue_family_index(indices.graphics);
 
     data.command_pool = device.create_command_pool(&info, None)?;
 
+    let info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::TRANSIENT | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+        .queue_family_index(indices.compute);
+
+    data.compute_command_pool = device.create_command_pool(&info, None)?;
+
+    let info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+        .queue_family_index(indices.transfer);
+
+    data.transfer_command_pool = device.create_command_pool(&info, None)?;
+
     Ok(())
 }
 
-pub unsafe fn create_command_buffers(device: &Device, data: &mut app_data::Data, indices_count: u32) -> Result<()> {
-    // Allocate
-
+pub unsafe fn create_command_buffers(device: &Device, data: &mut app_data::Data) -> Result<()> {
     let allocate_info = vk::CommandBufferAllocateInfo::builder()
         .command_pool(data.command_pool)
         .level(vk::CommandBufferLevel::PRIMARY)
-        .command_buffer_count(data.framebuffers.len() as u32);
+        .command_buffer_count(data.swapchain_images.len() as u32);
 
     data.command_buffers = device.allocate_command_buffers(&allocate_info)?;
 
-    // Commands
+    if data.async_compute {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(data.compute_command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(data.swapchain_images.len() as u32);
 
-    for (i, command_buffer) in data.command_buffers.iter().enumerate() {
-        let info = vk::CommandBufferBeginInfo::builder();
+        data.compute_command_buffers = device.allocate_command_buffers(&allocate_info)?;
+    }
 
-        device.begin_command_buffer(*command_buffer, &info)?;
+    Ok(())
+}
 
-        let render_area = vk::Rect2D::builder()
-            .offset(vk::Offset2D::default())
-            .extent(data.swapchain_extent);
+/// Re-records the command buffer of a swapchain image. Called every frame since draws carry
+/// animated transforms. `point_shadows` is the number of cube shadow maps in use and `delta`
+/// the seconds since the last frame, over which exposure adapts. `pick` copies the object ID
+/// at `(x, y)` into the readback buffer of frame `frame`. Images change layout through
+/// `data.image_states`, which carries their state from one recorded frame to the next.
+pub unsafe fn update_command_buffer(
+    device: &Device,
+    data: &mut app_data::Data,
+    image_index: usize,
+    draws: &[scene::Draw],
+    point_shadows: usize,
+    delta: f32,
+    pick: Option<(usize, u32, u32)>,
+) -> Result<()> {
+    // Put back on every exit, so a failed recording does not lose the layouts.
+    let mut images = std::mem::take(&mut data.image_states);
+    let result = record_command_buffer(device, data, &mut images, image_index, draws, point_shadows, delta, pick);
+    data.image_states = images;
 
-        let color_clear_value = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.0, 0.0, 0.0, 1.0],
-            },
-        };
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn record_command_buffer(
+    device: &Device,
+    data: &mut app_data::Data,
+    images: &mut image_state::ImageStates,
+    image_index: usize,
+    draws: &[scene::Draw],
+    point_shadows: usize,
+    delta: f32,
+    pick: Option<(usize, u32, u32)>,
+) -> Result<()> {
+    let command_buffer = data.command_buffers[image_index];
+
+    device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+
+    let info = vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    if data.compute_jobs.iter().any(|j| j.enabled) {
+        compute::cmd_run_jobs(device, command_buffer, data);
+    }
+
+    if data.async_compute {
+        // The compute command buffer recorded this frame released them after simulating.
+        if particles::is_active(data) {
+            particles::cmd_acquire_for_drawing(device, command_buffer, data);
+            data.particles_owner = data.graphics_family;
+        }
+
+        if indirect::is_active(data) {
+            indirect::cmd_acquire_for_drawing(device, command_buffer, data);
+        }
+    } else {
+        cmd_compute_passes(device, command_buffer, data, image_index);
+    }
+
+    shadow::cmd_draw_shadows(device, command_buffer, data, images, image_index, draws, point_shadows);
+
+    match data.render_path {
+        deferred::RenderPath::Forward => {
+            // Depth is drawn first so occlusion is ready before shading.
+            if data.ssao.enabled {
+                ssao::cmd_begin_depth_prepass(device, command_buffer, data, images);
+                cmd_draw_scene(device, command_buffer, data, image_index, data.depth_prepass_pipeline, draws);
+                ssao::cmd_end_depth_prepass(device, command_buffer, data, images);
+
+                ssao::cmd_draw_ssao(device, command_buffer, data, images, image_index);
+            }
+
+            let views = [
+                (data.hdr_image, data.hdr_image_view),
+                (data.depth_image, data.depth_image_view),
+                (data.velocity_image, data.velocity_image_view),
+                (data.id_image, data.id_image_view),
+            ];
+            let targets = render_pass::targets(&pipeline::forward_attachments(data), &views);
+            let framebuffer = data.framebuffers[image_index];
+            render_pass::cmd_begin(device, command_buffer, data, images, data.render_pass, framebuffer, data.swapchain_extent, &targets);
+
+            cmd_draw_scene(device, command_buffer, data, image_index, data.pipeline, draws);
+            if data.environment.enabled {
+                environment::cmd_draw_skybox(device, command_buffer, data, image_index);
+            }
+
+            render_pass::cmd_end(device, command_buffer, data, data.render_pass);
+
+            cmd_finish_shading(device, command_buffer, data, images);
+        }
+        deferred::RenderPath::Deferred => {
+            deferred::cmd_begin_geometry_pass(device, command_buffer, data, images);
+            cmd_draw_scene(device, command_buffer, data, image_index, data.gbuffer_pipeline, draws);
+            deferred::cmd_end_geometry_pass(device, command_buffer, data, images);
+
+            if data.ssao.enabled {
+                ssao::cmd_draw_ssao(device, command_buffer, data, images, image_index);
+            }
+
+            deferred::cmd_draw_lighting(device, command_buffer, data, images, image_index);
+
+            cmd_finish_shading(device, command_buffer, data, images);
+        }
+    }
+
+    if particles::is_active(data) {
+        particles::cmd_draw_particles(device, command_buffer, data, images);
+    }
+
+    if data.async_compute && data.particles_owner == data.graphics_family {
+        particles::cmd_release_for_simulation(device, command_buffer, data);
+    }
+
+    if data.anti_aliasing == antialiasing::AntiAliasing::Taa {
+        antialiasing::cmd_resolve_taa(device, command_buffer, data, images);
+    }
+
+    if data.auto_exposure.enabled {
+        exposure::cmd_update_exposure(device, command_buffer, data, delta);
+    }
+
+    if data.bloom.enabled {
+        bloom::cmd_draw_bloom(device, command_buffer, data, images);
+    }
 
-        let depth_clear_value = vk::ClearValue {
-            depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+    tonemap::cmd_tonemap(device, command_buffer, data, image_index, images);
+
+    if !data.post_process_steps.is_empty() {
+        postprocess::cmd_post_process(device, command_buffer, data, image_index, images);
+    }
+
+    if let (true, Some((frame, x, y))) = (data.picking, pick) {
+        picking::cmd_copy_id(device, command_buffer, data, images, frame, x, y);
+    }
+
+    let swapchain_image = data.swapchain_images[image_index];
+    images.cmd_use(device, command_buffer, swapchain_image, vk::ImageAspectFlags::COLOR, image_state::Usage::Present);
+
+    device.end_command_buffer(command_buffer)?;
+
+    Ok(())
+}
+
+/// Re-records the compute command buffer of a swapchain image, submitted to the compute queue
+/// before the graphics one when the device has a separate compute family. The buffers it
+/// writes are handed over to the graphics queue, which takes them in `update_command_buffer`.
+pub unsafe fn update_compute_command_buffer(device: &Device, data: &mut app_data::Data, image_index: usize) -> Result<()> {
+    let command_buffer = data.compute_command_buffers[image_index];
+
+    device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+
+    let info = vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    if data.particles_owner == data.graphics_family {
+        particles::cmd_acquire_for_simulation(device, command_buffer, data);
+        data.particles_owner = data.compute_family;
+    }
+
+    cmd_compute_passes(device, command_buffer, data, image_index);
+
+    device.end_command_buffer(command_buffer)?;
+
+    Ok(())
+}
+
+/// Simulates particles and culls the scene, on whichever queue compute work runs on.
+unsafe fn cmd_compute_passes(device: &Device, command_buffer: vk::CommandBuffer, data: &app_data::Data, image_index: usize) {
+    if particles::is_active(data) {
+        particles::cmd_simulate_particles(device, command_buffer, data);
+    }
+
+    if indirect::is_active(data) {
+        indirect::cmd_cull(device, command_buffer, data, image_index);
+    }
+}
+
+/// Leaves the lit HDR target, depth and velocity readable by the passes after shading, and the
+/// ID attachment ready to be copied from.
+unsafe fn cmd_finish_shading(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+) {
+    let color = vk::ImageAspectFlags::COLOR;
+    let depth = image_state::depth_aspect(data.depth_format);
+    let read = vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER;
+
+    images.transition(data.hdr_image, color, image_state::Usage::Sampled(read));
+    images.transition(data.depth_image, depth, image_state::Usage::DepthRead);
+    images.transition(data.velocity_image, color, image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER));
+    if data.picking {
+        images.transition(data.id_image, color, image_state::Usage::TransferSrc);
+    }
+    images.cmd_flush(device, command_buffer);
+}
+
+/// Draws every primitive with `pipeline`, which uses `data.pipeline_layout`, inside a begun
+/// render pass. GPU-driven frames draw the culled objects instead of looping over `draws`.
+unsafe fn cmd_draw_scene(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    image_index: usize,
+    pipeline: vk::Pipeline,
+    draws: &[scene::Draw],
+) {
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+    device.cmd_bind_vertex_buffers(command_buffer, 0, &[data.vertex_buffer], &[0]);
+    device.cmd_bind_index_buffer(command_buffer, data.index_buffer, 0, vk::IndexType::UINT32);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.pipeline_layout,
+        0,
+        &[data.descriptor_sets[image_index]],
+        &[],
+    );
+
+    if indirect::is_active(data) {
+        indirect::cmd_draw_objects(device, command_buffer, data);
+        return;
+    }
+
+    for draw in draws {
+        let push_constants = vertex_buffer::PushConstants {
+            model: draw.transform,
+            normal_matrix: vertex_buffer::PushConstants::normal_matrix(&draw.transform),
+            joint_offset: draw.joint_offset.map(|o| o as i32).unwrap_or(-1),
+            object_id: picking::object_id(draw.node),
+            indirect: 0,
         };
 
-        let clear_values = &[color_clear_value, depth_clear_value];
-        let info = vk::RenderPassBeginInfo::builder()
-            .render_pass(data.render_pass)
-            .framebuffer(data.framebuffers[i])
-            .render_area(render_area)
-            .clear_values(clear_values);
-
-        device.cmd_begin_render_pass(*command_buffer, &info, vk::SubpassContents::INLINE);
-        device.cmd_bind_pipeline(*command_buffer, vk::PipelineBindPoint::GRAPHICS, data.pipeline);
-        device.cmd_bind_vertex_buffers(*command_buffer, 0, &[data.vertex_buffer], &[0]);
-        device.cmd_bind_index_buffer(*command_buffer, data.index_buffer, 0, vk::IndexType::UINT16);
+        device.cmd_push_constants(
+            command_buffer,
+            data.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            0,
+            std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<vertex_buffer::PushConstants>()),
+        );
         device.cmd_bind_descriptor_sets(
-            *command_buffer,
+            command_buffer,
             vk::PipelineBindPoint::GRAPHICS,
             data.pipeline_layout,
-            0,
-            &[data.descriptor_sets[i]],
+            1,
+            &[material::descriptor_set(data, draw.material)],
             &[],
         );
-        device.cmd_draw_indexed(*command_buffer, indices_count, 1, 0, 0, 0);
-        device.cmd_end_render_pass(*command_buffer);
-
-        device.end_command_buffer(*command_buffer)?;
+        device.cmd_draw_indexed(command_buffer, draw.index_count, 1, draw.first_index, draw.vertex_offset, 0);
     }
-
-    Ok(())
 }
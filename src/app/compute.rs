@@ -0,0 +1,540 @@
+use super::app_data;
+use super::image;
+use super::image_state;
+use super::pipeline;
+use super::vertex_buffer;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+use std::ptr::copy_nonoverlapping as memcpy;
+use std::mem::{size_of, size_of_val};
+
+/// A resource bound to a compute kernel, at the binding of its position in the list.
+#[derive(Copy, Clone, Debug)]
+pub enum Binding {
+    UniformBuffer(vk::Buffer),
+    StorageBuffer(vk::Buffer),
+    /// An image in `GENERAL` layout.
+    StorageImage(vk::ImageView),
+    /// An image in `SHADER_READ_ONLY_OPTIMAL` layout, read through a combined image sampler.
+    SampledImage(vk::ImageView, vk::Sampler),
+}
+
+impl Binding {
+    fn descriptor_type(&self) -> vk::DescriptorType {
+        match self {
+            Self::UniformBuffer(_) => vk::DescriptorType::UNIFORM_BUFFER,
+            Self::StorageBuffer(_) => vk::DescriptorType::STORAGE_BUFFER,
+            Self::StorageImage(_) => vk::DescriptorType::STORAGE_IMAGE,
+            Self::SampledImage(_, _) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        }
+    }
+}
+
+/// A user resource bound to a kernel created through the app, by index into the buffers or
+/// images it created.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Resource {
+    Buffer(usize),
+    Image(usize),
+}
+
+/// A compute pipeline with a single descriptor set holding its bindings.
+#[derive(Clone, Debug, Default)]
+pub struct ComputeKernel {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    push_constant_size: u32,
+}
+
+/// A host-visible storage buffer, also usable as a vertex, index or indirect buffer.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ComputeBuffer {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    pub size: vk::DeviceSize,
+}
+
+/// A 2D storage image kept in `GENERAL` layout.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ComputeImage {
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+}
+
+/// A dispatch recorded at the start of every frame, before anything is drawn.
+#[derive(Clone, Debug)]
+pub struct ComputeJob {
+    pub kernel: usize,
+    pub push_constants: Vec<u8>,
+    pub group_count: [u32; 3],
+    pub enabled: bool,
+}
+
+impl ComputeJob {
+    pub fn new(kernel: usize, group_count: [u32; 3]) -> Self {
+        Self { kernel, push_constants: vec![], group_count, enabled: true }
+    }
+
+    pub fn with_push_constants<T: Copy>(self, push_constants: &T) -> Self {
+        let bytes = unsafe { std::slice::from_raw_parts(push_constants as *const _ as *const u8, size_of::<T>()) };
+        Self { push_constants: bytes.to_vec(), ..self }
+    }
+}
+
+/// Workgroups needed to cover `size` invocations with groups of `workgroup_size`.
+pub fn group_count(size: u32, workgroup_size: u32) -> u32 {
+    size.div_ceil(workgroup_size)
+}
+
+pub unsafe fn create_kernel(device: &Device, comp: &[u8], bindings: &[Binding], push_constant_size: u32) -> Result<ComputeKernel> {
+    // Descriptor Set Layout
+
+    let layout_bindings = bindings.iter().enumerate()
+        .map(|(i, b)| vk::DescriptorSetLayoutBinding::builder()
+            .binding(i as u32)
+            .descriptor_type(b.descriptor_type())
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build())
+        .collect::<Vec<_>>();
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&layout_bindings);
+
+    let descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    // Descriptor Pool
+
+    let pool_sizes = bindings.iter()
+        .map(|b| vk::DescriptorPoolSize::builder()
+            .type_(b.descriptor_type())
+            .descriptor_count(1)
+            .build())
+        .collect::<Vec<_>>();
+
+    // An empty pool is invalid, so kernels without bindings still reserve a storage buffer.
+    let pool_sizes = if pool_sizes.is_empty() {
+        vec![vk::DescriptorPoolSize::builder().type_(vk::DescriptorType::STORAGE_BUFFER).descriptor_count(1).build()]
+    } else {
+        pool_sizes
+    };
+
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(1);
+
+    let descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    // Descriptor Set
+
+    let layouts = &[descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(layouts);
+
+    let descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let buffer_infos = bindings.iter()
+        .map(|b| match b {
+            Binding::UniformBuffer(buffer) | Binding::StorageBuffer(buffer) => [vk::DescriptorBufferInfo::builder()
+                .buffer(*buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE as u64)
+                .build()],
+            _ => [vk::DescriptorBufferInfo::default()],
+        })
+        .collect::<Vec<_>>();
+
+    let image_infos = bindings.iter()
+        .map(|b| match b {
+            Binding::StorageImage(view) => [vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::GENERAL)
+                .image_view(*view)
+                .build()],
+            Binding::SampledImage(view, sampler) => [vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(*view)
+                .sampler(*sampler)
+                .build()],
+            _ => [vk::DescriptorImageInfo::default()],
+        })
+        .collect::<Vec<_>>();
+
+    let writes = bindings.iter().enumerate()
+        .map(|(i, b)| {
+            let write = vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(i as u32)
+                .dst_array_element(0)
+                .descriptor_type(b.descriptor_type());
+            match b {
+                Binding::UniformBuffer(_) | Binding::StorageBuffer(_) => write.buffer_info(&buffer_infos[i]).build(),
+                _ => write.image_info(&image_infos[i]).build(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+    // Pipeline
+
+    let push_constant_ranges = if push_constant_size > 0 {
+        vec![vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(push_constant_size)
+            .build()]
+    } else {
+        vec![]
+    };
+
+    let info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(layouts)
+        .push_constant_ranges(&push_constant_ranges);
+
+    let pipeline_layout = device.create_pipeline_layout(&info, None)?;
+    let pipeline = pipeline::create_compute_pipeline(device, comp, pipeline_layout)?;
+
+    Ok(ComputeKernel {
+        pipeline,
+        pipeline_layout,
+        descriptor_set_layout,
+        descriptor_pool,
+        descriptor_set,
+        push_constant_size,
+    })
+}
+
+pub unsafe fn destroy_kernel(device: &Device, kernel: &ComputeKernel) {
+    device.destroy_pipeline(kernel.pipeline, None);
+    device.destroy_pipeline_layout(kernel.pipeline_layout, None);
+    device.destroy_descriptor_pool(kernel.descriptor_pool, None);
+    device.destroy_descriptor_set_layout(kernel.descriptor_set_layout, None);
+}
+
+/// Queue families that resources used by both queues are shared between, so standalone and
+/// per-frame dispatches need no ownership transfers. Empty when compute runs on the graphics
+/// family.
+pub fn shared_families(data: &app_data::Data) -> Vec<u32> {
+    if data.async_compute {
+        vec![data.graphics_family, data.compute_family]
+    } else {
+        vec![]
+    }
+}
+
+pub unsafe fn create_compute_buffer(instance: &Instance, device: &Device, data: &app_data::Data, size: vk::DeviceSize) -> Result<ComputeBuffer> {
+    let (buffer, memory) = vertex_buffer::create_shared_buffer(
+        instance,
+        device,
+        data,
+        &shared_families(data),
+        size,
+        vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::VERTEX_BUFFER
+            | vk::BufferUsageFlags::INDEX_BUFFER
+            | vk::BufferUsageFlags::INDIRECT_BUFFER
+            | vk::BufferUsageFlags::TRANSFER_SRC
+            | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    Ok(ComputeBuffer { buffer, memory, size })
+}
+
+/// Copies `values` to the start of `buffer`, which must not be in use by the GPU.
+pub unsafe fn write_compute_buffer<T: Copy>(device: &Device, buffer: &ComputeBuffer, values: &[T]) -> Result<()> {
+    let size = size_of_val(values) as u64;
+    if size > buffer.size {
+        return Err(anyhow!("Writing {} bytes into a compute buffer of {} bytes!", size, buffer.size));
+    }
+
+    let memory = device.map_memory(buffer.memory, 0, size, vk::MemoryMapFlags::empty())?;
+    memcpy(values.as_ptr(), memory.cast(), values.len());
+    device.unmap_memory(buffer.memory);
+
+    Ok(())
+}
+
+/// Reads `count` values from the start of `buffer`, which must not be in use by the GPU.
+pub unsafe fn read_compute_buffer<T: Copy>(device: &Device, buffer: &ComputeBuffer, count: usize) -> Result<Vec<T>> {
+    let size = (size_of::<T>() * count) as u64;
+    if size > buffer.size {
+        return Err(anyhow!("Reading {} bytes from a compute buffer of {} bytes!", size, buffer.size));
+    }
+
+    let mut values = Vec::with_capacity(count);
+    let memory = device.map_memory(buffer.memory, 0, size, vk::MemoryMapFlags::empty())?;
+    memcpy(memory.cast(), values.as_mut_ptr(), count);
+    values.set_len(count);
+    device.unmap_memory(buffer.memory);
+
+    Ok(values)
+}
+
+pub unsafe fn destroy_compute_buffer(device: &Device, buffer: &ComputeBuffer) {
+    device.destroy_buffer(buffer.buffer, None);
+    device.free_memory(buffer.memory, None);
+}
+
+pub unsafe fn create_compute_image(
+    instance: &Instance,
+    device: &Device,
+    data: &mut app_data::Data,
+    width: u32,
+    height: u32,
+    format: vk::Format,
+) -> Result<ComputeImage> {
+    let (compute_image, memory) = image::create_shared_image(
+        instance,
+        device,
+        data,
+        &shared_families(data),
+        width,
+        height,
+        1,
+        vk::ImageCreateFlags::empty(),
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    image::transition_image_layout(device, data, compute_image, image_state::Usage::Storage)?;
+
+    let view = image::create_image_view(device, compute_image, format, vk::ImageAspectFlags::COLOR)?;
+
+    Ok(ComputeImage { image: compute_image, memory, view })
+}
+
+pub unsafe fn destroy_compute_image(device: &Device, image: &ComputeImage) {
+    device.destroy_image_view(image.view, None);
+    device.free_memory(image.memory, None);
+    device.destroy_image(image.image, None);
+}
+
+/// Binds `kernel` and dispatches `group_count` workgroups. `push_constants` must be as large
+/// as the range the kernel was created with.
+pub unsafe fn cmd_dispatch(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    kernel: &ComputeKernel,
+    push_constants: &[u8],
+    group_count: [u32; 3],
+) {
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, kernel.pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        kernel.pipeline_layout,
+        0,
+        &[kernel.descriptor_set],
+        &[],
+    );
+
+    if kernel.push_constant_size > 0 {
+        device.cmd_push_constants(
+            command_buffer,
+            kernel.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &push_constants[..kernel.push_constant_size as usize],
+        );
+    }
+
+    device.cmd_dispatch(command_buffer, group_count[0], group_count[1], group_count[2]);
+}
+
+/// A global memory barrier, given source and destination stages and access masks.
+pub unsafe fn cmd_memory_barrier(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    stages: (vk::PipelineStageFlags, vk::PipelineStageFlags),
+    access: (vk::AccessFlags, vk::AccessFlags),
+) {
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(access.0)
+        .dst_access_mask(access.1);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        stages.0,
+        stages.1,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
+
+/// Makes compute shader writes visible to everything the graphics pipeline reads them as:
+/// indirect commands, vertices, indices, uniforms and shader reads.
+pub unsafe fn cmd_compute_to_graphics_barrier(device: &Device, command_buffer: vk::CommandBuffer) {
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(
+            vk::AccessFlags::INDIRECT_COMMAND_READ
+                | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                | vk::AccessFlags::INDEX_READ
+                | vk::AccessFlags::UNIFORM_READ
+                | vk::AccessFlags::SHADER_READ,
+        );
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::DRAW_INDIRECT
+            | vk::PipelineStageFlags::VERTEX_INPUT
+            | vk::PipelineStageFlags::VERTEX_SHADER
+            | vk::PipelineStageFlags::FRAGMENT_SHADER
+            | vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
+
+/// Makes the previous frame's graphics and compute work finish reading and writing shared
+/// resources before compute shaders write them again.
+pub unsafe fn cmd_graphics_to_compute_barrier(device: &Device, command_buffer: vk::CommandBuffer) {
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::DRAW_INDIRECT
+            | vk::PipelineStageFlags::VERTEX_INPUT
+            | vk::PipelineStageFlags::VERTEX_SHADER
+            | vk::PipelineStageFlags::FRAGMENT_SHADER
+            | vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
+
+/// Releases `buffers` from the queue family `families.0` to `families.1`, after their last use
+/// in `src`. Must be matched by `cmd_acquire_buffers` on the other queue.
+pub unsafe fn cmd_release_buffers(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    buffers: &[vk::Buffer],
+    families: (u32, u32),
+    src: (vk::PipelineStageFlags, vk::AccessFlags),
+) {
+    let barriers = ownership_barriers(buffers, families, (src.1, vk::AccessFlags::empty()));
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src.0,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &barriers,
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
+
+/// Acquires `buffers` released by `cmd_release_buffers` for their first use in `dst`. The
+/// submission must wait on a semaphore signaled after the release, in `dst.0`.
+pub unsafe fn cmd_acquire_buffers(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    buffers: &[vk::Buffer],
+    families: (u32, u32),
+    dst: (vk::PipelineStageFlags, vk::AccessFlags),
+) {
+    let barriers = ownership_barriers(buffers, families, (vk::AccessFlags::empty(), dst.1));
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        dst.0,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &barriers,
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
+
+fn ownership_barriers(buffers: &[vk::Buffer], families: (u32, u32), access: (vk::AccessFlags, vk::AccessFlags)) -> Vec<vk::BufferMemoryBarrier> {
+    buffers.iter()
+        .map(|b| vk::BufferMemoryBarrier::builder()
+            .src_access_mask(access.0)
+            .dst_access_mask(access.1)
+            .src_queue_family_index(families.0)
+            .dst_queue_family_index(families.1)
+            .buffer(*b)
+            .offset(0)
+            .size(vk::WHOLE_SIZE as u64)
+            .build())
+        .collect()
+}
+
+/// Records the enabled per-frame jobs, fenced off from the graphics work on either side.
+pub unsafe fn cmd_run_jobs(device: &Device, command_buffer: vk::CommandBuffer, data: &app_data::Data) {
+    cmd_graphics_to_compute_barrier(device, command_buffer);
+
+    for job in data.compute_jobs.iter().filter(|j| j.enabled) {
+        cmd_dispatch(device, command_buffer, &data.compute_kernels[job.kernel], &job.push_constants, job.group_count);
+    }
+
+    cmd_compute_to_graphics_barrier(device, command_buffer);
+}
+
+/// Records commands with `f` and runs them on the compute queue, returning once they are done
+/// and their writes are visible to the host.
+pub unsafe fn run_standalone<F>(device: &Device, data: &app_data::Data, f: F) -> Result<()>
+where
+    F: FnOnce(vk::CommandBuffer),
+{
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_pool(data.compute_command_pool)
+        .command_buffer_count(1);
+
+    let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+    let info = vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    f(command_buffer);
+
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    device.end_command_buffer(command_buffer)?;
+
+    let fence = device.create_fence(&vk::FenceCreateInfo::builder(), None)?;
+
+    let command_buffers = &[command_buffer];
+    let info = vk::SubmitInfo::builder().command_buffers(command_buffers);
+
+    device.queue_submit(data.compute_queue, &[info], fence)?;
+    device.wait_for_fences(&[fence], true, u64::MAX)?;
+
+    device.destroy_fence(fence, None);
+    device.free_command_buffers(data.compute_command_pool, &[command_buffer]);
+
+    Ok(())
+}
@@ -0,0 +1,334 @@
+use super::app_data;
+use super::image;
+use super::image_state;
+use super::motion;
+use super::picking;
+use super::pipeline;
+use super::render_pass;
+use super::swapchain;
+use super::tonemap;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RenderPath {
+    /// Every light is evaluated while drawing each primitive.
+    #[default]
+    Forward,
+    /// Primitives fill a G-buffer that a full-screen pass then lights.
+    Deferred,
+}
+
+/// Albedo with occlusion in alpha, world-space normal, metallic-roughness and emissive.
+const GBUFFER_FORMATS: [vk::Format; 4] = [
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+];
+
+/// Creates the G-buffer, the geometry and lighting passes and their pipelines. Everything is
+/// sized to the swapchain, so this runs again on resize.
+pub unsafe fn create_deferred_objects(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let extent = data.swapchain_extent;
+    let depth_format = swapchain::get_sampled_depth_format(instance, data)?;
+
+    // Images
+
+    for format in GBUFFER_FORMATS {
+        let (gbuffer_image, gbuffer_image_memory) = image::create_image(
+            instance,
+            device,
+            data,
+            extent.width,
+            extent.height,
+            1,
+            vk::ImageCreateFlags::empty(),
+            format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        data.gbuffer_images.push(gbuffer_image);
+        data.gbuffer_images_memory.push(gbuffer_image_memory);
+        data.gbuffer_image_views.push(image::create_image_view(device, gbuffer_image, format, vk::ImageAspectFlags::COLOR)?);
+    }
+
+    create_geometry_pass(device, data, depth_format)?;
+    create_lighting_pass(device, data)?;
+
+    // Framebuffers
+
+    let mut attachments = data.gbuffer_image_views.clone();
+    attachments.push(data.depth_image_view);
+    attachments.push(data.velocity_image_view);
+    if data.picking {
+        attachments.push(data.id_image_view);
+    }
+
+    data.gbuffer_framebuffer = render_pass::create_framebuffer(device, data.gbuffer_render_pass, &attachments, extent)?;
+
+    data.lighting_framebuffers = data.swapchain_image_views.iter()
+        .map(|_| render_pass::create_framebuffer(device, data.lighting_render_pass, &[data.hdr_image_view], extent))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Pipelines
+
+    let frag = include_bytes!("../../shaders/gbuffer.bin");
+    let attachments = geometry_attachments(data, depth_format);
+    data.gbuffer_pipeline = pipeline::create_scene_pipeline(device, data, &frag[..], data.gbuffer_render_pass, &attachments)?;
+
+    create_gbuffer_descriptor_set(device, data)?;
+    create_lighting_pipeline(device, data)?;
+
+    Ok(())
+}
+
+/// Writes the G-buffer, depth and velocity, plus object IDs when picking.
+unsafe fn create_geometry_pass(device: &Device, data: &mut app_data::Data, depth_format: vk::Format) -> Result<()> {
+    data.gbuffer_render_pass = render_pass::create_unless_dynamic(device, data, &geometry_attachments(data, depth_format))?;
+
+    Ok(())
+}
+
+/// Shades the G-buffer into the HDR target.
+unsafe fn create_lighting_pass(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    data.lighting_render_pass = render_pass::create_unless_dynamic(device, data, &[lighting_attachment()])?;
+
+    Ok(())
+}
+
+/// Attachments of the geometry pass: the G-buffer, depth, velocity, then object IDs when
+/// picking.
+fn geometry_attachments(data: &app_data::Data, depth_format: vk::Format) -> Vec<render_pass::Attachment> {
+    let clear = vk::AttachmentLoadOp::CLEAR;
+
+    let mut attachments = GBUFFER_FORMATS.iter()
+        .map(|f| render_pass::Attachment::color(*f, clear))
+        .collect::<Vec<_>>();
+
+    attachments.push(render_pass::Attachment::depth(depth_format, clear));
+    attachments.push(motion::velocity_attachment());
+    if data.picking {
+        attachments.push(render_pass::Attachment::color(picking::ID_FORMAT, clear));
+    }
+
+    attachments
+}
+
+fn lighting_attachment() -> render_pass::Attachment {
+    render_pass::Attachment::color(tonemap::HDR_FORMAT, vk::AttachmentLoadOp::CLEAR)
+}
+
+/// Set 1 of the lighting pass: the G-buffer, depth and a nearest sampler. One set serves every
+/// frame since the G-buffer is shared like the depth buffer.
+unsafe fn create_gbuffer_descriptor_set(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    // Sampler
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::NEAREST)
+        .min_filter(vk::Filter::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.gbuffer_sampler = device.create_sampler(&info, None)?;
+
+    // Layout
+
+    let image_count = GBUFFER_FORMATS.len() as u32 + 1;
+    let mut bindings = (0..image_count)
+        .map(|b| vk::DescriptorSetLayoutBinding::builder()
+            .binding(b)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build())
+        .collect::<Vec<_>>();
+
+    bindings.push(vk::DescriptorSetLayoutBinding::builder()
+        .binding(image_count)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .build());
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    data.gbuffer_descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    // Pool
+
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(image_count);
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1);
+
+    let pool_sizes = &[image_size, sampler_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+
+    data.gbuffer_descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let layouts = &[data.gbuffer_descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder().descriptor_pool(data.gbuffer_descriptor_pool).set_layouts(layouts);
+
+    data.gbuffer_descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    // Update
+
+    let views = data.gbuffer_image_views.iter()
+        .map(|v| (*v, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL))
+        .chain([(data.depth_image_view, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)]);
+
+    let image_infos = views
+        .map(|(view, layout)| [vk::DescriptorImageInfo::builder().image_layout(layout).image_view(view).build()])
+        .collect::<Vec<_>>();
+
+    let mut writes = (0..).zip(&image_infos)
+        .map(|(binding, image_info)| vk::WriteDescriptorSet::builder()
+            .dst_set(data.gbuffer_descriptor_set)
+            .dst_binding(binding)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(image_info)
+            .build())
+        .collect::<Vec<_>>();
+
+    let sampler_info = &[vk::DescriptorImageInfo::builder().sampler(data.gbuffer_sampler)];
+    writes.push(vk::WriteDescriptorSet::builder()
+        .dst_set(data.gbuffer_descriptor_set)
+        .dst_binding(image_count)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .image_info(sampler_info)
+        .build());
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+    Ok(())
+}
+
+/// A full-screen triangle lighting every pixel with geometry in the G-buffer.
+unsafe fn create_lighting_pipeline(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let set_layouts = &[data.descriptor_set_layout, data.gbuffer_descriptor_set_layout];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(set_layouts);
+
+    data.lighting_pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    let vert = include_bytes!("../../shaders/deferred_vert.bin");
+    let frag = include_bytes!("../../shaders/deferred_frag.bin");
+    data.lighting_pipeline = pipeline::create_fullscreen_pipeline(
+        device,
+        data.swapchain_extent,
+        &vert[..],
+        &frag[..],
+        data.lighting_pipeline_layout,
+        data.lighting_render_pass,
+        tonemap::HDR_FORMAT,
+        false,
+    )?;
+
+    Ok(())
+}
+
+/// Destroys what `create_deferred_objects` created and resets the handles, since the objects
+/// only exist while the deferred path is selected.
+pub unsafe fn destroy_deferred_objects(device: &Device, data: &mut app_data::Data) {
+    device.destroy_pipeline(data.lighting_pipeline, None);
+    device.destroy_pipeline_layout(data.lighting_pipeline_layout, None);
+    device.destroy_descriptor_pool(data.gbuffer_descriptor_pool, None);
+    device.destroy_descriptor_set_layout(data.gbuffer_descriptor_set_layout, None);
+    device.destroy_sampler(data.gbuffer_sampler, None);
+    device.destroy_pipeline(data.gbuffer_pipeline, None);
+    data.lighting_framebuffers.drain(..).for_each(|f| device.destroy_framebuffer(f, None));
+    device.destroy_framebuffer(data.gbuffer_framebuffer, None);
+    device.destroy_render_pass(data.lighting_render_pass, None);
+    device.destroy_render_pass(data.gbuffer_render_pass, None);
+    data.gbuffer_image_views.drain(..).for_each(|v| device.destroy_image_view(v, None));
+    data.gbuffer_images_memory.drain(..).for_each(|m| device.free_memory(m, None));
+    data.gbuffer_images.drain(..).for_each(|i| device.destroy_image(i, None));
+
+    data.lighting_pipeline = vk::Pipeline::null();
+    data.lighting_pipeline_layout = vk::PipelineLayout::null();
+    data.gbuffer_descriptor_pool = vk::DescriptorPool::null();
+    data.gbuffer_descriptor_set_layout = vk::DescriptorSetLayout::null();
+    data.gbuffer_descriptor_set = vk::DescriptorSet::null();
+    data.gbuffer_sampler = vk::Sampler::null();
+    data.gbuffer_pipeline = vk::Pipeline::null();
+    data.gbuffer_framebuffer = vk::Framebuffer::null();
+    data.lighting_render_pass = vk::RenderPass::null();
+    data.gbuffer_render_pass = vk::RenderPass::null();
+}
+
+/// Begins the geometry pass, clearing the G-buffer, depth, velocity and object IDs.
+pub unsafe fn cmd_begin_geometry_pass(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+) {
+    let mut views = data.gbuffer_images.iter().copied().zip(data.gbuffer_image_views.iter().copied()).collect::<Vec<_>>();
+    views.push((data.depth_image, data.depth_image_view));
+    views.push((data.velocity_image, data.velocity_image_view));
+    views.push((data.id_image, data.id_image_view));
+
+    let targets = render_pass::targets(&geometry_attachments(data, data.depth_format), &views);
+    let render_pass = data.gbuffer_render_pass;
+    let framebuffer = data.gbuffer_framebuffer;
+    render_pass::cmd_begin(device, command_buffer, data, images, render_pass, framebuffer, data.swapchain_extent, &targets);
+}
+
+/// Ends the geometry pass, leaving the G-buffer and depth readable by the lighting pass.
+pub unsafe fn cmd_end_geometry_pass(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+) {
+    render_pass::cmd_end(device, command_buffer, data, data.gbuffer_render_pass);
+
+    let sampled = image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER);
+    for image in &data.gbuffer_images {
+        images.transition(*image, vk::ImageAspectFlags::COLOR, sampled);
+    }
+    images.transition(data.depth_image, image_state::depth_aspect(data.depth_format), image_state::Usage::DepthRead);
+    images.cmd_flush(device, command_buffer);
+}
+
+/// Lights every pixel of the G-buffer into the HDR target.
+pub unsafe fn cmd_draw_lighting(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+    image_index: usize,
+) {
+    let target = lighting_attachment().target(data.hdr_image, data.hdr_image_view);
+    let render_pass = data.lighting_render_pass;
+    let framebuffer = data.lighting_framebuffers[image_index];
+    render_pass::cmd_begin(device, command_buffer, data, images, render_pass, framebuffer, data.swapchain_extent, &[target]);
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.lighting_pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.lighting_pipeline_layout,
+        0,
+        &[data.descriptor_sets[image_index], data.gbuffer_descriptor_set],
+        &[],
+    );
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+    render_pass::cmd_end(device, command_buffer, data, render_pass);
+}
@@ -1,25 +1,127 @@
 use super::app_data;
+use super::app_defines;
+use super::indirect;
+use super::lighting;
+use super::motion;
+use super::shadow;
 use super::vertex_buffer;
 
 use anyhow::{Result};
 use vulkanalia::prelude::v1_0::*;
 use std::mem::size_of;
+use nalgebra_glm as glm;
+
+/// First of the `MAX_POINT_SHADOWS` cube shadow map bindings.
+const POINT_SHADOW_MAP_BINDING: u32 = 7;
+
+const POINT_SHADOW_SAMPLER_BINDING: u32 = POINT_SHADOW_MAP_BINDING + app_defines::MAX_POINT_SHADOWS as u32;
+
+const AMBIENT_OCCLUSION_BINDING: u32 = POINT_SHADOW_SAMPLER_BINDING + 1;
+
+const AMBIENT_OCCLUSION_SAMPLER_BINDING: u32 = AMBIENT_OCCLUSION_BINDING + 1;
+
+/// First of the environment, irradiance, prefiltered and BRDF lookup bindings.
+const ENVIRONMENT_MAP_BINDING: u32 = AMBIENT_OCCLUSION_SAMPLER_BINDING + 1;
+
+const ENVIRONMENT_SAMPLER_BINDING: u32 = ENVIRONMENT_MAP_BINDING + 4;
+
+/// Per-object data read by GPU-driven draws.
+const OBJECT_BUFFER_BINDING: u32 = ENVIRONMENT_SAMPLER_BINDING + 1;
+
+const TRANSFORM_BUFFER_BINDING: u32 = OBJECT_BUFFER_BINDING + 1;
+
+/// The previous frame's joint matrices and node transforms, for motion vectors.
+const PREVIOUS_FRAME_BUFFER_BINDING: u32 = TRANSFORM_BUFFER_BINDING + 1;
 
 pub unsafe fn create_descriptor_set_layout(device: &Device, data: &mut app_data::Data) -> Result<()> {
     let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
     .binding(0)
     .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
     .descriptor_count(1)
-    .stage_flags(vk::ShaderStageFlags::VERTEX);
+    .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+
+    let joints_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(2)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::VERTEX);
+
+    let lights_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(3)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let shadows_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(4)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+
+    let shadow_map_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(5)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
 
-    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
-        .binding(1)
-        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+    let shadow_sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(6)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
         .descriptor_count(1)
         .stage_flags(vk::ShaderStageFlags::FRAGMENT);
 
-    let bindings = &[ubo_binding, sampler_binding];
-    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    let mut bindings = vec![ubo_binding, joints_binding, lights_binding, shadows_binding, shadow_map_binding, shadow_sampler_binding];
+
+    // One binding per point light cube map, since cube map arrays are an optional feature.
+    for slot in 0..app_defines::MAX_POINT_SHADOWS as u32 {
+        bindings.push(vk::DescriptorSetLayoutBinding::builder()
+            .binding(POINT_SHADOW_MAP_BINDING + slot)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT));
+    }
+
+    bindings.push(vk::DescriptorSetLayoutBinding::builder()
+        .binding(POINT_SHADOW_SAMPLER_BINDING)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT));
+
+    bindings.push(vk::DescriptorSetLayoutBinding::builder()
+        .binding(AMBIENT_OCCLUSION_BINDING)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT));
+
+    bindings.push(vk::DescriptorSetLayoutBinding::builder()
+        .binding(AMBIENT_OCCLUSION_SAMPLER_BINDING)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT));
+
+    for binding in ENVIRONMENT_MAP_BINDING..ENVIRONMENT_SAMPLER_BINDING {
+        bindings.push(vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT));
+    }
+
+    bindings.push(vk::DescriptorSetLayoutBinding::builder()
+        .binding(ENVIRONMENT_SAMPLER_BINDING)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT));
+
+    for binding in [OBJECT_BUFFER_BINDING, TRANSFORM_BUFFER_BINDING, PREVIOUS_FRAME_BUFFER_BINDING] {
+        bindings.push(vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX));
+    }
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
 
     data.descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
 
@@ -50,7 +152,205 @@ pub unsafe fn create_descriptor_sets(device: &Device, data: &mut app_data::Data)
             .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
             .buffer_info(buffer_info);
 
-        device.update_descriptor_sets(&[ubo_write], &[] as &[vk::CopyDescriptorSet]);
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(data.joint_buffers[i])
+            .offset(0)
+            .range((size_of::<glm::Mat4>() * app_defines::MAX_JOINTS) as u64);
+
+        let buffer_info = &[info];
+        let joints_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(2)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(buffer_info);
+
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(data.light_buffers[i])
+            .offset(0)
+            .range(size_of::<lighting::LightsUniform>() as u64);
+
+        let buffer_info = &[info];
+        let lights_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(3)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(buffer_info);
+
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(data.shadow_buffers[i])
+            .offset(0)
+            .range(size_of::<shadow::ShadowUniform>() as u64);
+
+        let buffer_info = &[info];
+        let shadows_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(4)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(buffer_info);
+
+        let info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .image_view(data.shadow_image_view);
+
+        let image_info = &[info];
+        let shadow_map_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(5)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(image_info);
+
+        let info = vk::DescriptorImageInfo::builder().sampler(data.shadow_sampler);
+
+        let sampler_info = &[info];
+        let shadow_sampler_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(6)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(sampler_info);
+
+        let cube_infos = data.point_shadow_cube_views.iter()
+            .map(|v| [vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+                .image_view(*v)
+                .build()])
+            .collect::<Vec<_>>();
+
+        let info = vk::DescriptorImageInfo::builder().sampler(data.point_shadow_sampler);
+
+        let point_sampler_info = &[info];
+        let point_sampler_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(POINT_SHADOW_SAMPLER_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(point_sampler_info);
+
+        let info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(data.ssao_image_view);
+
+        let ao_info = &[info];
+        let ao_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(AMBIENT_OCCLUSION_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(ao_info);
+
+        let info = vk::DescriptorImageInfo::builder().sampler(data.ssao_sampler);
+
+        let ao_sampler_info = &[info];
+        let ao_sampler_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(AMBIENT_OCCLUSION_SAMPLER_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(ao_sampler_info);
+
+        let environment_infos = [
+            data.environment_image_view,
+            data.irradiance_image_view,
+            data.prefiltered_image_view,
+            data.brdf_lut_image_view,
+        ]
+        .iter()
+        .map(|v| [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(*v)
+            .build()])
+        .collect::<Vec<_>>();
+
+        let info = vk::DescriptorImageInfo::builder().sampler(data.environment_sampler);
+
+        let environment_sampler_info = &[info];
+        let environment_sampler_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(ENVIRONMENT_SAMPLER_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(environment_sampler_info);
+
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(data.object_buffer)
+            .offset(0)
+            .range(indirect::OBJECT_BUFFER_SIZE);
+
+        let buffer_info = &[info];
+        let objects_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(OBJECT_BUFFER_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(buffer_info);
+
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(data.transform_buffer)
+            .offset(0)
+            .range(indirect::TRANSFORM_BUFFER_SIZE);
+
+        let buffer_info = &[info];
+        let transforms_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(TRANSFORM_BUFFER_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(buffer_info);
+
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(data.previous_frame_buffers[i])
+            .offset(0)
+            .range(motion::PREVIOUS_FRAME_BUFFER_SIZE);
+
+        let buffer_info = &[info];
+        let previous_frame_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(PREVIOUS_FRAME_BUFFER_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(buffer_info);
+
+        let mut writes = vec![
+            ubo_write.build(),
+            joints_write.build(),
+            lights_write.build(),
+            shadows_write.build(),
+            shadow_map_write.build(),
+            shadow_sampler_write.build(),
+            point_sampler_write.build(),
+            ao_write.build(),
+            ao_sampler_write.build(),
+            environment_sampler_write.build(),
+            objects_write.build(),
+            transforms_write.build(),
+            previous_frame_write.build(),
+        ];
+
+        for (binding, image_info) in (POINT_SHADOW_MAP_BINDING..).zip(&cube_infos) {
+            writes.push(vk::WriteDescriptorSet::builder()
+                .dst_set(data.descriptor_sets[i])
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(image_info)
+                .build());
+        }
+
+        for (binding, image_info) in (ENVIRONMENT_MAP_BINDING..).zip(&environment_infos) {
+            writes.push(vk::WriteDescriptorSet::builder()
+                .dst_set(data.descriptor_sets[i])
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(image_info)
+                .build());
+        }
+
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
     }
 
     Ok(())
@@ -59,9 +359,21 @@ pub unsafe fn create_descriptor_sets(device: &Device, data: &mut app_data::Data)
 pub unsafe fn create_descriptor_pool(device: &Device, data: &mut app_data::Data) -> Result<()> {
     let ubo_size = vk::DescriptorPoolSize::builder()
         .type_(vk::DescriptorType::UNIFORM_BUFFER)
-        .descriptor_count(data.swapchain_images.len() as u32);
+        .descriptor_count(data.swapchain_images.len() as u32 * 4);
+
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(data.swapchain_images.len() as u32 * (6 + app_defines::MAX_POINT_SHADOWS as u32));
+
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLER)
+        .descriptor_count(data.swapchain_images.len() as u32 * 4);
+
+    let storage_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(data.swapchain_images.len() as u32 * 3);
 
-    let pool_sizes = &[ubo_size];
+    let pool_sizes = &[ubo_size, image_size, sampler_size, storage_size];
     let info = vk::DescriptorPoolCreateInfo::builder()
         .pool_sizes(pool_sizes)
         .max_sets(data.swapchain_images.len() as u32);
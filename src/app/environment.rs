@@ -0,0 +1,1054 @@
+use super::app_data;
+use super::image;
+use super::image_state;
+use super::pipeline;
+use super::render_pass;
+use super::sync;
+use super::tonemap;
+use super::vertex_buffer;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+use nalgebra_glm as glm;
+use std::fs;
+use std::mem::size_of;
+use std::path::Path;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+/// Edge of the sky cube map's base level, which is mipmapped down to 1×1.
+const ENVIRONMENT_SIZE: u32 = 512;
+
+const IRRADIANCE_SIZE: u32 = 32;
+
+/// Edge of the prefiltered map's base level.
+const PREFILTERED_SIZE: u32 = 128;
+
+/// Levels in the prefiltered map; level `n` is filtered for roughness `n / (levels - 1)`.
+/// Matches `MAX_REFLECTION_LOD + 1` in the lighting shaders.
+const PREFILTERED_LEVELS: u32 = 5;
+
+const PREFILTER_SAMPLES: u32 = 512;
+
+const BRDF_LUT_SIZE: u32 = 256;
+
+const BRDF_LUT_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+
+const BRDF_LUT_SAMPLES: u32 = 1024;
+
+/// Direction the baked sun's light travels, matching the default directional light.
+const SUN_DIRECTION: [f32; 3] = [-1.0, -0.5, -2.0];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Environment {
+    /// Draws the sky behind the scene and lights materials with it; otherwise the background
+    /// is black and only the lights' ambient terms remain.
+    pub enabled: bool,
+    /// Scale of the diffuse and specular light taken from the environment.
+    pub intensity: f32,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self { enabled: true, intensity: 1.0 }
+    }
+}
+
+/// Linear radiance with four floats per texel, the last unused.
+#[derive(Clone, Debug)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<f32>,
+}
+
+impl HdrImage {
+    /// Reads a Radiance `.hdr` file with flat or run-length encoded scanlines, stored top to
+    /// bottom.
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        Self::parse(&bytes).map_err(|e| anyhow!("`{}`: {}", path.display(), e))
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        // Header
+
+        let mut lines = bytes.split(|b| *b == b'\n');
+        let mut offset = 0;
+        let mut line = |offset: &mut usize| {
+            let line = lines.next().unwrap_or_default();
+            *offset += line.len() + 1;
+            String::from_utf8_lossy(line).into_owned()
+        };
+
+        if !line(&mut offset).starts_with("#?") {
+            return Err(anyhow!("Not a Radiance HDR image."));
+        }
+
+        loop {
+            let header = line(&mut offset);
+            if header.is_empty() {
+                break;
+            } else if header.starts_with("FORMAT=") && header != "FORMAT=32-bit_rle_rgbe" {
+                return Err(anyhow!("Unsupported pixel format `{}`.", &header[7..]));
+            }
+        }
+
+        let resolution = line(&mut offset);
+        let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (height.parse::<u32>()?, width.parse::<u32>()?),
+            _ => return Err(anyhow!("Unsupported orientation `{}`.", resolution)),
+        };
+
+        // Scanlines
+
+        let mut data = bytes.get(offset..).unwrap_or_default().iter().copied();
+        let mut next = || data.next().ok_or_else(|| anyhow!("The pixel data is truncated."));
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        let mut scanline = vec![[0u8; 4]; width as usize];
+
+        for _ in 0..height {
+            let start = [next()?, next()?, next()?, next()?];
+            let encoded = (8..0x8000).contains(&width)
+                && start[0] == 2
+                && start[1] == 2
+                && ((start[2] as u32) << 8 | start[3] as u32) == width;
+
+            if encoded {
+                for channel in 0..4 {
+                    let mut x = 0;
+                    while x < scanline.len() {
+                        let count = next()? as usize;
+                        let (count, run) = if count > 128 { (count - 128, Some(next()?)) } else { (count, None) };
+                        if count == 0 || x + count > scanline.len() {
+                            return Err(anyhow!("A scanline overruns the image."));
+                        }
+
+                        for texel in &mut scanline[x..x + count] {
+                            texel[channel] = match run {
+                                Some(value) => value,
+                                None => next()?,
+                            };
+                        }
+                        x += count;
+                    }
+                }
+            } else {
+                scanline[0] = start;
+                for texel in &mut scanline[1..] {
+                    *texel = [next()?, next()?, next()?, next()?];
+                }
+            }
+
+            for [r, g, b, e] in &scanline {
+                let scale = if *e == 0 { 0.0 } else { 2.0f32.powi(*e as i32 - 136) };
+                pixels.extend([*r as f32 * scale, *g as f32 * scale, *b as f32 * scale, 1.0]);
+            }
+        }
+
+        Ok(Self { width, height, pixels })
+    }
+}
+
+/// What the environment cube map is baked from.
+#[derive(Clone, Debug, Default)]
+pub enum Source {
+    /// A procedural sky with a sun along the default directional light.
+    #[default]
+    Sky,
+    /// Six square faces of equal size in `+X, -X, +Y, -Y, +Z, -Z` order. They are given along
+    /// the world axes, which are Z-up, so the `+Z` face looks at the zenith.
+    Faces(Vec<HdrImage>),
+    /// A latitude-longitude panorama whose top row is the zenith and whose left edge looks
+    /// along `-X`.
+    Equirectangular(HdrImage),
+}
+
+impl Source {
+    /// Checks the images fit together before anything is created from them.
+    pub fn validate(&self) -> Result<()> {
+        let images = match self {
+            Source::Sky => return Ok(()),
+            Source::Faces(faces) => {
+                let size = faces.first().map(|f| f.width).unwrap_or_default();
+                if faces.len() != 6 || faces.iter().any(|f| f.width != size || f.height != size) {
+                    return Err(anyhow!("An environment needs six square faces of the same size."));
+                }
+                &faces[..]
+            }
+            Source::Equirectangular(panorama) => std::slice::from_ref(panorama),
+        };
+
+        if images.iter().any(|i| i.width == 0 || i.height == 0 || i.pixels.len() != (i.width * i.height * 4) as usize) {
+            return Err(anyhow!("Environment images need four floats per texel."));
+        }
+
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct BakePushConstants {
+    sun_direction: glm::Vec4,
+    face: u32,
+    roughness: f32,
+    /// Edge of the sampled environment's base level.
+    resolution: f32,
+    sample_count: u32,
+}
+
+/// Objects shared by the passes of the bake, destroyed once it has finished.
+struct Bake<'a> {
+    device: &'a Device,
+    command_buffer: vk::CommandBuffer,
+    pipeline_layout: vk::PipelineLayout,
+    descriptor_set: vk::DescriptorSet,
+    pipelines: Vec<vk::Pipeline>,
+    framebuffers: Vec<vk::Framebuffer>,
+    views: Vec<vk::ImageView>,
+    /// The uploaded source and its staging buffer, if the environment is not the sky.
+    images: Vec<(vk::Image, vk::DeviceMemory)>,
+    buffers: Vec<(vk::Buffer, vk::DeviceMemory)>,
+    image_states: image_state::ImageStates,
+}
+
+/// Renders `data.environment_source` into a cube map, then bakes from it the diffuse
+/// irradiance map, the specular map prefiltered per roughness and the BRDF lookup table, all on
+/// the GPU. The results do not depend on the swapchain.
+pub unsafe fn create_environment_maps(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    data.environment_source.validate()?;
+
+    // Images
+
+    let environment_levels = ENVIRONMENT_SIZE.ilog2() + 1;
+
+    let (environment_image, environment_image_memory) = create_cube_image(
+        instance,
+        device,
+        data,
+        ENVIRONMENT_SIZE,
+        environment_levels,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::TRANSFER_SRC
+            | vk::ImageUsageFlags::TRANSFER_DST,
+    )?;
+
+    data.environment_image = environment_image;
+    data.environment_image_memory = environment_image_memory;
+    data.environment_image_view = create_cube_view(device, environment_image, environment_levels)?;
+
+    let (irradiance_image, irradiance_image_memory) = create_cube_image(
+        instance,
+        device,
+        data,
+        IRRADIANCE_SIZE,
+        1,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+    )?;
+
+    data.irradiance_image = irradiance_image;
+    data.irradiance_image_memory = irradiance_image_memory;
+    data.irradiance_image_view = create_cube_view(device, irradiance_image, 1)?;
+
+    let (prefiltered_image, prefiltered_image_memory) = create_cube_image(
+        instance,
+        device,
+        data,
+        PREFILTERED_SIZE,
+        PREFILTERED_LEVELS,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+    )?;
+
+    data.prefiltered_image = prefiltered_image;
+    data.prefiltered_image_memory = prefiltered_image_memory;
+    data.prefiltered_image_view = create_cube_view(device, prefiltered_image, PREFILTERED_LEVELS)?;
+
+    let (brdf_lut_image, brdf_lut_image_memory) = image::create_image(
+        instance,
+        device,
+        data,
+        BRDF_LUT_SIZE,
+        BRDF_LUT_SIZE,
+        1,
+        vk::ImageCreateFlags::empty(),
+        BRDF_LUT_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.brdf_lut_image = brdf_lut_image;
+    data.brdf_lut_image_memory = brdf_lut_image_memory;
+    data.brdf_lut_image_view = image::create_image_view(device, brdf_lut_image, BRDF_LUT_FORMAT, vk::ImageAspectFlags::COLOR)?;
+
+    // Sampler
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+        .min_lod(0.0)
+        .max_lod(environment_levels as f32);
+
+    data.environment_sampler = device.create_sampler(&info, None)?;
+
+    // Descriptor Set
+
+    let image_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[image_binding, sampler_binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    let descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    // The second set reads a supplied source.
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(2);
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLER)
+        .descriptor_count(2);
+
+    let pool_sizes = &[image_size, sampler_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(2);
+
+    let descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let set_layouts = &[descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(set_layouts);
+
+    let descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+    write_descriptor_set(device, data, descriptor_set, data.environment_image_view);
+
+    // Pipeline Layout
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<BakePushConstants>() as u32);
+
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    let pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    // Render Passes
+
+    let cube_render_pass = render_pass::create(device, &[output_attachment(tonemap::HDR_FORMAT)])?;
+    let lut_render_pass = render_pass::create(device, &[output_attachment(BRDF_LUT_FORMAT)])?;
+
+    // Record
+
+    let mut bake = Bake {
+        device,
+        command_buffer: vertex_buffer::begin_single_time_commands(device, data)?,
+        pipeline_layout,
+        descriptor_set,
+        pipelines: vec![],
+        framebuffers: vec![],
+        views: vec![],
+        images: vec![],
+        buffers: vec![],
+        image_states: image_state::ImageStates::default(),
+    };
+
+    // The sky's base level is blitted down its mip chain afterwards.
+    let transfer_src = image_state::Usage::TransferSrc;
+    let sampled = image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER);
+
+    let sun_direction = glm::vec4(SUN_DIRECTION[0], SUN_DIRECTION[1], SUN_DIRECTION[2], 0.0);
+    let push_constants = BakePushConstants { sun_direction, ..Default::default() };
+
+    let source = match &data.environment_source {
+        Source::Sky => None,
+        Source::Faces(faces) => {
+            let view = bake.cmd_upload_source(instance, data, faces, vk::ImageViewType::CUBE)?;
+            Some((view, &include_bytes!("../../shaders/environment_faces.bin")[..]))
+        }
+        Source::Equirectangular(panorama) => {
+            let view = bake.cmd_upload_source(instance, data, std::slice::from_ref(panorama), vk::ImageViewType::_2D)?;
+            Some((view, &include_bytes!("../../shaders/environment_equirect.bin")[..]))
+        }
+    };
+
+    match source {
+        Some((view, frag)) => {
+            let source_descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+            write_descriptor_set(device, data, source_descriptor_set, view);
+
+            bake.descriptor_set = source_descriptor_set;
+            bake.cmd_draw_cube_level(cube_render_pass, environment_image, 0, ENVIRONMENT_SIZE, frag, push_constants, transfer_src)?;
+            bake.descriptor_set = descriptor_set;
+        }
+        None => {
+            let sky = include_bytes!("../../shaders/environment_sky.bin");
+            bake.cmd_draw_cube_level(cube_render_pass, environment_image, 0, ENVIRONMENT_SIZE, &sky[..], push_constants, transfer_src)?;
+        }
+    }
+
+    cmd_generate_mipmaps(device, bake.command_buffer, &mut bake.image_states, environment_image, ENVIRONMENT_SIZE, environment_levels);
+
+    let irradiance = include_bytes!("../../shaders/environment_irradiance.bin");
+    let push_constants = BakePushConstants { resolution: ENVIRONMENT_SIZE as f32, ..Default::default() };
+    bake.cmd_draw_cube_level(cube_render_pass, irradiance_image, 0, IRRADIANCE_SIZE, &irradiance[..], push_constants, sampled)?;
+
+    let prefilter = include_bytes!("../../shaders/environment_prefilter.bin");
+    for level in 0..PREFILTERED_LEVELS {
+        let push_constants = BakePushConstants {
+            roughness: level as f32 / (PREFILTERED_LEVELS - 1) as f32,
+            resolution: ENVIRONMENT_SIZE as f32,
+            sample_count: PREFILTER_SAMPLES,
+            ..Default::default()
+        };
+        bake.cmd_draw_cube_level(
+            cube_render_pass,
+            prefiltered_image,
+            level,
+            PREFILTERED_SIZE >> level,
+            &prefilter[..],
+            push_constants,
+            sampled,
+        )?;
+    }
+
+    let lut = include_bytes!("../../shaders/brdf_lut.bin");
+    let pipeline = bake.create_pipeline(lut_render_pass, BRDF_LUT_FORMAT, BRDF_LUT_SIZE, &lut[..])?;
+    let push_constants = BakePushConstants { sample_count: BRDF_LUT_SAMPLES, ..Default::default() };
+    let color = vk::ImageAspectFlags::COLOR;
+    bake.image_states.cmd_use(device, bake.command_buffer, data.brdf_lut_image, color, image_state::Usage::ColorAttachment);
+    bake.cmd_draw(lut_render_pass, data.brdf_lut_image_view, BRDF_LUT_SIZE, pipeline, push_constants)?;
+    bake.image_states.cmd_use(device, bake.command_buffer, data.brdf_lut_image, color, sampled);
+
+    // Cleanup
+
+    let mut deletions = vec![];
+    deletions.extend(bake.pipelines.iter().map(|p| sync::Deletion::Pipeline(*p)));
+    deletions.extend(bake.framebuffers.iter().map(|f| sync::Deletion::Framebuffer(*f)));
+    deletions.extend(bake.views.iter().map(|v| sync::Deletion::ImageView(*v)));
+    deletions.extend(bake.images.iter().map(|(i, m)| sync::Deletion::Image(*i, *m, vk::ImageView::null())));
+    deletions.extend(bake.buffers.iter().map(|(b, m)| sync::Deletion::Buffer(*b, *m)));
+    deletions.extend([
+        sync::Deletion::RenderPass(cube_render_pass),
+        sync::Deletion::RenderPass(lut_render_pass),
+        sync::Deletion::PipelineLayout(pipeline_layout),
+        sync::Deletion::DescriptorPool(descriptor_pool),
+        sync::Deletion::DescriptorSetLayout(descriptor_set_layout),
+    ]);
+
+    vertex_buffer::end_single_time_commands(device, data, bake.command_buffer, deletions)?;
+
+    Ok(())
+}
+
+pub unsafe fn destroy_environment_maps(device: &Device, data: &mut app_data::Data) {
+    device.destroy_sampler(data.environment_sampler, None);
+    device.destroy_image_view(data.brdf_lut_image_view, None);
+    device.free_memory(data.brdf_lut_image_memory, None);
+    device.destroy_image(data.brdf_lut_image, None);
+    device.destroy_image_view(data.prefiltered_image_view, None);
+    device.free_memory(data.prefiltered_image_memory, None);
+    device.destroy_image(data.prefiltered_image, None);
+    device.destroy_image_view(data.irradiance_image_view, None);
+    device.free_memory(data.irradiance_image_memory, None);
+    device.destroy_image(data.irradiance_image, None);
+    device.destroy_image_view(data.environment_image_view, None);
+    device.free_memory(data.environment_image_memory, None);
+    device.destroy_image(data.environment_image, None);
+}
+
+/// Creates the pipeline drawing the sky behind the forward pass's geometry. It tests against
+/// the scene's depth at the far plane, writes the sky's motion under the view's rotation and
+/// leaves the ID attachment alone.
+pub unsafe fn create_skybox_pipeline(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    // Stages
+
+    let vert = include_bytes!("../../shaders/skybox_vert.bin");
+    let frag = include_bytes!("../../shaders/skybox_frag.bin");
+
+    let vert_shader_module = pipeline::create_shader_module(device, &vert[..])?;
+    let frag_shader_module = pipeline::create_shader_module(device, &frag[..])?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0");
+
+    // Fixed Function State
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    // The triangle lies on the far plane, so it only covers pixels still at the cleared depth.
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let id_attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::empty())
+        .blend_enable(false);
+
+    let mut attachments = vec![attachment, attachment];
+    if data.picking {
+        attachments.push(id_attachment);
+    }
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(&attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    // Create
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(data.pipeline_layout)
+        .render_pass(data.render_pass)
+        .subpass(0);
+
+    let (color_formats, depth_format) = render_pass::formats(&pipeline::forward_attachments(data));
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(&color_formats)
+        .depth_attachment_format(depth_format);
+
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.skybox_pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0[0];
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(())
+}
+
+/// Draws the sky inside the begun forward render pass, after the scene.
+pub unsafe fn cmd_draw_skybox(device: &Device, command_buffer: vk::CommandBuffer, data: &app_data::Data, image_index: usize) {
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.skybox_pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.pipeline_layout,
+        0,
+        &[data.descriptor_sets[image_index]],
+        &[],
+    );
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+}
+
+/// The nearest half float to `value`, saturated to the largest finite one.
+fn to_f16(value: f32) -> u16 {
+    let sign = ((value.to_bits() >> 16) & 0x8000) as u16;
+    let magnitude = value.abs();
+
+    if magnitude.is_nan() {
+        return sign | 0x7e00;
+    } else if magnitude < 6.1035156e-5 {
+        // Subnormal halves count in steps of 2^-24.
+        return sign | (magnitude * 16_777_216.0).round() as u16;
+    }
+
+    let bits = magnitude.min(65504.0).to_bits();
+    let exponent = (bits >> 23) - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+
+    // A carry out of the mantissa correctly moves on to the next exponent.
+    let half = (exponent << 10 | mantissa >> 13) + (mantissa >> 12 & 1);
+    sign | half as u16
+}
+
+/// Points `descriptor_set` at `view`, sampled with the environment sampler.
+unsafe fn write_descriptor_set(device: &Device, data: &app_data::Data, descriptor_set: vk::DescriptorSet, view: vk::ImageView) {
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(view);
+
+    let image_info = &[info];
+    let image_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .image_info(image_info);
+
+    let info = vk::DescriptorImageInfo::builder().sampler(data.environment_sampler);
+
+    let sampler_info = &[info];
+    let sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .image_info(sampler_info);
+
+    device.update_descriptor_sets(&[image_write, sampler_write], &[] as &[vk::CopyDescriptorSet]);
+}
+
+impl Bake<'_> {
+    /// Uploads `layers`, which have been validated, as a half float image sampled through a
+    /// view of `view_type`, and returns that view.
+    unsafe fn cmd_upload_source(
+        &mut self,
+        instance: &Instance,
+        data: &app_data::Data,
+        layers: &[HdrImage],
+        view_type: vk::ImageViewType,
+    ) -> Result<vk::ImageView> {
+        let (width, height) = (layers[0].width, layers[0].height);
+
+        // Staging
+
+        let pixels = layers.iter().flat_map(|l| l.pixels.iter().map(|p| to_f16(*p))).collect::<Vec<_>>();
+        let size = (pixels.len() * size_of::<u16>()) as u64;
+
+        let (staging_buffer, staging_buffer_memory) = vertex_buffer::create_buffer(
+            instance,
+            self.device,
+            data,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        self.buffers.push((staging_buffer, staging_buffer_memory));
+
+        let memory = self.device.map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
+        memcpy(pixels.as_ptr(), memory.cast(), pixels.len());
+        self.device.unmap_memory(staging_buffer_memory);
+
+        // Image
+
+        let flags = if view_type == vk::ImageViewType::CUBE { vk::ImageCreateFlags::CUBE_COMPATIBLE } else { vk::ImageCreateFlags::empty() };
+        let (image, image_memory) = image::create_image(
+            instance,
+            self.device,
+            data,
+            width,
+            height,
+            layers.len() as u32,
+            flags,
+            tonemap::HDR_FORMAT,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        self.images.push((image, image_memory));
+
+        let view = image::create_image_view_layers(
+            self.device,
+            image,
+            tonemap::HDR_FORMAT,
+            vk::ImageAspectFlags::COLOR,
+            view_type,
+            0,
+            layers.len() as u32,
+        )?;
+
+        self.views.push(view);
+
+        // Copy
+
+        let color = vk::ImageAspectFlags::COLOR;
+        self.image_states.cmd_use(self.device, self.command_buffer, image, color, image_state::Usage::TransferDst);
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(color)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(layers.len() as u32);
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(subresource)
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D { width, height, depth: 1 });
+
+        self.device.cmd_copy_buffer_to_image(
+            self.command_buffer,
+            staging_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region],
+        );
+
+        let usage = image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER);
+        self.image_states.cmd_use(self.device, self.command_buffer, image, color, usage);
+
+        Ok(view)
+    }
+
+    unsafe fn create_pipeline(&mut self, render_pass: vk::RenderPass, format: vk::Format, size: u32, frag: &[u8]) -> Result<vk::Pipeline> {
+        let vert = include_bytes!("../../shaders/fullscreen.bin");
+        let extent = vk::Extent2D { width: size, height: size };
+        let pipeline = pipeline::create_fullscreen_pipeline(
+            self.device,
+            extent,
+            &vert[..],
+            frag,
+            self.pipeline_layout,
+            render_pass,
+            format,
+            false,
+        )?;
+
+        self.pipelines.push(pipeline);
+
+        Ok(pipeline)
+    }
+
+    /// Renders `frag` into each face of mip `level` of the cube `image`, which is `size`
+    /// texels wide at that level, and leaves the level ready for `usage`.
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn cmd_draw_cube_level(
+        &mut self,
+        render_pass: vk::RenderPass,
+        image: vk::Image,
+        level: u32,
+        size: u32,
+        frag: &[u8],
+        push_constants: BakePushConstants,
+        usage: image_state::Usage,
+    ) -> Result<()> {
+        let pipeline = self.create_pipeline(render_pass, tonemap::HDR_FORMAT, size, frag)?;
+
+        let color = vk::ImageAspectFlags::COLOR;
+        let levels = level..level + 1;
+        self.image_states.transition_levels(image, color, levels.clone(), image_state::Usage::ColorAttachment);
+        self.image_states.cmd_flush(self.device, self.command_buffer);
+
+        for face in 0..6 {
+            let subresource_range = vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(level)
+                .level_count(1)
+                .base_array_layer(face)
+                .layer_count(1);
+
+            let view = image::create_image_view_range(
+                self.device,
+                image,
+                tonemap::HDR_FORMAT,
+                vk::ImageViewType::_2D,
+                subresource_range.build(),
+            )?;
+
+            self.views.push(view);
+
+            self.cmd_draw(render_pass, view, size, pipeline, BakePushConstants { face, ..push_constants })?;
+        }
+
+        self.image_states.transition_levels(image, color, levels, usage);
+        self.image_states.cmd_flush(self.device, self.command_buffer);
+
+        Ok(())
+    }
+
+    unsafe fn cmd_draw(
+        &mut self,
+        render_pass: vk::RenderPass,
+        view: vk::ImageView,
+        size: u32,
+        pipeline: vk::Pipeline,
+        push_constants: BakePushConstants,
+    ) -> Result<()> {
+        let attachments = &[view];
+        let info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(attachments)
+            .width(size)
+            .height(size)
+            .layers(1);
+
+        let framebuffer = self.device.create_framebuffer(&info, None)?;
+        self.framebuffers.push(framebuffer);
+
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(vk::Extent2D { width: size, height: size });
+
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
+            .render_area(render_area);
+
+        self.device.cmd_begin_render_pass(self.command_buffer, &info, vk::SubpassContents::INLINE);
+        self.device.cmd_bind_pipeline(self.command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        self.device.cmd_bind_descriptor_sets(
+            self.command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &[self.descriptor_set],
+            &[],
+        );
+        self.device.cmd_push_constants(
+            self.command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<BakePushConstants>()),
+        );
+        self.device.cmd_draw(self.command_buffer, 3, 1, 0, 0);
+        self.device.cmd_end_render_pass(self.command_buffer);
+
+        Ok(())
+    }
+}
+
+unsafe fn create_cube_image(
+    instance: &Instance,
+    device: &Device,
+    data: &app_data::Data,
+    size: u32,
+    mip_levels: u32,
+    usage: vk::ImageUsageFlags,
+) -> Result<(vk::Image, vk::DeviceMemory)> {
+    let info = vk::ImageCreateInfo::builder()
+        .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE)
+        .image_type(vk::ImageType::_2D)
+        .extent(vk::Extent3D { width: size, height: size, depth: 1 })
+        .mip_levels(mip_levels)
+        .array_layers(6)
+        .format(tonemap::HDR_FORMAT)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .samples(vk::SampleCountFlags::_1);
+
+    let image = device.create_image(&info, None)?;
+
+    let requirements = device.get_image_memory_requirements(image);
+
+    let info = vk::MemoryAllocateInfo::builder()
+        .allocation_size(requirements.size)
+        .memory_type_index(vertex_buffer::get_memory_type_index(
+            instance,
+            data,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            requirements,
+        )?);
+
+    let image_memory = device.allocate_memory(&info, None)?;
+
+    device.bind_image_memory(image, image_memory, 0)?;
+
+    Ok((image, image_memory))
+}
+
+unsafe fn create_cube_view(device: &Device, image: vk::Image, mip_levels: u32) -> Result<vk::ImageView> {
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(mip_levels)
+        .base_array_layer(0)
+        .layer_count(6);
+
+    image::create_image_view_range(device, image, tonemap::HDR_FORMAT, vk::ImageViewType::CUBE, subresource_range.build())
+}
+
+/// One face of one level, written for every texel.
+fn output_attachment(format: vk::Format) -> render_pass::Attachment {
+    render_pass::Attachment::color(format, vk::AttachmentLoadOp::DONT_CARE)
+}
+
+/// Blits each level of every face from the one above it, starting from the base level, and
+/// leaves the whole chain ready for sampling.
+unsafe fn cmd_generate_mipmaps(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    images: &mut image_state::ImageStates,
+    image: vk::Image,
+    size: u32,
+    mip_levels: u32,
+) {
+    let color = vk::ImageAspectFlags::COLOR;
+    images.transition_levels(image, color, 0..1, image_state::Usage::TransferSrc);
+
+    for level in 1..mip_levels {
+        images.transition_levels(image, color, level..level + 1, image_state::Usage::TransferDst);
+        images.cmd_flush(device, command_buffer);
+
+        let src_size = (size >> (level - 1)) as i32;
+        let dst_size = (size >> level) as i32;
+
+        let src_subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(color)
+            .mip_level(level - 1)
+            .base_array_layer(0)
+            .layer_count(6);
+
+        let dst_subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(color)
+            .mip_level(level)
+            .base_array_layer(0)
+            .layer_count(6);
+
+        let blit = vk::ImageBlit::builder()
+            .src_offsets([vk::Offset3D { x: 0, y: 0, z: 0 }, vk::Offset3D { x: src_size, y: src_size, z: 1 }])
+            .src_subresource(src_subresource)
+            .dst_offsets([vk::Offset3D { x: 0, y: 0, z: 0 }, vk::Offset3D { x: dst_size, y: dst_size, z: 1 }])
+            .dst_subresource(dst_subresource);
+
+        device.cmd_blit_image(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[blit],
+            vk::Filter::LINEAR,
+        );
+
+        images.transition_levels(image, color, level..level + 1, image_state::Usage::TransferSrc);
+        images.cmd_flush(device, command_buffer);
+    }
+
+    images.cmd_use(device, command_buffer, image, color, image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_floats_round_and_saturate() {
+        assert_eq!(to_f16(0.0), 0x0000);
+        assert_eq!(to_f16(-0.0), 0x8000);
+        assert_eq!(to_f16(1.0), 0x3c00);
+        assert_eq!(to_f16(-2.5), 0xc100);
+        assert_eq!(to_f16(65504.0), 0x7bff);
+        assert_eq!(to_f16(1.0e9), 0x7bff);
+        assert_eq!(to_f16(5.9604645e-8), 0x0001);
+        assert_eq!(to_f16(1.0 + 1.0 / 1024.0), 0x3c01);
+        // Halfway between two halves rounds away from zero, carrying into the exponent.
+        assert_eq!(to_f16(2.0 - 1.0 / 4096.0), 0x4000);
+    }
+
+    fn header(width: u32, height: u32) -> Vec<u8> {
+        format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width).into_bytes()
+    }
+
+    #[test]
+    fn reads_flat_scanlines() {
+        let mut bytes = header(2, 1);
+        bytes.extend([128, 64, 0, 129, 0, 0, 0, 0]);
+
+        let image = HdrImage::parse(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels, vec![1.0, 0.5, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn reads_run_length_encoded_scanlines() {
+        let mut bytes = header(8, 1);
+        bytes.extend([2, 2, 0, 8]);
+        // Red is a run, green literal values, blue and exponent runs.
+        bytes.extend([136, 128]);
+        bytes.extend([8, 0, 32, 64, 96, 128, 160, 192, 224]);
+        bytes.extend([136, 0]);
+        bytes.extend([136, 128]);
+
+        let image = HdrImage::parse(&bytes).unwrap();
+        assert_eq!(image.pixels.len(), 8 * 4);
+        assert_eq!(&image.pixels[0..4], &[0.5, 0.0, 0.0, 1.0]);
+        assert_eq!(&image.pixels[28..32], &[0.5, 0.875, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn rejects_truncated_and_unsupported_files() {
+        let mut bytes = header(2, 2);
+        bytes.extend([128, 64, 0, 129]);
+        assert!(HdrImage::parse(&bytes).is_err());
+
+        assert!(HdrImage::parse(b"#?RADIANCE\n\n+Y 1 +X 1\n\0\0\0\0").is_err());
+        assert!(HdrImage::parse(b"P6\n1 1\n255\n").is_err());
+    }
+}
@@ -0,0 +1,324 @@
+use super::app_data;
+use super::pipeline;
+use super::vertex_buffer;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+use std::ptr::copy_nonoverlapping as memcpy;
+use std::mem::size_of;
+
+/// Luminance histogram bins; bin 0 collects black pixels.
+const BIN_COUNT: u32 = 256;
+
+/// Pixels covered by each histogram workgroup along each axis; every invocation takes one
+/// bilinear sample of a 2×2 block.
+const TILE_SIZE: u32 = 32;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Metering {
+    /// Every pixel counts equally.
+    #[default]
+    Average,
+    /// Weight falls off towards the edges of the screen.
+    CenterWeighted,
+    /// Only a small circle in the middle of the screen counts.
+    Spot,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AutoExposure {
+    pub enabled: bool,
+    /// Adaptation rate; the gap to the target shrinks by `1 - e^(-speed · seconds)`.
+    pub speed: f32,
+    /// Darkest average scene luminance adapted to, as `log2(luminance)`.
+    pub min_ev: f32,
+    /// Brightest average scene luminance adapted to, as `log2(luminance)`.
+    pub max_ev: f32,
+    pub metering: Metering,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            speed: 1.5,
+            min_ev: -10.0,
+            max_ev: 10.0,
+            metering: Metering::default(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct ExposurePushConstants {
+    min_log2: f32,
+    log2_range: f32,
+    delta: f32,
+    speed: f32,
+    metering: u32,
+    tile_count: u32,
+}
+
+/// The adapted average luminance. It is kept across swapchain recreation so a resize does not
+/// restart adaptation.
+pub unsafe fn create_exposure_buffer(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let (exposure_buffer, exposure_buffer_memory) = vertex_buffer::create_buffer(
+        instance,
+        device,
+        data,
+        size_of::<f32>() as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    let memory = device.map_memory(exposure_buffer_memory, 0, size_of::<f32>() as u64, vk::MemoryMapFlags::empty())?;
+    memcpy(&0.0f32, memory.cast(), 1);
+    device.unmap_memory(exposure_buffer_memory);
+
+    data.exposure_buffer = exposure_buffer;
+    data.exposure_buffer_memory = exposure_buffer_memory;
+
+    Ok(())
+}
+
+/// Creates the histogram buffer, sized to the swapchain, and the two compute pipelines that
+/// fill and reduce it. Samples the HDR target with the tone mapping sampler.
+pub unsafe fn create_exposure_objects(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    // Histogram
+
+    let (histogram_buffer, histogram_buffer_memory) = vertex_buffer::create_buffer(
+        instance,
+        device,
+        data,
+        (tile_count(data.swapchain_extent) * BIN_COUNT) as u64 * size_of::<f32>() as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.histogram_buffer = histogram_buffer;
+    data.histogram_buffer_memory = histogram_buffer_memory;
+
+    // Descriptor Set
+
+    let image_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let histogram_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(2)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let exposure_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(3)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let bindings = &[image_binding, sampler_binding, histogram_binding, exposure_binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.exposure_descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(1);
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1);
+    let buffer_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(2);
+
+    let pool_sizes = &[image_size, sampler_size, buffer_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+
+    data.exposure_descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let layouts = &[data.exposure_descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder().descriptor_pool(data.exposure_descriptor_pool).set_layouts(layouts);
+
+    data.exposure_descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let image_info = &[vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(data.hdr_image_view)];
+    let image_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.exposure_descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .image_info(image_info);
+
+    let sampler_info = &[vk::DescriptorImageInfo::builder().sampler(data.tonemap_sampler)];
+    let sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.exposure_descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .image_info(sampler_info);
+
+    let histogram_info = &[vk::DescriptorBufferInfo::builder()
+        .buffer(data.histogram_buffer)
+        .offset(0)
+        .range(vk::WHOLE_SIZE as u64)];
+    let histogram_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.exposure_descriptor_set)
+        .dst_binding(2)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(histogram_info);
+
+    let exposure_info = &[vk::DescriptorBufferInfo::builder()
+        .buffer(data.exposure_buffer)
+        .offset(0)
+        .range(size_of::<f32>() as u64)];
+    let exposure_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.exposure_descriptor_set)
+        .dst_binding(3)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(exposure_info);
+
+    device.update_descriptor_sets(
+        &[image_write, sampler_write, histogram_write, exposure_write],
+        &[] as &[vk::CopyDescriptorSet],
+    );
+
+    // Pipelines
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(size_of::<ExposurePushConstants>() as u32);
+
+    let set_layouts = &[data.exposure_descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.exposure_pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    let histogram = include_bytes!("../../shaders/histogram.bin");
+    let exposure = include_bytes!("../../shaders/exposure.bin");
+    data.histogram_pipeline = pipeline::create_compute_pipeline(device, &histogram[..], data.exposure_pipeline_layout)?;
+    data.exposure_pipeline = pipeline::create_compute_pipeline(device, &exposure[..], data.exposure_pipeline_layout)?;
+
+    Ok(())
+}
+
+fn tile_counts(extent: vk::Extent2D) -> (u32, u32) {
+    (extent.width.div_ceil(TILE_SIZE), extent.height.div_ceil(TILE_SIZE))
+}
+
+fn tile_count(extent: vk::Extent2D) -> u32 {
+    let (x, y) = tile_counts(extent);
+    x * y
+}
+
+/// Bins the lit HDR target and adapts the exposure by `delta` seconds. Must be recorded after
+/// the scene is lit and before it is tone mapped.
+pub unsafe fn cmd_update_exposure(device: &Device, command_buffer: vk::CommandBuffer, data: &app_data::Data, delta: f32) {
+    let settings = &data.auto_exposure;
+    let (tiles_x, tiles_y) = tile_counts(data.swapchain_extent);
+    let push_constants = ExposurePushConstants {
+        min_log2: settings.min_ev,
+        log2_range: (settings.max_ev - settings.min_ev).max(0.001),
+        delta,
+        speed: settings.speed,
+        metering: settings.metering as u32,
+        tile_count: tiles_x * tiles_y,
+    };
+
+    // The previous frame must be done with both buffers before they are written again.
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        data.exposure_pipeline_layout,
+        0,
+        &[data.exposure_descriptor_set],
+        &[],
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        data.exposure_pipeline_layout,
+        vk::ShaderStageFlags::COMPUTE,
+        0,
+        std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<ExposurePushConstants>()),
+    );
+
+    // Histogram
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.histogram_pipeline);
+    device.cmd_dispatch(command_buffer, tiles_x, tiles_y, 1);
+
+    let histogram_barrier = vk::BufferMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(data.histogram_buffer)
+        .offset(0)
+        .size(vk::WHOLE_SIZE as u64);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[histogram_barrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    // Adaptation
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.exposure_pipeline);
+    device.cmd_dispatch(command_buffer, 1, 1, 1);
+
+    let exposure_barrier = vk::BufferMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(data.exposure_buffer)
+        .offset(0)
+        .size(vk::WHOLE_SIZE as u64);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[exposure_barrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
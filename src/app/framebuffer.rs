@@ -1,22 +1,21 @@
 use super::app_data;
+use super::render_pass;
 
 use anyhow::{Result};
 use vulkanalia::prelude::v1_0::*;
 
+/// Creates the forward pass framebuffers, which are null with dynamic rendering.
 pub unsafe fn create(device: &Device, data: &mut app_data::Data) -> Result<()> {
     data.framebuffers = data.swapchain_image_views.iter()
-        .map(|i| {
-            let attachments = &[*i, data.depth_image_view];
-            let create_info = vk::FramebufferCreateInfo::builder()
-                .render_pass(data.render_pass)
-                .attachments(attachments)
-                .width(data.swapchain_extent.width)
-                .height(data.swapchain_extent.height)
-                .layers(1);
+        .map(|_| {
+            let mut attachments = vec![data.hdr_image_view, data.depth_image_view, data.velocity_image_view];
+            if data.picking {
+                attachments.push(data.id_image_view);
+            }
 
-            device.create_framebuffer(&create_info, None)
+            render_pass::create_framebuffer(device, data.render_pass, &attachments, data.swapchain_extent)
         })
-        .collect::<Result<Vec<_>, _>>()?;
+        .collect::<Result<Vec<_>>>()?;
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,322 @@
+use super::animation;
+use super::app_defines;
+use super::mesh_processing;
+use super::scene;
+use super::vertex_buffer;
+
+use anyhow::{anyhow, Result};
+use log::*;
+use nalgebra_glm as glm;
+use std::fs;
+use std::path::Path;
+
+/// Extensions the loader understands. Anything else listed by a file produces a diagnostic.
+pub const SUPPORTED_EXTENSIONS: &[&str] = &[];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Import {
+    pub scene: scene::Scene,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Loads a `.gltf` (with external or embedded buffers) or `.glb` file.
+///
+/// All primitives are packed into the scene's shared vertex and index arrays. Vertex colors
+/// are `COLOR_0`; material factors are applied when shading.
+pub fn load(path: &Path) -> Result<Import> {
+    let bytes = fs::read(path)?;
+    let mut diagnostics = vec![];
+
+    // Extensions
+
+    let unchecked = gltf::Gltf::from_slice_without_validation(&bytes)?;
+
+    for extension in unchecked.extensions_used() {
+        if !SUPPORTED_EXTENSIONS.contains(&extension) {
+            let required = unchecked.extensions_required().any(|e| e == extension);
+            let severity = if required { Severity::Error } else { Severity::Warning };
+            push(&mut diagnostics, severity, format!("Unsupported extension `{}`.", extension));
+        }
+    }
+
+    let errors = diagnostics.iter()
+        .filter(|d| d.severity == Severity::Error)
+        .map(|d| d.message.as_str())
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(anyhow!("`{}` requires unsupported extensions: {}", path.display(), errors.join(" ")));
+    }
+
+    // Resources
+
+    let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(&bytes)?;
+    let base = path.parent().unwrap_or_else(|| Path::new("./"));
+    let buffers = gltf::import_buffers(&document, Some(base), blob)?;
+    let images = gltf::import_images(&document, Some(base), &buffers)?;
+
+    let mut scene = scene::Scene::default();
+
+    // Materials
+
+    let mut srgb = vec![false; images.len()];
+    let texture_image = |info: Option<gltf::texture::Texture>| info.map(|t| t.source().index());
+
+    for material in document.materials() {
+        let pbr = material.pbr_metallic_roughness();
+        let normal = material.normal_texture();
+        let occlusion = material.occlusion_texture();
+
+        let base_color_texture = texture_image(pbr.base_color_texture().map(|i| i.texture()));
+        let emissive_texture = texture_image(material.emissive_texture().map(|i| i.texture()));
+        base_color_texture.iter().chain(emissive_texture.iter()).for_each(|i| srgb[*i] = true);
+
+        scene.materials.push(scene::Material {
+            name: material.name().map(String::from),
+            base_color_factor: glm::Vec4::from(pbr.base_color_factor()),
+            base_color_texture,
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+            metallic_roughness_texture: texture_image(pbr.metallic_roughness_texture().map(|i| i.texture())),
+            normal_texture: texture_image(normal.as_ref().map(|n| n.texture())),
+            normal_scale: normal.as_ref().map(|n| n.scale()).unwrap_or(1.0),
+            occlusion_texture: texture_image(occlusion.as_ref().map(|o| o.texture())),
+            occlusion_strength: occlusion.as_ref().map(|o| o.strength()).unwrap_or(1.0),
+            emissive_factor: glm::Vec3::from(material.emissive_factor()),
+            emissive_texture,
+            alpha_mode: match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => scene::AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => scene::AlphaMode::Mask,
+                gltf::material::AlphaMode::Blend => scene::AlphaMode::Blend,
+            },
+            alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+            double_sided: material.double_sided(),
+        });
+    }
+
+    // Images
+
+    for (index, image) in images.iter().enumerate() {
+        let pixels = match to_rgba8(image) {
+            Some(pixels) => pixels,
+            None => {
+                push(&mut diagnostics, Severity::Warning, format!("Image {} has an unsupported format ({:?}).", index, image.format));
+                vec![255; (image.width * image.height * 4) as usize]
+            }
+        };
+
+        scene.images.push(scene::ImageData { width: image.width, height: image.height, pixels, srgb: srgb[index] });
+    }
+
+    // Meshes
+
+    for mesh in document.meshes() {
+        let mut primitives = vec![];
+
+        for primitive in mesh.primitives() {
+            let label = format!("Mesh {} primitive {}", mesh.index(), primitive.index());
+
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                push(&mut diagnostics, Severity::Warning, format!("{} uses unsupported mode {:?}.", label, primitive.mode()));
+                continue;
+            }
+
+            let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+
+            let positions = match reader.read_positions() {
+                Some(positions) => positions.collect::<Vec<_>>(),
+                None => {
+                    push(&mut diagnostics, Severity::Warning, format!("{} has no positions.", label));
+                    continue;
+                }
+            };
+
+            let normals = reader.read_normals().map(|n| n.collect::<Vec<_>>());
+            let colors = reader.read_colors(0).map(|c| c.into_rgb_f32().collect::<Vec<_>>());
+            let tex_coords = reader.read_tex_coords(0).map(|t| t.into_f32().collect::<Vec<_>>());
+            let joints = reader.read_joints(0).map(|j| j.into_u16().collect::<Vec<_>>());
+            let weights = reader.read_weights(0).map(|w| w.into_f32().collect::<Vec<_>>());
+            let indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                None => (0..positions.len() as u32).collect(),
+            };
+
+            let material = primitive.material().index();
+
+            let vertex_offset = scene.vertices.len() as i32;
+            for (i, position) in positions.iter().enumerate() {
+                let color = colors.as_ref().map(|c| glm::Vec3::from(c[i])).unwrap_or_else(|| glm::vec3(1.0, 1.0, 1.0));
+                let tex_coord = tex_coords.as_ref().map(|t| glm::Vec2::from(t[i])).unwrap_or_else(glm::Vec2::zeros);
+                let mut vertex = vertex_buffer::Vertex::new(glm::Vec3::from(*position), color, tex_coord);
+                if let Some(normals) = &normals {
+                    vertex = vertex.with_normal(glm::Vec3::from(normals[i]));
+                }
+                if let (Some(joints), Some(weights)) = (&joints, &weights) {
+                    let [j0, j1, j2, j3] = joints[i];
+                    vertex = vertex.with_skin(glm::UVec4::new(j0.into(), j1.into(), j2.into(), j3.into()), glm::Vec4::from(weights[i]));
+                }
+                scene.vertices.push(vertex);
+            }
+
+            if normals.is_none() {
+                mesh_processing::compute_normals(&mut scene.vertices[vertex_offset as usize..], &indices);
+            }
+
+            let first_index = scene.indices.len() as u32;
+            scene.indices.extend_from_slice(&indices);
+
+            primitives.push(scene::Primitive {
+                first_index,
+                index_count: indices.len() as u32,
+                vertex_offset,
+                vertex_count: positions.len() as u32,
+                material,
+                lods: vec![],
+            });
+        }
+
+        scene.meshes.push(scene::Mesh { name: mesh.name().map(String::from), primitives });
+    }
+
+    // Nodes
+
+    for node in document.nodes() {
+        let (translation, rotation, scale) = node.transform().decomposed();
+        let [x, y, z, w] = rotation;
+
+        scene.nodes.push(scene::Node {
+            name: node.name().map(String::from),
+            parent: None,
+            children: node.children().map(|c| c.index()).collect(),
+            transform: glm::Mat4::from(node.transform().matrix()),
+            rest: scene::Transform {
+                translation: glm::Vec3::from(translation),
+                rotation: glm::quat(x, y, z, w),
+                scale: glm::Vec3::from(scale),
+            },
+            mesh: node.mesh().map(|m| m.index()),
+            skin: node.skin().map(|s| s.index()),
+        });
+    }
+
+    for index in 0..scene.nodes.len() {
+        for child in scene.nodes[index].children.clone() {
+            scene.nodes[child].parent = Some(index);
+        }
+    }
+
+    // Skins
+
+    for skin in document.skins() {
+        let joints = skin.joints().map(|j| j.index()).collect::<Vec<_>>();
+        let inverse_bind_matrices = match skin.reader(|b| Some(&buffers[b.index()])).read_inverse_bind_matrices() {
+            Some(matrices) => matrices.map(glm::Mat4::from).collect(),
+            None => vec![glm::identity(); joints.len()],
+        };
+
+        scene.skins.push(scene::Skin { name: skin.name().map(String::from), joints, inverse_bind_matrices });
+    }
+
+    // Every skinned node has its own joint matrices, which must fit the shared joint buffer.
+    let joint_count = scene.joint_count();
+    if joint_count > app_defines::MAX_JOINTS {
+        return Err(anyhow!(
+            "`{}` skins {} joints, more than the limit of {}.",
+            path.display(),
+            joint_count,
+            app_defines::MAX_JOINTS,
+        ));
+    }
+
+    // Animations
+
+    for animation in document.animations() {
+        let mut channels = vec![];
+
+        for channel in animation.channels() {
+            let reader = channel.reader(|b| Some(&buffers[b.index()]));
+            let times = reader.read_inputs().map(|i| i.collect::<Vec<_>>()).unwrap_or_default();
+
+            use gltf::animation::util::ReadOutputs;
+            let (property, values) = match reader.read_outputs() {
+                Some(ReadOutputs::Translations(t)) => (animation::Property::Translation, t.map(|v| glm::vec4(v[0], v[1], v[2], 0.0)).collect()),
+                Some(ReadOutputs::Rotations(r)) => (animation::Property::Rotation, r.into_f32().map(glm::Vec4::from).collect()),
+                Some(ReadOutputs::Scales(s)) => (animation::Property::Scale, s.map(|v| glm::vec4(v[0], v[1], v[2], 0.0)).collect::<Vec<_>>()),
+                _ => {
+                    push(&mut diagnostics, Severity::Warning, format!("Animation {} uses unsupported morph target weights.", animation.index()));
+                    continue;
+                }
+            };
+
+            if times.is_empty() {
+                continue;
+            }
+
+            channels.push(animation::Channel {
+                node: channel.target().node().index(),
+                property,
+                interpolation: match channel.sampler().interpolation() {
+                    gltf::animation::Interpolation::Linear => animation::Interpolation::Linear,
+                    gltf::animation::Interpolation::Step => animation::Interpolation::Step,
+                    gltf::animation::Interpolation::CubicSpline => animation::Interpolation::CubicSpline,
+                },
+                times,
+                values,
+            });
+        }
+
+        let duration = channels.iter().filter_map(|c| c.times.last()).fold(0.0f32, |a, b| a.max(*b));
+        scene.animations.push(animation::AnimationClip { name: animation.name().map(String::from), duration, channels });
+    }
+
+    scene.roots = match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(s) => s.nodes().map(|n| n.index()).collect(),
+        None => (0..scene.nodes.len()).filter(|n| scene.nodes[*n].parent.is_none()).collect(),
+    };
+
+    info!(
+        "Loaded `{}` ({} meshes, {} nodes, {} materials, {} images, {} skins, {} animations).",
+        path.display(),
+        scene.meshes.len(),
+        scene.nodes.len(),
+        scene.materials.len(),
+        scene.images.len(),
+        scene.skins.len(),
+        scene.animations.len(),
+    );
+
+    Ok(Import { scene, diagnostics })
+}
+
+fn push(diagnostics: &mut Vec<Diagnostic>, severity: Severity, message: String) {
+    diagnostics.push(Diagnostic { severity, message });
+}
+
+fn to_rgba8(image: &gltf::image::Data) -> Option<Vec<u8>> {
+    use gltf::image::Format;
+
+    let pixels = &image.pixels;
+    let rgba = match image.format {
+        Format::R8G8B8A8 => pixels.clone(),
+        Format::R8G8B8 => pixels.chunks(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        Format::R8G8 => pixels.chunks(2).flat_map(|p| [p[0], p[1], 0, 255]).collect(),
+        Format::R8 => pixels.iter().flat_map(|p| [*p, *p, *p, 255]).collect(),
+        // 16-bit channels are little-endian; keep the high byte.
+        Format::R16G16B16A16 => pixels.chunks(8).flat_map(|p| [p[1], p[3], p[5], p[7]]).collect(),
+        Format::R16G16B16 => pixels.chunks(6).flat_map(|p| [p[1], p[3], p[5], 255]).collect(),
+        _ => return None,
+    };
+
+    Some(rgba)
+}
@@ -1,39 +1,91 @@
 use super::vertex_buffer;
 use super::app_data;
+use super::image_state;
+use super::scene;
+use super::upload;
 
 use anyhow::{Result};
 use vulkanalia::prelude::v1_0::*;
 
 pub unsafe fn create_image_view(device: &Device, image: vk::Image, format: vk::Format, aspects: vk::ImageAspectFlags) -> Result<vk::ImageView> {
+    create_image_view_layers(device, image, format, aspects, vk::ImageViewType::_2D, 0, 1)
+}
+
+/// A view of `layer_count` array layers starting at `base_layer`.
+pub unsafe fn create_image_view_layers(
+    device: &Device,
+    image: vk::Image,
+    format: vk::Format,
+    aspects: vk::ImageAspectFlags,
+    view_type: vk::ImageViewType,
+    base_layer: u32,
+    layer_count: u32,
+) -> Result<vk::ImageView> {
     let subresource_range = vk::ImageSubresourceRange::builder()
         .aspect_mask(aspects)
         .base_mip_level(0)
         .level_count(1)
-        .base_array_layer(0)
-        .layer_count(1);
+        .base_array_layer(base_layer)
+        .layer_count(layer_count);
 
+    create_image_view_range(device, image, format, view_type, subresource_range.build())
+}
+
+/// A view of any range of mip levels and array layers.
+pub unsafe fn create_image_view_range(
+    device: &Device,
+    image: vk::Image,
+    format: vk::Format,
+    view_type: vk::ImageViewType,
+    subresource_range: vk::ImageSubresourceRange,
+) -> Result<vk::ImageView> {
     let info = vk::ImageViewCreateInfo::builder()
         .image(image)
-        .view_type(vk::ImageViewType::_2D)
+        .view_type(view_type)
         .format(format)
         .subresource_range(subresource_range);
 
     Ok(device.create_image_view(&info, None)?)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub unsafe fn create_image(
     instance: &Instance,
     device: &Device,
     data: &app_data::Data,
     width: u32,
     height: u32,
+    layers: u32,
+    flags: vk::ImageCreateFlags,
+    format: vk::Format,
+    tiling: vk::ImageTiling,
+    usage: vk::ImageUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<(vk::Image, vk::DeviceMemory)> {
+    create_shared_image(instance, device, data, &[], width, height, layers, flags, format, tiling, usage, properties)
+}
+
+/// An image used concurrently by the queue families in `families`, or exclusively if fewer
+/// than two are given.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn create_shared_image(
+    instance: &Instance,
+    device: &Device,
+    data: &app_data::Data,
+    families: &[u32],
+    width: u32,
+    height: u32,
+    layers: u32,
+    flags: vk::ImageCreateFlags,
     format: vk::Format,
     tiling: vk::ImageTiling,
     usage: vk::ImageUsageFlags,
     properties: vk::MemoryPropertyFlags,
 ) -> Result<(vk::Image, vk::DeviceMemory)> {
+    let sharing_mode = if families.len() > 1 { vk::SharingMode::CONCURRENT } else { vk::SharingMode::EXCLUSIVE };
 
     let info = vk::ImageCreateInfo::builder()
+        .flags(flags)
         .image_type(vk::ImageType::_2D)
         .extent(vk::Extent3D {
             width,
@@ -41,12 +93,13 @@ pub unsafe fn create_image(
             depth: 1,
         })
         .mip_levels(1)
-        .array_layers(1)
+        .array_layers(layers)
         .format(format)
         .tiling(tiling)
         .initial_layout(vk::ImageLayout::UNDEFINED)
         .usage(usage)
-        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .sharing_mode(sharing_mode)
+        .queue_family_indices(families)
         .samples(vk::SampleCountFlags::_1);
 
     let image = device.create_image(&info, None)?;
@@ -65,3 +118,53 @@ pub unsafe fn create_image(
 
     Ok((image, image_memory))
 }
+
+/// Creates a sampled texture and records the upload of its pixels into `uploads`.
+pub unsafe fn create_texture_image(
+    instance: &Instance,
+    device: &Device,
+    data: &app_data::Data,
+    uploads: &mut upload::Batch,
+    image: &scene::ImageData,
+) -> Result<(vk::Image, vk::DeviceMemory, vk::ImageView)> {
+    let format = if image.srgb { vk::Format::R8G8B8A8_SRGB } else { vk::Format::R8G8B8A8_UNORM };
+
+    let (texture_image, texture_image_memory) = create_image(
+        instance,
+        device,
+        data,
+        image.width,
+        image.height,
+        1,
+        vk::ImageCreateFlags::empty(),
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    upload::upload_image(instance, device, data, uploads, image, texture_image)?;
+
+    let texture_image_view = create_image_view(device, texture_image, format, vk::ImageAspectFlags::COLOR)?;
+
+    Ok((texture_image, texture_image_memory, texture_image_view))
+}
+
+/// Moves a whole color image from `UNDEFINED` into the layout `usage` needs, before any work
+/// submitted to the graphics queue afterwards, and records it in `data.image_states`.
+pub unsafe fn transition_image_layout(
+    device: &Device,
+    data: &mut app_data::Data,
+    image: vk::Image,
+    usage: image_state::Usage,
+) -> Result<()> {
+    let command_buffer = vertex_buffer::begin_single_time_commands(device, data)?;
+
+    image_state::ImageStates::default().cmd_use(device, command_buffer, image, vk::ImageAspectFlags::COLOR, usage);
+
+    vertex_buffer::end_single_time_commands(device, data, command_buffer, vec![])?;
+
+    data.image_states.set(image, usage);
+
+    Ok(())
+}
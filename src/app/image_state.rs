@@ -0,0 +1,389 @@
+use vulkanalia::prelude::v1_0::*;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A way an image is used, which decides its layout and the stages and accesses the barrier
+/// before it waits in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Usage {
+    TransferSrc,
+    TransferDst,
+    /// Read through a sampler by shaders in these stages.
+    Sampled(vk::PipelineStageFlags),
+    /// Read and written by compute shaders as a storage image.
+    Storage,
+    ColorAttachment,
+    DepthAttachment,
+    /// Depth tested without writes, or read by fragment shaders.
+    DepthRead,
+    Present,
+}
+
+impl Usage {
+    pub fn layout(self) -> vk::ImageLayout {
+        match self {
+            Usage::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            Usage::TransferDst => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            Usage::Sampled(_) => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            Usage::Storage => vk::ImageLayout::GENERAL,
+            Usage::ColorAttachment => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            Usage::DepthAttachment => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            Usage::DepthRead => vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            Usage::Present => vk::ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+
+    fn stages(self) -> vk::PipelineStageFlags {
+        match self {
+            Usage::TransferSrc | Usage::TransferDst => vk::PipelineStageFlags::TRANSFER,
+            Usage::Sampled(stages) => stages,
+            Usage::Storage => vk::PipelineStageFlags::COMPUTE_SHADER,
+            Usage::ColorAttachment => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            Usage::DepthAttachment => {
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+            }
+            Usage::DepthRead => {
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER
+            }
+            // Presentation waits on a semaphore, so nothing later in the queue waits.
+            Usage::Present => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        }
+    }
+
+    fn access(self) -> vk::AccessFlags {
+        match self {
+            Usage::TransferSrc => vk::AccessFlags::TRANSFER_READ,
+            Usage::TransferDst => vk::AccessFlags::TRANSFER_WRITE,
+            Usage::Sampled(_) => vk::AccessFlags::SHADER_READ,
+            Usage::Storage => vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            Usage::ColorAttachment => vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            Usage::DepthAttachment => {
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+            Usage::DepthRead => vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::SHADER_READ,
+            Usage::Present => vk::AccessFlags::empty(),
+        }
+    }
+}
+
+const WRITES: vk::AccessFlags = vk::AccessFlags::from_bits_truncate(
+    vk::AccessFlags::SHADER_WRITE.bits()
+        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE.bits()
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.bits()
+        | vk::AccessFlags::TRANSFER_WRITE.bits(),
+);
+
+/// An image's layout and the stages and accesses that used it since its last barrier.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct State {
+    layout: vk::ImageLayout,
+    stages: vk::PipelineStageFlags,
+    access: vk::AccessFlags,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            layout: vk::ImageLayout::UNDEFINED,
+            stages: vk::PipelineStageFlags::TOP_OF_PIPE,
+            access: vk::AccessFlags::empty(),
+        }
+    }
+}
+
+impl State {
+    fn new(usage: Usage) -> Self {
+        Self { layout: usage.layout(), stages: usage.stages(), access: usage.access() }
+    }
+}
+
+/// A queue family ownership transfer, from the first family to the second.
+pub type Families = (u32, u32);
+
+/// Tracks the layout and last use of images, per mip level, as commands are recorded, and
+/// records the barriers moving them between uses. Images it has not seen start out
+/// `UNDEFINED`. Levels always cover every array layer.
+#[derive(Clone, Debug, Default)]
+pub struct ImageStates {
+    /// The state of each mip level, the last one standing for every level after it as well.
+    states: HashMap<vk::Image, Vec<State>>,
+    barriers: Vec<vk::ImageMemoryBarrier>,
+    src_stages: vk::PipelineStageFlags,
+    dst_stages: vk::PipelineStageFlags,
+}
+
+impl ImageStates {
+    /// Records that commands recorded elsewhere, such as a one-time command buffer, left
+    /// `image` ready for `usage`.
+    pub fn set(&mut self, image: vk::Image, usage: Usage) {
+        self.states.insert(image, vec![State::new(usage)]);
+    }
+
+    /// Queues the barrier `image` needs before being used for `usage`, if any. Reads that
+    /// follow reads in the same layout need none. Each level is transitioned at most once
+    /// between flushes.
+    pub fn transition(&mut self, image: vk::Image, aspect_mask: vk::ImageAspectFlags, usage: Usage) {
+        self.transition_levels(image, aspect_mask, 0..vk::REMAINING_MIP_LEVELS, usage);
+    }
+
+    /// Like `transition`, but levels that have to change layout lose their contents, so their
+    /// barriers start from `UNDEFINED`. They still wait for the image's last use.
+    pub fn transition_discarding(&mut self, image: vk::Image, aspect_mask: vk::ImageAspectFlags, usage: Usage) {
+        if let Some(states) = self.states.get_mut(&image) {
+            states.iter_mut()
+                .filter(|s| s.layout != usage.layout())
+                .for_each(|s| s.layout = vk::ImageLayout::UNDEFINED);
+        }
+
+        self.transition(image, aspect_mask, usage);
+    }
+
+    /// Like `transition`, for `levels` only. `levels.end` may be `vk::REMAINING_MIP_LEVELS`.
+    pub fn transition_levels(&mut self, image: vk::Image, aspect_mask: vk::ImageAspectFlags, levels: Range<u32>, usage: Usage) {
+        let new = State::new(usage);
+        let states = self.split(image, &levels);
+        let end = if levels.end == vk::REMAINING_MIP_LEVELS { states.len() } else { levels.end as usize };
+
+        let mut barriers = vec![];
+        let (mut src_stages, mut dst_stages) = (vk::PipelineStageFlags::empty(), vk::PipelineStageFlags::empty());
+        let count = states.len();
+        for (level, state) in states.iter_mut().enumerate().take(end).skip(levels.start as usize) {
+            if state.layout == new.layout && !(state.access | new.access).intersects(WRITES) {
+                state.stages |= new.stages;
+                state.access |= new.access;
+                continue;
+            }
+
+            let level = level as u32;
+            let range = if level as usize + 1 == count { level..vk::REMAINING_MIP_LEVELS } else { level..level + 1 };
+
+            // Only writes need to be made available; earlier reads only need to finish.
+            barriers.push(barrier(image, aspect_mask, range, (*state, new), None));
+            src_stages |= state.stages;
+            dst_stages |= new.stages;
+
+            *state = new;
+        }
+
+        self.barriers.extend(barriers);
+        self.src_stages |= src_stages;
+        self.dst_stages |= dst_stages;
+        self.merge(image);
+    }
+
+    /// Queues the release of the whole of `image`, whose levels share a layout, from the queue
+    /// recording it to another family. It is moved to the layout `usage` needs and no longer
+    /// tracked here; the other queue records the matching `acquire`.
+    pub fn release(&mut self, image: vk::Image, aspect_mask: vk::ImageAspectFlags, families: Families, usage: Usage) {
+        let states = self.states.remove(&image).unwrap_or_else(|| vec![State::default()]);
+        let state = states.iter().fold(State { stages: vk::PipelineStageFlags::empty(), ..states[0] }, |a, s| State {
+            stages: a.stages | s.stages,
+            access: a.access | s.access,
+            ..a
+        });
+
+        // A released image is not used on this queue again, so nothing here waits.
+        let new = State { access: vk::AccessFlags::empty(), ..State::new(usage) };
+        self.barriers.push(barrier(image, aspect_mask, 0..vk::REMAINING_MIP_LEVELS, (state, new), Some(families)));
+        self.src_stages |= state.stages;
+        self.dst_stages |= vk::PipelineStageFlags::BOTTOM_OF_PIPE;
+    }
+
+    /// Queues the acquire matching a `release` of `image` from a queue that last used it for
+    /// `from`, leaving it ready for `usage`.
+    pub fn acquire(&mut self, image: vk::Image, aspect_mask: vk::ImageAspectFlags, families: Families, from: Usage, usage: Usage) {
+        let old = State { stages: vk::PipelineStageFlags::TOP_OF_PIPE, access: vk::AccessFlags::empty(), ..State::new(from) };
+        let new = State::new(usage);
+        self.barriers.push(barrier(image, aspect_mask, 0..vk::REMAINING_MIP_LEVELS, (old, new), Some(families)));
+        self.src_stages |= old.stages;
+        self.dst_stages |= new.stages;
+        self.states.insert(image, vec![new]);
+    }
+
+    /// Records the queued barriers in a single `cmd_pipeline_barrier`.
+    pub unsafe fn cmd_flush(&mut self, device: &Device, command_buffer: vk::CommandBuffer) {
+        if self.barriers.is_empty() {
+            return;
+        }
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            self.src_stages,
+            self.dst_stages,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &self.barriers,
+        );
+
+        self.barriers.clear();
+        self.src_stages = vk::PipelineStageFlags::empty();
+        self.dst_stages = vk::PipelineStageFlags::empty();
+    }
+
+    /// Transitions a single image for `usage` and flushes.
+    pub unsafe fn cmd_use(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        aspect_mask: vk::ImageAspectFlags,
+        usage: Usage,
+    ) {
+        self.transition(image, aspect_mask, usage);
+        self.cmd_flush(device, command_buffer);
+    }
+
+    /// Gives `levels` of `image` their own entries, so they change without the levels around
+    /// them.
+    fn split(&mut self, image: vk::Image, levels: &Range<u32>) -> &mut Vec<State> {
+        let states = self.states.entry(image).or_insert_with(|| vec![State::default()]);
+        let last = if levels.end == vk::REMAINING_MIP_LEVELS { levels.start } else { levels.end };
+        while states.len() <= last as usize {
+            states.push(states[states.len() - 1]);
+        }
+
+        states
+    }
+
+    /// Drops trailing levels that the one before them already stands for.
+    fn merge(&mut self, image: vk::Image) {
+        let states = self.states.get_mut(&image).unwrap();
+        while states.len() > 1 && states[states.len() - 1] == states[states.len() - 2] {
+            states.pop();
+        }
+    }
+}
+
+/// A barrier over `levels` of every layer, between the states `(old, new)`, that also
+/// transfers ownership between `families` if given.
+fn barrier(
+    image: vk::Image,
+    aspect_mask: vk::ImageAspectFlags,
+    levels: Range<u32>,
+    (old, new): (State, State),
+    families: Option<Families>,
+) -> vk::ImageMemoryBarrier {
+    let level_count = if levels.end == vk::REMAINING_MIP_LEVELS { levels.end } else { levels.end - levels.start };
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspect_mask)
+        .base_mip_level(levels.start)
+        .level_count(level_count)
+        .base_array_layer(0)
+        .layer_count(vk::REMAINING_ARRAY_LAYERS);
+
+    let (src_family, dst_family) = families.unwrap_or((vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED));
+
+    vk::ImageMemoryBarrier::builder()
+        .old_layout(old.layout)
+        .new_layout(new.layout)
+        .src_queue_family_index(src_family)
+        .dst_queue_family_index(dst_family)
+        .image(image)
+        .subresource_range(subresource)
+        .src_access_mask(old.access & WRITES)
+        .dst_access_mask(new.access)
+        .build()
+}
+
+/// The aspects of a depth image, which has both transitioned together if it has stencil.
+pub fn depth_aspect(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D32_SFLOAT_S8_UINT | vk::Format::D24_UNORM_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::DEPTH,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vulkanalia::vk::Handle;
+
+    const COLOR: vk::ImageAspectFlags = vk::ImageAspectFlags::COLOR;
+
+    fn levels(barrier: &vk::ImageMemoryBarrier) -> (u32, u32) {
+        (barrier.subresource_range.base_mip_level, barrier.subresource_range.level_count)
+    }
+
+    #[test]
+    fn levels_change_on_their_own_and_merge_back() {
+        let image = vk::Image::from_raw(1);
+        let mut images = ImageStates::default();
+        images.transition_levels(image, COLOR, 0..1, Usage::TransferSrc);
+        images.barriers.clear();
+
+        images.transition_levels(image, COLOR, 1..2, Usage::TransferDst);
+        assert_eq!(images.barriers.len(), 1);
+        assert_eq!(levels(&images.barriers[0]), (1, 1));
+        assert_eq!(images.barriers[0].old_layout, vk::ImageLayout::UNDEFINED);
+        images.barriers.clear();
+
+        images.transition_levels(image, COLOR, 1..2, Usage::TransferSrc);
+        images.barriers.clear();
+
+        // Levels 0 and 1 now share a state, and every level after them another.
+        images.transition(image, COLOR, Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER));
+        let ranges = images.barriers.iter().map(levels).collect::<Vec<_>>();
+        assert_eq!(ranges, vec![(0, 1), (1, 1), (2, vk::REMAINING_MIP_LEVELS)]);
+        assert_eq!(images.states[&image].len(), 1);
+    }
+
+    #[test]
+    fn reads_after_reads_need_no_barrier() {
+        let image = vk::Image::from_raw(1);
+        let mut images = ImageStates::default();
+        images.set(image, Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER));
+
+        images.transition(image, COLOR, Usage::Sampled(vk::PipelineStageFlags::COMPUTE_SHADER));
+        assert!(images.barriers.is_empty());
+
+        images.transition(image, COLOR, Usage::Storage);
+        assert_eq!(images.barriers.len(), 1);
+        assert_eq!(images.src_stages, vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER);
+    }
+
+    #[test]
+    fn discarded_images_wait_for_their_last_use() {
+        let image = vk::Image::from_raw(1);
+        let mut images = ImageStates::default();
+        images.set(image, Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER));
+
+        images.transition_discarding(image, COLOR, Usage::ColorAttachment);
+        assert_eq!(images.barriers.len(), 1);
+        assert_eq!(images.barriers[0].old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(images.barriers[0].src_access_mask, vk::AccessFlags::empty());
+        assert_eq!(images.src_stages, vk::PipelineStageFlags::FRAGMENT_SHADER);
+        images.barriers.clear();
+
+        // Already in the layout, so the contents are kept while the writes are ordered.
+        images.transition_discarding(image, COLOR, Usage::ColorAttachment);
+        assert_eq!(images.barriers[0].old_layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(images.barriers[0].src_access_mask, vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+    }
+
+    #[test]
+    fn release_and_acquire_match() {
+        let image = vk::Image::from_raw(1);
+        let usage = Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER);
+        let mut transfer = ImageStates::default();
+        let mut graphics = ImageStates::default();
+
+        transfer.transition(image, COLOR, Usage::TransferDst);
+        transfer.barriers.clear();
+        transfer.release(image, COLOR, (1, 0), usage);
+        graphics.acquire(image, COLOR, (1, 0), Usage::TransferDst, usage);
+
+        let (release, acquire) = (transfer.barriers[0], graphics.barriers[0]);
+        assert_eq!((release.old_layout, release.new_layout), (acquire.old_layout, acquire.new_layout));
+        assert_eq!((release.src_queue_family_index, release.dst_queue_family_index), (1, 0));
+        assert_eq!((acquire.src_queue_family_index, acquire.dst_queue_family_index), (1, 0));
+        assert_eq!(release.src_access_mask, vk::AccessFlags::TRANSFER_WRITE);
+        assert_eq!(acquire.dst_access_mask, vk::AccessFlags::SHADER_READ);
+        assert!(!transfer.states.contains_key(&image));
+        assert_eq!(graphics.states[&image], vec![State::new(usage)]);
+    }
+}
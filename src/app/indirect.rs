@@ -0,0 +1,480 @@
+use super::app_data;
+use super::compute;
+use super::material;
+use super::picking;
+use super::scene;
+use super::vertex_buffer;
+
+use anyhow::{Result};
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::vk::KhrDrawIndirectCountExtension;
+use nalgebra_glm as glm;
+use std::mem::size_of;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+/// Objects drawn at once by GPU-driven rendering. Frames with more fall back to CPU draws.
+pub const MAX_OBJECTS: usize = 16384;
+
+/// Material batches drawn at once by GPU-driven rendering, as in `cull.comp`.
+pub const MAX_BATCHES: usize = 1024;
+
+pub const OBJECT_BUFFER_SIZE: u64 = (MAX_OBJECTS * size_of::<GpuObject>()) as u64;
+
+pub const TRANSFORM_BUFFER_SIZE: u64 = (MAX_OBJECTS * size_of::<GpuTransform>()) as u64;
+
+const COMMAND_SIZE: u32 = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+
+/// An object as read by `cull.comp` and `shader.vert`. Only rewritten when the draw list
+/// changes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct GpuObject {
+    /// Model space bounding sphere; a negative radius is never culled.
+    sphere: glm::Vec4,
+    index_count: u32,
+    first_index: u32,
+    vertex_offset: i32,
+    joint_offset: i32,
+    object_id: u32,
+    batch: u32,
+    batch_start: u32,
+    _padding: u32,
+}
+
+/// The transform of the object at the same index, rewritten whenever it changes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct GpuTransform {
+    model: glm::Mat4,
+    normal_matrix: [glm::Vec4; 3],
+}
+
+impl GpuTransform {
+    fn new(model: &glm::Mat4) -> Self {
+        Self { model: *model, normal_matrix: vertex_buffer::PushConstants::normal_matrix(model) }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct CullPushConstants {
+    planes: [glm::Vec4; 6],
+    object_count: u32,
+    batch_count: u32,
+    compact: u32,
+    _padding: u32,
+}
+
+/// A range of objects sharing a material, drawn by one indirect call.
+#[derive(Copy, Clone, Debug)]
+pub struct Batch {
+    pub material: Option<usize>,
+    pub first: u32,
+    pub count: u32,
+}
+
+/// What the culling pass and indirect draws of the next recorded frame use, and the draws the
+/// object and transform buffers hold.
+#[derive(Clone, Debug, Default)]
+pub struct IndirectFrame {
+    batches: Vec<Batch>,
+    object_count: u32,
+    planes: [glm::Vec4; 6],
+    /// The draws in the object buffer, in object order, and the index of each in the draw list.
+    draws: Vec<scene::Draw>,
+    order: Vec<usize>,
+    /// Ranges of the staging buffer to copy into the object and transform buffers.
+    object_copies: Vec<vk::BufferCopy>,
+    transform_copies: Vec<vk::BufferCopy>,
+    /// Whether falling back to CPU draws for too many objects or materials was logged.
+    fallback_logged: bool,
+}
+
+/// Whether the next recorded frame culls and draws on the GPU.
+pub fn is_active(data: &app_data::Data) -> bool {
+    data.gpu_driven && !data.indirect_frame.batches.is_empty()
+}
+
+/// Bounding spheres of every primitive in model space, indexed by mesh then primitive.
+pub fn primitive_bounds(scene: &scene::Scene) -> Vec<Vec<glm::Vec4>> {
+    scene.meshes.iter()
+        .map(|m| m.primitives.iter()
+            .map(|p| {
+                let start = p.vertex_offset as usize;
+                let vertices = &scene.vertices[start..start + p.vertex_count as usize];
+
+                let (min, max) = vertices.iter().fold(
+                    (glm::vec3(f32::MAX, f32::MAX, f32::MAX), glm::vec3(f32::MIN, f32::MIN, f32::MIN)),
+                    |(min, max), v| (glm::min2(&min, &v.pos()), glm::max2(&max, &v.pos())),
+                );
+
+                let center = (min + max) * 0.5;
+                let radius = vertices.iter().map(|v| glm::distance(&center, &v.pos())).fold(0.0, f32::max);
+                glm::vec4(center.x, center.y, center.z, radius)
+            })
+            .collect())
+        .collect()
+}
+
+/// Creates the device local object, indirect command and draw count buffers and the culling
+/// kernel. These do not depend on the swapchain.
+pub unsafe fn create_indirect_buffers(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let buffer = |size: u64, usage: vk::BufferUsageFlags| vertex_buffer::create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::STORAGE_BUFFER | usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    );
+
+    // Objects and transforms are kept from frame to frame, so they are shared by both queues
+    // rather than handed back and forth.
+    let kept = |size: u64| vertex_buffer::create_shared_buffer(
+        instance,
+        device,
+        data,
+        &compute::shared_families(data),
+        size,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    );
+
+    let (object_buffer, object_buffer_memory) = kept(OBJECT_BUFFER_SIZE)?;
+    let (transform_buffer, transform_buffer_memory) = kept(TRANSFORM_BUFFER_SIZE)?;
+    let (command_buffer, command_buffer_memory) = buffer((MAX_OBJECTS as u32 * COMMAND_SIZE) as u64, vk::BufferUsageFlags::INDIRECT_BUFFER)?;
+    let (count_buffer, count_buffer_memory) = buffer((MAX_BATCHES * size_of::<u32>()) as u64, vk::BufferUsageFlags::INDIRECT_BUFFER)?;
+
+    data.object_buffer = object_buffer;
+    data.object_buffer_memory = object_buffer_memory;
+    data.transform_buffer = transform_buffer;
+    data.transform_buffer_memory = transform_buffer_memory;
+    data.indirect_command_buffer = command_buffer;
+    data.indirect_command_buffer_memory = command_buffer_memory;
+    data.indirect_count_buffer = count_buffer;
+    data.indirect_count_buffer_memory = count_buffer_memory;
+
+    let bindings = &[
+        compute::Binding::StorageBuffer(object_buffer),
+        compute::Binding::StorageBuffer(command_buffer),
+        compute::Binding::StorageBuffer(count_buffer),
+        compute::Binding::StorageBuffer(transform_buffer),
+    ];
+
+    let cull = include_bytes!("../../shaders/cull.bin");
+    data.cull_kernel = compute::create_kernel(device, &cull[..], bindings, size_of::<CullPushConstants>() as u32)?;
+
+    Ok(())
+}
+
+pub unsafe fn destroy_indirect_buffers(device: &Device, data: &mut app_data::Data) {
+    compute::destroy_kernel(device, &data.cull_kernel);
+    device.free_memory(data.indirect_count_buffer_memory, None);
+    device.destroy_buffer(data.indirect_count_buffer, None);
+    device.free_memory(data.indirect_command_buffer_memory, None);
+    device.destroy_buffer(data.indirect_command_buffer, None);
+    device.free_memory(data.transform_buffer_memory, None);
+    device.destroy_buffer(data.transform_buffer, None);
+    device.free_memory(data.object_buffer_memory, None);
+    device.destroy_buffer(data.object_buffer, None);
+}
+
+/// Creates a host visible staging buffer per swapchain image the objects, then the transforms,
+/// are written to.
+pub unsafe fn create_object_staging_buffers(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    data.object_staging_buffers.clear();
+    data.object_staging_buffers_memory.clear();
+
+    for _ in 0..data.swapchain_images.len() {
+        let (buffer, buffer_memory) = vertex_buffer::create_buffer(
+            instance,
+            device,
+            data,
+            OBJECT_BUFFER_SIZE + TRANSFORM_BUFFER_SIZE,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.object_staging_buffers.push(buffer);
+        data.object_staging_buffers_memory.push(buffer_memory);
+    }
+
+    Ok(())
+}
+
+/// Writes to the staging buffer of `image_index` what changed in `draws` since the last frame:
+/// every object, sorted into material batches, if the draw list or its materials changed, else
+/// only the transforms that moved. Also takes the frustum of `view_proj`, with `model` the scene
+/// transform applied on top of each draw's. Leaves no batches, and so CPU draws, if GPU-driven
+/// rendering is off or the frame has too many objects or materials.
+pub unsafe fn update_objects(
+    device: &Device,
+    data: &mut app_data::Data,
+    image_index: usize,
+    draws: &[scene::Draw],
+    model: &glm::Mat4,
+    view_proj: &glm::Mat4,
+) -> Result<()> {
+    let frame = &mut data.indirect_frame;
+    frame.object_copies.clear();
+    frame.transform_copies.clear();
+
+    let unchanged = data.gpu_driven
+        && frame.order.len() == draws.len()
+        && frame.order.iter().zip(&frame.draws).all(|(i, d)| same_object(&draws[*i], d));
+
+    if !unchanged {
+        frame.batches.clear();
+        frame.draws.clear();
+        frame.order.clear();
+        frame.object_count = 0;
+
+        // Nothing is uploaded while off, so everything is again when turned back on.
+        if !data.gpu_driven || draws.is_empty() {
+            return Ok(());
+        }
+
+        let mut order = (0..draws.len()).collect::<Vec<_>>();
+        order.sort_by_key(|i| draws[*i].material.map(|m| m + 1).unwrap_or(0));
+
+        let mut batches = vec![];
+        for (index, draw) in order.iter().map(|i| &draws[*i]).enumerate() {
+            match batches.last_mut() {
+                Some(Batch { material, count, .. }) if *material == draw.material => *count += 1,
+                _ => batches.push(Batch { material: draw.material, first: index as u32, count: 1 }),
+            }
+        }
+
+        if draws.len() > MAX_OBJECTS || batches.len() > MAX_BATCHES {
+            if !frame.fallback_logged {
+                warn!(
+                    "{} objects in {} materials exceed the GPU-driven limits of {} and {}; drawing from the CPU.",
+                    draws.len(),
+                    batches.len(),
+                    MAX_OBJECTS,
+                    MAX_BATCHES,
+                );
+                frame.fallback_logged = true;
+            }
+
+            return Ok(());
+        }
+
+        let mut objects = Vec::with_capacity(draws.len());
+        for (batch_index, batch) in batches.iter().enumerate() {
+            for index in batch.first..batch.first + batch.count {
+                let draw = &draws[order[index as usize]];
+                let bounds = data.primitive_bounds[draw.mesh][draw.primitive];
+                let radius = if draw.joint_offset.is_some() { -1.0 } else { bounds.w };
+
+                objects.push(GpuObject {
+                    sphere: glm::vec4(bounds.x, bounds.y, bounds.z, radius),
+                    index_count: draw.index_count,
+                    first_index: draw.first_index,
+                    vertex_offset: draw.vertex_offset,
+                    joint_offset: draw.joint_offset.map(|o| o as i32).unwrap_or(-1),
+                    object_id: picking::object_id(draw.node),
+                    batch: batch_index as u32,
+                    batch_start: batch.first,
+                    _padding: 0,
+                });
+            }
+        }
+
+        let transforms = order.iter().map(|i| GpuTransform::new(&draws[*i].transform)).collect::<Vec<_>>();
+
+        let memory = device.map_memory(data.object_staging_buffers_memory[image_index], 0, vk::WHOLE_SIZE as u64, vk::MemoryMapFlags::empty())?;
+        memcpy(objects.as_ptr(), memory.cast(), objects.len());
+        memcpy(transforms.as_ptr(), memory.add(OBJECT_BUFFER_SIZE as usize).cast(), transforms.len());
+        device.unmap_memory(data.object_staging_buffers_memory[image_index]);
+
+        let frame = &mut data.indirect_frame;
+        frame.object_copies.push(vk::BufferCopy::builder().size((objects.len() * size_of::<GpuObject>()) as u64).build());
+        frame.transform_copies.push(vk::BufferCopy::builder()
+            .src_offset(OBJECT_BUFFER_SIZE)
+            .size((transforms.len() * size_of::<GpuTransform>()) as u64)
+            .build());
+
+        frame.draws = order.iter().map(|i| draws[*i]).collect();
+        frame.order = order;
+        frame.batches = batches;
+        frame.object_count = objects.len() as u32;
+    } else {
+        let moved = frame.order.iter()
+            .enumerate()
+            .filter(|(object, index)| draws[**index].transform != frame.draws[*object].transform)
+            .map(|(object, index)| (object, draws[*index].transform))
+            .collect::<Vec<_>>();
+
+        if !moved.is_empty() {
+            let size = size_of::<GpuTransform>() as u64;
+            let memory = device.map_memory(data.object_staging_buffers_memory[image_index], 0, vk::WHOLE_SIZE as u64, vk::MemoryMapFlags::empty())?;
+
+            for (object, transform) in moved {
+                let offset = object as u64 * size;
+                memcpy(&GpuTransform::new(&transform), memory.add((OBJECT_BUFFER_SIZE + offset) as usize).cast(), 1);
+                frame.draws[object].transform = transform;
+
+                // Neighbouring objects are copied together.
+                match frame.transform_copies.last_mut() {
+                    Some(copy) if copy.dst_offset + copy.size == offset => copy.size += size,
+                    _ => frame.transform_copies.push(vk::BufferCopy::builder()
+                        .src_offset(OBJECT_BUFFER_SIZE + offset)
+                        .dst_offset(offset)
+                        .size(size)
+                        .build()),
+                }
+            }
+
+            device.unmap_memory(data.object_staging_buffers_memory[image_index]);
+        }
+    }
+
+    // Culling tests the spheres in scene space, before `model`.
+    data.indirect_frame.planes = frustum_planes(&(view_proj * model));
+
+    Ok(())
+}
+
+/// Whether two draws are the same object, wherever they are.
+fn same_object(a: &scene::Draw, b: &scene::Draw) -> bool {
+    (a.node, a.mesh, a.primitive, a.material) == (b.node, b.mesh, b.primitive, b.material)
+        && (a.first_index, a.index_count, a.vertex_offset, a.joint_offset) == (b.first_index, b.index_count, b.vertex_offset, b.joint_offset)
+}
+
+/// Normalized planes bounding the clip volume of `view_proj`, facing inwards.
+fn frustum_planes(view_proj: &glm::Mat4) -> [glm::Vec4; 6] {
+    let row = |r: usize| view_proj.row(r).transpose();
+    let normalize = |p: glm::Vec4| p / glm::length(&p.xyz());
+    [
+        normalize(row(3) + row(0)),
+        normalize(row(3) - row(0)),
+        normalize(row(3) + row(1)),
+        normalize(row(3) - row(1)),
+        normalize(row(2)),
+        normalize(row(3) - row(2)),
+    ]
+}
+
+/// Uploads what changed in the objects and transforms of `image_index`, then culls them into
+/// indirect draw commands.
+pub unsafe fn cmd_cull(device: &Device, command_buffer: vk::CommandBuffer, data: &app_data::Data, image_index: usize) {
+    let frame = &data.indirect_frame;
+
+    // The previous frame must be done drawing from the buffers before they are rewritten. On
+    // the compute queue, drawing is waited for by a semaphore. The draw commands are not kept,
+    // so they are not handed back.
+    let previous = if data.async_compute {
+        vk::PipelineStageFlags::COMPUTE_SHADER
+    } else {
+        vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER
+    };
+
+    compute::cmd_memory_barrier(
+        device,
+        command_buffer,
+        (previous, vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER),
+        (vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE),
+    );
+
+    let staging_buffer = data.object_staging_buffers[image_index];
+    if !frame.object_copies.is_empty() {
+        device.cmd_copy_buffer(command_buffer, staging_buffer, data.object_buffer, &frame.object_copies);
+    }
+    if !frame.transform_copies.is_empty() {
+        device.cmd_copy_buffer(command_buffer, staging_buffer, data.transform_buffer, &frame.transform_copies);
+    }
+
+    let readers = if data.async_compute {
+        vk::PipelineStageFlags::COMPUTE_SHADER
+    } else {
+        vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::VERTEX_SHADER
+    };
+
+    compute::cmd_memory_barrier(
+        device,
+        command_buffer,
+        (vk::PipelineStageFlags::TRANSFER, readers),
+        (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ),
+    );
+
+    let push_constants = CullPushConstants {
+        planes: frame.planes,
+        object_count: frame.object_count,
+        batch_count: frame.batches.len() as u32,
+        compact: data.draw_indirect_count as u32,
+        _padding: 0,
+    };
+
+    let push_constants = std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<CullPushConstants>());
+    compute::cmd_dispatch(device, command_buffer, &data.cull_kernel, push_constants, [1, 1, 1]);
+
+    if data.async_compute {
+        let src = (vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE);
+        compute::cmd_release_buffers(device, command_buffer, &shared_buffers(data), (data.compute_family, data.graphics_family), src);
+    } else {
+        compute::cmd_compute_to_graphics_barrier(device, command_buffer);
+    }
+}
+
+/// Takes the draw commands culled on the compute queue for drawing.
+pub unsafe fn cmd_acquire_for_drawing(device: &Device, command_buffer: vk::CommandBuffer, data: &app_data::Data) {
+    let dst = (
+        vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_SHADER,
+        vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::SHADER_READ,
+    );
+    compute::cmd_acquire_buffers(device, command_buffer, &shared_buffers(data), (data.compute_family, data.graphics_family), dst);
+}
+
+/// Buffers written by culling and read by the draws, owned by one queue at a time.
+fn shared_buffers(data: &app_data::Data) -> [vk::Buffer; 2] {
+    [data.indirect_command_buffer, data.indirect_count_buffer]
+}
+
+/// Draws the culled objects with one indirect call per material batch. The scene pipeline and
+/// buffers must be bound.
+pub unsafe fn cmd_draw_objects(device: &Device, command_buffer: vk::CommandBuffer, data: &app_data::Data) {
+    let push_constants = vertex_buffer::PushConstants {
+        model: glm::identity(),
+        normal_matrix: vertex_buffer::PushConstants::normal_matrix(&glm::identity()),
+        joint_offset: -1,
+        object_id: 0,
+        indirect: 1,
+    };
+
+    device.cmd_push_constants(
+        command_buffer,
+        data.pipeline_layout,
+        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        0,
+        std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<vertex_buffer::PushConstants>()),
+    );
+
+    for (index, batch) in data.indirect_frame.batches.iter().enumerate() {
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            data.pipeline_layout,
+            1,
+            &[material::descriptor_set(data, batch.material)],
+            &[],
+        );
+
+        let offset = (batch.first * COMMAND_SIZE) as u64;
+        if data.draw_indirect_count {
+            let count_offset = (index * size_of::<u32>()) as u64;
+            device.cmd_draw_indexed_indirect_count_khr(
+                command_buffer,
+                data.indirect_command_buffer,
+                offset,
+                data.indirect_count_buffer,
+                count_offset,
+                batch.count,
+                COMMAND_SIZE,
+            );
+        } else {
+            device.cmd_draw_indexed_indirect(command_buffer, data.indirect_command_buffer, offset, batch.count, COMMAND_SIZE);
+        }
+    }
+}
@@ -7,17 +7,25 @@ use std::collections::HashSet;
 use log::*;
 use vulkanalia::prelude::v1_0::*;
 use vulkanalia::window as vk_window;
+use vulkanalia::Version;
 use winit::window::{Window};
 
 use vulkanalia::vk::ExtDebugUtilsExtension;
 
 pub unsafe fn create(window: &Window, entry: &Entry, data: &mut app_data::Data) -> Result<Instance> {
+    // Vulkan 1.0 loaders reject any other version, later ones accept versions they don't know.
+    data.api_version = if entry.version()? >= Version::V1_1_0 {
+        app_defines::API_VERSION
+    } else {
+        vk::make_version(1, 0, 0)
+    };
+
     let application_info = vk::ApplicationInfo::builder()
         .application_name(b"Vulkan Tutorial (Rust)\0")
         .application_version(vk::make_version(1, 0, 0))
         .engine_name(b"No Engine\0")
         .engine_version(vk::make_version(1, 0, 0))
-        .api_version(vk::make_version(1, 0, 0));
+        .api_version(data.api_version);
 
     // Layers
 
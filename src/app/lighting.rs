@@ -0,0 +1,180 @@
+use super::app_data;
+use super::app_defines;
+use super::environment;
+use super::vertex_buffer;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+use std::ptr::copy_nonoverlapping as memcpy;
+use std::mem::size_of;
+use nalgebra_glm as glm;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Shading {
+    BlinnPhong,
+    /// Cook-Torrance metallic-roughness with a GGX distribution.
+    Pbr,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightKind {
+    /// Parallel light travelling along `direction`.
+    Directional { direction: glm::Vec3 },
+    Point { position: glm::Vec3 },
+    /// A point light limited to a cone; angles are half-angles in radians, faded from inner to outer.
+    Spot { position: glm::Vec3, direction: glm::Vec3, inner_angle: f32, outer_angle: f32 },
+}
+
+/// A light with Blinn-Phong terms; PBR shading uses `diffuse` as the radiance and `ambient` as
+/// a constant fill. Point and spot lights fall off with `1 / (c + l·d + q·d²)`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub ambient: glm::Vec3,
+    pub diffuse: glm::Vec3,
+    pub specular: glm::Vec3,
+    /// Constant, linear and quadratic attenuation.
+    pub attenuation: glm::Vec3,
+}
+
+impl Light {
+    pub fn directional(direction: glm::Vec3, color: glm::Vec3) -> Self {
+        Self::new(LightKind::Directional { direction }, color)
+    }
+
+    pub fn point(position: glm::Vec3, color: glm::Vec3) -> Self {
+        Self::new(LightKind::Point { position }, color)
+    }
+
+    pub fn spot(position: glm::Vec3, direction: glm::Vec3, inner_angle: f32, outer_angle: f32, color: glm::Vec3) -> Self {
+        Self::new(LightKind::Spot { position, direction, inner_angle, outer_angle }, color)
+    }
+
+    fn new(kind: LightKind, color: glm::Vec3) -> Self {
+        Self {
+            kind,
+            ambient: color * 0.1,
+            diffuse: color,
+            specular: color,
+            attenuation: glm::vec3(1.0, 0.09, 0.032),
+        }
+    }
+}
+
+/// `Light` in the std140 layout of `shader.frag`. `position.w` holds the kind.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct LightData {
+    position: glm::Vec4,
+    direction: glm::Vec4,
+    ambient: glm::Vec4,
+    diffuse: glm::Vec4,
+    specular: glm::Vec4,
+    attenuation: glm::Vec4,
+    /// Cosines of the inner and outer spot angles.
+    cone: glm::Vec4,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct LightsUniform {
+    camera_position: glm::Vec4,
+    count: u32,
+    /// Blinn-Phong specular exponent.
+    shininess: f32,
+    shading: u32,
+    ambient_occlusion: u32,
+    environment: u32,
+    /// Scale of the image-based ambient light.
+    environment_intensity: f32,
+    _padding: [u32; 2],
+    lights: [LightData; app_defines::MAX_LIGHTS],
+}
+
+impl LightsUniform {
+    /// Packs the first `MAX_LIGHTS` of `lights`, given in world space.
+    pub fn new(lights: &[Light], camera_position: glm::Vec3, shininess: f32, shading: Shading) -> Self {
+        let mut uniform = Self {
+            camera_position: glm::vec3_to_vec4(&camera_position),
+            count: lights.len().min(app_defines::MAX_LIGHTS) as u32,
+            shininess,
+            shading: shading as u32,
+            ambient_occlusion: 0,
+            environment: 0,
+            environment_intensity: 0.0,
+            _padding: [0; 2],
+            lights: [LightData::default(); app_defines::MAX_LIGHTS],
+        };
+
+        for (data, light) in uniform.lights.iter_mut().zip(lights) {
+            let (kind, position, direction, cone) = match light.kind {
+                LightKind::Directional { direction } => (0.0, glm::Vec3::zeros(), direction, glm::Vec2::zeros()),
+                LightKind::Point { position } => (1.0, position, glm::Vec3::zeros(), glm::Vec2::zeros()),
+                LightKind::Spot { position, direction, inner_angle, outer_angle } => {
+                    (2.0, position, direction, glm::vec2(inner_angle.cos(), outer_angle.cos()))
+                }
+            };
+
+            *data = LightData {
+                position: glm::vec4(position.x, position.y, position.z, kind),
+                direction: glm::vec3_to_vec4(&direction.try_normalize(f32::EPSILON).unwrap_or_else(glm::Vec3::zeros)),
+                ambient: glm::vec3_to_vec4(&light.ambient),
+                diffuse: glm::vec3_to_vec4(&light.diffuse),
+                specular: glm::vec3_to_vec4(&light.specular),
+                attenuation: glm::vec3_to_vec4(&light.attenuation),
+                cone: glm::vec4(cone.x, cone.y, 0.0, 0.0),
+            };
+        }
+
+        uniform
+    }
+
+    /// Has shading darken ambient light by the screen-space ambient occlusion.
+    pub fn with_ambient_occlusion(mut self, enabled: bool) -> Self {
+        self.ambient_occlusion = enabled as u32;
+        self
+    }
+
+    /// Has shading add light from the environment maps, and the deferred path show the sky.
+    pub fn with_environment(mut self, environment: &environment::Environment) -> Self {
+        self.environment = environment.enabled as u32;
+        self.environment_intensity = environment.intensity;
+        self
+    }
+}
+
+pub unsafe fn create_light_buffers(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    data.light_buffers.clear();
+    data.light_buffers_memory.clear();
+
+    for _ in 0..data.swapchain_images.len() {
+        let (light_buffer, light_buffer_memory) = vertex_buffer::create_buffer(
+            instance,
+            device,
+            data,
+            size_of::<LightsUniform>() as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.light_buffers.push(light_buffer);
+        data.light_buffers_memory.push(light_buffer_memory);
+    }
+
+    Ok(())
+}
+
+pub unsafe fn update_light_buffer(device: &Device, data: &app_data::Data, image_index: usize, uniform: &LightsUniform) -> Result<()> {
+    let memory = device.map_memory(
+        data.light_buffers_memory[image_index],
+        0,
+        size_of::<LightsUniform>() as u64,
+        vk::MemoryMapFlags::empty(),
+    )?;
+
+    memcpy(uniform, memory.cast(), 1);
+
+    device.unmap_memory(data.light_buffers_memory[image_index]);
+
+    Ok(())
+}
@@ -6,6 +6,8 @@ use anyhow::{Result};
 use std::collections::HashSet;
 use vulkanalia::prelude::v1_0::*;
 
+use vulkanalia::vk::InstanceV1_1;
+
 pub unsafe fn create(instance: &Instance, data: &mut app_data::Data) -> Result<Device> {
     // Queue Create Infos
 
@@ -14,6 +16,8 @@ pub unsafe fn create(instance: &Instance, data: &mut app_data::Data) -> Result<D
     let mut unique_indices = HashSet::new();
     unique_indices.insert(indices.graphics);
     unique_indices.insert(indices.present);
+    unique_indices.insert(indices.compute);
+    unique_indices.insert(indices.transfer);
 
     let queue_priorities = &[1.0];
     let queue_infos = unique_indices
@@ -35,26 +39,107 @@ pub unsafe fn create(instance: &Instance, data: &mut app_data::Data) -> Result<D
 
     // Extensions
 
-    let extensions = app_defines::DEVICE_EXTENSIONS.iter().map(|n| n.as_ptr()).collect::<Vec<_>>();
+    let mut extensions = app_defines::DEVICE_EXTENSIONS.iter().map(|n| n.as_ptr()).collect::<Vec<_>>();
 
     // Features
 
-    let features = vk::PhysicalDeviceFeatures::builder();
+    // GPU-driven rendering issues many indirect draws per call, each instance indexing its object.
+    let supported = instance.get_physical_device_features(data.physical_device);
+    data.gpu_driven_supported = supported.multi_draw_indirect == vk::TRUE && supported.draw_indirect_first_instance == vk::TRUE;
+    data.gpu_driven = data.gpu_driven_supported;
+
+    let available = instance.enumerate_device_extension_properties(data.physical_device, None)?;
+    data.draw_indirect_count = data.gpu_driven_supported
+        && available.iter().any(|e| e.extension_name == vk::KHR_DRAW_INDIRECT_COUNT_EXTENSION.name);
+
+    if data.draw_indirect_count {
+        extensions.push(vk::KHR_DRAW_INDIRECT_COUNT_EXTENSION.name.as_ptr());
+    }
+
+    let features = vk::PhysicalDeviceFeatures::builder()
+        .multi_draw_indirect(data.gpu_driven_supported)
+        .draw_indirect_first_instance(data.gpu_driven_supported);
+
+    // Frames signal a timeline semaphore (core in Vulkan 1.2) when available, else fences. The
+    // per-frame passes use dynamic rendering (core in Vulkan 1.3) when available, else render passes.
+    let newer = NewerFeatures::get(instance, data, &available);
+    data.timeline = newer.timeline;
+    data.dynamic_rendering_supported = newer.dynamic_rendering;
+    data.dynamic_rendering = newer.dynamic_rendering;
+    data.dynamic_rendering_khr = newer.dynamic_rendering && newer.version < vk::make_version(1, 3, 0);
+
+    if data.dynamic_rendering_khr {
+        extensions.push(vk::KHR_DYNAMIC_RENDERING_EXTENSION.name.as_ptr());
+    }
+
+    let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::builder().timeline_semaphore(true);
+    let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::builder().dynamic_rendering(true);
 
     // Create
 
-    let info = vk::DeviceCreateInfo::builder()
+    let mut info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_infos)
         .enabled_layer_names(&layers)
         .enabled_extension_names(&extensions)
         .enabled_features(&features);
 
+    if data.timeline {
+        info = info.push_next(&mut timeline_features);
+    }
+
+    if data.dynamic_rendering_supported {
+        info = info.push_next(&mut dynamic_rendering_features);
+    }
+
     let device = instance.create_device(data.physical_device, &info, None)?;
 
     // Queues
 
     data.graphics_queue = device.get_device_queue(indices.graphics, 0);
     data.present_queue = device.get_device_queue(indices.present, 0);
+    data.compute_queue = device.get_device_queue(indices.compute, 0);
+    data.transfer_queue = device.get_device_queue(indices.transfer, 0);
+
+    data.graphics_family = indices.graphics;
+    data.compute_family = indices.compute;
+    data.particles_owner = indices.compute;
+    data.async_compute = indices.compute != indices.graphics;
 
     Ok(device)
 }
+
+/// Features of Vulkan 1.2 and later, queried when both the instance and device are new enough.
+struct NewerFeatures {
+    /// The Vulkan version both the instance and device support.
+    version: u32,
+    timeline: bool,
+    dynamic_rendering: bool,
+}
+
+impl NewerFeatures {
+    unsafe fn get(instance: &Instance, data: &app_data::Data, extensions: &[vk::ExtensionProperties]) -> Self {
+        let properties = instance.get_physical_device_properties(data.physical_device);
+        let version = data.api_version.min(properties.api_version);
+        if version < vk::make_version(1, 2, 0) {
+            return Self { version, timeline: false, dynamic_rendering: false };
+        }
+
+        // Before Vulkan 1.3, dynamic rendering needs its extension.
+        let dynamic_rendering_available = version >= vk::make_version(1, 3, 0)
+            || extensions.iter().any(|e| e.extension_name == vk::KHR_DYNAMIC_RENDERING_EXTENSION.name);
+
+        let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::builder();
+        let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::builder();
+        let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut timeline_features);
+        if dynamic_rendering_available {
+            features = features.push_next(&mut dynamic_rendering_features);
+        }
+        instance.get_physical_device_features2(data.physical_device, &mut features);
+
+        Self {
+            version,
+            timeline: timeline_features.timeline_semaphore == vk::TRUE,
+            dynamic_rendering: dynamic_rendering_features.dynamic_rendering == vk::TRUE,
+        }
+    }
+}
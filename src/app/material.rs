@@ -0,0 +1,236 @@
+use super::app_data;
+use super::image;
+use super::scene;
+use super::upload;
+use super::vertex_buffer;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+use std::ptr::copy_nonoverlapping as memcpy;
+use std::mem::size_of;
+use nalgebra_glm as glm;
+
+/// Texture bindings of the material set, after the uniform at binding 0.
+const TEXTURE_COUNT: u32 = 5;
+
+/// Binding of the sampler shared by every material texture.
+const SAMPLER_BINDING: u32 = TEXTURE_COUNT + 1;
+
+/// `Material` in the std140 layout of `shader.frag`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialUniform {
+    pub base_color_factor: glm::Vec4,
+    pub emissive_factor: glm::Vec4,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub alpha_cutoff: f32,
+    /// Zero for opaque, one for masked; blended materials are drawn opaque.
+    pub alpha_mask: u32,
+    pub has_normal_texture: u32,
+    _padding: u32,
+}
+
+impl MaterialUniform {
+    pub fn new(material: &scene::Material) -> Self {
+        Self {
+            base_color_factor: material.base_color_factor,
+            emissive_factor: glm::vec3_to_vec4(&material.emissive_factor),
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+            alpha_cutoff: material.alpha_cutoff,
+            alpha_mask: (material.alpha_mode == scene::AlphaMode::Mask) as u32,
+            has_normal_texture: material.normal_texture.is_some() as u32,
+            _padding: 0,
+        }
+    }
+}
+
+/// Set 1: the material uniform, its base color, metallic-roughness, normal, occlusion and
+/// emissive textures, and a shared sampler.
+pub unsafe fn create_descriptor_set_layout(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let mut bindings = vec![
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+    ];
+
+    for binding in 1..=TEXTURE_COUNT {
+        bindings.push(vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build());
+    }
+
+    bindings.push(vk::DescriptorSetLayoutBinding::builder()
+        .binding(SAMPLER_BINDING)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .build());
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    data.material_descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    Ok(())
+}
+
+pub unsafe fn create_texture_sampler(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::REPEAT)
+        .address_mode_v(vk::SamplerAddressMode::REPEAT)
+        .address_mode_w(vk::SamplerAddressMode::REPEAT)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR);
+
+    data.texture_sampler = device.create_sampler(&info, None)?;
+
+    Ok(())
+}
+
+/// Creates a uniform buffer and descriptor set for every material of `scene`, plus a trailing
+/// default material for primitives without one. Textures must already be in
+/// `data.texture_image_views`, indexed like `scene.images`; missing textures are replaced by
+/// 1×1 defaults appended after them.
+pub unsafe fn create_materials(
+    instance: &Instance,
+    device: &Device,
+    data: &mut app_data::Data,
+    uploads: &mut upload::Batch,
+    scene: &scene::Scene,
+) -> Result<()> {
+    // Defaults
+
+    let white = scene::ImageData { width: 1, height: 1, pixels: vec![255, 255, 255, 255], srgb: false };
+    let flat_normal = scene::ImageData { width: 1, height: 1, pixels: vec![128, 128, 255, 255], srgb: false };
+
+    let white_index = data.texture_image_views.len();
+    for default in [white, flat_normal] {
+        let (texture_image, texture_image_memory, texture_image_view) = image::create_texture_image(instance, device, data, uploads, &default)?;
+        data.texture_images.push(texture_image);
+        data.texture_images_memory.push(texture_image_memory);
+        data.texture_image_views.push(texture_image_view);
+    }
+
+    let materials = scene.materials.iter().cloned().chain([scene::Material::default()]).collect::<Vec<_>>();
+
+    // Pool
+
+    let count = materials.len() as u32;
+    let ubo_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(count);
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(count * TEXTURE_COUNT);
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLER)
+        .descriptor_count(count);
+
+    let pool_sizes = &[ubo_size, image_size, sampler_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(count);
+
+    data.material_descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let layouts = vec![data.material_descriptor_set_layout; materials.len()];
+    let info = vk::DescriptorSetAllocateInfo::builder().descriptor_pool(data.material_descriptor_pool).set_layouts(&layouts);
+
+    data.material_descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    // Materials
+
+    for (material, descriptor_set) in materials.iter().zip(data.material_descriptor_sets.clone()) {
+        let uniform = MaterialUniform::new(material);
+
+        let (buffer, buffer_memory) = vertex_buffer::create_buffer(
+            instance,
+            device,
+            data,
+            size_of::<MaterialUniform>() as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        let memory = device.map_memory(buffer_memory, 0, size_of::<MaterialUniform>() as u64, vk::MemoryMapFlags::empty())?;
+        memcpy(&uniform, memory.cast(), 1);
+        device.unmap_memory(buffer_memory);
+
+        data.material_buffers.push(buffer);
+        data.material_buffers_memory.push(buffer_memory);
+
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(buffer)
+            .offset(0)
+            .range(size_of::<MaterialUniform>() as u64);
+
+        let buffer_info = &[info];
+        let ubo_write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(buffer_info);
+
+        let textures = [
+            material.base_color_texture.unwrap_or(white_index),
+            material.metallic_roughness_texture.unwrap_or(white_index),
+            material.normal_texture.unwrap_or(white_index + 1),
+            material.occlusion_texture.unwrap_or(white_index),
+            material.emissive_texture.unwrap_or(white_index),
+        ];
+
+        let image_infos = textures.map(|t| [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(data.texture_image_views[t])
+            .build()]);
+
+        let mut writes = vec![ubo_write.build()];
+        for (binding, image_info) in (1..).zip(&image_infos) {
+            writes.push(vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(image_info)
+                .build());
+        }
+
+        let sampler_info = &[vk::DescriptorImageInfo::builder().sampler(data.texture_sampler)];
+        writes.push(vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(SAMPLER_BINDING)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(sampler_info)
+            .build());
+
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    Ok(())
+}
+
+/// Descriptor set of `material`, or of the default material.
+pub fn descriptor_set(data: &app_data::Data, material: Option<usize>) -> vk::DescriptorSet {
+    let default = data.material_descriptor_sets.len() - 1;
+    data.material_descriptor_sets[material.unwrap_or(default)]
+}
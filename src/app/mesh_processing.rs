@@ -0,0 +1,536 @@
+use super::scene;
+use super::vertex_buffer;
+
+use nalgebra_glm as glm;
+use std::collections::{HashMap, HashSet};
+
+/// Vertex cache size assumed when reordering triangles.
+const CACHE_SIZE: usize = 32;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Options {
+    /// Maximum number of simplified levels generated per primitive.
+    pub lod_count: usize,
+    /// Target index count of each level relative to the previous one.
+    pub lod_ratio: f32,
+    /// Largest simplification error allowed, relative to the primitive's bounding radius.
+    pub max_error: f32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { lod_count: 4, lod_ratio: 0.5, max_error: 0.05 }
+    }
+}
+
+/// Optimizes every primitive of `scene` for the post-transform vertex cache and vertex fetch
+/// and appends a chain of simplified levels of detail. Rebuilds `scene.indices`.
+pub fn optimize(scene: &mut scene::Scene, options: &Options) {
+    let mut indices = Vec::with_capacity(scene.indices.len());
+
+    for mesh in &mut scene.meshes {
+        for primitive in &mut mesh.primitives {
+            let base = primitive.vertex_offset as usize;
+            let count = primitive.vertex_count as usize;
+            let first = primitive.first_index as usize;
+
+            let positions = scene.vertices[base..base + count].iter().map(|v| v.pos()).collect::<Vec<_>>();
+            let original = &scene.indices[first..first + primitive.index_count as usize];
+
+            // Levels
+
+            let lod0 = optimize_vertex_cache(original, count);
+            let mut levels = vec![(lod0, 0.0)];
+
+            let max_error = options.max_error * bounding_radius(&positions);
+            for level in 1..=options.lod_count {
+                let target = (levels[0].0.len() as f32 * options.lod_ratio.powi(level as i32)) as usize / 3 * 3;
+                let previous = levels.last().unwrap().0.len();
+                if target < 3 {
+                    break;
+                }
+
+                let (simplified, error) = simplify(&positions, &levels[0].0, target, max_error);
+                if simplified.is_empty() || simplified.len() as f32 > previous as f32 * 0.9 {
+                    break;
+                }
+
+                levels.push((optimize_vertex_cache(&simplified, count), error));
+            }
+
+            // Vertex Fetch
+
+            let remap = vertex_fetch_remap(&levels[0].0, count);
+            let original_vertices = scene.vertices[base..base + count].to_vec();
+            for (old, new) in remap.iter().enumerate() {
+                scene.vertices[base + *new as usize] = original_vertices[old];
+            }
+
+            // Indices
+
+            primitive.first_index = indices.len() as u32;
+            primitive.lods.clear();
+
+            for (level, (level_indices, error)) in levels.iter().enumerate() {
+                let first_index = indices.len() as u32;
+                indices.extend(level_indices.iter().map(|i| remap[*i as usize]));
+                if level > 0 {
+                    primitive.lods.push(scene::Lod { first_index, index_count: level_indices.len() as u32, error: *error });
+                }
+            }
+        }
+    }
+
+    scene.indices = indices;
+}
+
+/// Chooses a level of detail from the projected size of a primitive's simplification error.
+#[derive(Copy, Clone, Debug)]
+pub struct LodSelector {
+    pub view: glm::Mat4,
+    /// `proj[(1, 1)]` of the projection matrix: the cotangent of half the vertical field of view.
+    pub projection_scale: f32,
+    pub viewport_height: f32,
+    /// Largest on-screen error, in pixels, a level may introduce.
+    pub max_pixel_error: f32,
+}
+
+impl LodSelector {
+    /// The index and count of the coarsest acceptable level of `primitive` drawn with `transform`.
+    pub fn select(&self, primitive: &scene::Primitive, transform: &glm::Mat4) -> (u32, u32) {
+        let origin = self.view * transform * glm::vec4(0.0, 0.0, 0.0, 1.0);
+        let distance = origin.xyz().norm().max(f32::EPSILON);
+
+        let scale = (0..3).map(|c| transform.column(c).xyz().norm()).fold(0.0f32, f32::max);
+        let pixels_per_unit = self.projection_scale * self.viewport_height * 0.5 / distance;
+
+        primitive.lods.iter()
+            .rev()
+            .find(|l| l.error * scale * pixels_per_unit <= self.max_pixel_error)
+            .map(|l| (l.first_index, l.index_count))
+            .unwrap_or((primitive.first_index, primitive.index_count))
+    }
+
+    pub fn apply(&self, scene: &scene::Scene, draws: &mut [scene::Draw]) {
+        for draw in draws {
+            let primitive = &scene.meshes[draw.mesh].primitives[draw.primitive];
+            (draw.first_index, draw.index_count) = self.select(primitive, &draw.transform);
+        }
+    }
+}
+
+/// Overwrites the normals of `vertices` with the area-weighted average of the faces using them.
+/// `indices` are relative to the start of `vertices`.
+pub fn compute_normals(vertices: &mut [vertex_buffer::Vertex], indices: &[u32]) {
+    let mut normals = vec![glm::Vec3::zeros(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| vertices[triangle[i] as usize].pos());
+        let normal = glm::cross(&(b - a), &(c - a));
+        triangle.iter().for_each(|v| normals[*v as usize] += normal);
+    }
+
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        let normal = if normal.norm() > 0.0 { normal.normalize() } else { glm::vec3(0.0, 0.0, 1.0) };
+        *vertex = vertex.with_normal(normal);
+    }
+}
+
+fn bounding_radius(positions: &[glm::Vec3]) -> f32 {
+    let center = positions.iter().fold(glm::Vec3::zeros(), |a, p| a + p) / positions.len().max(1) as f32;
+    positions.iter().map(|p| glm::distance(p, &center)).fold(0.0, f32::max)
+}
+
+// Vertex Cache
+
+fn cache_score(cache_position: Option<usize>, live_triangles: usize) -> f32 {
+    if live_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache = match cache_position {
+        Some(p) if p < 3 => 0.75,
+        Some(p) => (1.0 - (p - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(1.5),
+        None => 0.0,
+    };
+
+    cache + 2.0 * (live_triangles as f32).powf(-0.5)
+}
+
+/// Reorders triangles to reduce post-transform vertex cache misses (Forsyth's algorithm).
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+
+    let mut adjacency = vec![vec![]; vertex_count];
+    for (triangle, vertices) in indices.chunks_exact(3).enumerate() {
+        vertices.iter().for_each(|v| adjacency[*v as usize].push(triangle));
+    }
+
+    let mut cache_position = vec![None; vertex_count];
+    let mut vertex_scores = adjacency.iter().map(|a| cache_score(None, a.len())).collect::<Vec<_>>();
+    let triangle_score = |t: usize, scores: &[f32]| indices[t * 3..t * 3 + 3].iter().map(|v| scores[*v as usize]).sum::<f32>();
+
+    let mut emitted = vec![false; triangle_count];
+    let mut cache: Vec<u32> = vec![];
+    let mut result = Vec::with_capacity(indices.len());
+    let mut best = (0..triangle_count).max_by(|a, b| triangle_score(*a, &vertex_scores).total_cmp(&triangle_score(*b, &vertex_scores)));
+    let mut cursor = 0;
+
+    while result.len() < triangle_count * 3 {
+        let triangle = match best {
+            Some(t) => t,
+            None => {
+                while emitted[cursor] {
+                    cursor += 1;
+                }
+                cursor
+            }
+        };
+
+        let vertices = &indices[triangle * 3..triangle * 3 + 3];
+        result.extend_from_slice(vertices);
+        emitted[triangle] = true;
+
+        for v in vertices {
+            adjacency[*v as usize].retain(|t| *t != triangle);
+        }
+
+        let mut new_cache = vertices.to_vec();
+        new_cache.extend(cache.iter().filter(|v| !vertices.contains(v)));
+
+        for (position, v) in new_cache.iter().enumerate() {
+            cache_position[*v as usize] = if position < CACHE_SIZE { Some(position) } else { None };
+            vertex_scores[*v as usize] = cache_score(cache_position[*v as usize], adjacency[*v as usize].len());
+        }
+
+        best = None;
+        let mut best_score = f32::MIN;
+        for v in &new_cache {
+            for t in &adjacency[*v as usize] {
+                let score = triangle_score(*t, &vertex_scores);
+                if score > best_score {
+                    best = Some(*t);
+                    best_score = score;
+                }
+            }
+        }
+
+        new_cache.truncate(CACHE_SIZE);
+        cache = new_cache;
+    }
+
+    result
+}
+
+// Vertex Fetch
+
+/// New position of each vertex so that vertices are stored in the order they are first used.
+pub fn vertex_fetch_remap(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let mut remap = vec![u32::MAX; vertex_count];
+    let mut next = 0;
+
+    for index in indices.iter().map(|i| *i as usize).chain(0..vertex_count) {
+        if remap[index] == u32::MAX {
+            remap[index] = next;
+            next += 1;
+        }
+    }
+
+    remap
+}
+
+// Simplification
+
+/// A symmetric 4×4 error quadric (upper triangle) with its accumulated area.
+#[derive(Copy, Clone, Debug, Default)]
+struct Quadric {
+    a: [f64; 10],
+    weight: f64,
+}
+
+impl Quadric {
+    fn from_plane(normal: glm::DVec3, distance: f64, weight: f64) -> Self {
+        let [x, y, z] = [normal.x, normal.y, normal.z];
+        let d = distance;
+        let a = [x * x, x * y, x * z, x * d, y * y, y * z, y * d, z * z, z * d, d * d].map(|v| v * weight);
+        Self { a, weight }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        self.a.iter_mut().zip(other.a).for_each(|(a, b)| *a += b);
+        self.weight += other.weight;
+    }
+
+    /// Mean squared distance of `p` to the accumulated planes.
+    fn error(&self, p: &glm::Vec3) -> f64 {
+        let [x, y, z] = [p.x as f64, p.y as f64, p.z as f64];
+        let a = &self.a;
+        let sum = a[0] * x * x + a[4] * y * y + a[7] * z * z + a[9]
+            + 2.0 * (a[1] * x * y + a[2] * x * z + a[5] * y * z + a[3] * x + a[6] * y + a[8] * z);
+        (sum / self.weight.max(f64::EPSILON)).max(0.0)
+    }
+}
+
+/// Collapses edges in order of quadric error until `indices` has at most `target_index_count`
+/// indices or the next collapse would exceed `max_error`. Vertices on borders and attribute
+/// seams are never moved. Returns the simplified indices and the largest error introduced.
+pub fn simplify(positions: &[glm::Vec3], indices: &[u32], target_index_count: usize, max_error: f32) -> (Vec<u32>, f32) {
+    let vertex_count = positions.len();
+
+    // Quadrics
+
+    let mut quadrics = vec![Quadric::default(); vertex_count];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| glm::convert::<glm::Vec3, glm::DVec3>(positions[triangle[i] as usize]));
+        let cross = glm::cross(&(b - a), &(c - a));
+        let area = cross.norm();
+        if area > 0.0 {
+            let normal = cross / area;
+            let quadric = Quadric::from_plane(normal, -glm::dot(&normal, &a), area * 0.5);
+            triangle.iter().for_each(|v| quadrics[*v as usize].add(&quadric));
+        }
+    }
+
+    // Locked Vertices
+
+    let mut locked = vec![false; vertex_count];
+
+    let mut edges = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+        for (a, b) in [(triangle[0], triangle[1]), (triangle[1], triangle[2]), (triangle[2], triangle[0])] {
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+
+    for ((a, b), count) in &edges {
+        if *count == 1 {
+            locked[*a as usize] = true;
+            locked[*b as usize] = true;
+        }
+    }
+
+    let mut first_at_position = HashMap::new();
+    for (vertex, position) in positions.iter().enumerate() {
+        let key = [position.x.to_bits(), position.y.to_bits(), position.z.to_bits()];
+        if let Some(other) = first_at_position.insert(key, vertex) {
+            locked[other] = true;
+            locked[vertex] = true;
+        }
+    }
+
+    // Collapses
+
+    let mut indices = indices.to_vec();
+    let mut error = 0.0f32;
+
+    while indices.len() > target_index_count {
+        let mut adjacency = vec![vec![]; vertex_count];
+        let mut candidates = vec![];
+        let mut seen = HashSet::new();
+
+        for (index, triangle) in indices.chunks_exact(3).enumerate() {
+            triangle.iter().for_each(|v| adjacency[*v as usize].push(index));
+
+            for (a, b) in [(triangle[0], triangle[1]), (triangle[1], triangle[2]), (triangle[2], triangle[0])] {
+                if !seen.insert((a.min(b), a.max(b))) {
+                    continue;
+                }
+
+                let mut merged = quadrics[a as usize];
+                merged.add(&quadrics[b as usize]);
+
+                let options = [(a, b), (b, a)].into_iter().filter(|(from, _)| !locked[*from as usize]);
+                if let Some((from, to)) = options.min_by(|x, y| merged.error(&positions[x.1 as usize]).total_cmp(&merged.error(&positions[y.1 as usize]))) {
+                    candidates.push((merged.error(&positions[to as usize]).sqrt() as f32, from, to));
+                }
+            }
+        }
+
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut remap = (0..vertex_count as u32).collect::<Vec<_>>();
+        let mut touched = vec![false; vertex_count];
+        let mut remaining = indices.len();
+
+        for (cost, from, to) in candidates {
+            if cost > max_error || remaining <= target_index_count {
+                break;
+            }
+
+            if touched[from as usize] || touched[to as usize] || flips(positions, &indices, &adjacency[from as usize], from, to) {
+                continue;
+            }
+
+            remap[from as usize] = to;
+            let source = quadrics[from as usize];
+            quadrics[to as usize].add(&source);
+            error = error.max(cost);
+
+            for triangle in &adjacency[from as usize] {
+                indices[triangle * 3..triangle * 3 + 3].iter().for_each(|v| touched[*v as usize] = true);
+                if indices[triangle * 3..triangle * 3 + 3].contains(&to) {
+                    remaining -= 3;
+                }
+            }
+        }
+
+        if remaining == indices.len() {
+            break;
+        }
+
+        indices = indices
+            .chunks_exact(3)
+            .map(|t| [remap[t[0] as usize], remap[t[1] as usize], remap[t[2] as usize]])
+            .filter(|[a, b, c]| a != b && b != c && c != a)
+            .flatten()
+            .collect();
+    }
+
+    (indices, error)
+}
+
+/// Whether moving `from` onto `to` would flip, sharply turn or degenerate any triangle around
+/// `from`.
+fn flips(positions: &[glm::Vec3], indices: &[u32], triangles: &[usize], from: u32, to: u32) -> bool {
+    triangles.iter().any(|t| {
+        let triangle = &indices[t * 3..t * 3 + 3];
+        if triangle.contains(&to) {
+            return false;
+        }
+
+        let corners = |moved: bool| {
+            triangle.iter().map(|v| if moved && *v == from { positions[to as usize] } else { positions[*v as usize] }).collect::<Vec<_>>()
+        };
+
+        let normal = |p: &[glm::Vec3]| glm::cross(&(p[1] - p[0]), &(p[2] - p[0]));
+        let before = normal(&corners(false));
+        let after = normal(&corners(true));
+
+        // Also reject turning a triangle more than about 75 degrees, which leaves slivers
+        // standing on edge.
+        glm::dot(&before, &after) <= 0.25 * before.norm() * after.norm() || after.norm() <= before.norm() * 1e-3
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `size` × `size` quad grid in the XY plane facing +Z, gently displaced along Z.
+    fn grid(size: u32) -> (Vec<glm::Vec3>, Vec<u32>) {
+        let mut positions = vec![];
+        for y in 0..=size {
+            for x in 0..=size {
+                let (fx, fy) = (x as f32 / size as f32, y as f32 / size as f32);
+                positions.push(glm::vec3(fx, fy, 0.02 * (fx * 6.0).sin() * (fy * 4.0).cos()));
+            }
+        }
+
+        let mut indices = vec![];
+        for y in 0..size {
+            for x in 0..size {
+                let i = y * (size + 1) + x;
+                indices.extend([i, i + 1, i + size + 2, i + size + 2, i + size + 1, i]);
+            }
+        }
+
+        (positions, indices)
+    }
+
+    fn sorted_triangles(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut triangles = indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect::<Vec<_>>();
+        triangles.sort();
+        triangles
+    }
+
+    fn faces_up(positions: &[glm::Vec3], indices: &[u32]) -> bool {
+        indices.chunks_exact(3).all(|t| {
+            let [a, b, c] = [0, 1, 2].map(|i| positions[t[i] as usize]);
+            glm::cross(&(b - a), &(c - a)).z > 0.0
+        })
+    }
+
+    #[test]
+    fn vertex_cache_order_keeps_every_triangle() {
+        let (positions, indices) = grid(8);
+        let optimized = optimize_vertex_cache(&indices, positions.len());
+
+        assert_eq!(sorted_triangles(&optimized), sorted_triangles(&indices));
+    }
+
+    #[test]
+    fn vertex_fetch_remap_is_a_permutation_in_first_use_order() {
+        let (positions, indices) = grid(4);
+        let reversed = indices.iter().rev().copied().collect::<Vec<_>>();
+        let remap = vertex_fetch_remap(&reversed, positions.len());
+
+        let mut sorted = remap.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..positions.len() as u32).collect::<Vec<_>>());
+        assert_eq!(remap[reversed[0] as usize], 0);
+    }
+
+    #[test]
+    fn simplify_reduces_without_flipping() {
+        let (positions, indices) = grid(8);
+        let (simplified, error) = simplify(&positions, &indices, indices.len() / 2, 1.0);
+
+        assert!(simplified.len() < indices.len());
+        assert!(simplified.len() % 3 == 0);
+        assert!(error >= 0.0);
+        assert!(faces_up(&positions, &simplified));
+    }
+
+    #[test]
+    fn levels_get_coarser() {
+        let (positions, indices) = grid(16);
+        let vertices = positions.iter()
+            .map(|p| vertex_buffer::Vertex::new(*p, glm::vec3(1.0, 1.0, 1.0), p.xy()))
+            .collect::<Vec<_>>();
+        let mut scene = scene::Scene::new(&vertices, &indices);
+
+        optimize(&mut scene, &Options { max_error: 1.0, ..Default::default() });
+
+        let primitive = &scene.meshes[0].primitives[0];
+        assert!(!primitive.lods.is_empty());
+        assert_eq!(primitive.index_count as usize, indices.len());
+
+        let mut previous = (primitive.index_count, 0.0);
+        for lod in &primitive.lods {
+            assert!(lod.index_count < previous.0);
+            assert!(lod.error >= previous.1);
+            previous = (lod.index_count, lod.error);
+
+            let range = lod.first_index as usize..(lod.first_index + lod.index_count) as usize;
+            let positions = scene.vertices.iter().map(|v| v.pos()).collect::<Vec<_>>();
+            assert!(faces_up(&positions, &scene.indices[range]));
+        }
+    }
+
+    #[test]
+    fn distant_primitives_select_coarser_levels() {
+        let primitive = scene::Primitive {
+            first_index: 0,
+            index_count: 600,
+            vertex_offset: 0,
+            vertex_count: 121,
+            material: None,
+            lods: vec![
+                scene::Lod { first_index: 600, index_count: 300, error: 0.001 },
+                scene::Lod { first_index: 900, index_count: 150, error: 0.01 },
+            ],
+        };
+
+        let selector = LodSelector {
+            view: glm::identity(),
+            projection_scale: 1.0,
+            viewport_height: 1000.0,
+            max_pixel_error: 1.0,
+        };
+
+        let at = |distance: f32| selector.select(&primitive, &glm::translation(&glm::vec3(0.0, 0.0, -distance)));
+        assert_eq!(at(0.1), (0, 600));
+        assert_eq!(at(1.0), (600, 300));
+        assert_eq!(at(10.0), (900, 150));
+    }
+}
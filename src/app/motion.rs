@@ -0,0 +1,192 @@
+use super::app_data;
+use super::app_defines;
+use super::image;
+use super::render_pass;
+use super::scene;
+use super::vertex_buffer;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+use nalgebra_glm as glm;
+use std::mem::size_of;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+/// Screen-space motion since the previous frame, in texture coordinates.
+pub const VELOCITY_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+
+/// Nodes whose previous world transform is kept; later nodes are drawn without motion.
+pub const MAX_NODES: usize = 16384;
+
+/// The previous frame's joint matrices, then a world transform per node.
+pub const PREVIOUS_FRAME_BUFFER_SIZE: u64 = ((app_defines::MAX_JOINTS + MAX_NODES) * size_of::<glm::Mat4>()) as u64;
+
+/// Model, view and projection matrices, without jitter.
+pub type Camera = (glm::Mat4, glm::Mat4, glm::Mat4);
+
+/// What the previous frame was drawn with, from which scene passes write each pixel's motion.
+#[derive(Clone, Debug, Default)]
+pub struct PreviousFrame {
+    camera: Option<Camera>,
+    /// World transform of every node drawn, by node.
+    transforms: Vec<Option<glm::Mat4>>,
+    joint_matrices: Vec<glm::Mat4>,
+}
+
+impl PreviousFrame {
+    /// The previous frame's camera, or `current` on the first frame.
+    pub fn camera(&self, current: &Camera) -> Camera {
+        self.camera.unwrap_or(*current)
+    }
+
+    /// The previous frame's joint matrices, or `current` if the number of joints changed.
+    fn joint_matrices<'a>(&'a self, current: &'a [glm::Mat4]) -> &'a [glm::Mat4] {
+        let joints = if self.joint_matrices.len() == current.len() { &self.joint_matrices } else { current };
+        &joints[..joints.len().min(app_defines::MAX_JOINTS)]
+    }
+
+    /// The previous world transform of every node up to the last one in `draws`, at most
+    /// `MAX_NODES`. Nodes not drawn last frame get their current transform.
+    fn node_transforms(&self, draws: &[scene::Draw]) -> Vec<glm::Mat4> {
+        let count = draws.iter().map(|d| d.node + 1).max().unwrap_or(0).min(MAX_NODES);
+        let mut transforms = vec![glm::Mat4::identity(); count];
+        for draw in draws.iter().filter(|d| d.node < count) {
+            transforms[draw.node] = self.transforms.get(draw.node).copied().flatten().unwrap_or(draw.transform);
+        }
+        transforms
+    }
+}
+
+/// The velocity target every scene pass writes next to its color, sized to the swapchain.
+pub unsafe fn create_velocity_objects(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let (velocity_image, velocity_image_memory) = image::create_image(
+        instance,
+        device,
+        data,
+        data.swapchain_extent.width,
+        data.swapchain_extent.height,
+        1,
+        vk::ImageCreateFlags::empty(),
+        VELOCITY_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.velocity_image = velocity_image;
+    data.velocity_image_memory = velocity_image_memory;
+    data.velocity_image_view = image::create_image_view(device, velocity_image, VELOCITY_FORMAT, vk::ImageAspectFlags::COLOR)?;
+
+    Ok(())
+}
+
+/// The velocity attachment of a scene pass. Pixels nothing is drawn to keep a zero motion.
+pub fn velocity_attachment() -> render_pass::Attachment {
+    render_pass::Attachment::color(VELOCITY_FORMAT, vk::AttachmentLoadOp::CLEAR)
+}
+
+pub unsafe fn create_previous_frame_buffers(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    data.previous_frame_buffers.clear();
+    data.previous_frame_buffers_memory.clear();
+
+    for _ in 0..data.swapchain_images.len() {
+        let (buffer, buffer_memory) = vertex_buffer::create_buffer(
+            instance,
+            device,
+            data,
+            PREVIOUS_FRAME_BUFFER_SIZE,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.previous_frame_buffers.push(buffer);
+        data.previous_frame_buffers_memory.push(buffer_memory);
+    }
+
+    Ok(())
+}
+
+/// Writes the previous frame's joint matrices and node transforms for swapchain image
+/// `image_index`, then keeps this frame's `camera`, `draws` and `joint_matrices` for the next.
+/// Nodes not drawn last frame, and joints when their number changed, are given no motion.
+pub unsafe fn update_previous_frame(
+    device: &Device,
+    data: &mut app_data::Data,
+    image_index: usize,
+    camera: &Camera,
+    draws: &[scene::Draw],
+    joint_matrices: &[glm::Mat4],
+) -> Result<()> {
+    let joints = data.previous_frame.joint_matrices(joint_matrices);
+    let previous_transforms = data.previous_frame.node_transforms(draws);
+
+    let mut transforms = vec![None; draws.iter().map(|d| d.node + 1).max().unwrap_or(0)];
+    for draw in draws {
+        transforms[draw.node] = Some(draw.transform);
+    }
+
+    // Copy
+
+    let node_offset = app_defines::MAX_JOINTS * size_of::<glm::Mat4>();
+    let size = node_offset + previous_transforms.len() * size_of::<glm::Mat4>();
+    let memory = device.map_memory(
+        data.previous_frame_buffers_memory[image_index],
+        0,
+        size as u64,
+        vk::MemoryMapFlags::empty(),
+    )?;
+
+    memcpy(joints.as_ptr(), memory.cast(), joints.len());
+    memcpy(previous_transforms.as_ptr(), memory.cast::<u8>().add(node_offset).cast(), previous_transforms.len());
+
+    device.unmap_memory(data.previous_frame_buffers_memory[image_index]);
+
+    data.previous_frame = PreviousFrame {
+        camera: Some(*camera),
+        transforms,
+        joint_matrices: joint_matrices.to_vec(),
+    };
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(node: usize, transform: glm::Mat4) -> scene::Draw {
+        scene::Draw {
+            node,
+            mesh: 0,
+            primitive: 0,
+            material: None,
+            first_index: 0,
+            index_count: 3,
+            vertex_offset: 0,
+            transform,
+            joint_offset: None,
+        }
+    }
+
+    #[test]
+    fn test_node_transforms_use_the_previous_frame() {
+        let moved = glm::translation(&glm::vec3(1.0, 0.0, 0.0));
+        let previous = PreviousFrame { transforms: vec![None, Some(glm::identity())], ..Default::default() };
+
+        let transforms = previous.node_transforms(&[draw(1, moved), draw(2, moved)]);
+
+        // Node 1 moved since the previous frame; node 2 was not drawn then, so it has no motion.
+        assert_eq!(transforms.len(), 3);
+        assert_eq!(transforms[1], glm::Mat4::identity());
+        assert_eq!(transforms[2], moved);
+    }
+
+    #[test]
+    fn test_joint_matrices_fall_back_when_the_count_changes() {
+        let previous = PreviousFrame { joint_matrices: vec![glm::identity(); 2], ..Default::default() };
+        let current = vec![glm::translation(&glm::vec3(0.0, 1.0, 0.0)); 2];
+
+        assert_eq!(previous.joint_matrices(&current), &previous.joint_matrices[..]);
+        assert_eq!(previous.joint_matrices(&current[..1]), &current[..1]);
+    }
+}
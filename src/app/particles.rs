@@ -0,0 +1,631 @@
+use super::app_data;
+use super::compute;
+use super::image_state;
+use super::pipeline;
+use super::render_pass;
+use super::swapchain;
+use super::tonemap;
+use super::vertex_buffer;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+use nalgebra_glm as glm;
+use std::mem::size_of;
+
+/// Particles alive at once, across all emitters.
+pub const MAX_PARTICLES: u32 = 65536;
+
+/// Emitters beyond this are ignored.
+pub const MAX_EMITTERS: usize = 16;
+
+/// Invocations per workgroup of the emit and simulate passes.
+const WORKGROUP_SIZE: u32 = 256;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ParticleBlend {
+    /// Adds light, for sparks and fire; order does not matter.
+    #[default]
+    Additive = 0,
+    /// Covers what is behind, for smoke and dust. Particles are not sorted.
+    Alpha = 1,
+}
+
+/// A source of particles, in world space.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Emitter {
+    pub enabled: bool,
+    pub position: glm::Vec3,
+    pub direction: glm::Vec3,
+    /// Half-angle of the cone particles leave in, in radians.
+    pub spread: f32,
+    /// Initial speed, varied by ±20%.
+    pub speed: f32,
+    /// Particles per second.
+    pub rate: f32,
+    /// Seconds a particle lives, varied by ±25%.
+    pub lifetime: f32,
+    /// Half the width of a particle, shrinking to half over its life.
+    pub size: f32,
+    /// Linear HDR color; alpha fades to 0 over a particle's life.
+    pub color: glm::Vec4,
+    /// Constant acceleration, such as gravity or wind.
+    pub acceleration: glm::Vec3,
+    /// Velocity lost per second, as `1 - e^(-drag · seconds)`.
+    pub drag: f32,
+    /// Height of the ground plane particles bounce off.
+    pub ground: f32,
+    /// Share of the vertical speed kept on bouncing.
+    pub bounce: f32,
+    pub blend: ParticleBlend,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            position: glm::vec3(0.0, 0.0, 0.0),
+            direction: glm::vec3(0.0, 0.0, 1.0),
+            spread: 0.3,
+            speed: 3.0,
+            rate: 2000.0,
+            lifetime: 2.0,
+            size: 0.015,
+            color: glm::vec4(4.0, 1.6, 0.4, 1.0),
+            acceleration: glm::vec3(0.0, 0.0, -9.81),
+            drag: 0.2,
+            ground: 0.0,
+            bounce: 0.4,
+            blend: ParticleBlend::Additive,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct GpuEmitter {
+    /// `xyz` position, `w` spread.
+    position: glm::Vec4,
+    /// `xyz` direction, `w` speed.
+    direction: glm::Vec4,
+    color: glm::Vec4,
+    /// `xyz` acceleration, `w` drag.
+    acceleration: glm::Vec4,
+    /// Lifetime, size, ground and bounce.
+    parameters: glm::Vec4,
+    /// First spawn index, spawn count and blend.
+    spawn: [u32; 4],
+}
+
+/// What the particle passes of the next recorded frame use.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ParticleFrame {
+    emitters: [GpuEmitter; MAX_EMITTERS],
+    emitter_count: u32,
+    spawn_count: u32,
+    delta: f32,
+    seed: u32,
+    view_proj: glm::Mat4,
+    camera_right: glm::Vec4,
+    camera_up: glm::Vec4,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct SimulatePushConstants {
+    delta: f32,
+    spawn_count: u32,
+    emitter_count: u32,
+    seed: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct DrawPushConstants {
+    view_proj: glm::Mat4,
+    camera_right: glm::Vec4,
+    camera_up: glm::Vec4,
+    blend: u32,
+    _padding: [u32; 3],
+}
+
+/// Size of the particle state buffer: the alive and dead counts, padding and the indirect
+/// draw command at offset 16.
+const STATE_SIZE: u64 = 32;
+
+/// Offset of the `VkDrawIndirectCommand` in the state buffer.
+const DRAW_OFFSET: u64 = 16;
+
+/// Whether the particle passes are recorded at all.
+pub fn is_active(data: &app_data::Data) -> bool {
+    !data.particle_emitters.is_empty()
+}
+
+/// Creates the particle storage, the compute kernels and the draw descriptor set, and builds
+/// the initial dead list. These are kept across swapchain recreation so a resize does not
+/// clear the particles.
+pub unsafe fn create_particle_buffers(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    // Buffers
+
+    let buffer = |size: u64, usage: vk::BufferUsageFlags| vertex_buffer::create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::STORAGE_BUFFER | usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    );
+
+    // Position and age, velocity and lifetime, emitter and size.
+    let particle_size = 48;
+
+    let (particle_buffer, particle_buffer_memory) = buffer(MAX_PARTICLES as u64 * particle_size, vk::BufferUsageFlags::TRANSFER_DST)?;
+    let (list_buffer, list_buffer_memory) = buffer(MAX_PARTICLES as u64 * 2 * size_of::<u32>() as u64, vk::BufferUsageFlags::empty())?;
+    let (state_buffer, state_buffer_memory) = buffer(STATE_SIZE, vk::BufferUsageFlags::INDIRECT_BUFFER)?;
+    let (emitter_buffer, emitter_buffer_memory) = buffer(size_of::<[GpuEmitter; MAX_EMITTERS]>() as u64, vk::BufferUsageFlags::TRANSFER_DST)?;
+
+    data.particle_buffer = particle_buffer;
+    data.particle_buffer_memory = particle_buffer_memory;
+    data.particle_list_buffer = list_buffer;
+    data.particle_list_buffer_memory = list_buffer_memory;
+    data.particle_state_buffer = state_buffer;
+    data.particle_state_buffer_memory = state_buffer_memory;
+    data.particle_emitter_buffer = emitter_buffer;
+    data.particle_emitter_buffer_memory = emitter_buffer_memory;
+
+    // Kernels
+
+    let bindings = &[
+        compute::Binding::StorageBuffer(particle_buffer),
+        compute::Binding::StorageBuffer(list_buffer),
+        compute::Binding::StorageBuffer(state_buffer),
+        compute::Binding::StorageBuffer(emitter_buffer),
+    ];
+
+    let emit = include_bytes!("../../shaders/particles_emit.bin");
+    let simulate = include_bytes!("../../shaders/particles_simulate.bin");
+    let compact = include_bytes!("../../shaders/particles_compact.bin");
+
+    let push_constant_size = size_of::<SimulatePushConstants>() as u32;
+    data.particle_emit_kernel = compute::create_kernel(device, &emit[..], bindings, push_constant_size)?;
+    data.particle_simulate_kernel = compute::create_kernel(device, &simulate[..], bindings, push_constant_size)?;
+    data.particle_compact_kernel = compute::create_kernel(device, &compact[..], bindings, 0)?;
+
+    // Descriptor Set
+
+    let bindings = [0, 1, 3]
+        .map(|binding| vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build());
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    data.particle_descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    let pool_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(3);
+
+    let pool_sizes = &[pool_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+
+    data.particle_descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let layouts = &[data.particle_descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.particle_descriptor_pool)
+        .set_layouts(layouts);
+
+    data.particle_descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let buffer_infos = [particle_buffer, list_buffer, emitter_buffer]
+        .map(|b| [vk::DescriptorBufferInfo::builder().buffer(b).offset(0).range(vk::WHOLE_SIZE as u64).build()]);
+
+    let writes = bindings.iter().zip(&buffer_infos)
+        .map(|(binding, info)| vk::WriteDescriptorSet::builder()
+            .dst_set(data.particle_descriptor_set)
+            .dst_binding(binding.binding)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(info)
+            .build())
+        .collect::<Vec<_>>();
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+    // Pipeline Layout
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<DrawPushConstants>() as u32);
+
+    let push_constant_ranges = &[push_constant_range];
+    let info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.particle_pipeline_layout = device.create_pipeline_layout(&info, None)?;
+
+    // Initial State
+
+    // Every particle starts dead, with an age and lifetime of 0.
+    compute::run_standalone(device, data, |command_buffer| {
+        device.cmd_fill_buffer(command_buffer, particle_buffer, 0, vk::WHOLE_SIZE as u64, 0);
+        compute::cmd_memory_barrier(
+            device,
+            command_buffer,
+            (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::COMPUTE_SHADER),
+            (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ),
+        );
+        compute::cmd_dispatch(device, command_buffer, &data.particle_compact_kernel, &[], [1, 1, 1]);
+    })?;
+
+    Ok(())
+}
+
+pub unsafe fn destroy_particle_buffers(device: &Device, data: &mut app_data::Data) {
+    device.destroy_pipeline_layout(data.particle_pipeline_layout, None);
+    device.destroy_descriptor_pool(data.particle_descriptor_pool, None);
+    device.destroy_descriptor_set_layout(data.particle_descriptor_set_layout, None);
+    compute::destroy_kernel(device, &data.particle_compact_kernel);
+    compute::destroy_kernel(device, &data.particle_simulate_kernel);
+    compute::destroy_kernel(device, &data.particle_emit_kernel);
+    device.free_memory(data.particle_emitter_buffer_memory, None);
+    device.destroy_buffer(data.particle_emitter_buffer, None);
+    device.free_memory(data.particle_state_buffer_memory, None);
+    device.destroy_buffer(data.particle_state_buffer, None);
+    device.free_memory(data.particle_list_buffer_memory, None);
+    device.destroy_buffer(data.particle_list_buffer, None);
+    device.free_memory(data.particle_buffer_memory, None);
+    device.destroy_buffer(data.particle_buffer, None);
+}
+
+/// Creates the pass drawing particles over the lit HDR target, tested against the depth
+/// either render path left, with a pipeline per blend mode.
+pub unsafe fn create_particle_objects(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    // Render Pass
+
+    let depth_format = swapchain::get_sampled_depth_format(instance, data)?;
+    data.particle_render_pass = render_pass::create_unless_dynamic(device, data, &particle_attachments(depth_format))?;
+
+    // Framebuffer
+
+    let attachments = &[data.hdr_image_view, data.depth_image_view];
+    data.particle_framebuffer =
+        render_pass::create_framebuffer(device, data.particle_render_pass, attachments, data.swapchain_extent)?;
+
+    // Pipelines
+
+    data.particle_pipelines = [ParticleBlend::Additive, ParticleBlend::Alpha]
+        .iter()
+        .map(|b| create_pipeline(device, data, *b))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(())
+}
+
+pub unsafe fn destroy_particle_objects(device: &Device, data: &mut app_data::Data) {
+    data.particle_pipelines.drain(..).for_each(|p| device.destroy_pipeline(p, None));
+    device.destroy_framebuffer(data.particle_framebuffer, None);
+    device.destroy_render_pass(data.particle_render_pass, None);
+}
+
+/// Attachments of the particle pass: the lit HDR target blended over, and depth tested
+/// without writes.
+fn particle_attachments(depth_format: vk::Format) -> [render_pass::Attachment; 2] {
+    [
+        render_pass::Attachment::color(tonemap::HDR_FORMAT, vk::AttachmentLoadOp::LOAD),
+        render_pass::Attachment::depth_read(depth_format),
+    ]
+}
+
+unsafe fn create_pipeline(device: &Device, data: &app_data::Data, blend: ParticleBlend) -> Result<vk::Pipeline> {
+    // Stages
+
+    let vert = include_bytes!("../../shaders/particles_vert.bin");
+    let frag = include_bytes!("../../shaders/particles_frag.bin");
+
+    let vert_shader_module = pipeline::create_shader_module(device, &vert[..])?;
+    let frag_shader_module = pipeline::create_shader_module(device, &frag[..])?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0");
+
+    // Fixed Function State
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    // Particles are hidden by the scene but do not hide each other.
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // Additive particles are premultiplied by the fragment shader.
+    let (src_color_blend_factor, dst_color_blend_factor) = match blend {
+        ParticleBlend::Additive => (vk::BlendFactor::ONE, vk::BlendFactor::ONE),
+        ParticleBlend::Alpha => (vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+    };
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(true)
+        .src_color_blend_factor(src_color_blend_factor)
+        .dst_color_blend_factor(dst_color_blend_factor)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    // Create
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(data.particle_pipeline_layout)
+        .render_pass(data.particle_render_pass)
+        .subpass(0);
+
+    let (color_formats, depth_format) = render_pass::formats(&particle_attachments(data.depth_format));
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(&color_formats)
+        .depth_attachment_format(depth_format);
+
+    if data.particle_render_pass.is_null() {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    let pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0[0];
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(pipeline)
+}
+
+/// Works out how many particles each emitter spawns over `delta` seconds and stores what the
+/// next recorded frame needs, seen through `view` and `proj`.
+pub fn update_particles(data: &mut app_data::Data, delta: f32, view: &glm::Mat4, proj: &glm::Mat4) {
+    let emitters = &data.particle_emitters[..data.particle_emitters.len().min(MAX_EMITTERS)];
+    data.particle_spawn_accumulators.resize(emitters.len(), 0.0);
+
+    let frame = &mut data.particle_frame;
+    frame.emitter_count = emitters.len() as u32;
+    frame.spawn_count = 0;
+    frame.delta = delta;
+    frame.seed = frame.seed.wrapping_add(1);
+    frame.view_proj = proj * view;
+    frame.camera_right = glm::vec4(view[(0, 0)], view[(0, 1)], view[(0, 2)], 0.0);
+    frame.camera_up = glm::vec4(view[(1, 0)], view[(1, 1)], view[(1, 2)], 0.0);
+
+    for (i, (emitter, accumulator)) in emitters.iter().zip(&mut data.particle_spawn_accumulators).enumerate() {
+        let mut spawn = 0;
+        if emitter.enabled {
+            *accumulator += emitter.rate * delta;
+            spawn = (*accumulator as u32).min(MAX_PARTICLES - frame.spawn_count);
+            *accumulator -= accumulator.floor();
+        }
+
+        frame.emitters[i] = GpuEmitter {
+            position: glm::vec4(emitter.position.x, emitter.position.y, emitter.position.z, emitter.spread),
+            direction: glm::vec4(emitter.direction.x, emitter.direction.y, emitter.direction.z, emitter.speed),
+            color: emitter.color,
+            acceleration: glm::vec4(emitter.acceleration.x, emitter.acceleration.y, emitter.acceleration.z, emitter.drag),
+            parameters: glm::vec4(emitter.lifetime, emitter.size, emitter.ground, emitter.bounce),
+            spawn: [frame.spawn_count, spawn, emitter.blend as u32, 0],
+        };
+
+        frame.spawn_count += spawn;
+    }
+}
+
+/// Uploads the emitters, then emits into dead slots, simulates every live particle and
+/// rebuilds the alive and dead lists along with the indirect draw.
+pub unsafe fn cmd_simulate_particles(device: &Device, command_buffer: vk::CommandBuffer, data: &app_data::Data) {
+    let frame = &data.particle_frame;
+
+    // The previous frame must be done drawing and simulating before anything is rewritten. On
+    // the compute queue, drawing is waited for by the semaphore that hands the buffers back.
+    let previous = if data.async_compute {
+        vk::PipelineStageFlags::COMPUTE_SHADER
+    } else {
+        vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER
+    };
+
+    compute::cmd_memory_barrier(
+        device,
+        command_buffer,
+        (previous, vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER),
+        (vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE),
+    );
+
+    let emitters = std::slice::from_raw_parts(&frame.emitters as *const _ as *const u8, size_of::<[GpuEmitter; MAX_EMITTERS]>());
+    device.cmd_update_buffer(command_buffer, data.particle_emitter_buffer, 0, emitters);
+
+    compute::cmd_memory_barrier(
+        device,
+        command_buffer,
+        (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::COMPUTE_SHADER),
+        (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ),
+    );
+
+    let push_constants = SimulatePushConstants {
+        delta: frame.delta,
+        spawn_count: frame.spawn_count,
+        emitter_count: frame.emitter_count,
+        seed: frame.seed,
+    };
+    let push_constants = std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<SimulatePushConstants>());
+
+    let compute_barrier = || compute::cmd_memory_barrier(
+        device,
+        command_buffer,
+        (vk::PipelineStageFlags::COMPUTE_SHADER, vk::PipelineStageFlags::COMPUTE_SHADER),
+        (vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE),
+    );
+
+    if frame.spawn_count > 0 {
+        let groups = compute::group_count(frame.spawn_count, WORKGROUP_SIZE);
+        compute::cmd_dispatch(device, command_buffer, &data.particle_emit_kernel, push_constants, [groups, 1, 1]);
+        compute_barrier();
+    }
+
+    let groups = compute::group_count(MAX_PARTICLES, WORKGROUP_SIZE);
+    compute::cmd_dispatch(device, command_buffer, &data.particle_simulate_kernel, push_constants, [groups, 1, 1]);
+    compute_barrier();
+
+    compute::cmd_dispatch(device, command_buffer, &data.particle_compact_kernel, &[], [1, 1, 1]);
+
+    if data.async_compute {
+        let src = (vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE);
+        compute::cmd_release_buffers(device, command_buffer, &shared_buffers(data), (data.compute_family, data.graphics_family), src);
+    } else {
+        compute::cmd_compute_to_graphics_barrier(device, command_buffer);
+    }
+}
+
+/// Takes the particle buffers back on the compute queue after the last frame drew them.
+pub unsafe fn cmd_acquire_for_simulation(device: &Device, command_buffer: vk::CommandBuffer, data: &app_data::Data) {
+    let dst = (
+        vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::TRANSFER,
+        vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+    );
+    compute::cmd_acquire_buffers(device, command_buffer, &shared_buffers(data), (data.graphics_family, data.compute_family), dst);
+}
+
+/// Takes the particle buffers simulated on the compute queue for drawing.
+pub unsafe fn cmd_acquire_for_drawing(device: &Device, command_buffer: vk::CommandBuffer, data: &app_data::Data) {
+    let dst = (
+        vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_SHADER,
+        vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::SHADER_READ,
+    );
+    compute::cmd_acquire_buffers(device, command_buffer, &shared_buffers(data), (data.compute_family, data.graphics_family), dst);
+}
+
+/// Hands the drawn particle buffers back to the compute queue for the next simulation.
+pub unsafe fn cmd_release_for_simulation(device: &Device, command_buffer: vk::CommandBuffer, data: &app_data::Data) {
+    let src = (vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_SHADER, vk::AccessFlags::empty());
+    compute::cmd_release_buffers(device, command_buffer, &shared_buffers(data), (data.graphics_family, data.compute_family), src);
+}
+
+/// Buffers used by both the simulation and the draw, which reads the particles, lists and
+/// emitters in the vertex shader and its command from the state buffer.
+fn shared_buffers(data: &app_data::Data) -> [vk::Buffer; 4] {
+    [data.particle_buffer, data.particle_list_buffer, data.particle_state_buffer, data.particle_emitter_buffer]
+}
+
+/// Draws the live particles over the lit HDR target, additive ones first, and leaves it
+/// readable again.
+pub unsafe fn cmd_draw_particles(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+) {
+    let frame = &data.particle_frame;
+
+    let views = [(data.hdr_image, data.hdr_image_view), (data.depth_image, data.depth_image_view)];
+    let targets = render_pass::targets(&particle_attachments(data.depth_format), &views);
+    let (render_pass, framebuffer) = (data.particle_render_pass, data.particle_framebuffer);
+    render_pass::cmd_begin(device, command_buffer, data, images, render_pass, framebuffer, data.swapchain_extent, &targets);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.particle_pipeline_layout,
+        0,
+        &[data.particle_descriptor_set],
+        &[],
+    );
+
+    for (blend, pipeline) in data.particle_pipelines.iter().enumerate() {
+        let push_constants = DrawPushConstants {
+            view_proj: frame.view_proj,
+            camera_right: frame.camera_right,
+            camera_up: frame.camera_up,
+            blend: blend as u32,
+            _padding: [0; 3],
+        };
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, *pipeline);
+        device.cmd_push_constants(
+            command_buffer,
+            data.particle_pipeline_layout,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            0,
+            std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<DrawPushConstants>()),
+        );
+        device.cmd_draw_indirect(command_buffer, data.particle_state_buffer, DRAW_OFFSET, 1, 0);
+    }
+
+    render_pass::cmd_end(device, command_buffer, data, render_pass);
+
+    let read = vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER;
+    images.cmd_use(device, command_buffer, data.hdr_image, vk::ImageAspectFlags::COLOR, image_state::Usage::Sampled(read));
+}
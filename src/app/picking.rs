@@ -0,0 +1,132 @@
+use super::app_data;
+use super::app_defines;
+use super::image;
+use super::image_state;
+use super::vertex_buffer;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+use std::mem::size_of;
+
+pub const ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+
+/// The scene node under a window pixel, read back from the ID attachment.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Pick {
+    pub x: u32,
+    pub y: u32,
+    pub node: Option<usize>,
+}
+
+/// Object ID written for a node; zero is left for the cleared background.
+pub fn object_id(node: usize) -> u32 {
+    node as u32 + 1
+}
+
+pub unsafe fn create_id_objects(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let (id_image, id_image_memory) = image::create_image(
+        instance,
+        device,
+        data,
+        data.swapchain_extent.width,
+        data.swapchain_extent.height,
+        1,
+        vk::ImageCreateFlags::empty(),
+        ID_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.id_image = id_image;
+    data.id_image_memory = id_image_memory;
+    data.id_image_view = image::create_image_view(device, data.id_image, ID_FORMAT, vk::ImageAspectFlags::COLOR)?;
+
+    Ok(())
+}
+
+/// One single-pixel readback buffer per frame in flight, so a pick is read once that frame's
+/// fence has signalled.
+pub unsafe fn create_readback_buffers(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    for _ in 0..app_defines::MAX_FRAMES_IN_FLIGHT {
+        let (buffer, buffer_memory) = vertex_buffer::create_buffer(
+            instance,
+            device,
+            data,
+            size_of::<u32>() as u64,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.pick_buffers.push(buffer);
+        data.pick_buffers_memory.push(buffer_memory);
+    }
+
+    Ok(())
+}
+
+/// Copies the ID under `(x, y)` into the readback buffer of `frame`, first moving the ID
+/// attachment to `TRANSFER_SRC_OPTIMAL` if the scene pass did not leave it there.
+pub unsafe fn cmd_copy_id(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+    frame: usize,
+    x: u32,
+    y: u32,
+) {
+    images.cmd_use(device, command_buffer, data.id_image, vk::ImageAspectFlags::COLOR, image_state::Usage::TransferSrc);
+
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(subresource)
+        .image_offset(vk::Offset3D { x: x as i32, y: y as i32, z: 0 })
+        .image_extent(vk::Extent3D { width: 1, height: 1, depth: 1 });
+
+    device.cmd_copy_image_to_buffer(
+        command_buffer,
+        data.id_image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        data.pick_buffers[frame],
+        &[region],
+    );
+
+    let barrier = vk::BufferMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(data.pick_buffers[frame])
+        .offset(0)
+        .size(vk::WHOLE_SIZE as vk::DeviceSize);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[barrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
+
+/// Reads the ID copied for `frame`. Only valid after that frame's fence has signalled.
+pub unsafe fn read_id(device: &Device, data: &app_data::Data, frame: usize) -> Result<u32> {
+    let memory = device.map_memory(data.pick_buffers_memory[frame], 0, size_of::<u32>() as u64, vk::MemoryMapFlags::empty())?;
+
+    let id = *(memory as *const u32);
+
+    device.unmap_memory(data.pick_buffers_memory[frame]);
+
+    Ok(id)
+}
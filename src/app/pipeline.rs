@@ -1,82 +1,81 @@
 use super::app_data;
+use super::motion;
+use super::picking;
+use super::render_pass;
+use super::tonemap;
 use super::vertex_buffer;
 use super::swapchain;
 
 use anyhow::{anyhow, Result};
 use vulkanalia::prelude::v1_0::*;
+use std::mem::size_of;
 
 pub unsafe fn create_render_pass(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
-    // Attachments
-
-    let color_attachment = vk::AttachmentDescription::builder()
-        .format(data.swapchain_format)
-        .samples(vk::SampleCountFlags::_1)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
-        .store_op(vk::AttachmentStoreOp::STORE)
-        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
-
-    let depth_stencil_attachment = vk::AttachmentDescription::builder()
-        .format(swapchain::get_depth_format(instance, data)?)
-        .samples(vk::SampleCountFlags::_1)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
-        .store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
-
-    // Subpasses
-
-    let color_attachment_ref = vk::AttachmentReference::builder()
-        .attachment(0)
-        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
-
-    let depth_stencil_attachment_ref = vk::AttachmentReference::builder()
-        .attachment(1)
-        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
-
-    let color_attachments = &[color_attachment_ref];
-    let subpass = vk::SubpassDescription::builder()
-        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-        .color_attachments(color_attachments)
-        .depth_stencil_attachment(&depth_stencil_attachment_ref);
-
-    // Dependencies
-
-    let dependency = vk::SubpassDependency::builder()
-        .src_subpass(vk::SUBPASS_EXTERNAL)
-        .dst_subpass(0)
-        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
-        .src_access_mask(vk::AccessFlags::empty())
-        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
-        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
+    data.depth_format = swapchain::get_sampled_depth_format(instance, data)?;
+    data.render_pass = render_pass::create_unless_dynamic(device, data, &forward_attachments(data))?;
 
-    // Create
+    Ok(())
+}
 
-    let attachments = &[color_attachment, depth_stencil_attachment];
-    let subpasses = &[subpass];
-    let dependencies = &[dependency];
-    let info = vk::RenderPassCreateInfo::builder()
-        .attachments(attachments)
-        .subpasses(subpasses)
-        .dependencies(dependencies);
+/// Attachments of the forward pass: the HDR target, depth, velocity, then object IDs when
+/// picking. Depth continues from the depth prepass when ambient occlusion needs it before
+/// shading.
+pub fn forward_attachments(data: &app_data::Data) -> Vec<render_pass::Attachment> {
+    let depth_load_op = if data.ssao.enabled { vk::AttachmentLoadOp::LOAD } else { vk::AttachmentLoadOp::CLEAR };
 
-    data.render_pass = device.create_render_pass(&info, None)?;
+    let mut attachments = vec![
+        render_pass::Attachment::color(tonemap::HDR_FORMAT, vk::AttachmentLoadOp::CLEAR),
+        render_pass::Attachment::depth(data.depth_format, depth_load_op),
+        motion::velocity_attachment(),
+    ];
 
-    Ok(())
+    if data.picking {
+        attachments.push(render_pass::Attachment::color(picking::ID_FORMAT, vk::AttachmentLoadOp::CLEAR));
+    }
+
+    attachments
 }
 
 pub unsafe fn create_pipeline(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    // Layout
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<vertex_buffer::PushConstants>() as u32);
+
+    let set_layouts = &[data.descriptor_set_layout, data.material_descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    // Create
+
+    let frag = include_bytes!("../../shaders/frag.bin");
+    data.pipeline = create_scene_pipeline(device, data, &frag[..], data.render_pass, &forward_attachments(data))?;
+
+    Ok(())
+}
+
+/// A pipeline drawing scene primitives with `shader.vert` and `frag` into subpass 0 of
+/// `render_pass`, which has `attachments`, or into them with dynamic rendering if it is null.
+/// Only the red channel of the ID attachment is written.
+pub unsafe fn create_scene_pipeline(
+    device: &Device,
+    data: &app_data::Data,
+    frag: &[u8],
+    render_pass: vk::RenderPass,
+    attachments: &[render_pass::Attachment],
+) -> Result<vk::Pipeline> {
     // Stages
 
     let vert = include_bytes!("../../shaders/vert.bin");
-    let frag = include_bytes!("../../shaders/frag.bin");
 
     let vert_shader_module = create_shader_module(device, &vert[..])?;
-    let frag_shader_module = create_shader_module(device, &frag[..])?;
+    let frag_shader_module = create_shader_module(device, frag)?;
 
     let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::VERTEX)
@@ -141,10 +140,11 @@ pub unsafe fn create_pipeline(device: &Device, data: &mut app_data::Data) -> Res
 
     // Depth Stencil State
 
+    // Equal depths pass so shading after a depth prepass covers what the prepass wrote.
     let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
         .depth_test_enable(true)
         .depth_write_enable(true)
-        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
         .depth_bounds_test_enable(false)
         .stencil_test_enable(false);
 
@@ -154,37 +154,164 @@ pub unsafe fn create_pipeline(device: &Device, data: &mut app_data::Data) -> Res
         .color_write_mask(vk::ColorComponentFlags::all())
         .blend_enable(false);
 
-    let attachments = &[attachment];
+    let id_attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::R)
+        .blend_enable(false);
+
+    let (color_formats, depth_format) = render_pass::formats(attachments);
+    let blend_attachments = color_formats.iter()
+        .map(|f| if *f == picking::ID_FORMAT { id_attachment } else { attachment })
+        .collect::<Vec<_>>();
     let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
         .logic_op_enable(false)
         .logic_op(vk::LogicOp::COPY)
-        .attachments(attachments)
+        .attachments(&blend_attachments)
         .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
-    // Layout
+    // Create
 
-    let set_layouts = &[data.descriptor_set_layout];
-    let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(set_layouts);
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .layout(data.pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(0);
 
-    data.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(&color_formats)
+        .depth_attachment_format(depth_format);
+
+    if render_pass.is_null() {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    let pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0[0];
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    Ok(pipeline)
+}
+
+/// A pipeline drawing a single full-screen triangle, with no vertex input or depth, into the
+/// single color attachment of `render_pass`, or into one of `format` with dynamic rendering if
+/// it is null. `additive` adds the output to the attachment instead of replacing it.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn create_fullscreen_pipeline(
+    device: &Device,
+    extent: vk::Extent2D,
+    vert: &[u8],
+    frag: &[u8],
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    format: vk::Format,
+    additive: bool,
+) -> Result<vk::Pipeline> {
+    // Stages
+
+    let vert_shader_module = create_shader_module(device, vert)?;
+    let frag_shader_module = create_shader_module(device, frag)?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0");
+
+    // Fixed Function State
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(extent.width as f32)
+        .height(extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(additive)
+        .src_color_blend_factor(vk::BlendFactor::ONE)
+        .dst_color_blend_factor(vk::BlendFactor::ONE)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
     // Create
 
     let stages = &[vert_stage, frag_stage];
-    let info = vk::GraphicsPipelineCreateInfo::builder()
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
         .stages(stages)
         .vertex_input_state(&vertex_input_state)
         .input_assembly_state(&input_assembly_state)
         .viewport_state(&viewport_state)
         .rasterization_state(&rasterization_state)
         .multisample_state(&multisample_state)
-        .depth_stencil_state(&depth_stencil_state)
         .color_blend_state(&color_blend_state)
-        .layout(data.pipeline_layout)
-        .render_pass(data.render_pass)
+        .layout(layout)
+        .render_pass(render_pass)
         .subpass(0);
 
-    data.pipeline = device
+    let color_formats = &[format];
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder().color_attachment_formats(color_formats);
+
+    if render_pass.is_null() {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    let pipeline = device
         .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
         .0[0];
 
@@ -193,7 +320,28 @@ pub unsafe fn create_pipeline(device: &Device, data: &mut app_data::Data) -> Res
     device.destroy_shader_module(vert_shader_module, None);
     device.destroy_shader_module(frag_shader_module, None);
 
-    Ok(())
+    Ok(pipeline)
+}
+
+pub unsafe fn create_compute_pipeline(device: &Device, comp: &[u8], layout: vk::PipelineLayout) -> Result<vk::Pipeline> {
+    let comp_shader_module = create_shader_module(device, comp)?;
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(comp_shader_module)
+        .name(b"main\0");
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(layout);
+
+    let pipeline = device
+        .create_compute_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0[0];
+
+    device.destroy_shader_module(comp_shader_module, None);
+
+    Ok(pipeline)
 }
 
 pub unsafe fn create_shader_module(device: &Device, bytecode: &[u8]) -> Result<vk::ShaderModule> {
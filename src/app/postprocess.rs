@@ -0,0 +1,551 @@
+//! An ordered chain of full-screen passes run on the tone-mapped image before presenting.
+//!
+//! Fragment passes are drawn with `fullscreen.vert` and read `fragTexCoord` at location 0.
+//! Compute passes run in `WORKGROUP_SIZE`² workgroups and write their output with
+//! `imageStore`. Both see the same set 0:
+//!
+//! * binding 0: `texture2D` holding the previous pass's output,
+//! * binding 1: linear clamping `sampler`,
+//! * binding 2: `image2D` to write, for compute passes only,
+//!
+//! and push constants `{ vec2 texelSize; vec4 parameters[2]; }`.
+
+use super::antialiasing;
+use super::app_data;
+use super::image;
+use super::image_state;
+use super::pipeline;
+use super::render_pass;
+
+use anyhow::{anyhow, Result};
+use vulkanalia::prelude::v1_0::*;
+use std::mem::size_of;
+
+/// Format of the tone-mapped image the first pass reads.
+pub const SOURCE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+pub const MAX_PARAMETERS: usize = 8;
+
+/// Edge of the square workgroups compute passes are dispatched in.
+pub const WORKGROUP_SIZE: u32 = 16;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PassShader {
+    /// SPIR-V of a fragment shader drawn over the whole target.
+    Fragment(Vec<u8>),
+    /// SPIR-V of a compute shader dispatched over the whole target.
+    Compute(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PostProcessPass {
+    pub name: String,
+    pub shader: PassShader,
+    /// Format of the image the pass samples; must match the previous pass's output.
+    pub input_format: vk::Format,
+    /// Format of the target the pass writes. The last enabled pass writes the swapchain
+    /// image instead.
+    pub output_format: vk::Format,
+    /// Passed to the shader each frame; changing them needs no rebuild.
+    pub parameters: [f32; MAX_PARAMETERS],
+    pub enabled: bool,
+}
+
+impl PostProcessPass {
+    pub fn fragment(name: &str, spirv: &[u8], input_format: vk::Format, output_format: vk::Format) -> Self {
+        Self::new(name, PassShader::Fragment(spirv.to_vec()), input_format, output_format)
+    }
+
+    pub fn compute(name: &str, spirv: &[u8], input_format: vk::Format, output_format: vk::Format) -> Self {
+        Self::new(name, PassShader::Compute(spirv.to_vec()), input_format, output_format)
+    }
+
+    fn new(name: &str, shader: PassShader, input_format: vk::Format, output_format: vk::Format) -> Self {
+        Self {
+            name: name.to_string(),
+            shader,
+            input_format,
+            output_format,
+            parameters: [0.0; MAX_PARAMETERS],
+            enabled: true,
+        }
+    }
+
+    pub fn with_parameters(mut self, parameters: [f32; MAX_PARAMETERS]) -> Self {
+        self.parameters = parameters;
+        self
+    }
+}
+
+/// An intermediate image the chain ping-pongs between.
+#[derive(Copy, Clone, Debug)]
+pub struct Target {
+    format: vk::Format,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    /// Unset for targets only compute passes write.
+    framebuffer: vk::Framebuffer,
+}
+
+/// An enabled pass resolved against the targets it reads and writes.
+#[derive(Copy, Clone, Debug)]
+pub struct Step {
+    /// Index into `data.post_process` to read parameters from each frame, or `None` for
+    /// built-in passes.
+    pass: Option<usize>,
+    /// Parameters of a built-in pass.
+    parameters: [f32; MAX_PARAMETERS],
+    compute: bool,
+    pipeline: vk::Pipeline,
+    descriptor_set: vk::DescriptorSet,
+    /// Index into `data.post_process_targets`, or `None` for the swapchain image.
+    output: Option<usize>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct PostProcessPushConstants {
+    texel_size: [f32; 2],
+    _padding: [f32; 2],
+    parameters: [f32; MAX_PARAMETERS],
+}
+
+/// Checks that each enabled pass reads the format the one before it writes.
+pub fn validate(passes: &[PostProcessPass]) -> Result<()> {
+    let mut format = SOURCE_FORMAT;
+    for pass in passes.iter().filter(|p| p.enabled) {
+        if pass.input_format != format {
+            return Err(anyhow!(
+                "Post-process pass `{}` reads {:?} but its input is {:?}!",
+                pass.name,
+                pass.input_format,
+                format,
+            ));
+        }
+        format = pass.output_format;
+    }
+    Ok(())
+}
+
+/// Whether `a` and `b` differ only in their parameters, so the built chain still fits.
+pub fn same_structure(a: &[PostProcessPass], b: &[PostProcessPass]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| PostProcessPass { parameters: b.parameters, ..a.clone() } == *b)
+}
+
+/// Whether any pass is enabled, in which case tone mapping writes the chain's source target
+/// instead of the swapchain image.
+pub fn is_active(data: &app_data::Data) -> bool {
+    antialiasing::fxaa_pass(data).is_some() || data.post_process.iter().any(|p| p.enabled)
+}
+
+/// The enabled passes in order, built-in ones first, each with its index into
+/// `data.post_process` if it has one.
+fn enabled_passes(data: &app_data::Data) -> Vec<(Option<usize>, PostProcessPass)> {
+    antialiasing::fxaa_pass(data).map(|p| (None, p)).into_iter()
+        .chain(data.post_process.iter().cloned().enumerate().map(|(i, p)| (Some(i), p)))
+        .filter(|(_, p)| p.enabled)
+        .collect()
+}
+
+/// The target tone mapping writes while the chain is active.
+pub fn source(data: &app_data::Data) -> Option<(vk::Format, vk::ImageView)> {
+    data.post_process_targets.first().map(|t| (t.format, t.view))
+}
+
+/// The image behind `source`, with its view.
+pub fn source_image(data: &app_data::Data) -> Option<(vk::Image, vk::ImageView)> {
+    data.post_process_targets.first().map(|t| (t.image, t.view))
+}
+
+/// Allocates the ping-pong targets and builds a pipeline and descriptor set per enabled pass.
+/// Everything is sized to the swapchain, so this runs again on resize.
+pub unsafe fn create_post_process_objects(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    if !is_active(data) {
+        return Ok(());
+    }
+
+    // Plan
+
+    // Each enabled pass reads the target the one before wrote and writes a target of its
+    // output format other than that one. A trailing compute pass is followed by a copy, as
+    // it cannot write the swapchain image.
+    let mut passes = enabled_passes(data);
+    let output_format = passes.last().map(|(_, p)| p.output_format).unwrap_or(SOURCE_FORMAT);
+    if passes.last().is_some_and(|(_, p)| matches!(p.shader, PassShader::Compute(_))) {
+        let copy = include_bytes!("../../shaders/postprocess_copy.bin");
+        passes.push((None, PostProcessPass::fragment("Copy", &copy[..], output_format, data.swapchain_format)));
+    }
+
+    validate(&passes.iter().map(|(_, p)| p.clone()).collect::<Vec<_>>())?;
+
+    let mut formats = vec![(SOURCE_FORMAT, false, false)];
+    let mut plan = vec![];
+    let mut input = 0;
+    let count = passes.len();
+    for (n, (index, pass)) in passes.into_iter().enumerate() {
+        let compute = matches!(pass.shader, PassShader::Compute(_));
+        let output = if n + 1 == count {
+            None
+        } else {
+            let existing = formats.iter().enumerate().position(|(i, (f, _, _))| *f == pass.output_format && i != input);
+            let output = existing.unwrap_or_else(|| {
+                formats.push((pass.output_format, false, false));
+                formats.len() - 1
+            });
+            if compute {
+                formats[output].1 = true;
+            } else {
+                formats[output].2 = true;
+            }
+            Some(output)
+        };
+
+        plan.push((index, pass, input, output));
+        input = output.unwrap_or(input);
+    }
+
+    // Tone mapping renders into the source target.
+    formats[0].2 = true;
+
+    // Render Passes
+
+    let render_pass_formats = formats.iter()
+        .filter(|(_, _, drawn)| *drawn)
+        .map(|(f, _, _)| *f)
+        .collect::<Vec<_>>();
+
+    for format in render_pass_formats {
+        if !data.post_process_render_passes.iter().any(|(f, _)| *f == format) {
+            let render_pass = render_pass::create_unless_dynamic(device, data, &[output_attachment(format)])?;
+            data.post_process_render_passes.push((format, render_pass));
+        }
+    }
+
+    data.post_process_present_render_pass = render_pass::create_unless_dynamic(device, data, &[output_attachment(data.swapchain_format)])?;
+
+    data.post_process_present_framebuffers = data.swapchain_image_views.iter()
+        .map(|v| render_pass::create_framebuffer(device, data.post_process_present_render_pass, &[*v], data.swapchain_extent))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Targets
+
+    for (format, stored, drawn) in formats {
+        let mut usage = vk::ImageUsageFlags::SAMPLED;
+        if stored {
+            usage |= vk::ImageUsageFlags::STORAGE;
+        }
+        if drawn {
+            usage |= vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        }
+
+        let (target_image, target_image_memory) = image::create_image(
+            instance,
+            device,
+            data,
+            data.swapchain_extent.width,
+            data.swapchain_extent.height,
+            1,
+            vk::ImageCreateFlags::empty(),
+            format,
+            vk::ImageTiling::OPTIMAL,
+            usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let view = image::create_image_view(device, target_image, format, vk::ImageAspectFlags::COLOR)?;
+
+        let framebuffer = if drawn {
+            render_pass::create_framebuffer(device, render_pass(data, format), &[view], data.swapchain_extent)?
+        } else {
+            vk::Framebuffer::null()
+        };
+
+        data.post_process_targets.push(Target { format, image: target_image, memory: target_image_memory, view, framebuffer });
+    }
+
+    // Sampler
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.post_process_sampler = device.create_sampler(&info, None)?;
+
+    // Descriptor Sets
+
+    let stages = vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE;
+
+    let input_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(stages);
+
+    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(stages);
+
+    let output_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(2)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let bindings = &[input_binding, sampler_binding, output_binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.post_process_descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    let set_count = plan.len() as u32;
+
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(set_count);
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLER)
+        .descriptor_count(set_count);
+    let storage_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(set_count);
+
+    let pool_sizes = &[image_size, sampler_size, storage_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(set_count);
+
+    data.post_process_descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let layouts = vec![data.post_process_descriptor_set_layout; plan.len()];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.post_process_descriptor_pool)
+        .set_layouts(&layouts);
+
+    let descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    // Pipelines
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(stages)
+        .offset(0)
+        .size(size_of::<PostProcessPushConstants>() as u32);
+
+    let set_layouts = &[data.post_process_descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.post_process_pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    let vert = include_bytes!("../../shaders/fullscreen.bin");
+
+    for ((index, pass, input, output), descriptor_set) in plan.into_iter().zip(descriptor_sets) {
+        let input_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(data.post_process_targets[input].view)];
+        let input_write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(input_info);
+
+        let sampler_info = &[vk::DescriptorImageInfo::builder().sampler(data.post_process_sampler)];
+        let sampler_write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(1)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(sampler_info);
+
+        device.update_descriptor_sets(&[input_write, sampler_write], &[] as &[vk::CopyDescriptorSet]);
+
+        let (shader, compute) = match &pass.shader {
+            PassShader::Fragment(spirv) => (&spirv[..], false),
+            PassShader::Compute(spirv) => (&spirv[..], true),
+        };
+
+        let pipeline = if compute {
+            // Compute passes never write the swapchain image.
+            let output = output.unwrap_or_default();
+            let output_info = &[vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::GENERAL)
+                .image_view(data.post_process_targets[output].view)];
+            let output_write = vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(2)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(output_info);
+
+            device.update_descriptor_sets(&[output_write], &[] as &[vk::CopyDescriptorSet]);
+
+            pipeline::create_compute_pipeline(device, shader, data.post_process_pipeline_layout)?
+        } else {
+            let (render_pass, format) = match output {
+                Some(output) => {
+                    let format = data.post_process_targets[output].format;
+                    (render_pass(data, format), format)
+                }
+                None => (data.post_process_present_render_pass, data.swapchain_format),
+            };
+
+            pipeline::create_fullscreen_pipeline(
+                device,
+                data.swapchain_extent,
+                &vert[..],
+                shader,
+                data.post_process_pipeline_layout,
+                render_pass,
+                format,
+                false,
+            )?
+        };
+
+        data.post_process_steps.push(Step {
+            pass: index,
+            parameters: pass.parameters,
+            compute,
+            pipeline,
+            descriptor_set,
+            output,
+        });
+    }
+
+    Ok(())
+}
+
+pub unsafe fn destroy_post_process_objects(device: &Device, data: &mut app_data::Data) {
+    data.post_process_steps.drain(..).for_each(|s| device.destroy_pipeline(s.pipeline, None));
+    device.destroy_pipeline_layout(data.post_process_pipeline_layout, None);
+    device.destroy_descriptor_pool(data.post_process_descriptor_pool, None);
+    device.destroy_descriptor_set_layout(data.post_process_descriptor_set_layout, None);
+    device.destroy_sampler(data.post_process_sampler, None);
+    for target in data.post_process_targets.drain(..) {
+        device.destroy_framebuffer(target.framebuffer, None);
+        device.destroy_image_view(target.view, None);
+        device.free_memory(target.memory, None);
+        device.destroy_image(target.image, None);
+    }
+    data.post_process_present_framebuffers.drain(..).for_each(|f| device.destroy_framebuffer(f, None));
+    device.destroy_render_pass(data.post_process_present_render_pass, None);
+    data.post_process_render_passes.drain(..).for_each(|(_, r)| device.destroy_render_pass(r, None));
+
+    data.post_process_pipeline_layout = vk::PipelineLayout::null();
+    data.post_process_descriptor_pool = vk::DescriptorPool::null();
+    data.post_process_descriptor_set_layout = vk::DescriptorSetLayout::null();
+    data.post_process_sampler = vk::Sampler::null();
+    data.post_process_present_render_pass = vk::RenderPass::null();
+}
+
+/// Runs the enabled passes in order after tone mapping, ending in swapchain image
+/// `image_index`, which is left as a color attachment.
+pub unsafe fn cmd_post_process(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    image_index: usize,
+    images: &mut image_state::ImageStates,
+) {
+    let extent = data.swapchain_extent;
+    let color = vk::ImageAspectFlags::COLOR;
+    let sampled = image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER);
+
+    for step in &data.post_process_steps {
+        let push_constants = PostProcessPushConstants {
+            texel_size: [1.0 / extent.width as f32, 1.0 / extent.height as f32],
+            _padding: [0.0; 2],
+            parameters: step.pass.map(|i| data.post_process[i].parameters).unwrap_or(step.parameters),
+        };
+
+        let bind_point = if step.compute { vk::PipelineBindPoint::COMPUTE } else { vk::PipelineBindPoint::GRAPHICS };
+
+        if step.compute {
+            let image = data.post_process_targets[step.output.unwrap_or_default()].image;
+            images.cmd_use(device, command_buffer, image, color, image_state::Usage::Storage);
+        } else {
+            let (render_pass, framebuffer, target) = match step.output {
+                Some(output) => {
+                    let target = &data.post_process_targets[output];
+                    let attachment = output_attachment(target.format);
+                    (render_pass(data, target.format), target.framebuffer, attachment.target(target.image, target.view))
+                }
+                None => (
+                    data.post_process_present_render_pass,
+                    data.post_process_present_framebuffers[image_index],
+                    output_attachment(data.swapchain_format)
+                        .target(data.swapchain_images[image_index], data.swapchain_image_views[image_index]),
+                ),
+            };
+
+            render_pass::cmd_begin(device, command_buffer, data, images, render_pass, framebuffer, extent, &[target]);
+        }
+
+        device.cmd_bind_pipeline(command_buffer, bind_point, step.pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            bind_point,
+            data.post_process_pipeline_layout,
+            0,
+            &[step.descriptor_set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            data.post_process_pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE,
+            0,
+            std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<PostProcessPushConstants>()),
+        );
+
+        if step.compute {
+            device.cmd_dispatch(
+                command_buffer,
+                extent.width.div_ceil(WORKGROUP_SIZE),
+                extent.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+
+            let image = data.post_process_targets[step.output.unwrap_or_default()].image;
+            images.cmd_use(device, command_buffer, image, color, sampled);
+        } else {
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+            let render_pass = match step.output {
+                Some(output) => render_pass(data, data.post_process_targets[output].format),
+                None => data.post_process_present_render_pass,
+            };
+
+            render_pass::cmd_end(device, command_buffer, data, render_pass);
+
+            if let Some(output) = step.output {
+                images.cmd_use(device, command_buffer, data.post_process_targets[output].image, color, sampled);
+            }
+        }
+    }
+}
+
+fn render_pass(data: &app_data::Data, format: vk::Format) -> vk::RenderPass {
+    data.post_process_render_passes.iter()
+        .find(|(f, _)| *f == format)
+        .map(|(_, r)| *r)
+        .unwrap_or_default()
+}
+
+/// A whole target, written for every pixel.
+fn output_attachment(format: vk::Format) -> render_pass::Attachment {
+    render_pass::Attachment::color(format, vk::AttachmentLoadOp::DONT_CARE)
+}
@@ -9,14 +9,34 @@ use vulkanalia::vk::KhrSurfaceExtension;
 pub struct QueueFamilyIndices {
     pub graphics: u32,
     pub present: u32,
+    /// A compute family without graphics, whose queue runs particle simulation and culling
+    /// alongside the graphics queue. Falls back to the graphics family, where that work is
+    /// recorded into the frame's command buffer instead. Post-processing reads the frame it
+    /// follows, so it stays on the graphics queue.
+    pub compute: u32,
+    /// A transfer-only family, usually backed by dedicated copy engines, that uploads are
+    /// submitted to. Falls back to the graphics family when the device has none.
+    pub transfer: u32,
 }
 
 impl QueueFamilyIndices {
     pub unsafe fn get(instance: &Instance, data: &app_data::Data, physical_device: vk::PhysicalDevice) -> Result<Self> {
         let properties = instance.get_physical_device_queue_family_properties(physical_device);
 
-        let graphics =
-            properties.iter().position(|p| p.queue_flags.contains(vk::QueueFlags::GRAPHICS)).map(|i| i as u32);
+        // Every device with a graphics family has one that also supports compute, which the
+        // exposure passes are recorded on.
+        let graphics = properties.iter()
+            .position(|p| p.queue_flags.contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE))
+            .map(|i| i as u32);
+
+        let compute = properties.iter()
+            .position(|p| p.queue_flags.contains(vk::QueueFlags::COMPUTE) && !p.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            .map(|i| i as u32);
+
+        let transfer = properties.iter()
+            .position(|p| p.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                && !p.queue_flags.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE))
+            .map(|i| i as u32);
 
         let mut present = None;
         for (index, _properties) in properties.iter().enumerate() {
@@ -27,7 +47,7 @@ impl QueueFamilyIndices {
         }
 
         if let (Some(graphics), Some(present)) = (graphics, present) {
-            Ok(Self { graphics, present })
+            Ok(Self { graphics, present, compute: compute.unwrap_or(graphics), transfer: transfer.unwrap_or(graphics) })
         } else {
             Err(anyhow!("Missing required queue families."))
         }
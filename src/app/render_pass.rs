@@ -0,0 +1,246 @@
+use super::app_data;
+use super::image_state;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+
+use vulkanalia::vk::DeviceV1_3;
+use vulkanalia::vk::KhrDynamicRenderingExtension;
+
+/// An attachment of a single-subpass pass. Render passes start and end it in the layout of
+/// `usage`, so `ImageStates` makes every transition around them.
+#[derive(Copy, Clone, Debug)]
+pub struct Attachment {
+    pub format: vk::Format,
+    /// `ColorAttachment`, `DepthAttachment`, or `DepthRead` for depth tested without writes.
+    pub usage: image_state::Usage,
+    /// Anything but `LOAD` throws the previous contents away.
+    pub load_op: vk::AttachmentLoadOp,
+}
+
+impl Attachment {
+    pub fn color(format: vk::Format, load_op: vk::AttachmentLoadOp) -> Self {
+        Self { format, usage: image_state::Usage::ColorAttachment, load_op }
+    }
+
+    pub fn depth(format: vk::Format, load_op: vk::AttachmentLoadOp) -> Self {
+        Self { format, usage: image_state::Usage::DepthAttachment, load_op }
+    }
+
+    /// Depth loaded and tested without writes.
+    pub fn depth_read(format: vk::Format) -> Self {
+        Self { format, usage: image_state::Usage::DepthRead, load_op: vk::AttachmentLoadOp::LOAD }
+    }
+
+    fn is_color(&self) -> bool {
+        self.usage == image_state::Usage::ColorAttachment
+    }
+
+    /// Binds `image`, seen through `view`, to this attachment, cleared to the far plane or to
+    /// black, which integer formats read as zero.
+    pub fn target(self, image: vk::Image, view: vk::ImageView) -> Target {
+        let clear_value = if self.is_color() {
+            vk::ClearValue { color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] } }
+        } else {
+            vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } }
+        };
+
+        Target { attachment: self, image, view, clear_value }
+    }
+}
+
+/// An image bound to an attachment when a pass begins.
+#[derive(Copy, Clone)]
+pub struct Target {
+    pub attachment: Attachment,
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    /// Used when the attachment is cleared.
+    pub clear_value: vk::ClearValue,
+}
+
+/// Binds `views`, pairs of an image and a view of it, to `attachments` in order. Extra views
+/// are left out.
+pub fn targets(attachments: &[Attachment], views: &[(vk::Image, vk::ImageView)]) -> Vec<Target> {
+    attachments.iter()
+        .zip(views)
+        .map(|(a, (image, view))| a.target(*image, *view))
+        .collect()
+}
+
+/// A render pass with one subpass writing `attachments`, the depth one among them, if any,
+/// bound as depth. It has no layout transitions or external dependencies of its own.
+pub unsafe fn create(device: &Device, attachments: &[Attachment]) -> Result<vk::RenderPass> {
+    let descriptions = attachments.iter()
+        .map(|a| vk::AttachmentDescription::builder()
+            .format(a.format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(a.load_op)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(a.usage.layout())
+            .final_layout(a.usage.layout())
+            .build())
+        .collect::<Vec<_>>();
+
+    let reference = |(i, a): (usize, &Attachment)| vk::AttachmentReference::builder()
+        .attachment(i as u32)
+        .layout(a.usage.layout())
+        .build();
+
+    let color_attachment_refs = attachments.iter()
+        .enumerate()
+        .filter(|(_, a)| a.is_color())
+        .map(reference)
+        .collect::<Vec<_>>();
+
+    let depth_stencil_attachment_ref = attachments.iter()
+        .enumerate()
+        .find(|(_, a)| !a.is_color())
+        .map(reference);
+
+    let mut subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_attachment_refs);
+
+    if let Some(depth_stencil_attachment_ref) = &depth_stencil_attachment_ref {
+        subpass = subpass.depth_stencil_attachment(depth_stencil_attachment_ref);
+    }
+
+    let subpasses = &[subpass];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(&descriptions)
+        .subpasses(subpasses);
+
+    Ok(device.create_render_pass(&info, None)?)
+}
+
+/// Like `create`, but null when `data.dynamic_rendering` is set, as the passes recreated with
+/// the swapchain then begin rendering into their images directly.
+pub unsafe fn create_unless_dynamic(device: &Device, data: &app_data::Data, attachments: &[Attachment]) -> Result<vk::RenderPass> {
+    if data.dynamic_rendering {
+        Ok(vk::RenderPass::null())
+    } else {
+        create(device, attachments)
+    }
+}
+
+/// A framebuffer binding `views` to the attachments of `render_pass`, or null if it is null.
+pub unsafe fn create_framebuffer(
+    device: &Device,
+    render_pass: vk::RenderPass,
+    views: &[vk::ImageView],
+    extent: vk::Extent2D,
+) -> Result<vk::Framebuffer> {
+    if render_pass.is_null() {
+        return Ok(vk::Framebuffer::null());
+    }
+
+    let info = vk::FramebufferCreateInfo::builder()
+        .render_pass(render_pass)
+        .attachments(views)
+        .width(extent.width)
+        .height(extent.height)
+        .layers(1);
+
+    Ok(device.create_framebuffer(&info, None)?)
+}
+
+/// The color formats of `attachments` in order and the depth format, or `UNDEFINED`, which
+/// pipelines drawn with dynamic rendering declare in place of a render pass.
+pub fn formats(attachments: &[Attachment]) -> (Vec<vk::Format>, vk::Format) {
+    let colors = attachments.iter().filter(|a| a.is_color()).map(|a| a.format).collect();
+    let depth = attachments.iter().find(|a| !a.is_color()).map(|a| a.format).unwrap_or(vk::Format::UNDEFINED);
+    (colors, depth)
+}
+
+/// Moves `targets` into their attachment layouts, then begins `render_pass` on `framebuffer`,
+/// or dynamic rendering into `targets` if `render_pass` is null.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn cmd_begin(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
+    targets: &[Target],
+) {
+    for target in targets {
+        let attachment = &target.attachment;
+        let aspect_mask = if attachment.is_color() {
+            vk::ImageAspectFlags::COLOR
+        } else {
+            image_state::depth_aspect(attachment.format)
+        };
+
+        if attachment.load_op == vk::AttachmentLoadOp::LOAD {
+            images.transition(target.image, aspect_mask, attachment.usage);
+        } else {
+            images.transition_discarding(target.image, aspect_mask, attachment.usage);
+        }
+    }
+
+    images.cmd_flush(device, command_buffer);
+
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(extent);
+
+    if !render_pass.is_null() {
+        let clear_values = targets.iter().map(|t| t.clear_value).collect::<Vec<_>>();
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
+            .render_area(render_area)
+            .clear_values(&clear_values);
+
+        device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+        return;
+    }
+
+    let attachment = |target: &Target| vk::RenderingAttachmentInfo::builder()
+        .image_view(target.view)
+        .image_layout(target.attachment.usage.layout())
+        .load_op(target.attachment.load_op)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .clear_value(target.clear_value)
+        .build();
+
+    let color_attachments = targets.iter()
+        .filter(|t| t.attachment.is_color())
+        .map(attachment)
+        .collect::<Vec<_>>();
+
+    let depth_attachment = targets.iter()
+        .find(|t| !t.attachment.is_color())
+        .map(attachment);
+
+    let mut info = vk::RenderingInfo::builder()
+        .render_area(render_area)
+        .layer_count(1)
+        .color_attachments(&color_attachments);
+
+    if let Some(depth_attachment) = &depth_attachment {
+        info = info.depth_attachment(depth_attachment);
+    }
+
+    if data.dynamic_rendering_khr {
+        device.cmd_begin_rendering_khr(command_buffer, &info);
+    } else {
+        device.cmd_begin_rendering(command_buffer, &info);
+    }
+}
+
+/// Ends a pass begun with `cmd_begin`.
+pub unsafe fn cmd_end(device: &Device, command_buffer: vk::CommandBuffer, data: &app_data::Data, render_pass: vk::RenderPass) {
+    if !render_pass.is_null() {
+        device.cmd_end_render_pass(command_buffer);
+    } else if data.dynamic_rendering_khr {
+        device.cmd_end_rendering_khr(command_buffer);
+    } else {
+        device.cmd_end_rendering(command_buffer);
+    }
+}
@@ -0,0 +1,286 @@
+use super::animation;
+use super::mesh_processing;
+use super::vertex_buffer;
+
+use nalgebra_glm as glm;
+
+/// A simplified copy of a primitive's indices, stored after the full-detail range.
+#[derive(Copy, Clone, Debug)]
+pub struct Lod {
+    pub first_index: u32,
+    pub index_count: u32,
+    /// Largest distance, in object space, between the simplified and the original surface.
+    pub error: f32,
+}
+
+/// A range of the shared index buffer drawn with a single material. Its vertices occupy
+/// `vertex_count` vertices from `vertex_offset` in the shared vertex array.
+#[derive(Clone, Debug)]
+pub struct Primitive {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+    pub vertex_count: u32,
+    pub material: Option<usize>,
+    /// Coarser levels of detail, from most to least detailed.
+    pub lods: Vec<Lod>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub name: Option<String>,
+    pub primitives: Vec<Primitive>,
+}
+
+/// A decomposed local transform.
+#[derive(Copy, Clone, Debug)]
+pub struct Transform {
+    pub translation: glm::Vec3,
+    pub rotation: glm::Quat,
+    pub scale: glm::Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: glm::vec3(0.0, 0.0, 0.0),
+            rotation: glm::quat_identity(),
+            scale: glm::vec3(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> glm::Mat4 {
+        glm::translation(&self.translation) * glm::quat_to_mat4(&self.rotation) * glm::scaling(&self.scale)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// The current local transform, overwritten by animation playback.
+    pub transform: glm::Mat4,
+    /// The local transform as authored, used where no animation channel applies.
+    pub rest: Transform,
+    pub mesh: Option<usize>,
+    pub skin: Option<usize>,
+}
+
+/// Joints deforming the meshes of the nodes that reference this skin.
+#[derive(Clone, Debug, Default)]
+pub struct Skin {
+    pub name: Option<String>,
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<glm::Mat4>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque,
+    Mask,
+    Blend,
+}
+
+/// Metallic-roughness material parameters. Texture fields index into `Scene::images`.
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: Option<String>,
+    pub base_color_factor: glm::Vec4,
+    pub base_color_texture: Option<usize>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub metallic_roughness_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<usize>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: glm::Vec3,
+    pub emissive_texture: Option<usize>,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            name: None,
+            base_color_factor: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_factor: glm::vec3(0.0, 0.0, 0.0),
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+/// Decoded RGBA8 pixels.
+#[derive(Clone, Debug)]
+pub struct ImageData {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    pub srgb: bool,
+}
+
+/// A single indexed draw of a primitive with its node's world transform.
+#[derive(Copy, Clone, Debug)]
+pub struct Draw {
+    pub node: usize,
+    pub mesh: usize,
+    pub primitive: usize,
+    pub material: Option<usize>,
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+    pub transform: glm::Mat4,
+    /// First joint matrix of the node, if it is skinned.
+    pub joint_offset: Option<u32>,
+}
+
+/// Geometry of every mesh packed into shared vertex/index arrays plus the node hierarchy.
+#[derive(Clone, Debug, Default)]
+pub struct Scene {
+    pub vertices: Vec<vertex_buffer::Vertex>,
+    pub indices: Vec<u32>,
+    pub meshes: Vec<Mesh>,
+    pub nodes: Vec<Node>,
+    pub roots: Vec<usize>,
+    pub materials: Vec<Material>,
+    pub images: Vec<ImageData>,
+    pub skins: Vec<Skin>,
+    pub animations: Vec<animation::AnimationClip>,
+}
+
+impl Scene {
+    /// A scene with one node drawing all of `indices` without a material. Normals are computed
+    /// if none of the vertices has one.
+    pub fn new(vertices: &[vertex_buffer::Vertex], indices: &[u32]) -> Self {
+        let mut vertices = vertices.to_vec();
+        if vertices.iter().all(|v| v.normal() == glm::Vec3::zeros()) {
+            mesh_processing::compute_normals(&mut vertices, indices);
+        }
+
+        let primitive = Primitive {
+            first_index: 0,
+            index_count: indices.len() as u32,
+            vertex_offset: 0,
+            vertex_count: vertices.len() as u32,
+            material: None,
+            lods: vec![],
+        };
+
+        let node = Node {
+            name: None,
+            parent: None,
+            children: vec![],
+            transform: glm::identity(),
+            rest: Transform::default(),
+            mesh: Some(0),
+            skin: None,
+        };
+
+        Self {
+            vertices,
+            indices: indices.to_vec(),
+            meshes: vec![Mesh { name: None, primitives: vec![primitive] }],
+            nodes: vec![node],
+            roots: vec![0],
+            ..Default::default()
+        }
+    }
+
+    pub fn world_transform(&self, node: usize) -> glm::Mat4 {
+        let mut transform = self.nodes[node].transform;
+        let mut parent = self.nodes[node].parent;
+        while let Some(p) = parent {
+            transform = self.nodes[p].transform * transform;
+            parent = self.nodes[p].parent;
+        }
+        transform
+    }
+
+    /// Each node drawing a skinned mesh, with its skin.
+    pub fn skinned_nodes(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.nodes.iter().enumerate().filter_map(|(index, node)| match (node.mesh, node.skin) {
+            (Some(_), Some(skin)) => Some((index, skin)),
+            _ => None,
+        })
+    }
+
+    /// The number of matrices `joint_matrices` returns.
+    pub fn joint_count(&self) -> usize {
+        self.skinned_nodes().map(|(_, skin)| self.skins[skin].joints.len()).sum()
+    }
+
+    /// The first joint matrix of each node within `joint_matrices`, if it draws a skinned mesh.
+    pub fn joint_offsets(&self) -> Vec<Option<u32>> {
+        let mut offsets = vec![None; self.nodes.len()];
+        let mut offset = 0;
+        for (node, skin) in self.skinned_nodes() {
+            offsets[node] = Some(offset);
+            offset += self.skins[skin].joints.len() as u32;
+        }
+        offsets
+    }
+
+    /// Joint matrices of every skinned node, concatenated in node order. Nodes sharing a skin
+    /// each get their own, relative to that node, since its transform is applied by its draw.
+    pub fn joint_matrices(&self) -> Vec<glm::Mat4> {
+        let mut matrices = vec![];
+
+        for (node, skin) in self.skinned_nodes() {
+            let skin = &self.skins[skin];
+            let inverse = glm::inverse(&self.world_transform(node));
+
+            for (joint, inverse_bind_matrix) in skin.joints.iter().zip(&skin.inverse_bind_matrices) {
+                matrices.push(inverse * self.world_transform(*joint) * inverse_bind_matrix);
+            }
+        }
+
+        matrices
+    }
+
+    pub fn draws(&self) -> Vec<Draw> {
+        let joint_offsets = self.joint_offsets();
+        let mut draws = vec![];
+        let mut stack = self.roots.iter().map(|r| (*r, glm::identity())).collect::<Vec<(usize, glm::Mat4)>>();
+
+        while let Some((index, parent_transform)) = stack.pop() {
+            let node = &self.nodes[index];
+            let transform = parent_transform * node.transform;
+
+            if let Some(mesh) = node.mesh {
+                for (primitive_index, primitive) in self.meshes[mesh].primitives.iter().enumerate() {
+                    draws.push(Draw {
+                        node: index,
+                        mesh,
+                        primitive: primitive_index,
+                        material: primitive.material,
+                        first_index: primitive.first_index,
+                        index_count: primitive.index_count,
+                        vertex_offset: primitive.vertex_offset,
+                        transform,
+                        joint_offset: joint_offsets[index],
+                    });
+                }
+            }
+
+            stack.extend(node.children.iter().map(|c| (*c, transform)));
+        }
+
+        draws
+    }
+}
@@ -0,0 +1,653 @@
+use super::app_data;
+use super::app_defines;
+use super::image;
+use super::image_state;
+use super::pipeline;
+use super::render_pass;
+use super::scene;
+use super::swapchain;
+use super::vertex_buffer;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+use std::ptr::copy_nonoverlapping as memcpy;
+use std::mem::size_of;
+use nalgebra_glm as glm;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShadowSettings {
+    pub enabled: bool,
+    /// Number of cascades, up to `MAX_CASCADES`.
+    pub cascade_count: u32,
+    /// Width and height of each cascade in texels.
+    pub resolution: u32,
+    /// Blend between uniform (0) and logarithmic (1) cascade splits.
+    pub split_lambda: f32,
+    /// Width of the square PCF kernel in texels; 1 takes a single filtered sample.
+    pub pcf_kernel_size: u32,
+    pub depth_bias_constant: f32,
+    pub depth_bias_slope: f32,
+    /// Width and height of each point light cube face in texels.
+    pub point_resolution: u32,
+    /// Distance beyond which point lights cast no shadows.
+    pub point_far_plane: f32,
+    /// World-space distance subtracted before comparing against a cube map.
+    pub point_bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            cascade_count: 4,
+            resolution: 2048,
+            split_lambda: 0.75,
+            pcf_kernel_size: 3,
+            depth_bias_constant: 1.25,
+            depth_bias_slope: 1.75,
+            point_resolution: 512,
+            point_far_plane: app_defines::FAR_PLANE,
+            point_bias: 0.05,
+        }
+    }
+}
+
+/// The `Shadows` uniform block shared by the shadow and main passes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ShadowUniform {
+    light_view_proj: [glm::Mat4; app_defines::MAX_CASCADES],
+    /// View-space distance at which each cascade ends.
+    splits: glm::Vec4,
+    cascade_count: u32,
+    pcf_kernel_size: u32,
+    /// Index of the shadowed light, or `u32::MAX` when shadows are off.
+    light: u32,
+    point_bias: f32,
+    /// Six face projections per shadowed point light, in `+X, -X, +Y, -Y, +Z, -Z` order.
+    point_view_proj: [glm::Mat4; app_defines::MAX_POINT_SHADOWS * 6],
+    /// Light position, with the far plane in `w`.
+    point_positions: [glm::Vec4; app_defines::MAX_POINT_SHADOWS],
+    /// Index of the light shadowed by each cube map, or `u32::MAX` if it is unused.
+    point_lights: [u32; app_defines::MAX_POINT_SHADOWS],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct ShadowPushConstants {
+    model: glm::Mat4,
+    joint_offset: i32,
+    /// Cascade, or cube face across all point lights, being rendered.
+    view: u32,
+}
+
+/// Fits one orthographic light projection around each slice of the view frustum. `light` is
+/// the index and direction of the shadowed directional light, if any.
+pub fn cascades(
+    settings: &ShadowSettings,
+    view: &glm::Mat4,
+    proj: &glm::Mat4,
+    near: f32,
+    far: f32,
+    light: Option<(usize, glm::Vec3)>,
+) -> ShadowUniform {
+    let count = settings.cascade_count.clamp(1, app_defines::MAX_CASCADES as u32) as usize;
+    let mut uniform = ShadowUniform {
+        light_view_proj: [glm::identity(); app_defines::MAX_CASCADES],
+        splits: glm::vec4(far, far, far, far),
+        cascade_count: count as u32,
+        pcf_kernel_size: settings.pcf_kernel_size.max(1),
+        light: u32::MAX,
+        point_bias: settings.point_bias,
+        point_view_proj: [glm::identity(); app_defines::MAX_POINT_SHADOWS * 6],
+        point_positions: [glm::Vec4::zeros(); app_defines::MAX_POINT_SHADOWS],
+        point_lights: [u32::MAX; app_defines::MAX_POINT_SHADOWS],
+    };
+
+    let (index, direction) = match light {
+        Some((index, direction)) if settings.enabled => (index, direction.normalize()),
+        _ => return uniform,
+    };
+    uniform.light = index as u32;
+
+    // Splits
+
+    for i in 0..count {
+        let p = (i + 1) as f32 / count as f32;
+        let logarithmic = near * (far / near).powf(p);
+        let uniform_split = near + (far - near) * p;
+        uniform.splits[i] = settings.split_lambda * logarithmic + (1.0 - settings.split_lambda) * uniform_split;
+    }
+
+    // Projections
+
+    let inverse_view = glm::inverse(view);
+    let tan_y = 1.0 / proj[(1, 1)].abs();
+    let tan_x = 1.0 / proj[(0, 0)].abs();
+    let up = if direction.z.abs() > 0.99 { glm::vec3(0.0, 1.0, 0.0) } else { glm::vec3(0.0, 0.0, 1.0) };
+
+    let mut slice_near = near;
+    for i in 0..count {
+        let slice_far = uniform.splits[i];
+
+        let corners = [slice_near, slice_far].iter()
+            .flat_map(|d| [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(x, y)| (*d, x, y)))
+            .map(|(d, x, y)| (inverse_view * glm::vec4(x * d * tan_x, y * d * tan_y, -d, 1.0)).xyz())
+            .collect::<Vec<_>>();
+
+        let center = corners.iter().fold(glm::Vec3::zeros(), |a, c| a + c) / corners.len() as f32;
+        // A sphere keeps the projection size constant as the camera turns, which stops edges
+        // from shimmering.
+        let radius = (corners.iter().map(|c| glm::distance(c, &center)).fold(0.0, f32::max) * 16.0).ceil() / 16.0;
+
+        // Casters up to `far` behind the slice still land in the map.
+        let eye = center - direction * (radius + far);
+        let light_view = glm::look_at(&eye, &center, &up);
+        let mut light_proj = glm::ortho_rh_zo(-radius, radius, -radius, radius, 0.0, 2.0 * radius + far);
+
+        // Snap to whole texels so the map only moves in texel steps.
+        let half_resolution = settings.resolution as f32 * 0.5;
+        let origin = (light_proj * light_view * glm::vec4(0.0, 0.0, 0.0, 1.0)).xy() * half_resolution;
+        let offset = (glm::round(&origin) - origin) / half_resolution;
+        light_proj[(0, 3)] += offset.x;
+        light_proj[(1, 3)] += offset.y;
+
+        uniform.light_view_proj[i] = light_proj * light_view;
+        slice_near = slice_far;
+    }
+
+    uniform
+}
+
+impl ShadowUniform {
+    /// Adds cube map projections for up to `MAX_POINT_SHADOWS` point or spot lights, given as
+    /// light index and position.
+    pub fn with_point_lights(mut self, settings: &ShadowSettings, lights: &[(usize, glm::Vec3)]) -> Self {
+        if !settings.enabled {
+            return self;
+        }
+
+        let far = settings.point_far_plane;
+        let proj = glm::perspective_rh_zo(1.0, std::f32::consts::FRAC_PI_2, app_defines::NEAR_PLANE, far);
+
+        for (slot, (index, position)) in lights.iter().take(app_defines::MAX_POINT_SHADOWS).enumerate() {
+            for (face, (target, up)) in CUBE_FACES.iter().enumerate() {
+                let view = glm::look_at(position, &(position + target), up);
+                self.point_view_proj[slot * 6 + face] = proj * view;
+            }
+
+            self.point_positions[slot] = glm::vec4(position.x, position.y, position.z, far);
+            self.point_lights[slot] = *index as u32;
+        }
+
+        self
+    }
+}
+
+/// Forward and up vectors of each cube face, matching the cube map sampling convention.
+const CUBE_FACES: [(glm::Vec3, glm::Vec3); 6] = [
+    (glm::Vec3::new(1.0, 0.0, 0.0), glm::Vec3::new(0.0, -1.0, 0.0)),
+    (glm::Vec3::new(-1.0, 0.0, 0.0), glm::Vec3::new(0.0, -1.0, 0.0)),
+    (glm::Vec3::new(0.0, 1.0, 0.0), glm::Vec3::new(0.0, 0.0, 1.0)),
+    (glm::Vec3::new(0.0, -1.0, 0.0), glm::Vec3::new(0.0, 0.0, -1.0)),
+    (glm::Vec3::new(0.0, 0.0, 1.0), glm::Vec3::new(0.0, -1.0, 0.0)),
+    (glm::Vec3::new(0.0, 0.0, -1.0), glm::Vec3::new(0.0, -1.0, 0.0)),
+];
+
+/// Creates everything that depends on `data.shadow_settings`: the cascade array, the point
+/// light cube maps, their views, render pass, framebuffers and pipelines.
+pub unsafe fn create_shadow_objects(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let format = swapchain::get_sampled_depth_format(instance, data)?;
+    let resolution = data.shadow_settings.resolution;
+    let point_resolution = data.shadow_settings.point_resolution;
+    let layers = app_defines::MAX_CASCADES as u32;
+    let faces = app_defines::MAX_POINT_SHADOWS as u32 * 6;
+
+    // Cascades
+
+    let (shadow_image, shadow_image_memory) = image::create_image(
+        instance,
+        device,
+        data,
+        resolution,
+        resolution,
+        layers,
+        vk::ImageCreateFlags::empty(),
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.shadow_image = shadow_image;
+    data.shadow_image_memory = shadow_image_memory;
+    data.shadow_image_view = image::create_image_view_layers(
+        device, shadow_image, format, vk::ImageAspectFlags::DEPTH, vk::ImageViewType::_2D_ARRAY, 0, layers)?;
+    data.shadow_layer_views = (0..layers)
+        .map(|l| image::create_image_view_layers(device, shadow_image, format, vk::ImageAspectFlags::DEPTH, vk::ImageViewType::_2D, l, 1))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Cube Maps
+
+    let (point_shadow_image, point_shadow_image_memory) = image::create_image(
+        instance,
+        device,
+        data,
+        point_resolution,
+        point_resolution,
+        faces,
+        vk::ImageCreateFlags::CUBE_COMPATIBLE,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.point_shadow_image = point_shadow_image;
+    data.point_shadow_image_memory = point_shadow_image_memory;
+    data.point_shadow_cube_views = (0..app_defines::MAX_POINT_SHADOWS as u32)
+        .map(|c| image::create_image_view_layers(device, point_shadow_image, format, vk::ImageAspectFlags::DEPTH, vk::ImageViewType::CUBE, c * 6, 6))
+        .collect::<Result<Vec<_>, _>>()?;
+    data.point_shadow_face_views = (0..faces)
+        .map(|f| image::create_image_view_layers(device, point_shadow_image, format, vk::ImageAspectFlags::DEPTH, vk::ImageViewType::_2D, f, 1))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Render Pass
+
+    data.shadow_render_pass = render_pass::create_unless_dynamic(device, data, &[shadow_attachment(format)])?;
+
+    // Framebuffers
+
+    data.shadow_framebuffers = create_framebuffers(device, data.shadow_render_pass, &data.shadow_layer_views, resolution)?;
+    data.point_shadow_framebuffers = create_framebuffers(device, data.shadow_render_pass, &data.point_shadow_face_views, point_resolution)?;
+
+    // Pipelines
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .offset(0)
+        .size(size_of::<ShadowPushConstants>() as u32);
+
+    let set_layouts = &[data.descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.shadow_pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    let vert = include_bytes!("../../shaders/shadow.bin");
+    data.shadow_pipeline = create_depth_pipeline(device, data, format, &vert[..], None, resolution)?;
+
+    let vert = include_bytes!("../../shaders/point_shadow_vert.bin");
+    let frag = include_bytes!("../../shaders/point_shadow_frag.bin");
+    data.point_shadow_pipeline = create_depth_pipeline(device, data, format, &vert[..], Some(&frag[..]), point_resolution)?;
+
+    Ok(())
+}
+
+unsafe fn create_framebuffers(device: &Device, render_pass: vk::RenderPass, views: &[vk::ImageView], resolution: u32) -> Result<Vec<vk::Framebuffer>> {
+    let extent = vk::Extent2D { width: resolution, height: resolution };
+    views.iter()
+        .map(|v| render_pass::create_framebuffer(device, render_pass, &[*v], extent))
+        .collect()
+}
+
+/// A depth-only pipeline for `data.shadow_render_pass`, or for rendering into depth of `format`
+/// if it is null. The fragment stage is only needed to write a depth other than the
+/// rasterized one.
+unsafe fn create_depth_pipeline(
+    device: &Device,
+    data: &app_data::Data,
+    format: vk::Format,
+    vert: &[u8],
+    frag: Option<&[u8]>,
+    resolution: u32,
+) -> Result<vk::Pipeline> {
+    // Stages
+
+    let vert_shader_module = pipeline::create_shader_module(device, vert)?;
+    let frag_shader_module = frag.map(|f| pipeline::create_shader_module(device, f)).transpose()?;
+
+    let mut stages = vec![vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0")
+        .build()];
+
+    if let Some(module) = frag_shader_module {
+        stages.push(vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(module)
+            .name(b"main\0")
+            .build());
+    }
+
+    // Vertex Input State
+
+    let binding_descriptions = &[vertex_buffer::Vertex::binding_description()];
+    let attribute_descriptions = vertex_buffer::Vertex::attribute_descriptions()
+        .into_iter()
+        .filter(|a| [0, 3, 4].contains(&a.location))
+        .collect::<Vec<_>>();
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(&attribute_descriptions);
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Viewport State
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(resolution as f32)
+        .height(resolution as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(vk::Extent2D { width: resolution, height: resolution });
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // Rasterization State
+
+    // Both faces are drawn so open meshes still cast shadows; the bias is set per frame.
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(true);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY);
+
+    let dynamic_states = &[vk::DynamicState::DEPTH_BIAS];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(dynamic_states);
+
+    // Create
+
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(data.shadow_pipeline_layout)
+        .render_pass(data.shadow_render_pass)
+        .subpass(0);
+
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder().depth_attachment_format(format);
+
+    if data.shadow_render_pass.is_null() {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    let pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?
+        .0[0];
+
+    // Cleanup
+
+    device.destroy_shader_module(vert_shader_module, None);
+    if let Some(module) = frag_shader_module {
+        device.destroy_shader_module(module, None);
+    }
+
+    Ok(pipeline)
+}
+
+/// A comparison sampler returning the fraction of a 2×2 footprint that is lit.
+pub unsafe fn create_shadow_sampler(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+        .unnormalized_coordinates(false)
+        .compare_enable(true)
+        .compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.shadow_sampler = device.create_sampler(&info, None)?;
+
+    Ok(())
+}
+
+/// A plain sampler for cube maps, which are compared against light distances in the shader.
+pub unsafe fn create_point_shadow_sampler(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::NEAREST)
+        .min_filter(vk::Filter::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.point_shadow_sampler = device.create_sampler(&info, None)?;
+
+    Ok(())
+}
+
+pub unsafe fn create_shadow_buffers(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    data.shadow_buffers.clear();
+    data.shadow_buffers_memory.clear();
+
+    for _ in 0..data.swapchain_images.len() {
+        let (shadow_buffer, shadow_buffer_memory) = vertex_buffer::create_buffer(
+            instance,
+            device,
+            data,
+            size_of::<ShadowUniform>() as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.shadow_buffers.push(shadow_buffer);
+        data.shadow_buffers_memory.push(shadow_buffer_memory);
+    }
+
+    Ok(())
+}
+
+pub unsafe fn update_shadow_buffer(device: &Device, data: &app_data::Data, image_index: usize, uniform: &ShadowUniform) -> Result<()> {
+    let memory = device.map_memory(
+        data.shadow_buffers_memory[image_index],
+        0,
+        size_of::<ShadowUniform>() as u64,
+        vk::MemoryMapFlags::empty(),
+    )?;
+
+    memcpy(uniform, memory.cast(), 1);
+
+    device.unmap_memory(data.shadow_buffers_memory[image_index]);
+
+    Ok(())
+}
+
+fn shadow_attachment(format: vk::Format) -> render_pass::Attachment {
+    render_pass::Attachment::depth(format, vk::AttachmentLoadOp::CLEAR)
+}
+
+/// Renders every cascade and every face of the point light cube maps, leaving them readable
+/// by shading. Unused maps are still cleared so whatever the main pass samples is
+/// initialized.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn cmd_draw_shadows(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+    image_index: usize,
+    draws: &[scene::Draw],
+    point_shadow_count: usize,
+) {
+    let settings = &data.shadow_settings;
+    let cascade_count = settings.cascade_count.clamp(1, app_defines::MAX_CASCADES as u32) as usize;
+    let attachment = shadow_attachment(data.depth_format);
+
+    // Every layer is drawn, so each map moves between layouts as a whole.
+    let maps = [
+        (data.shadow_image, &data.shadow_layer_views, &data.shadow_framebuffers, settings.resolution),
+        (data.point_shadow_image, &data.point_shadow_face_views, &data.point_shadow_framebuffers, settings.point_resolution),
+    ];
+
+    for (point, (image, views, framebuffers, resolution)) in maps.into_iter().enumerate() {
+        for (view, (layer_view, framebuffer)) in views.iter().zip(framebuffers).enumerate() {
+            let pipeline = if point == 0 {
+                (settings.enabled && view < cascade_count).then_some(data.shadow_pipeline)
+            } else {
+                (settings.enabled && view < point_shadow_count * 6).then_some(data.point_shadow_pipeline)
+            };
+
+            let target = attachment.target(image, *layer_view);
+            let extent = vk::Extent2D { width: resolution, height: resolution };
+            render_pass::cmd_begin(device, command_buffer, data, images, data.shadow_render_pass, *framebuffer, extent, &[target]);
+            if let Some(pipeline) = pipeline {
+                cmd_draw_depth(device, command_buffer, data, image_index, pipeline, view, draws);
+            }
+            render_pass::cmd_end(device, command_buffer, data, data.shadow_render_pass);
+        }
+
+        let aspect_mask = image_state::depth_aspect(data.depth_format);
+        images.cmd_use(device, command_buffer, image, aspect_mask, image_state::Usage::DepthRead);
+    }
+}
+
+/// Draws every object into the begun layer from `view`.
+unsafe fn cmd_draw_depth(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    image_index: usize,
+    pipeline: vk::Pipeline,
+    view: usize,
+    draws: &[scene::Draw],
+) {
+    let settings = &data.shadow_settings;
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+    device.cmd_set_depth_bias(command_buffer, settings.depth_bias_constant, 0.0, settings.depth_bias_slope);
+    device.cmd_bind_vertex_buffers(command_buffer, 0, &[data.vertex_buffer], &[0]);
+    device.cmd_bind_index_buffer(command_buffer, data.index_buffer, 0, vk::IndexType::UINT32);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.shadow_pipeline_layout,
+        0,
+        &[data.descriptor_sets[image_index]],
+        &[],
+    );
+
+    for draw in draws {
+        let push_constants = ShadowPushConstants {
+            model: draw.transform,
+            joint_offset: draw.joint_offset.map(|o| o as i32).unwrap_or(-1),
+            view: view as u32,
+        };
+
+        device.cmd_push_constants(
+            command_buffer,
+            data.shadow_pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<ShadowPushConstants>()),
+        );
+        device.cmd_draw_indexed(command_buffer, draw.index_count, 1, draw.first_index, draw.vertex_offset, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIGHT: Option<(usize, glm::Vec3)> = Some((0, glm::Vec3::new(-1.0, -0.5, -2.0)));
+
+    fn splits(lambda: f32, count: u32) -> ShadowUniform {
+        let settings = ShadowSettings { split_lambda: lambda, cascade_count: count, ..Default::default() };
+        let view = glm::look_at(&glm::vec3(2.0, 2.0, 2.0), &glm::Vec3::zeros(), &glm::vec3(0.0, 0.0, 1.0));
+        let proj = glm::perspective_rh_zo(4.0 / 3.0, 1.0, 0.1, 100.0);
+        cascades(&settings, &view, &proj, 0.1, 100.0, LIGHT)
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() <= 1e-3 * b.abs().max(1.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn uniform_splits_are_evenly_spaced() {
+        let uniform = splits(0.0, 4);
+        for (i, expected) in [25.075, 50.05, 75.025, 100.0].iter().enumerate() {
+            assert_close(uniform.splits[i], *expected);
+        }
+    }
+
+    #[test]
+    fn logarithmic_splits_grow_geometrically() {
+        let uniform = splits(1.0, 3);
+        for (i, expected) in [1.0, 10.0, 100.0].iter().enumerate() {
+            assert_close(uniform.splits[i], *expected);
+        }
+    }
+
+    #[test]
+    fn blended_splits_increase_to_the_far_plane() {
+        let uniform = splits(0.75, 4);
+        assert_eq!(uniform.cascade_count, 4);
+        assert!((1..4).all(|i| uniform.splits[i] > uniform.splits[i - 1]));
+        assert!(uniform.splits[0] > 0.1);
+        assert_close(uniform.splits[3], 100.0);
+    }
+
+    #[test]
+    fn cascade_count_is_clamped() {
+        assert_eq!(splits(0.5, 0).cascade_count, 1);
+        assert_eq!(splits(0.5, 99).cascade_count, app_defines::MAX_CASCADES as u32);
+        assert_close(splits(0.5, 0).splits[0], 100.0);
+    }
+
+    #[test]
+    fn no_light_disables_cascades() {
+        let settings = ShadowSettings::default();
+        let uniform = cascades(&settings, &glm::identity(), &glm::identity(), 0.1, 100.0, None);
+        assert_eq!(uniform.light, u32::MAX);
+        assert_eq!(uniform.splits, glm::vec4(100.0, 100.0, 100.0, 100.0));
+    }
+}
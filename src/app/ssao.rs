@@ -0,0 +1,469 @@
+use super::app_data;
+use super::image;
+use super::image_state;
+use super::pipeline;
+use super::render_pass;
+use super::scene;
+use super::swapchain;
+use super::upload;
+use super::vertex_buffer;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+use std::ptr::copy_nonoverlapping as memcpy;
+use std::mem::size_of;
+use nalgebra_glm as glm;
+
+/// Size of the kernel uniform; `Ssao::sample_count` is clamped to it.
+pub const MAX_SSAO_SAMPLES: usize = 64;
+
+/// Side of the tiled rotation texture, and of the blur that removes its pattern.
+const NOISE_SIZE: u32 = 4;
+
+const OCCLUSION_FORMAT: vk::Format = vk::Format::R8_UNORM;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ssao {
+    pub enabled: bool,
+    /// View-space radius of the sampled hemisphere.
+    pub radius: f32,
+    /// Depth difference below which a sample does not occlude, against self-shadowing acne.
+    pub bias: f32,
+    pub sample_count: u32,
+}
+
+impl Default for Ssao {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            radius: 0.5,
+            bias: 0.025,
+            sample_count: 32,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct SsaoPushConstants {
+    radius: f32,
+    bias: f32,
+    sample_count: u32,
+}
+
+/// A small xorshift generator, so the kernel and noise are the same on every run.
+struct Random(u32);
+
+impl Random {
+    /// Uniform in `[0, 1)`.
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+/// Creates the hemisphere sample kernel and the noise texture rotating it. Neither depends on
+/// the swapchain.
+pub unsafe fn create_ssao_kernel(instance: &Instance, device: &Device, data: &mut app_data::Data, uploads: &mut upload::Batch) -> Result<()> {
+    let mut random = Random(0x9E37_79B9);
+
+    // Kernel
+
+    // Samples lie in the +Z hemisphere, packed closer to the origin so nearby occluders
+    // count for more.
+    let kernel = (0..MAX_SSAO_SAMPLES)
+        .map(|i| {
+            let direction = glm::vec3(random.next() * 2.0 - 1.0, random.next() * 2.0 - 1.0, random.next());
+            let direction = direction.try_normalize(f32::EPSILON).unwrap_or_else(glm::Vec3::z);
+            let scale = i as f32 / MAX_SSAO_SAMPLES as f32;
+            let scale = glm::lerp_scalar(0.1, 1.0, scale * scale);
+            glm::vec3_to_vec4(&(direction * random.next() * scale))
+        })
+        .collect::<Vec<_>>();
+
+    let size = (size_of::<glm::Vec4>() * MAX_SSAO_SAMPLES) as u64;
+    let (ssao_kernel_buffer, ssao_kernel_buffer_memory) = vertex_buffer::create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::UNIFORM_BUFFER,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    let memory = device.map_memory(ssao_kernel_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
+    memcpy(kernel.as_ptr(), memory.cast(), kernel.len());
+    device.unmap_memory(ssao_kernel_buffer_memory);
+
+    data.ssao_kernel_buffer = ssao_kernel_buffer;
+    data.ssao_kernel_buffer_memory = ssao_kernel_buffer_memory;
+
+    // Noise
+
+    // Random rotations about the view-space Z axis, encoded as unsigned bytes.
+    let pixels = (0..NOISE_SIZE * NOISE_SIZE)
+        .flat_map(|_| {
+            let x = random.next() * 2.0 - 1.0;
+            let y = random.next() * 2.0 - 1.0;
+            [x, y, 0.0, 1.0].map(|c| ((c * 0.5 + 0.5) * 255.0).round() as u8)
+        })
+        .collect::<Vec<_>>();
+
+    let noise = scene::ImageData { width: NOISE_SIZE, height: NOISE_SIZE, pixels, srgb: false };
+    let (ssao_noise_image, ssao_noise_image_memory, ssao_noise_image_view) =
+        image::create_texture_image(instance, device, data, uploads, &noise)?;
+
+    data.ssao_noise_image = ssao_noise_image;
+    data.ssao_noise_image_memory = ssao_noise_image_memory;
+    data.ssao_noise_image_view = ssao_noise_image_view;
+
+    Ok(())
+}
+
+/// Creates the occlusion targets, the occlusion and blur passes, and the depth prepass the
+/// forward path needs to have depth before shading. Everything is sized to the swapchain, so
+/// this runs again on resize.
+pub unsafe fn create_ssao_objects(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    // Images
+
+    let (ssao_raw_image, ssao_raw_image_memory, ssao_raw_image_view) = create_occlusion_image(instance, device, data)?;
+    data.ssao_raw_image = ssao_raw_image;
+    data.ssao_raw_image_memory = ssao_raw_image_memory;
+    data.ssao_raw_image_view = ssao_raw_image_view;
+
+    let (ssao_image, ssao_image_memory, ssao_image_view) = create_occlusion_image(instance, device, data)?;
+    data.ssao_image = ssao_image;
+    data.ssao_image_memory = ssao_image_memory;
+    data.ssao_image_view = ssao_image_view;
+
+    // Render Passes
+
+    create_ssao_render_pass(device, data)?;
+    create_depth_prepass(instance, device, data)?;
+
+    // Framebuffers
+
+    let framebuffer = |view: vk::ImageView, render_pass: vk::RenderPass| {
+        render_pass::create_framebuffer(device, render_pass, &[view], data.swapchain_extent)
+    };
+
+    data.ssao_raw_framebuffer = framebuffer(data.ssao_raw_image_view, data.ssao_render_pass)?;
+    data.ssao_framebuffer = framebuffer(data.ssao_image_view, data.ssao_render_pass)?;
+    data.depth_prepass_framebuffer = framebuffer(data.depth_image_view, data.depth_prepass_render_pass)?;
+
+    // Sampler
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::NEAREST)
+        .min_filter(vk::Filter::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.ssao_sampler = device.create_sampler(&info, None)?;
+
+    // Descriptor Set
+
+    create_ssao_descriptor_set(device, data)?;
+
+    // Pipelines
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<SsaoPushConstants>() as u32);
+
+    let set_layouts = &[data.descriptor_set_layout, data.ssao_descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.ssao_pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    let vert = include_bytes!("../../shaders/fullscreen.bin");
+    let occlusion = include_bytes!("../../shaders/ssao.bin");
+    let blur = include_bytes!("../../shaders/ssao_blur.bin");
+
+    data.ssao_pipeline = pipeline::create_fullscreen_pipeline(
+        device,
+        data.swapchain_extent,
+        &vert[..],
+        &occlusion[..],
+        data.ssao_pipeline_layout,
+        data.ssao_render_pass,
+        OCCLUSION_FORMAT,
+        false,
+    )?;
+
+    data.ssao_blur_pipeline = pipeline::create_fullscreen_pipeline(
+        device,
+        data.swapchain_extent,
+        &vert[..],
+        &blur[..],
+        data.ssao_pipeline_layout,
+        data.ssao_render_pass,
+        OCCLUSION_FORMAT,
+        false,
+    )?;
+
+    let prepass = include_bytes!("../../shaders/prepass.bin");
+    let attachments = &[depth_prepass_attachment(data.depth_format)];
+    data.depth_prepass_pipeline =
+        pipeline::create_scene_pipeline(device, data, &prepass[..], data.depth_prepass_render_pass, attachments)?;
+
+    Ok(())
+}
+
+/// A swapchain-sized occlusion target, readable before the pass first writes it since shading
+/// binds it even while the pass is off.
+unsafe fn create_occlusion_image(
+    instance: &Instance,
+    device: &Device,
+    data: &mut app_data::Data,
+) -> Result<(vk::Image, vk::DeviceMemory, vk::ImageView)> {
+    let (occlusion_image, occlusion_image_memory) = image::create_image(
+        instance,
+        device,
+        data,
+        data.swapchain_extent.width,
+        data.swapchain_extent.height,
+        1,
+        vk::ImageCreateFlags::empty(),
+        OCCLUSION_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    image::transition_image_layout(
+        device,
+        data,
+        occlusion_image,
+        image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER),
+    )?;
+
+    let occlusion_image_view =
+        image::create_image_view(device, occlusion_image, OCCLUSION_FORMAT, vk::ImageAspectFlags::COLOR)?;
+
+    Ok((occlusion_image, occlusion_image_memory, occlusion_image_view))
+}
+
+/// Shared by the occlusion and blur passes, which overwrite their whole target.
+unsafe fn create_ssao_render_pass(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let attachment = render_pass::Attachment::color(OCCLUSION_FORMAT, vk::AttachmentLoadOp::DONT_CARE);
+    data.ssao_render_pass = render_pass::create_unless_dynamic(device, data, &[attachment])?;
+
+    Ok(())
+}
+
+/// Depth only, read by the occlusion pass and then loaded by the forward pass.
+unsafe fn create_depth_prepass(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let attachment = depth_prepass_attachment(swapchain::get_sampled_depth_format(instance, data)?);
+    data.depth_prepass_render_pass = render_pass::create_unless_dynamic(device, data, &[attachment])?;
+
+    Ok(())
+}
+
+fn depth_prepass_attachment(format: vk::Format) -> render_pass::Attachment {
+    render_pass::Attachment::depth(format, vk::AttachmentLoadOp::CLEAR)
+}
+
+/// Begins the depth prepass, which draws the scene's depth before the forward pass so
+/// occlusion is ready before shading.
+pub unsafe fn cmd_begin_depth_prepass(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+) {
+    let target = depth_prepass_attachment(data.depth_format).target(data.depth_image, data.depth_image_view);
+    let render_pass = data.depth_prepass_render_pass;
+    let framebuffer = data.depth_prepass_framebuffer;
+    render_pass::cmd_begin(device, command_buffer, data, images, render_pass, framebuffer, data.swapchain_extent, &[target]);
+}
+
+/// Ends the depth prepass, leaving depth readable by the occlusion pass.
+pub unsafe fn cmd_end_depth_prepass(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+) {
+    render_pass::cmd_end(device, command_buffer, data, data.depth_prepass_render_pass);
+
+    let aspect_mask = image_state::depth_aspect(data.depth_format);
+    images.cmd_use(device, command_buffer, data.depth_image, aspect_mask, image_state::Usage::DepthRead);
+}
+
+/// Set 1 of both passes: depth, noise, kernel, a nearest sampler and the raw occlusion.
+unsafe fn create_ssao_descriptor_set(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let image_binding = |binding: u32| vk::DescriptorSetLayoutBinding::builder()
+        .binding(binding)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .build();
+
+    let kernel_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(2)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .build();
+
+    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(3)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .build();
+
+    let bindings = &[image_binding(0), image_binding(1), kernel_binding, sampler_binding, image_binding(4)];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.ssao_descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(3);
+    let ubo_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(1);
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1);
+
+    let pool_sizes = &[image_size, ubo_size, sampler_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+
+    data.ssao_descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let layouts = &[data.ssao_descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder().descriptor_pool(data.ssao_descriptor_pool).set_layouts(layouts);
+
+    data.ssao_descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let image_infos = [
+        (0, data.depth_image_view, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL),
+        (1, data.ssao_noise_image_view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        (4, data.ssao_raw_image_view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+    ]
+    .map(|(binding, view, layout)| (binding, [vk::DescriptorImageInfo::builder().image_layout(layout).image_view(view).build()]));
+
+    let mut writes = image_infos.iter()
+        .map(|(binding, info)| vk::WriteDescriptorSet::builder()
+            .dst_set(data.ssao_descriptor_set)
+            .dst_binding(*binding)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(info)
+            .build())
+        .collect::<Vec<_>>();
+
+    let kernel_info = &[vk::DescriptorBufferInfo::builder()
+        .buffer(data.ssao_kernel_buffer)
+        .offset(0)
+        .range((size_of::<glm::Vec4>() * MAX_SSAO_SAMPLES) as u64)];
+    writes.push(vk::WriteDescriptorSet::builder()
+        .dst_set(data.ssao_descriptor_set)
+        .dst_binding(2)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .buffer_info(kernel_info)
+        .build());
+
+    let sampler_info = &[vk::DescriptorImageInfo::builder().sampler(data.ssao_sampler)];
+    writes.push(vk::WriteDescriptorSet::builder()
+        .dst_set(data.ssao_descriptor_set)
+        .dst_binding(3)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .image_info(sampler_info)
+        .build());
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+    Ok(())
+}
+
+pub unsafe fn destroy_ssao_objects(device: &Device, data: &mut app_data::Data) {
+    device.destroy_pipeline(data.depth_prepass_pipeline, None);
+    device.destroy_pipeline(data.ssao_blur_pipeline, None);
+    device.destroy_pipeline(data.ssao_pipeline, None);
+    device.destroy_pipeline_layout(data.ssao_pipeline_layout, None);
+    device.destroy_descriptor_pool(data.ssao_descriptor_pool, None);
+    device.destroy_descriptor_set_layout(data.ssao_descriptor_set_layout, None);
+    device.destroy_sampler(data.ssao_sampler, None);
+    device.destroy_framebuffer(data.depth_prepass_framebuffer, None);
+    device.destroy_framebuffer(data.ssao_framebuffer, None);
+    device.destroy_framebuffer(data.ssao_raw_framebuffer, None);
+    device.destroy_render_pass(data.depth_prepass_render_pass, None);
+    device.destroy_render_pass(data.ssao_render_pass, None);
+    device.destroy_image_view(data.ssao_image_view, None);
+    device.free_memory(data.ssao_image_memory, None);
+    device.destroy_image(data.ssao_image, None);
+    device.destroy_image_view(data.ssao_raw_image_view, None);
+    device.free_memory(data.ssao_raw_image_memory, None);
+    device.destroy_image(data.ssao_raw_image, None);
+}
+
+/// Computes occlusion from the depth buffer into `data.ssao_image`, then blurs it, leaving it
+/// readable by shading. Depth must be written and left readable, by the depth prepass or the
+/// G-buffer pass.
+pub unsafe fn cmd_draw_ssao(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    images: &mut image_state::ImageStates,
+    image_index: usize,
+) {
+    let push_constants = SsaoPushConstants {
+        radius: data.ssao.radius,
+        bias: data.ssao.bias,
+        sample_count: data.ssao.sample_count.min(MAX_SSAO_SAMPLES as u32),
+    };
+
+    let attachment = render_pass::Attachment::color(OCCLUSION_FORMAT, vk::AttachmentLoadOp::DONT_CARE);
+    let sampled = image_state::Usage::Sampled(vk::PipelineStageFlags::FRAGMENT_SHADER);
+
+    for (image, view, framebuffer, pipeline) in [
+        (data.ssao_raw_image, data.ssao_raw_image_view, data.ssao_raw_framebuffer, data.ssao_pipeline),
+        (data.ssao_image, data.ssao_image_view, data.ssao_framebuffer, data.ssao_blur_pipeline),
+    ] {
+        let render_pass = data.ssao_render_pass;
+        let target = attachment.target(image, view);
+        render_pass::cmd_begin(device, command_buffer, data, images, render_pass, framebuffer, data.swapchain_extent, &[target]);
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            data.ssao_pipeline_layout,
+            0,
+            &[data.descriptor_sets[image_index], data.ssao_descriptor_set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            data.ssao_pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<SsaoPushConstants>()),
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        render_pass::cmd_end(device, command_buffer, data, render_pass);
+
+        images.cmd_use(device, command_buffer, image, vk::ImageAspectFlags::COLOR, sampled);
+    }
+}
@@ -95,7 +95,7 @@ fn get_swapchain_present_mode(present_modes: &[vk::PresentModeKHR]) -> vk::Prese
 }
 
 fn get_swapchain_extent(window: &Window, capabilities: vk::SurfaceCapabilitiesKHR) -> vk::Extent2D {
-    if capabilities.current_extent.width != u32::max_value() {
+    if capabilities.current_extent.width != u32::MAX {
         capabilities.current_extent
     } else {
         let size = window.inner_size();
@@ -125,7 +125,7 @@ pub unsafe fn create_swapchain_image_views(device: &Device, data: &mut app_data:
 }
 
 pub unsafe fn create_depth_objects(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
-    let format = get_depth_format(instance, data)?;
+    let format = get_sampled_depth_format(instance, data)?;
 
     let (depth_image, depth_image_memory) = image::create_image(
         instance,
@@ -133,9 +133,11 @@ pub unsafe fn create_depth_objects(instance: &Instance, device: &Device, data: &
         data,
         data.swapchain_extent.width,
         data.swapchain_extent.height,
+        1,
+        vk::ImageCreateFlags::empty(),
         format,
         vk::ImageTiling::OPTIMAL,
-        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
@@ -149,19 +151,20 @@ pub unsafe fn create_depth_objects(instance: &Instance, device: &Device, data: &
     Ok(())
 }
 
-pub unsafe fn get_depth_format(instance: &Instance, data: &app_data::Data) -> Result<vk::Format> {
-    let candidates = &[
-        vk::Format::D32_SFLOAT,
-        vk::Format::D32_SFLOAT_S8_UINT,
-        vk::Format::D24_UNORM_S8_UINT,
-    ];
+const DEPTH_FORMATS: &[vk::Format] = &[
+    vk::Format::D32_SFLOAT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+];
 
+/// A depth format that can also be sampled, for depth written in one pass and read in another.
+pub unsafe fn get_sampled_depth_format(instance: &Instance, data: &app_data::Data) -> Result<vk::Format> {
     get_supported_format(
         instance,
         data,
-        candidates,
+        DEPTH_FORMATS,
         vk::ImageTiling::OPTIMAL,
-        vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE,
     )
 }
 
@@ -1,9 +1,48 @@
 use super::app_data;
 use super::app_defines;
 
-use anyhow::{Result};
+use anyhow::{anyhow, Result};
 use vulkanalia::prelude::v1_0::*;
 
+use vulkanalia::vk::DeviceV1_2;
+
+/// A resource destroyed once the GPU has finished the frames that may use it.
+#[derive(Copy, Clone, Debug)]
+pub enum Deletion {
+    Buffer(vk::Buffer, vk::DeviceMemory),
+    Image(vk::Image, vk::DeviceMemory, vk::ImageView),
+    ImageView(vk::ImageView),
+    Framebuffer(vk::Framebuffer),
+    Pipeline(vk::Pipeline),
+    PipelineLayout(vk::PipelineLayout),
+    RenderPass(vk::RenderPass),
+    DescriptorPool(vk::DescriptorPool),
+    DescriptorSetLayout(vk::DescriptorSetLayout),
+}
+
+impl Deletion {
+    pub unsafe fn destroy(&self, device: &Device) {
+        match *self {
+            Deletion::Buffer(buffer, memory) => {
+                device.destroy_buffer(buffer, None);
+                device.free_memory(memory, None);
+            }
+            Deletion::Image(image, memory, view) => {
+                device.destroy_image_view(view, None);
+                device.free_memory(memory, None);
+                device.destroy_image(image, None);
+            }
+            Deletion::ImageView(view) => device.destroy_image_view(view, None),
+            Deletion::Framebuffer(framebuffer) => device.destroy_framebuffer(framebuffer, None),
+            Deletion::Pipeline(pipeline) => device.destroy_pipeline(pipeline, None),
+            Deletion::PipelineLayout(layout) => device.destroy_pipeline_layout(layout, None),
+            Deletion::RenderPass(render_pass) => device.destroy_render_pass(render_pass, None),
+            Deletion::DescriptorPool(pool) => device.destroy_descriptor_pool(pool, None),
+            Deletion::DescriptorSetLayout(layout) => device.destroy_descriptor_set_layout(layout, None),
+        }
+    }
+}
+
 pub unsafe fn create_sync_objects(device: &Device, data: &mut app_data::Data) -> Result<()> {
     let semaphore_info = vk::SemaphoreCreateInfo::builder();
     let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
@@ -14,10 +53,141 @@ pub unsafe fn create_sync_objects(device: &Device, data: &mut app_data::Data) ->
         data.render_finished_semaphores
             .push(device.create_semaphore(&semaphore_info, None)?);
 
-        data.in_flight_fences.push(device.create_fence(&fence_info, None)?);
+        if !data.timeline {
+            data.in_flight_fences.push(device.create_fence(&fence_info, None)?);
+        }
+
+        if data.async_compute {
+            data.compute_finished_semaphores
+                .push(device.create_semaphore(&semaphore_info, None)?);
+            data.graphics_released_semaphores
+                .push(device.create_semaphore(&semaphore_info, None)?);
+        }
+    }
+
+    if data.timeline {
+        let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let info = vk::SemaphoreCreateInfo::builder().push_next(&mut type_info);
+        data.timeline_semaphore = device.create_semaphore(&info, None)?;
+    }
+
+    data.frame_values = vec![0; app_defines::MAX_FRAMES_IN_FLIGHT];
+    data.image_values = vec![0; data.swapchain_images.len()];
+
+    Ok(())
+}
+
+/// The highest GPU progress value the GPU has reached.
+pub unsafe fn completed_value(device: &Device, data: &app_data::Data) -> Result<u64> {
+    if data.timeline {
+        return Ok(device.get_semaphore_counter_value(data.timeline_semaphore)?);
+    }
+
+    // Values no longer held by a frame in flight were waited on before their slot was reused.
+    let mut completed = data.submitted_value;
+    for (value, fence) in data.frame_values.iter().zip(&data.in_flight_fences) {
+        if device.get_fence_status(*fence)? != vk::SuccessCode::SUCCESS {
+            completed = completed.min(value.saturating_sub(1));
+        }
+    }
+
+    Ok(completed)
+}
+
+/// Blocks until the GPU has reached `value`, which must have been submitted.
+pub unsafe fn wait_for_value(device: &Device, data: &app_data::Data, value: u64) -> Result<()> {
+    if value > data.submitted_value {
+        return Err(anyhow!("GPU progress value {} has not been submitted.", value));
+    }
+
+    if data.timeline {
+        let semaphores = &[data.timeline_semaphore];
+        let values = &[value];
+        let info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(semaphores)
+            .values(values);
+
+        device.wait_semaphores(&info, u64::MAX)?;
+    } else if let Some(frame) = data.frame_values.iter().position(|v| *v == value && value > 0) {
+        device.wait_for_fences(&[data.in_flight_fences[frame]], true, u64::MAX)?;
+    }
+
+    Ok(())
+}
+
+/// Submits a frame's graphics work, signaling the next GPU progress value, which is returned.
+pub unsafe fn submit_frame(
+    device: &Device,
+    data: &mut app_data::Data,
+    frame: usize,
+    wait_semaphores: &[vk::Semaphore],
+    wait_stages: &[vk::PipelineStageFlags],
+    command_buffers: &[vk::CommandBuffer],
+    signal_semaphores: &[vk::Semaphore],
+) -> Result<u64> {
+    let value = data.submitted_value + 1;
+    let info = vk::SubmitInfo::builder()
+        .wait_semaphores(wait_semaphores)
+        .wait_dst_stage_mask(wait_stages)
+        .command_buffers(command_buffers);
+
+    if data.timeline {
+        let mut signal_semaphores = signal_semaphores.to_vec();
+        signal_semaphores.push(data.timeline_semaphore);
+
+        // Binary semaphores ignore their values.
+        let mut signal_values = vec![0; signal_semaphores.len()];
+        signal_values[signal_semaphores.len() - 1] = value;
+
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder().signal_semaphore_values(&signal_values);
+        let info = info.signal_semaphores(&signal_semaphores).push_next(&mut timeline_info);
+
+        device.queue_submit(data.graphics_queue, &[info], vk::Fence::null())?;
+    } else {
+        let fence = data.in_flight_fences[frame];
+        device.reset_fences(&[fence])?;
+
+        let info = info.signal_semaphores(signal_semaphores);
+        device.queue_submit(data.graphics_queue, &[info], fence)?;
     }
 
-    data.images_in_flight = data.swapchain_images.iter().map(|_| vk::Fence::null()).collect();
+    data.submitted_value = value;
+    data.frame_values[frame] = value;
+
+    Ok(value)
+}
+
+/// Destroys `deletion` once the GPU has finished every frame submitted so far.
+pub fn defer_deletion(data: &mut app_data::Data, deletion: Deletion) {
+    data.deferred_deletions.push((data.submitted_value, deletion));
+}
+
+/// Destroys the deferred resources the GPU has finished with.
+pub unsafe fn collect_deletions(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    if data.deferred_deletions.is_empty() {
+        return Ok(());
+    }
+
+    let completed = completed_value(device, data)?;
+    data.deferred_deletions.retain(|(value, deletion)| {
+        if *value > completed {
+            return true;
+        }
+
+        deletion.destroy(device);
+        false
+    });
 
     Ok(())
 }
+
+pub unsafe fn destroy_sync_objects(device: &Device, data: &mut app_data::Data) {
+    data.in_flight_fences.iter().for_each(|f| device.destroy_fence(*f, None));
+    data.render_finished_semaphores.iter().for_each(|s| device.destroy_semaphore(*s, None));
+    data.compute_finished_semaphores.iter().for_each(|s| device.destroy_semaphore(*s, None));
+    data.graphics_released_semaphores.iter().for_each(|s| device.destroy_semaphore(*s, None));
+    data.image_available_semaphores.iter().for_each(|s| device.destroy_semaphore(*s, None));
+    device.destroy_semaphore(data.timeline_semaphore, None);
+}
@@ -0,0 +1,286 @@
+use super::app_data;
+use super::bloom;
+use super::image;
+use super::image_state;
+use super::pipeline;
+use super::postprocess;
+use super::render_pass;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+use std::mem::size_of;
+
+/// Format the scene is lit into before tone mapping.
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ToneMapOperator {
+    /// Fitted ACES reference and output transforms.
+    #[default]
+    Aces,
+    /// Reinhard on luminance.
+    Reinhard,
+    /// AgX base look.
+    AgX,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// Exposure compensation in stops; the HDR color is scaled by `2^exposure`.
+    pub exposure: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct ToneMapPushConstants {
+    exposure: f32,
+    operator: u32,
+    auto_exposure: u32,
+    bloom_intensity: f32,
+}
+
+/// The offscreen target both render paths light into, which temporal anti-aliasing copies its
+/// resolve back into.
+pub unsafe fn create_hdr_objects(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let (hdr_image, hdr_image_memory) = image::create_image(
+        instance,
+        device,
+        data,
+        data.swapchain_extent.width,
+        data.swapchain_extent.height,
+        1,
+        vk::ImageCreateFlags::empty(),
+        HDR_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.hdr_image = hdr_image;
+    data.hdr_image_memory = hdr_image_memory;
+    data.hdr_image_view = image::create_image_view(device, hdr_image, HDR_FORMAT, vk::ImageAspectFlags::COLOR)?;
+
+    Ok(())
+}
+
+/// Creates the pass that tone maps the HDR target into the swapchain image, or into the
+/// post-processing chain's source while it is active, with its framebuffers, descriptor set
+/// and pipeline. Reads the adapted exposure from `data.exposure_buffer` and adds bloom level 0
+/// before exposing.
+pub unsafe fn create_tonemap_objects(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let views = match postprocess::source(data) {
+        Some((_, view)) => vec![view; data.swapchain_images.len()],
+        None => data.swapchain_image_views.clone(),
+    };
+
+    // Render Pass
+
+    data.tonemap_render_pass = render_pass::create_unless_dynamic(device, data, &[output_attachment(data)])?;
+
+    // Framebuffers
+
+    data.tonemap_framebuffers = views.iter()
+        .map(|v| render_pass::create_framebuffer(device, data.tonemap_render_pass, &[*v], data.swapchain_extent))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Sampler
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+
+    data.tonemap_sampler = device.create_sampler(&info, None)?;
+
+    // Descriptor Set
+
+    let image_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let exposure_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(2)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bloom_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(3)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[image_binding, sampler_binding, exposure_binding, bloom_binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+
+    data.tonemap_descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(2);
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLER)
+        .descriptor_count(1);
+
+    let buffer_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+
+    let pool_sizes = &[image_size, sampler_size, buffer_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+
+    data.tonemap_descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let layouts = &[data.tonemap_descriptor_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder().descriptor_pool(data.tonemap_descriptor_pool).set_layouts(layouts);
+
+    data.tonemap_descriptor_set = device.allocate_descriptor_sets(&info)?[0];
+
+    let image_info = &[vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(data.hdr_image_view)];
+    let image_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.tonemap_descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .image_info(image_info);
+
+    let sampler_info = &[vk::DescriptorImageInfo::builder().sampler(data.tonemap_sampler)];
+    let sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.tonemap_descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .image_info(sampler_info);
+
+    let exposure_info = &[vk::DescriptorBufferInfo::builder()
+        .buffer(data.exposure_buffer)
+        .offset(0)
+        .range(vk::WHOLE_SIZE as u64)];
+    let exposure_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.tonemap_descriptor_set)
+        .dst_binding(2)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(exposure_info);
+
+    let bloom_info = &[vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(data.bloom_image_views[0])];
+    let bloom_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.tonemap_descriptor_set)
+        .dst_binding(3)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .image_info(bloom_info);
+
+    device.update_descriptor_sets(
+        &[image_write, sampler_write, exposure_write, bloom_write],
+        &[] as &[vk::CopyDescriptorSet],
+    );
+
+    // Pipeline
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<ToneMapPushConstants>() as u32);
+
+    let set_layouts = &[data.tonemap_descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.tonemap_pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+    let vert = include_bytes!("../../shaders/fullscreen.bin");
+    let frag = include_bytes!("../../shaders/tonemap.bin");
+    data.tonemap_pipeline = pipeline::create_fullscreen_pipeline(
+        device,
+        data.swapchain_extent,
+        &vert[..],
+        &frag[..],
+        data.tonemap_pipeline_layout,
+        data.tonemap_render_pass,
+        output_attachment(data).format,
+        false,
+    )?;
+
+    Ok(())
+}
+
+/// The swapchain image, or the chain's source while post-processing is active, written for
+/// every pixel.
+fn output_attachment(data: &app_data::Data) -> render_pass::Attachment {
+    let format = postprocess::source(data).map(|(f, _)| f).unwrap_or(data.swapchain_format);
+    render_pass::Attachment::color(format, vk::AttachmentLoadOp::DONT_CARE)
+}
+
+/// Tone maps the HDR target into swapchain image `image_index`, left as a color attachment,
+/// or into the chain's source, left readable by its first pass.
+pub unsafe fn cmd_tonemap(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    data: &app_data::Data,
+    image_index: usize,
+    images: &mut image_state::ImageStates,
+) {
+    let push_constants = ToneMapPushConstants {
+        exposure: data.tone_mapping.exposure,
+        operator: data.tone_mapping.operator as u32,
+        auto_exposure: data.auto_exposure.enabled as u32,
+        bloom_intensity: bloom::composite_intensity(data),
+    };
+
+    let (image, view) = postprocess::source_image(data)
+        .unwrap_or((data.swapchain_images[image_index], data.swapchain_image_views[image_index]));
+
+    let target = output_attachment(data).target(image, view);
+    let (render_pass, framebuffer) = (data.tonemap_render_pass, data.tonemap_framebuffers[image_index]);
+    render_pass::cmd_begin(device, command_buffer, data, images, render_pass, framebuffer, data.swapchain_extent, &[target]);
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.tonemap_pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.tonemap_pipeline_layout,
+        0,
+        &[data.tonemap_descriptor_set],
+        &[],
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        data.tonemap_pipeline_layout,
+        vk::ShaderStageFlags::FRAGMENT,
+        0,
+        std::slice::from_raw_parts(&push_constants as *const _ as *const u8, size_of::<ToneMapPushConstants>()),
+    );
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+    render_pass::cmd_end(device, command_buffer, data, render_pass);
+
+    if postprocess::source_image(data).is_some() {
+        let read = vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER;
+        images.cmd_use(device, command_buffer, image, vk::ImageAspectFlags::COLOR, image_state::Usage::Sampled(read));
+    }
+}
@@ -0,0 +1,324 @@
+use super::app_data;
+use super::image_state;
+use super::queue_family;
+use super::scene;
+use super::vertex_buffer;
+
+use anyhow::{Result};
+use vulkanalia::prelude::v1_0::*;
+use std::mem::size_of_val;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+/// Copies recorded on the transfer queue and, when it is a separate family, the barriers that
+/// acquire their results on the graphics queue. Nothing runs until the batch is submitted.
+#[derive(Clone, Debug)]
+pub struct Batch {
+    transfer_family: u32,
+    graphics_family: u32,
+    transfer_command_buffer: vk::CommandBuffer,
+    graphics_command_buffer: vk::CommandBuffer,
+    /// Stages the acquire barriers wait in, for the graphics submission's semaphore wait.
+    acquire_stages: vk::PipelineStageFlags,
+    staging: Vec<(vk::Buffer, vk::DeviceMemory)>,
+    /// Layouts of the images copied into, on the transfer queue.
+    images: image_state::ImageStates,
+    /// Layouts of the images acquired on the graphics queue.
+    acquired_images: image_state::ImageStates,
+}
+
+/// A submitted batch. Its staging buffers are freed once `fence` signals.
+#[derive(Clone, Debug)]
+pub struct PendingUpload {
+    fence: vk::Fence,
+    semaphore: vk::Semaphore,
+    transfer_command_buffer: vk::CommandBuffer,
+    graphics_command_buffer: vk::CommandBuffer,
+    staging: Vec<(vk::Buffer, vk::DeviceMemory)>,
+}
+
+impl Batch {
+    fn separate_family(&self) -> bool {
+        self.transfer_family != self.graphics_family
+    }
+}
+
+/// Starts recording a batch of uploads.
+pub unsafe fn begin(instance: &Instance, device: &Device, data: &app_data::Data) -> Result<Batch> {
+    let indices = queue_family::QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+    let allocate = |pool: vk::CommandPool| -> Result<vk::CommandBuffer> {
+        let info = vk::CommandBufferAllocateInfo::builder()
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_pool(pool)
+            .command_buffer_count(1);
+
+        let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+        let info = vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(command_buffer, &info)?;
+
+        Ok(command_buffer)
+    };
+
+    let transfer_command_buffer = allocate(data.transfer_command_pool)?;
+    let graphics_command_buffer = if indices.transfer != indices.graphics {
+        allocate(data.command_pool)?
+    } else {
+        vk::CommandBuffer::null()
+    };
+
+    Ok(Batch {
+        transfer_family: indices.transfer,
+        graphics_family: indices.graphics,
+        transfer_command_buffer,
+        graphics_command_buffer,
+        acquire_stages: vk::PipelineStageFlags::empty(),
+        staging: vec![],
+        images: image_state::ImageStates::default(),
+        acquired_images: image_state::ImageStates::default(),
+    })
+}
+
+/// Copies `values` into the start of `destination`, which must allow `TRANSFER_DST`. The copy
+/// is made visible to `consumer`, the stage and access the buffer is first used with.
+pub unsafe fn upload_buffer<T: Copy>(
+    instance: &Instance,
+    device: &Device,
+    data: &app_data::Data,
+    batch: &mut Batch,
+    values: &[T],
+    destination: vk::Buffer,
+    consumer: (vk::PipelineStageFlags, vk::AccessFlags),
+) -> Result<()> {
+    let size = size_of_val(values) as u64;
+    let staging_buffer = create_staging_buffer(instance, device, data, batch, values)?;
+
+    let region = vk::BufferCopy::builder().size(size);
+    device.cmd_copy_buffer(batch.transfer_command_buffer, staging_buffer, destination, &[region]);
+
+    let barrier = |src_access: vk::AccessFlags, dst_access: vk::AccessFlags| vk::BufferMemoryBarrier::builder()
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .src_queue_family_index(batch.transfer_family)
+        .dst_queue_family_index(batch.graphics_family)
+        .buffer(destination)
+        .offset(0)
+        .size(size)
+        .build();
+
+    if batch.separate_family() {
+        let release = barrier(vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::empty());
+        let acquire = barrier(vk::AccessFlags::empty(), consumer.1);
+        cmd_hand_over(device, batch, consumer.0, &[release], &[acquire]);
+    } else {
+        let mut barrier = barrier(vk::AccessFlags::TRANSFER_WRITE, consumer.1);
+        barrier.src_queue_family_index = vk::QUEUE_FAMILY_IGNORED;
+        barrier.dst_queue_family_index = vk::QUEUE_FAMILY_IGNORED;
+        cmd_hand_over(device, batch, consumer.0, &[barrier], &[]);
+    }
+
+    Ok(())
+}
+
+/// Copies the pixels of `source` into the first mip level and layer of the color image
+/// `image`, which must allow `TRANSFER_DST` and is left `SHADER_READ_ONLY_OPTIMAL` for fragment
+/// shaders.
+pub unsafe fn upload_image(
+    instance: &Instance,
+    device: &Device,
+    data: &app_data::Data,
+    batch: &mut Batch,
+    source: &scene::ImageData,
+    image: vk::Image,
+) -> Result<()> {
+    let staging_buffer = create_staging_buffer(instance, device, data, batch, &source.pixels)?;
+
+    // Copy
+
+    batch.images.cmd_use(device, batch.transfer_command_buffer, image, vk::ImageAspectFlags::COLOR, image_state::Usage::TransferDst);
+
+    let layers = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(layers)
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D { width: source.width, height: source.height, depth: 1 });
+
+    device.cmd_copy_buffer_to_image(
+        batch.transfer_command_buffer,
+        staging_buffer,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[region],
+    );
+
+    // Hand Over
+
+    let stage = vk::PipelineStageFlags::FRAGMENT_SHADER;
+    let usage = image_state::Usage::Sampled(stage);
+    let color = vk::ImageAspectFlags::COLOR;
+    if batch.separate_family() {
+        let families = (batch.transfer_family, batch.graphics_family);
+        batch.images.release(image, color, families, usage);
+        batch.images.cmd_flush(device, batch.transfer_command_buffer);
+
+        batch.acquired_images.acquire(image, color, families, image_state::Usage::TransferDst, usage);
+        batch.acquired_images.cmd_flush(device, batch.graphics_command_buffer);
+        batch.acquire_stages |= stage;
+    } else {
+        batch.images.cmd_use(device, batch.transfer_command_buffer, image, color, usage);
+    }
+
+    Ok(())
+}
+
+/// Submits `batch` without waiting for it. The graphics queue waits for the copies on the GPU,
+/// so work submitted after this may use the uploaded resources right away.
+pub unsafe fn submit(device: &Device, data: &mut app_data::Data, batch: Batch) -> Result<()> {
+    device.end_command_buffer(batch.transfer_command_buffer)?;
+
+    let fence = device.create_fence(&vk::FenceCreateInfo::builder(), None)?;
+    let transfer_command_buffers = &[batch.transfer_command_buffer];
+
+    let semaphore = if batch.separate_family() {
+        device.end_command_buffer(batch.graphics_command_buffer)?;
+
+        let semaphore = device.create_semaphore(&vk::SemaphoreCreateInfo::builder(), None)?;
+        let semaphores = &[semaphore];
+
+        let info = vk::SubmitInfo::builder()
+            .command_buffers(transfer_command_buffers)
+            .signal_semaphores(semaphores);
+
+        device.queue_submit(data.transfer_queue, &[info], vk::Fence::null())?;
+
+        // An empty batch still waits somewhere, so the semaphore is unsignaled again.
+        let wait_stages = if batch.acquire_stages.is_empty() {
+            &[vk::PipelineStageFlags::ALL_COMMANDS]
+        } else {
+            &[batch.acquire_stages]
+        };
+        let graphics_command_buffers = &[batch.graphics_command_buffer];
+        let info = vk::SubmitInfo::builder()
+            .wait_semaphores(semaphores)
+            .wait_dst_stage_mask(wait_stages)
+            .command_buffers(graphics_command_buffers);
+
+        device.queue_submit(data.graphics_queue, &[info], fence)?;
+
+        semaphore
+    } else {
+        let info = vk::SubmitInfo::builder().command_buffers(transfer_command_buffers);
+        device.queue_submit(data.transfer_queue, &[info], fence)?;
+
+        vk::Semaphore::null()
+    };
+
+    data.pending_uploads.push(PendingUpload {
+        fence,
+        semaphore,
+        transfer_command_buffer: batch.transfer_command_buffer,
+        graphics_command_buffer: batch.graphics_command_buffer,
+        staging: batch.staging,
+    });
+
+    Ok(())
+}
+
+/// Frees the staging memory and command buffers of uploads the GPU has finished.
+pub unsafe fn collect_finished(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let mut index = 0;
+    while index < data.pending_uploads.len() {
+        if device.get_fence_status(data.pending_uploads[index].fence)? != vk::SuccessCode::SUCCESS {
+            index += 1;
+            continue;
+        }
+
+        let upload = data.pending_uploads.swap_remove(index);
+        upload.staging.iter().for_each(|(b, m)| {
+            device.destroy_buffer(*b, None);
+            device.free_memory(*m, None);
+        });
+
+        device.free_command_buffers(data.transfer_command_pool, &[upload.transfer_command_buffer]);
+        if !upload.graphics_command_buffer.is_null() {
+            device.free_command_buffers(data.command_pool, &[upload.graphics_command_buffer]);
+        }
+
+        device.destroy_semaphore(upload.semaphore, None);
+        device.destroy_fence(upload.fence, None);
+    }
+
+    Ok(())
+}
+
+unsafe fn create_staging_buffer<T: Copy>(
+    instance: &Instance,
+    device: &Device,
+    data: &app_data::Data,
+    batch: &mut Batch,
+    values: &[T],
+) -> Result<vk::Buffer> {
+    let size = size_of_val(values) as u64;
+
+    let (staging_buffer, staging_buffer_memory) = vertex_buffer::create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    let memory = device.map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
+    memcpy(values.as_ptr(), memory.cast(), values.len());
+    device.unmap_memory(staging_buffer_memory);
+
+    batch.staging.push((staging_buffer, staging_buffer_memory));
+
+    Ok(staging_buffer)
+}
+
+/// Records the barriers ending a buffer copy. `release` goes on the transfer queue and
+/// `acquire`, for ownership transfers only, on the graphics queue, waiting in `stage`.
+unsafe fn cmd_hand_over(
+    device: &Device,
+    batch: &mut Batch,
+    stage: vk::PipelineStageFlags,
+    release: &[vk::BufferMemoryBarrier],
+    acquire: &[vk::BufferMemoryBarrier],
+) {
+    // A released resource is not used on the transfer queue again, so nothing there waits.
+    let release_stage = if batch.separate_family() { vk::PipelineStageFlags::BOTTOM_OF_PIPE } else { stage };
+
+    device.cmd_pipeline_barrier(
+        batch.transfer_command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        release_stage,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        release,
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    if batch.separate_family() {
+        device.cmd_pipeline_barrier(
+            batch.graphics_command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            stage,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            acquire,
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        batch.acquire_stages |= stage;
+    }
+}
@@ -1,9 +1,11 @@
 use super::app_data;
+use super::app_defines;
+use super::sync;
+use super::upload;
 
 use anyhow::{anyhow, Result};
 use vulkanalia::prelude::v1_0::*;
-use std::ptr::copy_nonoverlapping as memcpy;
-use std::mem::size_of;
+use std::mem::{size_of, size_of_val};
 use nalgebra_glm as glm;
 
 #[repr(C)]
@@ -11,6 +13,10 @@ use nalgebra_glm as glm;
 pub struct Vertex {
     pos: glm::Vec3,
     color: glm::Vec3,
+    tex_coord: glm::Vec2,
+    joints: glm::UVec4,
+    weights: glm::Vec4,
+    normal: glm::Vec3,
 }
 
 #[repr(C)]
@@ -19,11 +25,63 @@ pub struct UniformBufferObject {
     pub model: glm::Mat4,
     pub view: glm::Mat4,
     pub proj: glm::Mat4,
+    /// Inverse transpose of `model`; only its upper 3×3 is used.
+    pub normal: glm::Mat4,
+    /// `proj` without the TAA jitter, for motion vectors.
+    pub unjittered_proj: glm::Mat4,
+    /// The previous frame's `model`, `view` and unjittered `proj`.
+    pub previous_model: glm::Mat4,
+    pub previous_view: glm::Mat4,
+    pub previous_proj: glm::Mat4,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PushConstants {
+    pub model: glm::Mat4,
+    /// Columns of the inverse transpose of `model`'s upper 3×3, padded to `vec4` as in GLSL.
+    pub normal_matrix: [glm::Vec4; 3],
+    /// First joint matrix of a skinned draw, or -1 for rigid geometry.
+    pub joint_offset: i32,
+    pub object_id: u32,
+    /// Non-zero to read the above from the object buffer instead, for GPU-driven draws.
+    pub indirect: u32,
+}
+
+/// A submitted one-time command buffer. It and `deletions` are destroyed once `fence` signals.
+#[derive(Clone, Debug)]
+pub struct PendingCommands {
+    fence: vk::Fence,
+    command_buffer: vk::CommandBuffer,
+    deletions: Vec<sync::Deletion>,
+}
+
+impl PushConstants {
+    pub fn normal_matrix(model: &glm::Mat4) -> [glm::Vec4; 3] {
+        let normal = glm::inverse_transpose(*model);
+        [0, 1, 2].map(|c| glm::vec4(normal[(0, c)], normal[(1, c)], normal[(2, c)], 0.0))
+    }
 }
 
 impl Vertex {
-    pub fn new(pos: glm::Vec3, color: glm::Vec3) -> Self {
-        Self { pos, color }
+    pub fn new(pos: glm::Vec3, color: glm::Vec3, tex_coord: glm::Vec2) -> Self {
+        Self { pos, color, tex_coord, joints: glm::UVec4::zeros(), weights: glm::Vec4::zeros(), normal: glm::Vec3::zeros() }
+    }
+
+    pub fn pos(&self) -> glm::Vec3 {
+        self.pos
+    }
+
+    pub fn normal(&self) -> glm::Vec3 {
+        self.normal
+    }
+
+    pub fn with_normal(self, normal: glm::Vec3) -> Self {
+        Self { normal, ..self }
+    }
+
+    pub fn with_skin(self, joints: glm::UVec4, weights: glm::Vec4) -> Self {
+        Self { joints, weights, ..self }
     }
 
     pub fn binding_description() -> vk::VertexInputBindingDescription {
@@ -34,7 +92,7 @@ impl Vertex {
             .build()
     }
 
-    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 6] {
         let pos = vk::VertexInputAttributeDescription::builder()
             .binding(0)
             .location(0)
@@ -47,40 +105,48 @@ impl Vertex {
             .format(vk::Format::R32G32B32_SFLOAT)
             .offset(size_of::<glm::Vec3>() as u32)
             .build();
-        [pos, color]
+        let tex_coord = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(2)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset((size_of::<glm::Vec3>() + size_of::<glm::Vec3>()) as u32)
+            .build();
+        let joints = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(3)
+            .format(vk::Format::R32G32B32A32_UINT)
+            .offset((size_of::<glm::Vec3>() + size_of::<glm::Vec3>() + size_of::<glm::Vec2>()) as u32)
+            .build();
+        let weights = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(4)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset((size_of::<glm::Vec3>() + size_of::<glm::Vec3>() + size_of::<glm::Vec2>() + size_of::<glm::UVec4>()) as u32)
+            .build();
+        let normal = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(5)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset((size_of::<glm::Vec3>() + size_of::<glm::Vec3>() + size_of::<glm::Vec2>() + size_of::<glm::UVec4>() + size_of::<glm::Vec4>()) as u32)
+            .build();
+        [pos, color, tex_coord, joints, weights, normal]
     }
 }
 
 
-pub unsafe fn create(instance: &Instance, device: &Device, data: &mut app_data::Data, vertices: &Vec<Vertex>) -> Result<()> {
-    // Create (staging)
-
-    let size = (size_of::<Vertex>() * vertices.len()) as u64;
-
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
-        instance,
-        device,
-        data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
-    )?;
-
-    // Copy (staging)
-
-    let memory = device.map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
-
-    memcpy(vertices.as_ptr(), memory.cast(), vertices.len());
-
-    device.unmap_memory(staging_buffer_memory);
-
-    // Create (vertex)
-
+/// Creates the device local vertex buffer and records its upload into `uploads`.
+pub unsafe fn create(
+    instance: &Instance,
+    device: &Device,
+    data: &mut app_data::Data,
+    uploads: &mut upload::Batch,
+    vertices: &[Vertex],
+) -> Result<()> {
     let (vertex_buffer, vertex_buffer_memory) = create_buffer(
         instance,
         device,
         data,
-        size,
+        size_of_val(vertices) as u64,
         vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
@@ -88,47 +154,23 @@ pub unsafe fn create(instance: &Instance, device: &Device, data: &mut app_data::
     data.vertex_buffer = vertex_buffer;
     data.vertex_buffer_memory = vertex_buffer_memory;
 
-    // Copy (vertex)
-
-    copy_buffer(device, data, staging_buffer, vertex_buffer, size)?;
-
-    // Cleanup
-
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
-
-    Ok(())
+    let consumer = (vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::VERTEX_ATTRIBUTE_READ);
+    upload::upload_buffer(instance, device, data, uploads, vertices, vertex_buffer, consumer)
 }
 
-pub unsafe fn create_index_buffer(instance: &Instance, device: &Device, data: &mut app_data::Data, indices: &Vec<u16>) -> Result<()> {
-    // Create (staging)
-
-    let size = (size_of::<u16>() * indices.len()) as u64;
-
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
-        instance,
-        device,
-        data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
-    )?;
-
-    // Copy (staging)
-
-    let memory = device.map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
-
-    memcpy(indices.as_ptr(), memory.cast(), indices.len());
-
-    device.unmap_memory(staging_buffer_memory);
-
-    // Create (index)
-
+/// Creates the device local index buffer and records its upload into `uploads`.
+pub unsafe fn create_index_buffer(
+    instance: &Instance,
+    device: &Device,
+    data: &mut app_data::Data,
+    uploads: &mut upload::Batch,
+    indices: &[u32],
+) -> Result<()> {
     let (index_buffer, index_buffer_memory) = create_buffer(
         instance,
         device,
         data,
-        size,
+        size_of_val(indices) as u64,
         vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
@@ -136,16 +178,8 @@ pub unsafe fn create_index_buffer(instance: &Instance, device: &Device, data: &m
     data.index_buffer = index_buffer;
     data.index_buffer_memory = index_buffer_memory;
 
-    // Copy (index)
-
-    copy_buffer(device, data, staging_buffer, index_buffer, size)?;
-
-    // Cleanup
-
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
-
-    Ok(())
+    let consumer = (vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::INDEX_READ);
+    upload::upload_buffer(instance, device, data, uploads, indices, index_buffer, consumer)
 }
 
 pub unsafe fn get_memory_type_index(
@@ -164,20 +198,36 @@ pub unsafe fn get_memory_type_index(
         .ok_or_else(|| anyhow!("Failed to find suitable memory type."))
 }
 
-unsafe fn create_buffer(
+pub unsafe fn create_buffer(
+    instance: &Instance,
+    device: &Device,
+    data: &app_data::Data,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+    create_shared_buffer(instance, device, data, &[], size, usage, properties)
+}
+
+/// A buffer used concurrently by the queue families in `families`, or exclusively if fewer
+/// than two are given.
+pub unsafe fn create_shared_buffer(
     instance: &Instance,
     device: &Device,
     data: &app_data::Data,
+    families: &[u32],
     size: vk::DeviceSize,
     usage: vk::BufferUsageFlags,
     properties: vk::MemoryPropertyFlags,
 ) -> Result<(vk::Buffer, vk::DeviceMemory)> {
     // Buffer
 
+    let sharing_mode = if families.len() > 1 { vk::SharingMode::CONCURRENT } else { vk::SharingMode::EXCLUSIVE };
     let buffer_info = vk::BufferCreateInfo::builder()
         .size(size)
         .usage(usage)
-        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        .sharing_mode(sharing_mode)
+        .queue_family_indices(families);
 
     let buffer = device.create_buffer(&buffer_info, None)?;
 
@@ -217,18 +267,28 @@ pub unsafe fn create_uniform_buffers(instance: &Instance, device: &Device, data:
     Ok(())
 }
 
-unsafe fn copy_buffer(device: &Device, data: &app_data::Data, source: vk::Buffer, destination: vk::Buffer, size: vk::DeviceSize) -> Result<()> {
-    let command_buffer = begin_single_time_commands(device, data)?;
+pub unsafe fn create_joint_buffers(instance: &Instance, device: &Device, data: &mut app_data::Data) -> Result<()> {
+    data.joint_buffers.clear();
+    data.joint_buffers_memory.clear();
 
-    let regions = vk::BufferCopy::builder().size(size);
-    device.cmd_copy_buffer(command_buffer, source, destination, &[regions]);
+    for _ in 0..data.swapchain_images.len() {
+        let (joint_buffer, joint_buffer_memory) = create_buffer(
+            instance,
+            device,
+            data,
+            (size_of::<glm::Mat4>() * app_defines::MAX_JOINTS) as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
 
-    end_single_time_commands(device, data, command_buffer)?;
+        data.joint_buffers.push(joint_buffer);
+        data.joint_buffers_memory.push(joint_buffer_memory);
+    }
 
     Ok(())
 }
 
-unsafe fn begin_single_time_commands(device: &Device, data: &app_data::Data) -> Result<vk::CommandBuffer> {
+pub unsafe fn begin_single_time_commands(device: &Device, data: &app_data::Data) -> Result<vk::CommandBuffer> {
     // Allocate
 
     let info = vk::CommandBufferAllocateInfo::builder()
@@ -247,22 +307,46 @@ unsafe fn begin_single_time_commands(device: &Device, data: &app_data::Data) ->
     Ok(command_buffer)
 }
 
-unsafe fn end_single_time_commands(device: &Device, data: &app_data::Data, command_buffer: vk::CommandBuffer) -> Result<()> {
+/// Submits `command_buffer` without waiting for it. Work submitted to the graphics queue after
+/// it runs after it; `deletions`, the objects it uses, are destroyed once it has finished.
+pub unsafe fn end_single_time_commands(
+    device: &Device,
+    data: &mut app_data::Data,
+    command_buffer: vk::CommandBuffer,
+    deletions: Vec<sync::Deletion>,
+) -> Result<()> {
     // End
 
     device.end_command_buffer(command_buffer)?;
 
     // Submit
 
+    let fence = device.create_fence(&vk::FenceCreateInfo::builder(), None)?;
     let command_buffers = &[command_buffer];
     let info = vk::SubmitInfo::builder().command_buffers(command_buffers);
 
-    device.queue_submit(data.graphics_queue, &[info], vk::Fence::null())?;
-    device.queue_wait_idle(data.graphics_queue)?;
+    device.queue_submit(data.graphics_queue, &[info], fence)?;
 
-    // Cleanup
+    data.pending_commands.push(PendingCommands { fence, command_buffer, deletions });
 
-    device.free_command_buffers(data.command_pool, &[command_buffer]);
+    Ok(())
+}
+
+/// Frees the one-time command buffers, and the objects they used, the GPU has finished.
+pub unsafe fn collect_single_time_commands(device: &Device, data: &mut app_data::Data) -> Result<()> {
+    let mut index = 0;
+    while index < data.pending_commands.len() {
+        if device.get_fence_status(data.pending_commands[index].fence)? != vk::SuccessCode::SUCCESS {
+            index += 1;
+            continue;
+        }
+
+        let commands = data.pending_commands.swap_remove(index);
+        commands.deletions.iter().for_each(|d| d.destroy(device));
+
+        device.free_command_buffers(data.command_pool, &[commands.command_buffer]);
+        device.destroy_fence(commands.fence, None);
+    }
 
     Ok(())
 }
\ No newline at end of file
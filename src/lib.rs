@@ -0,0 +1,4 @@
+// Every Vulkan call is unsafe; the renderer does not document per-function safety contracts.
+#![allow(clippy::missing_safety_doc)]
+
+pub mod app;
@@ -1,14 +1,14 @@
-///
-/// Enable debug logging: $env:RUST_LOG="debug"
-///
-
-mod app;
+//!
+//! Enable debug logging: $env:RUST_LOG="debug"
+//!
 
 use log::*;
+use vulkan::app;
 use winit::dpi::LogicalSize;
-use winit::event::{Event, WindowEvent};
+use winit::event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::{WindowBuilder};
+use std::path::PathBuf;
 
 fn main() {
     pretty_env_logger::init();
@@ -20,15 +20,142 @@ fn main() {
         .with_inner_size(LogicalSize::new(1024, 768))
         .build(&event_loop).unwrap();
 
-        let mut app = unsafe { app::App::create(&window).unwrap() };
+        let model = std::env::args().nth(1).map(PathBuf::from);
+        let environment = match std::env::args().nth(2) {
+            Some(path) => app::environment::Source::Equirectangular(app::environment::HdrImage::load(path.as_ref()).unwrap()),
+            None => app::environment::Source::Sky,
+        };
+        let options = app::Options { picking: true, environment, ..Default::default() };
+        let mut app = unsafe { app::App::create(&window, model.as_deref(), &options).unwrap() };
         let mut destroying = false;
         let mut minimized = false;
+        let mut cursor = (0, 0);
 
         event_loop.run(move |event, _, control_flow| {
             *control_flow = ControlFlow::Poll;
             match event {
 
-                Event::MainEventsCleared if !destroying && !minimized => unsafe { app.render(&window) }.unwrap(),
+                Event::MainEventsCleared if !destroying && !minimized => {
+                    unsafe { app.render(&window) }.unwrap();
+                    if let Some(pick) = app.take_pick() {
+                        info!("Picked node {:?} at ({}, {}).", pick.node, pick.x, pick.y);
+                    }
+                }
+
+                Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, .. } => {
+                    cursor = (position.x.max(0.0) as u32, position.y.max(0.0) as u32);
+                }
+
+                Event::WindowEvent { event: WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. }, .. } => {
+                    app.request_pick(cursor.0, cursor.1);
+                }
+
+                Event::WindowEvent { event: WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. }, .. }, .. } => {
+                    if key == VirtualKeyCode::D {
+                        let path = match app.render_path() {
+                            app::deferred::RenderPath::Forward => app::deferred::RenderPath::Deferred,
+                            app::deferred::RenderPath::Deferred => app::deferred::RenderPath::Forward,
+                        };
+                        unsafe { app.set_render_path(&window, path) }.unwrap();
+                        info!("Render path: {:?}.", path);
+                    }
+
+                    let mut tone_mapping = app.tone_mapping();
+                    match key {
+                        VirtualKeyCode::T => tone_mapping.operator = match tone_mapping.operator {
+                            app::tonemap::ToneMapOperator::Aces => app::tonemap::ToneMapOperator::Reinhard,
+                            app::tonemap::ToneMapOperator::Reinhard => app::tonemap::ToneMapOperator::AgX,
+                            app::tonemap::ToneMapOperator::AgX => app::tonemap::ToneMapOperator::Aces,
+                        },
+                        VirtualKeyCode::RBracket => tone_mapping.exposure += 0.5,
+                        VirtualKeyCode::LBracket => tone_mapping.exposure -= 0.5,
+                        _ => {}
+                    }
+                    if tone_mapping != app.tone_mapping() {
+                        app.set_tone_mapping(tone_mapping);
+                        info!("Tone mapping: {:?}.", tone_mapping);
+                    }
+
+                    let mut auto_exposure = app.auto_exposure();
+                    match key {
+                        VirtualKeyCode::E => auto_exposure.enabled = !auto_exposure.enabled,
+                        VirtualKeyCode::M => auto_exposure.metering = match auto_exposure.metering {
+                            app::exposure::Metering::Average => app::exposure::Metering::CenterWeighted,
+                            app::exposure::Metering::CenterWeighted => app::exposure::Metering::Spot,
+                            app::exposure::Metering::Spot => app::exposure::Metering::Average,
+                        },
+                        _ => {}
+                    }
+                    if auto_exposure != app.auto_exposure() {
+                        app.set_auto_exposure(auto_exposure);
+                        info!("Auto exposure: {:?}.", auto_exposure);
+                    }
+
+                    if key == VirtualKeyCode::O {
+                        let mut ssao = app.ssao();
+                        ssao.enabled = !ssao.enabled;
+                        unsafe { app.set_ssao(&window, ssao) }.unwrap();
+                        info!("Ambient occlusion: {:?}.", ssao);
+                    }
+
+                    if key == VirtualKeyCode::I {
+                        let mut environment = app.environment();
+                        environment.enabled = !environment.enabled;
+                        app.set_environment(environment);
+                        info!("Environment: {:?}.", environment);
+                    }
+
+                    if key == VirtualKeyCode::P {
+                        let emitters = app.particle_emitters();
+                        if emitters.is_empty() {
+                            emitters.push(app::particles::Emitter::default());
+                        } else {
+                            emitters.iter_mut().for_each(|e| e.enabled = !e.enabled);
+                        }
+                        info!("Particle emitters: {:?}.", emitters);
+                    }
+
+                    if key == VirtualKeyCode::G {
+                        app.set_gpu_driven(!app.gpu_driven());
+                        info!("GPU-driven rendering: {}.", app.gpu_driven());
+                    }
+
+                    if key == VirtualKeyCode::R {
+                        unsafe { app.set_dynamic_rendering(&window, !app.dynamic_rendering()) }.unwrap();
+                        info!("Dynamic rendering: {}.", app.dynamic_rendering());
+                    }
+
+                    if key == VirtualKeyCode::A {
+                        let anti_aliasing = match app.anti_aliasing() {
+                            app::antialiasing::AntiAliasing::None => app::antialiasing::AntiAliasing::Fxaa,
+                            app::antialiasing::AntiAliasing::Fxaa => app::antialiasing::AntiAliasing::Taa,
+                            app::antialiasing::AntiAliasing::Taa => app::antialiasing::AntiAliasing::None,
+                        };
+                        unsafe { app.set_anti_aliasing(&window, anti_aliasing) }.unwrap();
+                        info!("Anti-aliasing: {:?}.", anti_aliasing);
+                    }
+
+                    let mut bloom = app.bloom();
+                    match key {
+                        VirtualKeyCode::B => bloom.enabled = !bloom.enabled,
+                        VirtualKeyCode::Key0 => bloom.threshold += 0.25,
+                        VirtualKeyCode::Key9 => bloom.threshold = (bloom.threshold - 0.25).max(0.0),
+                        _ => {}
+                    }
+                    if bloom != app.bloom() {
+                        app.set_bloom(bloom);
+                        info!("Bloom: {:?}.", bloom);
+                    }
+
+                    let clock = app.clock();
+                    match key {
+                        VirtualKeyCode::Space => clock.set_paused(!clock.is_paused()),
+                        VirtualKeyCode::Period => clock.single_step(),
+                        VirtualKeyCode::Equals => clock.set_time_scale(clock.time_scale() * 2.0),
+                        VirtualKeyCode::Minus => clock.set_time_scale(clock.time_scale() * 0.5),
+                        _ => {}
+                    }
+                }
 
                 Event::WindowEvent { event: WindowEvent::Resized(size), .. } => {
                     if size.width == 0 || size.height == 0 {